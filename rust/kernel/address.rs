@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Address resolution : where does an address point to
+//!
+//! The provenance checks (hook handlers, probe targets, return addresses, ...) all need
+//! to know who owns an address : the kernel image, a module or nobody, in which section
+//! it is and which symbol contains it. [`resolve_address`] answers all of it at once.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::c_str;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use crate::module::is_bpf_jit_space;
+use crate::module::{
+    is_vmalloc_addr, symbols_lookup_address, symbols_lookup_name, ModMemType, Module,
+    MODULE_NAME_LEN,
+};
+use crate::str::{until_nul, BStr};
+use crate::sync::rcu;
+use kernel::prelude::*;
+
+/// Owner of an address
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    /// The kernel image
+    Kernel,
+    /// A module, by its name (null terminated)
+    Module([u8; MODULE_NAME_LEN]),
+    /// Nobody, the address is not in the kernel image nor in a module
+    None,
+}
+
+impl fmt::Debug for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Kernel => f.write_str("kernel"),
+            Owner::Module(name) => write!(f, "{}", BStr::from_bytes(until_nul(name))),
+            Owner::None => f.write_str("none"),
+        }
+    }
+}
+
+/// Type of the memory region containing an address
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RegionType {
+    /// Kernel text (`_stext` to `_etext`)
+    KernelText,
+    /// Kernel read-only data (`__start_rodata` to `__end_rodata`)
+    KernelRodata,
+    /// Kernel data and bss
+    KernelData,
+    /// Kernel init sections (`__init_begin` to `__init_end`)
+    KernelInit,
+    /// A region of a module
+    Module(ModMemType),
+    /// A BPF JIT image
+    BpfJit,
+    /// vmalloc memory not owned by a module (trampolines, kprobe slots, ...)
+    Vmalloc,
+    /// Anything else
+    Unknown,
+}
+
+/// Everything known about an address
+pub struct AddressInfo {
+    /// The resolved address
+    pub address: u64,
+    /// Owner of the address
+    pub owner: Owner,
+    /// Region containing the address
+    pub region: RegionType,
+    /// Name of the symbol containing the address (null terminated), if any
+    pub symbol: Option<KVec<u8>>,
+    /// Offset of the address from the start of the symbol
+    pub offset: u64,
+    /// Size of the symbol
+    pub size: u64,
+}
+
+impl AddressInfo {
+    /// The address is owned by the kernel or a module
+    pub fn is_attributed(&self) -> bool {
+        self.owner != Owner::None
+    }
+
+    /// The address is in executable memory of the kernel or a module
+    pub fn is_text(&self) -> bool {
+        match self.region {
+            RegionType::KernelText | RegionType::KernelInit | RegionType::BpfJit => true,
+            RegionType::Module(mem_type) => mem_type.is_text(),
+            _ => false,
+        }
+    }
+
+    /// Get the name of the owner module, empty if the owner is not a module
+    pub fn owner_name(&self) -> &[u8] {
+        match &self.owner {
+            Owner::Module(name) => until_nul(name),
+            _ => &[],
+        }
+    }
+
+    /// Get the name of the symbol (without the null terminator), empty if unknown
+    pub fn symbol_name(&self) -> &[u8] {
+        match &self.symbol {
+            Some(symbol) => until_nul(symbol),
+            None => &[],
+        }
+    }
+}
+
+impl fmt::Display for AddressInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} [{:?} {:?}]",
+            self.address, self.owner, self.region
+        )?;
+        if self.symbol.is_some() {
+            write!(
+                f,
+                " {}+{:#x}/{:#x}",
+                BStr::from_bytes(self.symbol_name()),
+                self.offset,
+                self.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Bounds of the sections of the kernel image searched by [`kernel_region`], the text,
+/// the init sections, the read-only data and the data, resolved once by [`kernel_sections`]
+static SECTIONS: [AtomicU64; 8] = [const { AtomicU64::new(0) }; 8];
+
+/// [`SECTIONS`] is resolved
+static SECTIONS_RESOLVED: AtomicBool = AtomicBool::new(false);
+
+/// Get the bounds of the sections of the kernel image searched by [`kernel_region`]
+///
+/// They are resolved with kallsyms at the first call, two concurrent first calls resolve
+/// the same bounds.
+fn kernel_sections() -> [(u64, u64); 4] {
+    if !SECTIONS_RESOLVED.load(Ordering::Acquire) {
+        let names = [
+            (c_str!("_stext"), c_str!("_etext")),
+            (c_str!("__init_begin"), c_str!("__init_end")),
+            (c_str!("__start_rodata"), c_str!("__end_rodata")),
+            (c_str!("_sdata"), c_str!("_end")),
+        ];
+        for (i, (start, end)) in names.iter().enumerate() {
+            SECTIONS[2 * i].store(symbols_lookup_name(start), Ordering::Relaxed);
+            SECTIONS[2 * i + 1].store(symbols_lookup_name(end), Ordering::Relaxed);
+        }
+        SECTIONS_RESOLVED.store(true, Ordering::Release);
+    }
+    core::array::from_fn(|i| {
+        (
+            SECTIONS[2 * i].load(Ordering::Relaxed),
+            SECTIONS[2 * i + 1].load(Ordering::Relaxed),
+        )
+    })
+}
+
+/// Find the section of the kernel image containing `addr`
+fn kernel_region(addr: u64) -> Option<RegionType> {
+    let [text, init, rodata, data] = kernel_sections();
+    let inside = |(start, end): (u64, u64)| start != 0 && (start..end).contains(&addr);
+
+    if inside(text) {
+        Some(RegionType::KernelText)
+    } else if inside(init) {
+        Some(RegionType::KernelInit)
+    } else if inside(rodata) {
+        Some(RegionType::KernelRodata)
+    } else if inside(data) {
+        Some(RegionType::KernelData)
+    } else {
+        None
+    }
+}
+
+/// Find the module containing `addr`
+///
+/// # Return
+/// The name of the module and the type of the region containing the address
+fn module_region(addr: u64) -> Option<([u8; MODULE_NAME_LEN], ModMemType)> {
+    let _guard = rcu::read_lock();
+
+    // SAFETY: Just an FFI call, we hold the RCU read lock as required
+    let module = unsafe { bindings::__module_address(addr as _) };
+    if module.is_null() {
+        return None;
+    }
+
+    // SAFETY: The module can't be freed while we hold the RCU read lock
+    let module = unsafe { Module::from_raw(module) };
+    let region = module.contains_addr(addr)?;
+
+    let mut name = [0u8; MODULE_NAME_LEN];
+    let src = module.name().as_bytes();
+    name[..src.len()].copy_from_slice(src);
+
+    Some((name, region.mem_type))
+}
+
+/// Resolve an address to its owner, region and symbol
+///
+/// The symbol name is allocated with `GFP_KERNEL`, so this must not be called from a
+/// probe handler or any other atomic context, use [`symbols_lookup_address_buf`] there.
+///
+/// [`symbols_lookup_address_buf`]: crate::module::symbols_lookup_address_buf
+pub fn resolve_address(addr: u64) -> Result<AddressInfo> {
+    let (owner, region) = if let Some(region) = kernel_region(addr) {
+        (Owner::Kernel, region)
+    } else if let Some((name, mem_type)) = module_region(addr) {
+        (Owner::Module(name), RegionType::Module(mem_type))
+    } else {
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        let bpf = is_bpf_jit_space(addr);
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        let bpf = false;
+
+        let region = if bpf {
+            RegionType::BpfJit
+        } else if is_vmalloc_addr(addr) {
+            RegionType::Vmalloc
+        } else {
+            RegionType::Unknown
+        };
+        (Owner::None, region)
+    };
+
+    let mut offset = 0;
+    let mut size = 0;
+    let (_, symbol) = symbols_lookup_address(addr, &mut offset, &mut size)?;
+
+    Ok(AddressInfo {
+        address: addr,
+        owner,
+        region,
+        symbol,
+        offset,
+        size,
+    })
+}
@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Analysis : recognition of the redirections at the start of a function
+//!
+//! An inline hook overwrites the prologue of a function with a redirection to the code of
+//! the rootkit. [`classify_prologue`] recognizes the classic ones and resolves their
+//! destination, which [`resolve_address`](crate::address::resolve_address) attributes to
+//! the hijacker. The landing pad of the indirect branches (the `ENDBR64` of an IBT kernel,
+//! the `bti c` of arm64) is skipped.
+//!
+//! The relative jumps and calls are decoded through [`Instruction`], the other patterns
+//! depend on the architecture.
+//!
+//! C header : [`arch/x86/include/asm/insn.h`](../../../../arch/x86/include/asm/insn.h)
+
+use core::fmt;
+
+use crate::insn::{register_name, Branch, BranchKind, InsnIter, Instruction};
+
+/// A redirection at the start of a function
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HookKind {
+    /// No redirection recognized
+    NotHooked,
+    /// `jmp rel32` or `jmp rel8`
+    JmpRel {
+        /// Destination of the jump
+        destination: u64,
+    },
+    /// A jump followed by `int3` padding, a thunk like the static call trampolines
+    Thunk {
+        /// Destination of the jump
+        destination: u64,
+    },
+    /// `mov reg, imm64 ; jmp reg` on x86
+    MovJmpReg {
+        /// The register holding the destination
+        register: u8,
+        /// Destination of the jump
+        destination: u64,
+    },
+    /// `push imm32 ; ret` on x86
+    PushRet {
+        /// Destination of the return, the sign extended immediate
+        destination: u64,
+    },
+    /// `jmp [rip+disp]` on x86, the pointer follows the instruction when `disp` is 0, or
+    /// `ldr xn, label ; br xn` on arm64
+    IndirectJmp {
+        /// Address of the pointer
+        pointer: u64,
+        /// Destination of the jump, `None` if the pointer can't be read
+        destination: Option<u64>,
+    },
+    /// `call rel32` to the ftrace entry (`__fentry__` or an ftrace trampoline when the
+    /// function is traced) on x86, the `bl` of the enabled ftrace patch site on arm64, its
+    /// destination must be checked by the caller
+    Fentry {
+        /// Destination of the call
+        destination: u64,
+    },
+}
+
+impl HookKind {
+    /// Get the destination of the redirection
+    pub fn destination(&self) -> Option<u64> {
+        match *self {
+            HookKind::NotHooked => None,
+            HookKind::JmpRel { destination }
+            | HookKind::Thunk { destination }
+            | HookKind::MovJmpReg { destination, .. }
+            | HookKind::PushRet { destination }
+            | HookKind::Fentry { destination } => Some(destination),
+            HookKind::IndirectJmp { destination, .. } => destination,
+        }
+    }
+}
+
+impl fmt::Display for HookKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HookKind::NotHooked => f.write_str("not hooked"),
+            HookKind::JmpRel { destination } => write!(f, "jmp {:#x}", destination),
+            HookKind::Thunk { destination } => write!(f, "thunk to {:#x}", destination),
+            HookKind::MovJmpReg {
+                register,
+                destination,
+            } => write!(
+                f,
+                "mov {}, {:#x} ; jmp {}",
+                register_name(register),
+                destination,
+                register_name(register)
+            ),
+            HookKind::PushRet { destination } => write!(f, "push {:#x} ; ret", destination),
+            HookKind::IndirectJmp {
+                pointer,
+                destination: Some(destination),
+            } => write!(f, "jmp [{:#x}] to {:#x}", pointer, destination),
+            HookKind::IndirectJmp {
+                pointer,
+                destination: None,
+            } => write!(f, "jmp [{:#x}] (unreadable)", pointer),
+            HookKind::Fentry { destination } => write!(f, "call {:#x}", destination),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use super::HookKind;
+    use crate::insn::Insn;
+
+    /// `endbr64`, the first instruction of the functions of an IBT kernel
+    pub(super) const LANDING_PAD: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+
+    /// `int3`, used as padding after the thunks
+    pub(super) const THUNK_PADDING: Option<u8> = Some(0xcc);
+
+    /// Recognize `mov reg, imm64 ; jmp reg`
+    fn mov_jmp_reg(code: &[u8]) -> Option<HookKind> {
+        let [rex @ (0x48 | 0x49), mov @ 0xb8..=0xbf, ref rest @ ..] = *code else {
+            return None;
+        };
+        let register = (mov & 7) | ((rex & 1) << 3);
+        let destination = u64::from_le_bytes(rest.get(..8)?.try_into().ok()?);
+        // `jmp reg` is `FF /4`, with a REX.B prefix for r8 to r15
+        let modrm = match *rest.get(8..)? {
+            [0xff, modrm, ..] if register < 8 => modrm,
+            [0x41, 0xff, modrm, ..] if register >= 8 => modrm,
+            _ => return None,
+        };
+        (modrm == 0xe0 | (register & 7)).then_some(HookKind::MovJmpReg {
+            register,
+            destination,
+        })
+    }
+
+    /// Recognize the redirections of x86 which aren't a relative jump or call
+    ///
+    /// The destination of `jmp [rip+disp]` with a non null `disp` is read with the nofault
+    /// reader. The other jumps and calls through a pointer or a register aren't hooks.
+    pub(super) fn classify(code: &[u8], address: u64) -> Option<HookKind> {
+        if let Some(kind) = mov_jmp_reg(code) {
+            return Some(kind);
+        }
+        match *code {
+            [0x68, a, b, c, d, 0xc3, ..] => Some(HookKind::PushRet {
+                destination: i32::from_le_bytes([a, b, c, d]) as i64 as u64,
+            }),
+            [0xff, 0x25, 0, 0, 0, 0, ref pointer @ ..] if pointer.len() >= 8 => {
+                Some(HookKind::IndirectJmp {
+                    pointer: address.wrapping_add(6),
+                    destination: pointer[..8].try_into().ok().map(u64::from_le_bytes),
+                })
+            }
+            [0xff, 0x25, ..] => {
+                let mut insn = Insn::new(code);
+                let (Ok(length), Ok(Some((disp, _)))) =
+                    (insn.get_length(), insn.get_displacement())
+                else {
+                    return Some(HookKind::NotHooked);
+                };
+                Some(HookKind::IndirectJmp {
+                    pointer: address
+                        .wrapping_add(length as u64)
+                        .wrapping_add(disp as i64 as u64),
+                    destination: insn.branch_target(address).ok().flatten(),
+                })
+            }
+            [0xff, ..] => Some(HookKind::NotHooked),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use super::HookKind;
+    use crate::insn::arm64::{decode_patch_site, Opcode, PatchSite, INSN_SIZE};
+    use crate::insn::Insn;
+    use crate::nofault;
+
+    /// `bti c`, the first instruction of the functions called indirectly with BTI
+    pub(super) const LANDING_PAD: [u8; 4] = [0x5f, 0x24, 0x03, 0xd5];
+
+    /// No padding follows the thunks
+    pub(super) const THUNK_PADDING: Option<u8> = None;
+
+    /// Recognize the redirections of arm64 which aren't a relative jump or call
+    ///
+    /// The literal of `ldr xn, label ; br xn` is read from `code` when it follows the jump,
+    /// with the nofault reader otherwise.
+    pub(super) fn classify(code: &[u8], address: u64) -> Option<HookKind> {
+        match decode_patch_site(code, address) {
+            Some(PatchSite::Enabled { destination }) => {
+                return Some(HookKind::Fentry { destination })
+            }
+            Some(PatchSite::Disabled) => return Some(HookKind::NotHooked),
+            None => (),
+        }
+
+        let first = Insn::new(code).get_opcode().ok()?;
+        let second = Insn::new(code.get(INSN_SIZE..)?).get_opcode().ok()?;
+        match (first, second) {
+            (
+                Opcode::LdrLiteral {
+                    register,
+                    offset,
+                    size: 8,
+                },
+                Opcode::Br { register: target },
+            ) if register == target => {
+                let pointer = address.wrapping_add(offset as u64);
+                let destination = match usize::try_from(offset)
+                    .ok()
+                    .and_then(|offset| code.get(offset..offset.checked_add(8)?))
+                {
+                    Some(literal) => literal.try_into().ok().map(u64::from_le_bytes),
+                    None => nofault::read::<u64>(pointer as usize).ok(),
+                };
+                Some(HookKind::IndirectJmp {
+                    pointer,
+                    destination,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Recognize the redirection at the start of `code`, the code of the function at `address`
+///
+/// A `code` too short for a pattern doesn't match it.
+pub fn classify_prologue(code: &[u8], address: u64) -> HookKind {
+    let (code, address) = match code.strip_prefix(&arch::LANDING_PAD) {
+        Some(code) => (code, address.wrapping_add(arch::LANDING_PAD.len() as u64)),
+        None => (code, address),
+    };
+
+    if let Some(kind) = arch::classify(code, address) {
+        return kind;
+    }
+
+    let Some(Ok((_, mut insn))) = InsnIter::new(code).next() else {
+        return HookKind::NotHooked;
+    };
+    let (Ok(length), Ok(Some(branch))) = (insn.length(), insn.branch(address)) else {
+        return HookKind::NotHooked;
+    };
+    match branch {
+        Branch {
+            kind: BranchKind::Call,
+            target: Some(destination),
+        } => HookKind::Fentry { destination },
+        Branch {
+            kind: BranchKind::Jump,
+            target: Some(destination),
+        } => {
+            if arch::THUNK_PADDING.is_some() && code.get(length).copied() == arch::THUNK_PADDING {
+                HookKind::Thunk { destination }
+            } else {
+                HookKind::JmpRel { destination }
+            }
+        }
+        _ => HookKind::NotHooked,
+    }
+}
+
+/// The function starting with `code` starts with `endbr64`
+///
+/// With IBT every function reachable by an indirect branch starts with it, a function of
+/// an IBT kernel without it had its prologue overwritten (or is only called directly).
+#[cfg(target_arch = "x86_64")]
+pub fn has_endbr(code: &[u8]) -> bool {
+    crate::insn::Insn::new(code).is_endbr64().unwrap_or(false)
+}
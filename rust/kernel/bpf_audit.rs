@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! BPF audit : the loaded BPF programs and the risky helpers they call
+//!
+//! A rootkit can be shipped as BPF programs : a kprobe program calling
+//! `bpf_override_return` to hide the result of a syscall, a tracing program calling
+//! `bpf_probe_write_user` to rewrite the buffers returned to userspace, ... Each loaded
+//! program is listed by ID with its type, the kind of attach point it targets, the user
+//! and time of the load, and the risky helpers it calls.
+//!
+//! The helper calls are found in the verified instructions, where the immediate of a
+//! helper call is the offset of the helper from `__bpf_call_base`. The kernel doesn't
+//! record the task which loaded a program, only its user and the load time.
+//!
+//! C header: [`include/linux/bpf.h`](../../../../include/linux/bpf.h)
+
+use core::fmt;
+use core::mem::transmute;
+
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::symbols_lookup_name;
+use crate::str::{copy_from_char_ptr, until_nul, BStr};
+use kernel::prelude::*;
+
+/// Maximum number of programs listed
+const MAX_PROGRAMS: usize = 4096;
+
+/// Length of the program name (`BPF_OBJ_NAME_LEN`)
+pub const BPF_NAME_LEN: usize = bindings::BPF_OBJ_NAME_LEN as usize;
+
+/// Length of the attach function name, longer names are truncated
+pub const SYMBOL_LEN: usize = 64;
+
+/// `BPF_JMP | BPF_CALL`
+const BPF_CALL_OPCODE: u8 = (bindings::BPF_JMP | bindings::BPF_CALL) as u8;
+
+/// Prototype of `bpf_prog_get_curr_or_next`
+type BpfProgGetCurrOrNext = unsafe extern "C" fn(id: *mut u32) -> *mut bindings::bpf_prog;
+
+/// The risky helpers called by a program
+pub mod helpers {
+    /// `bpf_probe_write_user` : write to the memory of the current process
+    pub const PROBE_WRITE_USER: u32 = 1 << 0;
+    /// `bpf_override_return` : skip the probed function and set its return value
+    pub const OVERRIDE_RETURN: u32 = 1 << 1;
+    /// `bpf_send_signal` or `bpf_send_signal_thread` : signal the current process
+    pub const SEND_SIGNAL: u32 = 1 << 2;
+}
+
+/// Names of the risky helpers and their flag
+const RISKY_HELPERS: &[(&CStr, u32)] = &[
+    (c_str!("bpf_probe_write_user"), helpers::PROBE_WRITE_USER),
+    (c_str!("bpf_override_return"), helpers::OVERRIDE_RETURN),
+    (c_str!("bpf_send_signal"), helpers::SEND_SIGNAL),
+    (c_str!("bpf_send_signal_thread"), helpers::SEND_SIGNAL),
+];
+
+/// Kind of attach point of a program
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AttachKind {
+    /// A kprobe, kretprobe or uprobe
+    Kprobe,
+    /// A tracepoint or raw tracepoint
+    Tracepoint,
+    /// A fentry, fexit or fmod_ret trampoline
+    Tracing,
+    /// An LSM hook
+    Lsm,
+    /// The XDP hook of a network device
+    Xdp,
+    /// A traffic control classifier or action
+    Tc,
+    /// Anything else (socket filters, cgroups, ...)
+    Other,
+}
+
+impl AttachKind {
+    fn from_prog_type(prog_type: bindings::bpf_prog_type) -> Self {
+        match prog_type {
+            bindings::bpf_prog_type_BPF_PROG_TYPE_KPROBE => AttachKind::Kprobe,
+            bindings::bpf_prog_type_BPF_PROG_TYPE_TRACEPOINT
+            | bindings::bpf_prog_type_BPF_PROG_TYPE_RAW_TRACEPOINT
+            | bindings::bpf_prog_type_BPF_PROG_TYPE_RAW_TRACEPOINT_WRITABLE => {
+                AttachKind::Tracepoint
+            }
+            bindings::bpf_prog_type_BPF_PROG_TYPE_TRACING => AttachKind::Tracing,
+            bindings::bpf_prog_type_BPF_PROG_TYPE_LSM => AttachKind::Lsm,
+            bindings::bpf_prog_type_BPF_PROG_TYPE_XDP => AttachKind::Xdp,
+            bindings::bpf_prog_type_BPF_PROG_TYPE_SCHED_CLS
+            | bindings::bpf_prog_type_BPF_PROG_TYPE_SCHED_ACT => AttachKind::Tc,
+            _ => AttachKind::Other,
+        }
+    }
+
+    /// The program runs in the context of the task which triggered it
+    fn is_tracing(&self) -> bool {
+        matches!(
+            self,
+            AttachKind::Kprobe | AttachKind::Tracepoint | AttachKind::Tracing | AttachKind::Lsm
+        )
+    }
+}
+
+/// A loaded BPF program
+pub struct BpfProgram {
+    /// ID of the program
+    pub id: u32,
+    /// Name of the program, null terminated
+    pub name: [u8; BPF_NAME_LEN],
+    /// `enum bpf_prog_type`
+    pub prog_type: u32,
+    /// `enum bpf_attach_type`
+    pub expected_attach_type: u32,
+    /// Kind of attach point derived from the type
+    pub attach: AttachKind,
+    /// The attached function for the tracing and LSM programs, null terminated
+    pub attach_func: [u8; SYMBOL_LEN],
+    /// UID of the user which loaded the program
+    pub uid: u32,
+    /// Load time (`ktime_get_boottime`) in nanoseconds
+    pub load_time: u64,
+    /// The risky helpers called, see [`helpers`]
+    pub helpers: u32,
+}
+
+impl BpfProgram {
+    /// The program calls a helper hiding or forging data, or signals the processes it
+    /// traces
+    pub fn is_high_risk(&self) -> bool {
+        self.helpers & (helpers::PROBE_WRITE_USER | helpers::OVERRIDE_RETURN) != 0
+            || (self.helpers & helpers::SEND_SIGNAL != 0 && self.attach.is_tracing())
+    }
+}
+
+/// Result of the audit
+pub struct BpfAudit {
+    /// Every loaded program
+    pub programs: KVec<BpfProgram>,
+}
+
+/// Addresses of the risky helpers
+struct HelperTable {
+    call_base: u64,
+    helpers: [(u64, u32); RISKY_HELPERS.len()],
+}
+
+impl HelperTable {
+    fn new() -> Result<Self> {
+        let call_base = symbols_lookup_name(c_str!("__bpf_call_base"));
+        if call_base == 0 {
+            pr_err!("Couldn't find __bpf_call_base symbol\n");
+            return Err(ENOENT);
+        }
+
+        let mut helpers = [(0, 0); RISKY_HELPERS.len()];
+        for (slot, (name, flag)) in helpers.iter_mut().zip(RISKY_HELPERS) {
+            // A helper not built in the kernel is just never found
+            *slot = (symbols_lookup_name(name), *flag);
+        }
+
+        Ok(HelperTable { call_base, helpers })
+    }
+
+    /// Find the risky helpers called by a program
+    ///
+    /// # Safety
+    ///     `prog` must be a valid program on which we hold a reference
+    unsafe fn scan(&self, prog: *const bindings::bpf_prog) -> u32 {
+        // SAFETY: By the safety contract `prog` is valid
+        let len = unsafe { (*prog).len } as usize;
+        // The instructions are the flexible array at the end of `struct bpf_prog`
+        let insns = prog
+            .cast::<u8>()
+            .wrapping_add(core::mem::size_of::<bindings::bpf_prog>())
+            .cast::<bindings::bpf_insn>();
+
+        let mut found = 0;
+        for i in 0..len {
+            // SAFETY: The program has `len` instructions, they are not modified once the
+            // program is loaded
+            let insn = unsafe { &*insns.add(i) };
+            if insn.code != BPF_CALL_OPCODE || insn.src_reg() != 0 {
+                continue;
+            }
+            let target = self.call_base.wrapping_add(insn.imm as i64 as u64);
+            for (address, flag) in self.helpers.iter() {
+                if *address != 0 && *address == target {
+                    found |= flag;
+                }
+            }
+        }
+        found
+    }
+}
+
+impl BpfAudit {
+    /// List the loaded programs and the risky helpers they call
+    pub fn audit() -> Result<Self> {
+        let get_next = symbols_lookup_name(c_str!("bpf_prog_get_curr_or_next")) as *const ();
+        if get_next.is_null() {
+            pr_err!("Couldn't find bpf_prog_get_curr_or_next symbol\n");
+            return Err(ENOENT);
+        }
+        // SAFETY: The symbol is the function `bpf_prog_get_curr_or_next` which has this
+        // prototype
+        let get_next = unsafe { transmute::<*const (), BpfProgGetCurrOrNext>(get_next) };
+        let table = HelperTable::new()?;
+
+        let mut programs = KVec::new();
+        let mut id = 0u32;
+        loop {
+            if programs.len() >= MAX_PROGRAMS {
+                pr_warn!("Too many BPF programs, the audit is truncated\n");
+                break;
+            }
+
+            // SAFETY: Just an FFI call, on success we hold a reference on the program
+            let prog = unsafe { get_next(&mut id) };
+            if prog.is_null() {
+                break;
+            }
+
+            // SAFETY: We hold a reference on the program, `aux` live as long as it
+            let program = unsafe {
+                let aux = (*prog).aux;
+                let user = (*aux).user;
+                BpfProgram {
+                    id: (*aux).id,
+                    name: copy_from_char_ptr((*aux).name.as_ptr()),
+                    prog_type: (*prog).type_ as u32,
+                    expected_attach_type: (*prog).expected_attach_type as u32,
+                    attach: AttachKind::from_prog_type((*prog).type_),
+                    attach_func: copy_from_char_ptr((*aux).attach_func_name),
+                    uid: if user.is_null() { 0 } else { (*user).uid.val },
+                    load_time: (*aux).load_time,
+                    helpers: table.scan(prog),
+                }
+            };
+            // SAFETY: We release the reference taken by `bpf_prog_get_curr_or_next`
+            unsafe { bindings::bpf_prog_put(prog) };
+
+            programs.push(program, GFP_KERNEL)?;
+            id = id.wrapping_add(1);
+            if id == 0 {
+                break;
+            }
+        }
+
+        Ok(BpfAudit { programs })
+    }
+
+    /// Get the high risk programs
+    pub fn suspicious(&self) -> impl Iterator<Item = &BpfProgram> {
+        self.programs.iter().filter(|prog| prog.is_high_risk())
+    }
+
+    /// Create the event listing the high risk programs, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.suspicious().next().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousBpfProgram,
+            fmt!("high risk BPF programs : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for BpfAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, prog) in self.suspicious().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} (id {}, {:?}",
+                BStr::from_bytes(until_nul(&prog.name)),
+                prog.id,
+                prog.attach
+            )?;
+            let attach_func = until_nul(&prog.attach_func);
+            if !attach_func.is_empty() {
+                write!(f, " {}", BStr::from_bytes(attach_func))?;
+            }
+            write!(f, ", uid {}, helpers {:#x})", prog.uid, prog.helpers)?;
+        }
+        Ok(())
+    }
+}
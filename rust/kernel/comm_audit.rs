@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Command name audit : the processes whose name doesn't match their executable
+//!
+//! At exec the kernel names every thread after the basename of the executed path. A
+//! userland rootkit helper renames itself afterwards with `prctl(PR_SET_NAME)` to blend in
+//! `ps` and `top`, as a kernel thread (`kworker/0:1`) or as a system daemon (`sshd`). The
+//! name of the group leader of every process is compared with the basename of its
+//! executable, truncated the same way, and the unrelated ones (neither is a prefix of the
+//! other) are flagged when the mismatch is gross:
+//! - the name imitates a kernel thread or a critical daemon
+//! - the executable lives in a temporary directory, in a `memfd` or was deleted
+//!
+//! `PR_SET_NAME` only renames the calling thread: a thread of the process still carrying
+//! the name given at exec is reported as the proof of the renaming.
+//!
+//! The scripts are named after the script and not after their interpreter, and the
+//! multi-call binaries (`busybox`) after their applet, often through a symlink (`init`,
+//! `login`): their name is only checked for the imitation of a kernel thread carrying a
+//! `/`, which can't be the basename of an executed path.
+//!
+//! C header: [`include/linux/sched.h`](../../../../include/linux/sched.h)
+
+use core::fmt;
+
+use crate::event::{Event, EventKind};
+use crate::fake_kthread::looks_like_kthread;
+use crate::ptrace_monitor::CRITICAL_DAEMONS;
+use crate::str::{until_nul, BStr};
+use crate::task::{Task, TASK_COMM_LEN};
+use crate::task_cgroup::Attribution;
+use crate::task_iter::ThreadIter;
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Suffix of the path of a deleted file
+const DELETED_SUFFIX: &[u8] = b" (deleted)";
+
+/// Prefixes of the paths of the temporary executables
+const VOLATILE_PREFIXES: [&[u8]; 5] = [
+    b"/tmp/",
+    b"/var/tmp/",
+    b"/dev/shm/",
+    b"/run/user/",
+    b"/memfd:",
+];
+
+/// Basenames (up to a version suffix) of the interpreters and of the multi-call binaries
+const INTERPRETERS: [&[u8]; 14] = [
+    b"sh", b"bash", b"dash", b"zsh", b"busybox", b"python", b"perl", b"ruby", b"node", b"php",
+    b"java", b"lua", b"tclsh", b"qemu-",
+];
+
+/// What the name of a process imitates
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Imitation {
+    /// A kernel thread
+    KernelThread,
+    /// A critical daemon
+    Daemon,
+}
+
+/// A process whose name doesn't match its executable
+pub struct CommMismatch {
+    /// Process id
+    pub pid: i32,
+    /// Command name of the process, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Path of the executable of the process
+    pub exe: KVec<u8>,
+    /// Path of the cgroup of the process
+    pub cgroup: KVec<u8>,
+    /// What the name imitates, if anything
+    pub imitation: Option<Imitation>,
+    /// Thread id of a thread still carrying the name given at exec, if any
+    pub renamed_from: Option<i32>,
+}
+
+/// Result of the audit
+pub struct CommAudit {
+    /// Number of processes audited
+    pub scanned: usize,
+    /// The processes whose name doesn't match their executable
+    pub mismatches: KVec<CommMismatch>,
+}
+
+/// Get the name the kernel gives at exec of `exe`: its basename, truncated like a command name
+fn exec_name(exe: &[u8]) -> &[u8] {
+    let exe = exe.strip_suffix(DELETED_SUFFIX).unwrap_or(exe);
+    let start = exe.iter().rposition(|c| *c == b'/').map_or(0, |i| i + 1);
+    let name = &exe[start..];
+    &name[..name.len().min(TASK_COMM_LEN - 1)]
+}
+
+/// The names `a` and `b` are related: one is a prefix of the other
+fn related(a: &[u8], b: &[u8]) -> bool {
+    !a.is_empty() && !b.is_empty() && (a.starts_with(b) || b.starts_with(a))
+}
+
+/// The executable `exe` is temporary: in a temporary directory, a `memfd` or deleted
+fn is_volatile(exe: &[u8]) -> bool {
+    exe.ends_with(DELETED_SUFFIX)
+        || VOLATILE_PREFIXES
+            .iter()
+            .any(|prefix| exe.starts_with(prefix))
+}
+
+/// Get what the name `comm` imitates, if anything
+fn imitation(comm: &[u8]) -> Option<Imitation> {
+    if looks_like_kthread(comm) {
+        Some(Imitation::KernelThread)
+    } else if CRITICAL_DAEMONS.contains(&comm) {
+        Some(Imitation::Daemon)
+    } else {
+        None
+    }
+}
+
+/// Find a thread of the process of `leader` still carrying the name `name`
+fn thread_named(leader: &ARef<Task>, name: &[u8]) -> Option<i32> {
+    ThreadIter::new(leader.clone())
+        .find(|thread| until_nul(&thread.comm()) == name)
+        .map(|thread| thread.pid())
+}
+
+impl CommAudit {
+    /// Audit the name of every process of the system
+    pub fn audit() -> Result<Self> {
+        let origin: ARef<Task> = current!().group_leader().into();
+        let mut audit = CommAudit {
+            scanned: 0,
+            mismatches: KVec::new(),
+        };
+
+        for task in origin {
+            // The kernel threads have no executable
+            let Some(exe) = task.exe_path()? else {
+                continue;
+            };
+            audit.scanned += 1;
+            let comm = task.comm();
+            let name = until_nul(&comm);
+            let expected = exec_name(&exe);
+            if related(name, expected) {
+                continue;
+            }
+
+            let interpreter = INTERPRETERS
+                .iter()
+                .any(|prefix| expected.starts_with(prefix));
+            let imitation = imitation(name).filter(|_| !interpreter || name.contains(&b'/'));
+            if imitation.is_none() && (interpreter || !is_volatile(&exe)) {
+                continue;
+            }
+
+            let renamed_from = thread_named(&task, expected);
+            audit.mismatches.push(
+                CommMismatch {
+                    pid: task.pid(),
+                    comm,
+                    exe,
+                    cgroup: task.cgroup_path()?,
+                    imitation,
+                    renamed_from,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+        Ok(audit)
+    }
+
+    /// Create the event listing the processes whose name doesn't match their executable,
+    /// if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.mismatches.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::CommMismatch,
+            fmt!("processes named unlike their executable : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for CommAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, mismatch) in self.mismatches.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} (pid {}, {}) runs {}",
+                BStr::from_bytes(until_nul(&mismatch.comm)),
+                mismatch.pid,
+                Attribution(&mismatch.cgroup),
+                BStr::from_bytes(&mismatch.exe)
+            )?;
+            match mismatch.imitation {
+                Some(Imitation::KernelThread) => f.write_str(" imitating a kernel thread")?,
+                Some(Imitation::Daemon) => f.write_str(" imitating a daemon")?,
+                None => {}
+            }
+            if let Some(thread) = mismatch.renamed_from {
+                write!(f, " renamed (thread {} keeps the exec name)", thread)?;
+            }
+        }
+        Ok(())
+    }
+}
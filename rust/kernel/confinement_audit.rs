@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Confinement audit : the privileged daemons which lost their confinement
+//!
+//! The privileged daemons are confined by seccomp filters and by an LSM profile. A rootkit
+//! helping an exploited daemon removes its filters (`task->seccomp`) or switches its
+//! credentials to an unconfined label. A [`ConfinementBaseline`] records the seccomp state
+//! (the weakest among their threads) and the LSM label of the daemons (the root processes
+//! started by init), [`ConfinementBaseline::check`] reports the ones which since:
+//! - lost seccomp filters or left their seccomp mode, which the kernel never allows
+//! - moved from a confined label to an unconfined one
+//!
+//! A daemon is identified by its pid and its start time, a restarted daemon isn't
+//! compared with its baseline.
+//!
+//! C header: [`include/linux/seccomp.h`](../../../../include/linux/seccomp.h)
+
+use core::fmt;
+
+use crate::event::{Event, EventKind};
+use crate::str::{until_nul, BStr};
+use crate::task::{Task, TASK_COMM_LEN};
+use crate::task_cgroup::Attribution;
+use crate::task_security::{Seccomp, SeccompMode};
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Part of the unconfined labels of SELinux (`unconfined_t`) and AppArmor (`unconfined`)
+const UNCONFINED: &[u8] = b"unconfined";
+
+/// The confinement of a daemon
+pub struct Confinement {
+    /// Process id of the daemon
+    pub pid: i32,
+    /// Start time of the daemon, in nanoseconds of monotonic time
+    pub start_time: u64,
+    /// Command name of the daemon, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// The seccomp state
+    pub seccomp: Seccomp,
+    /// The LSM label
+    pub label: KVec<u8>,
+}
+
+/// How a daemon lost its confinement
+pub enum ConfinementAnomaly {
+    /// Seccomp filters were removed or the seccomp mode was left
+    SeccompLost {
+        /// The seccomp state of the baseline
+        before: Seccomp,
+        /// The current seccomp state
+        after: Seccomp,
+    },
+    /// The daemon moved from a confined label to an unconfined one
+    Unconfined {
+        /// The label of the baseline
+        before: KVec<u8>,
+        /// The current label
+        after: KVec<u8>,
+    },
+}
+
+/// A daemon which lost its confinement
+pub struct ConfinementLoss {
+    /// Process id of the daemon
+    pub pid: i32,
+    /// Command name of the daemon, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Path of the executable of the daemon
+    pub exe: KVec<u8>,
+    /// Path of the cgroup of the daemon
+    pub cgroup: KVec<u8>,
+    /// How the confinement was lost
+    pub anomaly: ConfinementAnomaly,
+}
+
+/// The confinement of the daemons
+pub struct ConfinementBaseline {
+    daemons: KVec<Confinement>,
+}
+
+/// Result of a check
+pub struct ConfinementReport {
+    /// The daemons which lost their confinement
+    pub losses: KVec<ConfinementLoss>,
+}
+
+/// The label `label` is an unconfined one
+fn is_unconfined(label: &[u8]) -> bool {
+    label
+        .windows(UNCONFINED.len())
+        .any(|window| window == UNCONFINED)
+}
+
+/// The seccomp state went backward from `before` to `after`
+fn seccomp_lost(before: &Seccomp, after: &Seccomp) -> bool {
+    after.filters < before.filters
+        || (before.mode != SeccompMode::Disabled && after.mode != before.mode)
+}
+
+/// The process `task` is a daemon: a root process started by init
+fn is_daemon(task: &Task) -> bool {
+    !task.is_kthread()
+        && task.creds().euid == 0
+        && task.real_parent().is_some_and(|parent| parent.pid() == 1)
+}
+
+/// Iterate over the processes of the system
+fn processes() -> impl Iterator<Item = ARef<Task>> {
+    let origin: ARef<Task> = current!().group_leader().into();
+    origin.into_iter()
+}
+
+impl ConfinementBaseline {
+    /// Record the confinement of the daemons
+    pub fn snapshot() -> Result<Self> {
+        let mut daemons = KVec::new();
+        for task in processes().filter(|task| is_daemon(task)) {
+            daemons.push(
+                Confinement {
+                    pid: task.pid(),
+                    start_time: task.start_time(),
+                    comm: task.comm(),
+                    seccomp: task.process_seccomp(),
+                    label: task.security_label()?,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+        Ok(ConfinementBaseline { daemons })
+    }
+
+    /// Get the recorded daemons
+    pub fn daemons(&self) -> &[Confinement] {
+        &self.daemons
+    }
+
+    /// Compare the confinement of the daemons with the baseline
+    pub fn check(&self) -> Result<ConfinementReport> {
+        let mut report = ConfinementReport {
+            losses: KVec::new(),
+        };
+
+        for task in processes() {
+            let (pid, start_time) = (task.pid(), task.start_time());
+            let Some(before) = self
+                .daemons
+                .iter()
+                .find(|d| d.pid == pid && d.start_time == start_time)
+            else {
+                continue;
+            };
+
+            let seccomp = task.process_seccomp();
+            if seccomp_lost(&before.seccomp, &seccomp) {
+                report.push(
+                    &task,
+                    ConfinementAnomaly::SeccompLost {
+                        before: before.seccomp,
+                        after: seccomp,
+                    },
+                )?;
+            }
+
+            let label = task.security_label()?;
+            if !is_unconfined(&before.label) && is_unconfined(&label) {
+                let mut previous = KVec::new();
+                previous.extend_from_slice(&before.label, GFP_KERNEL)?;
+                report.push(
+                    &task,
+                    ConfinementAnomaly::Unconfined {
+                        before: previous,
+                        after: label,
+                    },
+                )?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl ConfinementReport {
+    fn push(&mut self, task: &Task, anomaly: ConfinementAnomaly) -> Result {
+        self.losses.push(
+            ConfinementLoss {
+                pid: task.pid(),
+                comm: task.comm(),
+                exe: task.exe_path()?.unwrap_or_else(KVec::new),
+                cgroup: task.cgroup_path()?,
+                anomaly,
+            },
+            GFP_KERNEL,
+        )?;
+        Ok(())
+    }
+
+    /// Create the event listing the daemons which lost their confinement, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.losses.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::ConfinementLoss,
+            fmt!("privileged daemons lost their confinement : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for ConfinementAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfinementAnomaly::SeccompLost { before, after } => {
+                write!(f, "seccomp {} -> {}", before, after)
+            }
+            ConfinementAnomaly::Unconfined { before, after } => write!(
+                f,
+                "label {} -> {}",
+                BStr::from_bytes(before),
+                BStr::from_bytes(after)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ConfinementReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, loss) in self.losses.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} (pid {}, exe {}, {}) {}",
+                BStr::from_bytes(until_nul(&loss.comm)),
+                loss.pid,
+                BStr::from_bytes(&loss.exe),
+                Attribution(&loss.cgroup),
+                loss.anomaly
+            )?;
+        }
+        Ok(())
+    }
+}
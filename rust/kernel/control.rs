@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Userspace control interface
+//!
+//! The ioctl commands understood by the rkchk device, the misc device `ioctl`
+//! callback only need to forward them to [`handle_ioctl`].
+
+use crate::hook_table::{HookTable, HookTableHeader};
+use crate::ioctl::{_IOC_SIZE, _IOR, _IOW};
+#[cfg(target_arch = "x86_64")]
+use crate::protection_map::{ProtectionMap, ProtectionMapHeader};
+use crate::scoring::{self, PolicyHeader, PolicyRule, POLICY_MAX_SIZE};
+use crate::sensor_set::{self, SensorToggle};
+use crate::symbol_map::{self, MapEntry, MapHeader, MAP_MAX_SIZE};
+use crate::uaccess::UserSlice;
+use kernel::prelude::*;
+
+/// Magic number of the rkchk ioctls
+pub const IOCTL_MAGIC: u32 = b'R' as u32;
+
+/// Get the table of the currently registered hooks
+///
+/// The argument is a user buffer of `_IOC_SIZE` bytes at least, filled with a
+/// [`HookTableHeader`] followed by as many [`crate::hook_table::HookEntry`] as fit.
+/// The user buffer size is given by the `total` field of the header, which is read
+/// before being overwritten.
+pub const IOCTL_HOOK_TABLE: u32 = _IOR::<HookTableHeader>(IOCTL_MAGIC, 0x10);
+
+/// Get the run-length encoded map of the kernel space protections
+///
+/// Same buffer layout as [`IOCTL_HOOK_TABLE`] : a [`ProtectionMapHeader`] followed by
+/// [`crate::protection_map::ProtectionRun`]s.
+#[cfg(target_arch = "x86_64")]
+pub const IOCTL_PROTECTION_MAP: u32 = _IOR::<ProtectionMapHeader>(IOCTL_MAGIC, 0x11);
+
+/// Load the symbol map used by the kallsyms-free mode
+///
+/// The argument is the map (see [`crate::symbol_map`]), its size is computed from the
+/// `count` field of its [`MapHeader`].
+pub const IOCTL_SYMBOL_MAP: u32 = _IOW::<MapHeader>(IOCTL_MAGIC, 0x20);
+
+/// Load a scoring policy
+///
+/// The argument is the policy (see [`crate::scoring`]), its size is computed from the
+/// `count` field of its [`PolicyHeader`].
+pub const IOCTL_SCORING_POLICY: u32 = _IOW::<PolicyHeader>(IOCTL_MAGIC, 0x21);
+
+/// Enable or disable a sensor of the [`crate::sensor_set`]
+///
+/// The argument is a [`SensorToggle`].
+pub const IOCTL_SENSOR_TOGGLE: u32 = _IOW::<SensorToggle>(IOCTL_MAGIC, 0x22);
+
+/// Read the size of the user buffer, stored in the first `u32` of the argument
+fn user_buffer(arg: usize, min: usize) -> Result<UserSlice> {
+    let mut reader = UserSlice::new(arg as _, core::mem::size_of::<u32>()).reader();
+    let len = reader.read::<u32>()? as usize;
+    if len < min {
+        return Err(EINVAL);
+    }
+    Ok(UserSlice::new(arg as _, len))
+}
+
+/// Dispatch an ioctl command
+pub fn handle_ioctl(cmd: u32, arg: usize) -> Result<isize> {
+    match cmd {
+        IOCTL_HOOK_TABLE => {
+            let user = user_buffer(arg, _IOC_SIZE(cmd))?;
+            let table = HookTable::snapshot()?;
+            Ok(table.write_to_user(user.writer())? as isize)
+        }
+        #[cfg(target_arch = "x86_64")]
+        IOCTL_PROTECTION_MAP => {
+            let user = user_buffer(arg, _IOC_SIZE(cmd))?;
+            let map = ProtectionMap::snapshot()?;
+            Ok(map.write_to_user(user.writer())? as isize)
+        }
+        IOCTL_SYMBOL_MAP => {
+            let header_size = core::mem::size_of::<MapHeader>();
+            let mut reader = UserSlice::new(arg as _, header_size).reader();
+            let header = reader.read::<MapHeader>()?;
+            let len = (header.count as usize)
+                .checked_mul(core::mem::size_of::<MapEntry>())
+                .and_then(|len| len.checked_add(header_size))
+                .ok_or(E2BIG)?;
+            if len > MAP_MAX_SIZE {
+                return Err(E2BIG);
+            }
+            symbol_map::load_from_user(UserSlice::new(arg as _, len).reader())?;
+            Ok(0)
+        }
+        IOCTL_SCORING_POLICY => {
+            let header_size = core::mem::size_of::<PolicyHeader>();
+            let mut reader = UserSlice::new(arg as _, header_size).reader();
+            let header = reader.read::<PolicyHeader>()?;
+            let len = (header.count as usize)
+                .checked_mul(core::mem::size_of::<PolicyRule>())
+                .and_then(|len| len.checked_add(header_size))
+                .ok_or(E2BIG)?;
+            if len > POLICY_MAX_SIZE {
+                return Err(E2BIG);
+            }
+            scoring::load_from_user(UserSlice::new(arg as _, len).reader())?;
+            Ok(0)
+        }
+        IOCTL_SENSOR_TOGGLE => {
+            let mut reader =
+                UserSlice::new(arg as _, core::mem::size_of::<SensorToggle>()).reader();
+            sensor_set::toggle(&reader.read::<SensorToggle>()?)?;
+            Ok(0)
+        }
+        _ => Err(ENOTTY),
+    }
+}
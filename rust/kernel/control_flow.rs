@@ -0,0 +1,262 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Control flow : the basic blocks of a function and the branches leaving it
+//!
+//! The recognition of the prologue ([`classify_prologue`]) misses the hooks placed after
+//! the first instructions of a function. [`ControlFlow::build`] decodes the whole function
+//! with [`InsnIter`], collects the destinations of its branches inside the function (the
+//! leaders of its basic blocks) and follows the branches leaving it. Such a branch is
+//! flagged when its destination is owned by nobody, neither the kernel image nor a module
+//! (see [`resolve_address`]): the code of a hook lives in memory it allocated itself.
+//!
+//! Only the direct branches are followed, the destination of a branch through a register
+//! isn't known. The call of the ftrace patch site is left to the ftrace audit. The ftrace
+//! and BPF trampolines are allocated outside of the kernel image and the modules, a branch
+//! to one of them isn't flagged, nor a call to the accounting thunk of the call depth
+//! tracking (`CONFIG_CALL_THUNKS`) placed in the padding before its destination.
+//!
+//! C header : [`arch/x86/include/asm/insn.h`](../../../../arch/x86/include/asm/insn.h)
+
+use core::fmt;
+
+use crate::address::{resolve_address, AddressInfo, RegionType};
+use crate::analysis::{classify_prologue, HookKind};
+use crate::event::{Event, EventKind};
+use crate::insn::{BranchKind, FunctionCode, InsnIter, Instruction};
+#[cfg(CONFIG_CALL_THUNKS)]
+use crate::module::symbols_lookup_size_offset;
+#[cfg(CONFIG_DYNAMIC_FTRACE)]
+use crate::patch_site::is_ftrace_trampoline;
+use kernel::prelude::*;
+
+/// A basic block, a sequence of instructions only entered by its first one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BasicBlock {
+    /// Offset of the first instruction in the function
+    pub start: usize,
+    /// Offset of the end of the block in the function, excluded
+    pub end: usize,
+}
+
+/// A branch leaving the function toward memory owned by nobody
+pub struct ExitBranch {
+    /// Offset of the branch in the function
+    pub offset: usize,
+    /// Kind of the branch
+    pub kind: BranchKind,
+    /// The destination
+    pub target: AddressInfo,
+}
+
+/// The control flow of a function
+pub struct ControlFlow {
+    /// Address of the function
+    pub address: u64,
+    /// Size of the function
+    pub size: usize,
+    /// Number of bytes decoded, less than `size` when the end of the function couldn't be
+    /// decoded
+    pub decoded: usize,
+    /// Offsets of the destinations of the branches inside the function, sorted
+    pub targets: KVec<usize>,
+    /// The basic blocks, sorted
+    pub blocks: KVec<BasicBlock>,
+    /// Destinations inside the function which aren't the start of an instruction
+    pub misaligned: KVec<usize>,
+    /// The branches leaving the function toward memory owned by nobody
+    pub exits: KVec<ExitBranch>,
+}
+
+/// Size of the padding before a function, holding its call thunk
+#[cfg(CONFIG_CALL_THUNKS)]
+const CALL_THUNKS_PADDING: u64 = bindings::CONFIG_FUNCTION_PADDING_BYTES as u64;
+
+/// The destination `target` is a call thunk, in the padding before the start of a function
+/// of the kernel or of a module
+fn is_call_thunk(target: u64) -> Result<bool> {
+    #[cfg(CONFIG_CALL_THUNKS)]
+    {
+        let (size, offset) = symbols_lookup_size_offset(target);
+        if size == 0 {
+            return Ok(false);
+        }
+        let function = target - offset as u64 + size as u64;
+        if function - target > CALL_THUNKS_PADDING {
+            return Ok(false);
+        }
+        let function = resolve_address(function)?;
+        Ok(function.is_attributed() && function.is_text() && function.offset == 0)
+    }
+    #[cfg(not(CONFIG_CALL_THUNKS))]
+    {
+        let _ = target;
+        Ok(false)
+    }
+}
+
+/// The destination `target` is a trampoline or a thunk the kernel branches to on behalf
+/// of the function: an ftrace or BPF trampoline, or a call thunk
+fn is_trampoline(target: &AddressInfo) -> Result<bool> {
+    #[cfg(CONFIG_DYNAMIC_FTRACE)]
+    let ftrace = is_ftrace_trampoline(target.address);
+    #[cfg(not(CONFIG_DYNAMIC_FTRACE))]
+    let ftrace = false;
+
+    Ok(target.region == RegionType::BpfJit || ftrace || is_call_thunk(target.address)?)
+}
+
+/// Sort `values` and remove the duplicates
+fn sort_unique(mut values: KVec<usize>) -> Result<KVec<usize>> {
+    values.sort_unstable();
+    let mut unique = KVec::with_capacity(values.len(), GFP_KERNEL)?;
+    for value in values {
+        if unique.last() != Some(&value) {
+            unique.push(value, GFP_KERNEL)?;
+        }
+    }
+    Ok(unique)
+}
+
+impl ControlFlow {
+    /// Build the control flow of `function`
+    ///
+    /// The decoding stops at the first instruction which can't be decoded, the end of a
+    /// function can be data or padding
+    pub fn build(function: &FunctionCode) -> Result<Self> {
+        let code = function.code();
+        let address = function.address();
+        let fentry = match classify_prologue(code, address) {
+            HookKind::Fentry { destination } => Some(destination),
+            _ => None,
+        };
+        let mut flow = ControlFlow {
+            address,
+            size: code.len(),
+            decoded: 0,
+            targets: KVec::new(),
+            blocks: KVec::new(),
+            misaligned: KVec::new(),
+            exits: KVec::new(),
+        };
+        // A block starts at the start of the function, at a destination of a branch and
+        // after a jump or a return
+        let mut leaders = KVec::new();
+        leaders.push(0, GFP_KERNEL)?;
+        let mut targets = KVec::new();
+
+        for insn in function.iter() {
+            let Ok((offset, mut insn)) = insn else {
+                break;
+            };
+            let next = offset + insn.length()?;
+            flow.decoded = next;
+            let Some(branch) = insn.branch(address + offset as u64)? else {
+                continue;
+            };
+            if branch.kind != BranchKind::Call {
+                leaders.push(next, GFP_KERNEL)?;
+            }
+            let Some(target) = branch.target else {
+                continue;
+            };
+
+            match target.checked_sub(address) {
+                Some(target) if target < code.len() as u64 => {
+                    targets.push(target as usize, GFP_KERNEL)?;
+                }
+                _ if branch.kind == BranchKind::Call && Some(target) == fentry => (),
+                _ => {
+                    let target = resolve_address(target)?;
+                    if !target.is_attributed() && !is_trampoline(&target)? {
+                        flow.exits.push(
+                            ExitBranch {
+                                offset,
+                                kind: branch.kind,
+                                target,
+                            },
+                            GFP_KERNEL,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        flow.targets = sort_unique(targets)?;
+        leaders.extend_from_slice(&flow.targets, GFP_KERNEL)?;
+        let leaders = sort_unique(leaders)?;
+        flow.split_blocks(function, &leaders)?;
+        Ok(flow)
+    }
+
+    /// Build the control flow of the function `name`
+    pub fn lookup(name: &CStr) -> Result<Self> {
+        Self::build(&FunctionCode::lookup(name)?)
+    }
+
+    /// Split the decoded code of `function` in blocks starting at the sorted `leaders`,
+    /// the leaders which aren't the start of an instruction are misaligned
+    fn split_blocks(&mut self, function: &FunctionCode, leaders: &[usize]) -> Result {
+        let decoded = self.decoded;
+        let mut starts = KVec::new();
+        let mut pending = leaders
+            .iter()
+            .copied()
+            .filter(|leader| *leader < decoded)
+            .peekable();
+
+        for insn in function.iter() {
+            let Ok((offset, _)) = insn else {
+                break;
+            };
+            while let Some(leader) = pending.next_if(|leader| *leader <= offset) {
+                if leader == offset {
+                    starts.push(leader, GFP_KERNEL)?;
+                } else {
+                    self.misaligned.push(leader, GFP_KERNEL)?;
+                }
+            }
+        }
+        for leader in pending {
+            self.misaligned.push(leader, GFP_KERNEL)?;
+        }
+
+        for (i, start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(decoded);
+            self.blocks
+                .push(BasicBlock { start: *start, end }, GFP_KERNEL)?;
+        }
+        Ok(())
+    }
+
+    /// Create the event listing the branches leaving the function toward memory owned by
+    /// nobody, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.exits.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::InlineHook,
+            fmt!(
+                "branches of the function at {:#x} leave it toward memory owned by nobody : {}",
+                self.address,
+                self
+            ),
+        )?))
+    }
+}
+
+impl fmt::Display for ControlFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, exit) in self.exits.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:?} at +{:#x} to {}",
+                exit.kind, exit.offset, exit.target
+            )?;
+        }
+        Ok(())
+    }
+}
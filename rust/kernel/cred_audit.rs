@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Credential audit : the credentials of the tasks tampered with by a rootkit
+//!
+//! A rootkit grants root to a process by replacing its credentials from the kernel
+//! (`commit_creds(prepare_kernel_cred(0))`) or by overwriting them in place. Every thread
+//! of the system is walked with [`AllThreadsIter`] and flagged when:
+//! - its subjective credentials (`cred`) grant privileges its objective ones (`real_cred`)
+//!   don't: only `cred` was replaced
+//! - the usage counter of its credentials is lower than the references the task holds on
+//!   them: the pointer was overwritten without taking a reference
+//! - it is privileged (euid 0 or the full capability set) but its lineage never went
+//!   through a privilege transition: the topmost privileged ancestor of its privileged
+//!   lineage is neither a kernel thread, nor the idle task, nor running a setuid root
+//!   executable
+//! - its credentials are shared with a task of another process: `copy_creds` only shares
+//!   them between the threads of a process (`CLONE_THREAD`), a fork always copies them, so
+//!   the credentials of another process (`init`) were assigned to it. The oldest process
+//!   sharing them is considered their owner, the others are flagged
+//!
+//! An `override_creds` in progress (overlayfs, access checks) briefly makes `cred` and
+//! `real_cred` diverge. A setuid program executing its target without forking (`pkexec`)
+//! hides the transition from the lineage, and the executables granted capabilities
+//! through their extended attributes aren't recognized.
+//!
+//! C header: [`include/linux/cred.h`](../../../../include/linux/cred.h)
+
+use core::fmt;
+use core::ptr;
+
+use crate::event::{Event, EventKind};
+use crate::str::{until_nul, BStr};
+use crate::sync::rcu;
+use crate::task::{Task, TaskCreds, TASK_COMM_LEN};
+use crate::task_cgroup::Attribution;
+use crate::task_iter::AllThreadsIter;
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Maximum number of ancestors followed, the lineage is walked without locking
+const MAX_LINEAGE_DEPTH: usize = 256;
+
+/// An anomaly of the credentials of a task
+pub enum CredAnomaly {
+    /// The subjective credentials grant privileges the objective ones don't
+    Divergence {
+        /// The subjective credentials, `cred`
+        subjective: TaskCreds,
+    },
+    /// The usage counter of the credentials is lower than the references the task holds
+    BadUsage {
+        /// The usage counter
+        usage: i64,
+        /// The references held by the task
+        expected: i64,
+    },
+    /// The task is privileged but its lineage never went through a privilege transition
+    ImpossiblePrivilege {
+        /// Thread id of the topmost privileged ancestor
+        origin: i32,
+        /// Command name of the topmost privileged ancestor, null terminated
+        origin_comm: [u8; TASK_COMM_LEN],
+    },
+    /// The credentials are shared with a task of another process
+    SharedWithProcess {
+        /// Thread id of the oldest task sharing the credentials
+        owner: i32,
+        /// Process id of the oldest task sharing the credentials
+        owner_tgid: i32,
+        /// Command name of the oldest task sharing the credentials, null terminated
+        owner_comm: [u8; TASK_COMM_LEN],
+    },
+}
+
+/// A task whose credentials are anomalous
+pub struct CredFinding {
+    /// Thread id of the task
+    pub pid: i32,
+    /// Process id of the task
+    pub tgid: i32,
+    /// Command name of the task, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Path of the executable of the task, empty for a kernel thread
+    pub exe: KVec<u8>,
+    /// Path of the cgroup of the task
+    pub cgroup: KVec<u8>,
+    /// The objective credentials, `real_cred`
+    pub creds: TaskCreds,
+    /// The anomaly
+    pub anomaly: CredAnomaly,
+}
+
+/// Result of the audit
+pub struct CredAudit {
+    /// Number of threads audited
+    pub scanned: usize,
+    /// The anomalous credentials
+    pub findings: KVec<CredFinding>,
+}
+
+/// The credentials grant the privileges of root over the host
+///
+/// # Safety
+///
+/// `cred` must be valid for the duration of the call
+unsafe fn is_privileged(cred: *const bindings::cred) -> bool {
+    let full = (1u64 << (bindings::CAP_LAST_CAP + 1)) - 1;
+    // SAFETY: `cred` is valid by the safety requirements, the fields of committed credentials
+    // never change. The capabilities only matter in the initial user namespace
+    unsafe {
+        (*cred).euid.val == 0
+            || ((*cred).cap_effective.val & full == full
+                && (*cred).user_ns == ptr::addr_of_mut!(bindings::init_user_ns))
+    }
+}
+
+/// The credentials of a task, read in a single RCU read side critical section
+struct CredState {
+    /// Address of the objective credentials
+    address: usize,
+    objective: TaskCreds,
+    subjective: TaskCreds,
+    shared: bool,
+    privileged: bool,
+    /// The subjective credentials grant privileges the objective ones don't
+    escalated: bool,
+    /// The usage counter lower than the references of the task, if any
+    bad_usage: Option<(i64, i64)>,
+}
+
+impl CredState {
+    fn read(task: &Task) -> Self {
+        let _guard = rcu::read_lock();
+        // SAFETY: The task is referenced, `cred` and `real_cred` are protected by RCU: the
+        // credentials they point to stay valid until the end of the critical section
+        unsafe {
+            let real = ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).real_cred));
+            let cred = ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).cred));
+            let shared = ptr::eq(real, cred);
+            let privileged = is_privileged(real);
+
+            // The task holds a reference on each, two when they are the same
+            let expected = if shared { 2 } else { 1 };
+            let bad_usage = [real, cred].into_iter().find_map(|c| {
+                let usage = ptr::read_volatile(ptr::addr_of!((*c).usage.counter));
+                (usage < expected).then_some((usage, expected))
+            });
+
+            CredState {
+                address: real as usize,
+                objective: TaskCreds::from_raw(real),
+                subjective: TaskCreds::from_raw(cred),
+                shared,
+                privileged,
+                escalated: !shared && !privileged && is_privileged(cred),
+                bad_usage,
+            }
+        }
+    }
+}
+
+/// The task runs a setuid root executable
+fn runs_setuid_root(task: &Task) -> bool {
+    let Some(file) = task.exe_file() else {
+        return false;
+    };
+    // SAFETY: The file is referenced, its inode is valid as long as the file is
+    unsafe {
+        let inode = (*file.as_ptr()).f_inode;
+        (*inode).i_mode as u32 & bindings::S_ISUID != 0 && (*inode).i_uid.val == 0
+    }
+}
+
+/// Get the topmost ancestor of the privileged lineage of `task`, if it didn't go through a
+/// privilege transition
+fn illegitimate_origin(task: &ARef<Task>) -> Option<ARef<Task>> {
+    let mut origin = task.clone();
+    for parent in task.ancestors().take(MAX_LINEAGE_DEPTH) {
+        if !CredState::read(&parent).privileged {
+            break;
+        }
+        origin = parent;
+    }
+    // The idle task and the kernel threads start privileged
+    let legitimate = origin.pid() == 0 || origin.is_kthread() || runs_setuid_root(&origin);
+    (!legitimate).then_some(origin)
+}
+
+impl CredAudit {
+    /// Audit the credentials of every thread of the system
+    pub fn audit() -> Result<Self> {
+        let mut audit = CredAudit {
+            scanned: 0,
+            findings: KVec::new(),
+        };
+
+        let mut holders = KVec::new();
+        for task in AllThreadsIter::new(current!().into()) {
+            audit.scanned += 1;
+            let state = CredState::read(&task);
+            holders.push((state.address, task.clone()), GFP_KERNEL)?;
+
+            if state.escalated {
+                audit.push(
+                    &task,
+                    &state,
+                    CredAnomaly::Divergence {
+                        subjective: state.subjective,
+                    },
+                )?;
+            }
+            if let Some((usage, expected)) = state.bad_usage {
+                audit.push(&task, &state, CredAnomaly::BadUsage { usage, expected })?;
+            }
+            if state.privileged && !task.is_kthread() {
+                if let Some(origin) = illegitimate_origin(&task) {
+                    let anomaly = CredAnomaly::ImpossiblePrivilege {
+                        origin: origin.pid(),
+                        origin_comm: origin.comm(),
+                    };
+                    audit.push(&task, &state, anomaly)?;
+                }
+            }
+        }
+        audit.shared_creds(&mut holders)?;
+        Ok(audit)
+    }
+
+    /// Flag the tasks sharing their credentials with a task of another process
+    ///
+    /// `holders` are the tasks and the address of their objective credentials.
+    fn shared_creds(&mut self, holders: &mut [(usize, ARef<Task>)]) -> Result {
+        holders.sort_unstable_by_key(|(address, task)| (*address, task.start_time()));
+        for group in holders.chunk_by(|a, b| a.0 == b.0) {
+            let owner = &group[0].1;
+            let owner_tgid = owner.group_leader().pid();
+            for (_, task) in &group[1..] {
+                if task.group_leader().pid() == owner_tgid {
+                    continue;
+                }
+                // The credentials were replaced since the walk, their address may be reused
+                let state = CredState::read(task);
+                if state.address != group[0].0 || CredState::read(owner).address != group[0].0 {
+                    continue;
+                }
+                let anomaly = CredAnomaly::SharedWithProcess {
+                    owner: owner.pid(),
+                    owner_tgid,
+                    owner_comm: owner.comm(),
+                };
+                self.push(task, &state, anomaly)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, task: &Task, state: &CredState, anomaly: CredAnomaly) -> Result {
+        self.findings.push(
+            CredFinding {
+                pid: task.pid(),
+                tgid: task.group_leader().pid(),
+                comm: task.comm(),
+                exe: task.exe_path()?.unwrap_or_else(KVec::new),
+                cgroup: task.cgroup_path()?,
+                creds: state.objective,
+                anomaly,
+            },
+            GFP_KERNEL,
+        )?;
+        Ok(())
+    }
+
+    /// Create the event listing the anomalous credentials, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.findings.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::CredentialAnomaly,
+            fmt!("anomalous credentials of tasks : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for CredAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredAnomaly::Divergence { subjective } => write!(
+                f,
+                "subjective credentials escalated (euid {}, caps {:#x})",
+                subjective.euid, subjective.cap_effective
+            ),
+            CredAnomaly::BadUsage { usage, expected } => {
+                write!(f, "usage counter {} below {}", usage, expected)
+            }
+            CredAnomaly::ImpossiblePrivilege {
+                origin,
+                origin_comm,
+            } => write!(
+                f,
+                "privileged without transition from {} ({})",
+                BStr::from_bytes(until_nul(origin_comm)),
+                origin
+            ),
+            CredAnomaly::SharedWithProcess {
+                owner,
+                owner_tgid,
+                owner_comm,
+            } => write!(
+                f,
+                "credentials shared with {} (pid {}, tgid {})",
+                BStr::from_bytes(until_nul(owner_comm)),
+                owner,
+                owner_tgid
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CredAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, finding) in self.findings.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} (pid {}, tgid {}, exe {}, {}, uid {}, euid {}, caps {:#x}) {}",
+                BStr::from_bytes(until_nul(&finding.comm)),
+                finding.pid,
+                finding.tgid,
+                BStr::from_bytes(&finding.exe),
+                Attribution(&finding.cgroup),
+                finding.creds.uid,
+                finding.creds.euid,
+                finding.creds.cap_effective,
+                finding.anomaly
+            )?;
+        }
+        Ok(())
+    }
+}
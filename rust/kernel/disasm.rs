@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Disassembly : the instructions of a function, readable in the reports
+//!
+//! The raw bytes of a hooked prologue are hard to read in a log. A [`Listing`] decodes the
+//! instructions through [`Instruction`] and renders each one as
+//! `address: bytes  mnemonic operands destination <symbol+offset>`, the mnemonic being the
+//! [`InsnClass`] of the instruction and the destination of a branch being resolved with
+//! kallsyms. A listing is rendered on a single line, the instructions separated by `; `, so
+//! it can be included in the message of an [`Event`](crate::event::Event).
+//!
+//! C header : [`include/linux/kallsyms.h`](../../../../include/linux/kallsyms.h)
+
+use core::fmt;
+
+use crate::insn::{FunctionCode, Insn, InsnClass, InsnIter, Instruction};
+use crate::module::{symbols_lookup_address_buf, SymbolBuffer};
+use crate::str::BStr;
+use kernel::prelude::*;
+
+/// Maximum length of an instruction of the supported architectures
+const MAX_INSN_SIZE: usize = 15;
+
+/// The symbol containing the destination of a branch
+struct Symbol {
+    /// Name of the symbol
+    name: KVec<u8>,
+    /// Name of the module containing the symbol, empty if in the kernel image
+    module: KVec<u8>,
+    /// Offset of the destination from the start of the symbol
+    offset: u64,
+}
+
+/// A decoded instruction
+pub struct Line {
+    /// Address of the instruction
+    pub address: u64,
+    bytes: [u8; MAX_INSN_SIZE],
+    length: usize,
+    /// Class of the instruction
+    pub class: InsnClass,
+    /// Operands of the instruction, the destination of a branch excluded
+    pub operands: Option<<Insn<'static> as Instruction<'static>>::Operands>,
+    /// Destination of the branch, if known
+    pub target: Option<u64>,
+    symbol: Option<Symbol>,
+}
+
+impl Line {
+    /// Decode `insn`, the instruction at `address` whose bytes are `bytes`
+    ///
+    /// `buf` is the storage of the kallsyms lookup of the destination
+    fn new(
+        insn: &mut Insn<'_>,
+        bytes: &[u8],
+        address: u64,
+        buf: &mut SymbolBuffer,
+    ) -> Result<Self> {
+        let length = bytes.len().min(MAX_INSN_SIZE);
+        let mut line = Line {
+            address,
+            bytes: [0; MAX_INSN_SIZE],
+            length,
+            class: insn.class()?,
+            operands: insn.operands()?,
+            target: insn.branch(address)?.and_then(|branch| branch.target),
+            symbol: None,
+        };
+        line.bytes[..length].copy_from_slice(&bytes[..length]);
+
+        let Some(target) = line.target else {
+            return Ok(line);
+        };
+        if let Some(resolved) = symbols_lookup_address_buf(target, buf) {
+            let mut name = KVec::new();
+            name.extend_from_slice(resolved.name, GFP_KERNEL)?;
+            let mut module = KVec::new();
+            module.extend_from_slice(resolved.module.unwrap_or(&[]), GFP_KERNEL)?;
+            line.symbol = Some(Symbol {
+                name,
+                module,
+                offset: resolved.offset,
+            });
+        }
+        Ok(line)
+    }
+
+    /// Get the bytes of the instruction
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}:", self.address)?;
+        for byte in self.bytes() {
+            write!(f, " {:02x}", byte)?;
+        }
+        write!(f, "  {}", self.class)?;
+        if let Some(operands) = &self.operands {
+            write!(f, " {}", operands)?;
+        }
+        if let Some(target) = self.target {
+            write!(f, " {:#x}", target)?;
+        }
+        if let Some(symbol) = &self.symbol {
+            write!(
+                f,
+                " <{}+{:#x}",
+                BStr::from_bytes(&symbol.name),
+                symbol.offset
+            )?;
+            if !symbol.module.is_empty() {
+                write!(f, " [{}]", BStr::from_bytes(&symbol.module))?;
+            }
+            f.write_str(">")?;
+        }
+        Ok(())
+    }
+}
+
+/// The decoded instructions of a piece of code
+pub struct Listing {
+    lines: KVec<Line>,
+    /// The decoding stopped at an instruction which can't be decoded
+    pub truncated: bool,
+}
+
+impl Listing {
+    /// Decode the first `count` instructions of `code`, the code at `address`
+    ///
+    /// The decoding stops at the first instruction which can't be decoded
+    pub fn decode(code: &[u8], address: u64, count: usize) -> Result<Self> {
+        let mut buf = KBox::new(SymbolBuffer::new(), GFP_KERNEL)?;
+        let mut listing = Listing {
+            lines: KVec::new(),
+            truncated: false,
+        };
+
+        for insn in InsnIter::new(code).take(count) {
+            let Ok((offset, mut insn)) = insn else {
+                listing.truncated = true;
+                break;
+            };
+            let bytes = &code[offset..offset + insn.length()?];
+            let line = Line::new(&mut insn, bytes, address + offset as u64, &mut buf)?;
+            listing.lines.push(line, GFP_KERNEL)?;
+        }
+        Ok(listing)
+    }
+
+    /// Get the decoded instructions
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i != 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", line)?;
+        }
+        if self.truncated {
+            f.write_str(if self.lines.is_empty() {
+                "(bad)"
+            } else {
+                "; (bad)"
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode the first `count` instructions of the function containing `address`
+pub fn dump_function(address: u64, count: usize) -> Result<Listing> {
+    let function = FunctionCode::read(address)?;
+    Listing::decode(function.code(), function.address(), count)
+}
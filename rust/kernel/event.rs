@@ -0,0 +1,463 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Events : the detections reported by the checks
+//!
+//! Each check produces [`Event`]s, a kind identifying the check, a severity, the time
+//! of the detection and a human readable description of the finding. The severity is
+//! not chosen by the checks but computed by the [`scoring`](crate::scoring) policy.
+//!
+//! The low-severity observations (module loads, text pokes, probe attaches) are not
+//! delivered but kept in a [`TimeTravelBuffer`], which is flushed and attached to the
+//! next critical event as its context.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+
+use crate::alloc::Flags;
+use crate::sampling::SamplingInfo;
+use crate::scoring::{self, Signals};
+use crate::str::CString;
+use crate::time::Ktime;
+use kernel::prelude::*;
+
+/// Severity of an event
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u32)]
+pub enum Severity {
+    /// Informational, not a detection by itself
+    Info = 0,
+    /// Unusual but often legitimate
+    Low = 1,
+    /// Suspicious
+    Medium = 2,
+    /// Very likely malicious
+    High = 3,
+    /// Tampering of a critical kernel structure
+    Critical = 4,
+}
+
+impl Severity {
+    /// Convert from the raw value
+    pub fn from_raw(severity: u32) -> Option<Self> {
+        Some(match severity {
+            0 => Severity::Info,
+            1 => Severity::Low,
+            2 => Severity::Medium,
+            3 => Severity::High,
+            4 => Severity::Critical,
+            _ => return None,
+        })
+    }
+}
+
+/// Kind of event, identify the check which raised it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum EventKind {
+    /// A module is visible in a secondary view but missing from the module list
+    ModuleViewMismatch = 0,
+    /// A module was loaded over the text of a recently unloaded module
+    ModuleTextReuse = 1,
+    /// A module was loaded without a valid signature
+    UnsignedModule = 2,
+    /// A module parameter is missing from sysfs
+    HiddenModuleParam = 3,
+    /// A module is in an abnormal state or its list entry is corrupted
+    AnomalousModuleState = 4,
+    /// A module imported a symbol commonly used by rootkits
+    SuspiciousImport = 5,
+    /// A field of a `struct module` is inconsistent
+    ModuleTampering = 6,
+    /// An ftrace_ops has a callback outside of the known text or modify the instruction pointer
+    SuspiciousFtraceOps = 7,
+    /// A kprobe on a critical function has a handler in hidden memory or an unlisted module
+    SuspiciousKprobe = 8,
+    /// A livepatch redirects a function outside of the text of its module
+    SuspiciousLivepatch = 9,
+    /// A BPF program calls helpers commonly used by rootkits
+    SuspiciousBpfProgram = 10,
+    /// A monitored syscall was called (module loading, signal, ptrace, sensitive open, ...)
+    MonitoredSyscall = 11,
+    /// A probe of rkchk was unregistered, redirected or disabled
+    ProbeTampering = 12,
+    /// An ftrace direct call jumps to a trampoline in anonymous memory
+    SuspiciousDirectCall = 13,
+    /// A probe of rkchk missed calls, their exit handlers were skipped
+    ProbeMissed = 14,
+    /// A kernel mapping is both writable and executable
+    WritableExecutableMapping = 15,
+    /// The direct map alias of a text page is executable or was made writable
+    TextAliasTampering = 16,
+    /// A large page mapping text was split into smaller pages
+    TextMappingSplit = 17,
+    /// The kernel and user copies of the page tables are inconsistent
+    PageTableIsolationMismatch = 18,
+    /// A trap instruction was injected in a kernel function
+    InjectedTrap = 19,
+    /// A branch of a kernel function leaves it toward memory owned by nobody
+    InlineHook = 20,
+    /// The credentials of a task were tampered with or grant privileges it couldn't acquire
+    CredentialAnomaly = 21,
+    /// A process maps memory executable the way an injected payload does
+    SuspiciousUserMapping = 22,
+    /// A user task is disguised as a kernel thread
+    FakeKernelThread = 23,
+    /// A task is hidden or isolated behind namespaces
+    NamespaceAnomaly = 24,
+    /// A tracer stays attached to a critical daemon
+    SuspiciousPtrace = 25,
+    /// A privileged daemon lost its seccomp filters or its LSM confinement
+    ConfinementLoss = 26,
+    /// A process was created, executed a program or exited
+    ProcessLifecycle = 27,
+    /// A process is named unlike its executable, imitating a kernel thread or a daemon
+    CommMismatch = 28,
+    /// A sensitive function was called from executable memory no kernel code owns
+    UnknownCaller = 29,
+    /// The unwind metadata covering a monitored function was tampered with
+    UnwindTampering = 30,
+    /// A sleeping task references suspicious memory from its kernel stack
+    SuspiciousStackReference = 31,
+}
+
+/// Number of [`EventKind`]
+pub const EVENT_KIND_COUNT: usize = 32;
+
+impl EventKind {
+    /// Get the kind corresponding to an identifier
+    pub fn from_id(id: u32) -> Option<Self> {
+        Some(match id {
+            0 => EventKind::ModuleViewMismatch,
+            1 => EventKind::ModuleTextReuse,
+            2 => EventKind::UnsignedModule,
+            3 => EventKind::HiddenModuleParam,
+            4 => EventKind::AnomalousModuleState,
+            5 => EventKind::SuspiciousImport,
+            6 => EventKind::ModuleTampering,
+            7 => EventKind::SuspiciousFtraceOps,
+            8 => EventKind::SuspiciousKprobe,
+            9 => EventKind::SuspiciousLivepatch,
+            10 => EventKind::SuspiciousBpfProgram,
+            11 => EventKind::MonitoredSyscall,
+            12 => EventKind::ProbeTampering,
+            13 => EventKind::SuspiciousDirectCall,
+            14 => EventKind::ProbeMissed,
+            15 => EventKind::WritableExecutableMapping,
+            16 => EventKind::TextAliasTampering,
+            17 => EventKind::TextMappingSplit,
+            18 => EventKind::PageTableIsolationMismatch,
+            19 => EventKind::InjectedTrap,
+            20 => EventKind::InlineHook,
+            21 => EventKind::CredentialAnomaly,
+            22 => EventKind::SuspiciousUserMapping,
+            23 => EventKind::FakeKernelThread,
+            24 => EventKind::NamespaceAnomaly,
+            25 => EventKind::SuspiciousPtrace,
+            26 => EventKind::ConfinementLoss,
+            27 => EventKind::ProcessLifecycle,
+            28 => EventKind::CommMismatch,
+            29 => EventKind::UnknownCaller,
+            30 => EventKind::UnwindTampering,
+            31 => EventKind::SuspiciousStackReference,
+            _ => return None,
+        })
+    }
+}
+
+/// An event raised by a check
+pub struct Event {
+    /// Kind of event
+    pub kind: EventKind,
+    /// Severity of the event
+    pub severity: Severity,
+    /// Time of the detection (`ktime_get`) in nanoseconds
+    pub timestamp: i64,
+    /// Description of the finding
+    pub message: CString,
+    /// Observations preceding the event, see [`TimeTravelBuffer`]
+    pub context: KVec<Observation>,
+    /// Sampling metadata if the check is sampled, see [`Sampler`](crate::sampling::Sampler)
+    pub sampling: Option<SamplingInfo>,
+}
+
+impl Event {
+    /// Create a new event timestamped now, scored without any signal
+    pub fn new(kind: EventKind, message: fmt::Arguments<'_>) -> Result<Self> {
+        Self::with_signals(kind, &Signals::default(), message)
+    }
+
+    /// Create a new event timestamped now, scored with the context of the finding
+    pub fn with_signals(
+        kind: EventKind,
+        signals: &Signals,
+        message: fmt::Arguments<'_>,
+    ) -> Result<Self> {
+        Ok(Event {
+            kind,
+            severity: scoring::score(kind, signals),
+            timestamp: Ktime::ktime_get().to_ns(),
+            message: CString::try_from_fmt(message)?,
+            context: KVec::new(),
+            sampling: None,
+        })
+    }
+
+    /// Attach the metadata of the sampler of the check which raised the event
+    pub fn with_sampling(mut self, sampling: SamplingInfo) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {:?}/{:?} : {}",
+            self.timestamp,
+            self.kind,
+            self.severity,
+            crate::str::BStr::from_bytes(self.message.as_bytes())
+        )?;
+        if let Some(sampling) = &self.sampling {
+            write!(
+                f,
+                " (sampled {:?}, {}/{})",
+                sampling.mode, sampling.sampled, sampling.seen
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of observations kept by a [`TimeTravelBuffer`]
+pub const TIME_TRAVEL_CAPACITY: usize = 64;
+
+/// Length of the detail of an [`Observation`]
+pub const OBSERVATION_DETAIL_LEN: usize = 48;
+
+/// Kind of low-severity observation
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ObservationKind {
+    /// A module was loaded
+    ModuleLoad = 0,
+    /// A module was unloaded
+    ModuleUnload = 1,
+    /// Kernel text was patched (`text_poke`)
+    TextPoke = 2,
+    /// A probe was attached (kprobe, ftrace_ops, BPF program)
+    ProbeAttach = 3,
+}
+
+impl ObservationKind {
+    /// Get the kind whose value is `kind`, `None` if it isn't a valid kind
+    fn from_u32(kind: u32) -> Option<Self> {
+        Some(match kind {
+            0 => ObservationKind::ModuleLoad,
+            1 => ObservationKind::ModuleUnload,
+            2 => ObservationKind::TextPoke,
+            3 => ObservationKind::ProbeAttach,
+            _ => return None,
+        })
+    }
+}
+
+/// A low-severity observation, never delivered by itself
+#[derive(Clone, Copy)]
+pub struct Observation {
+    /// Kind of observation
+    pub kind: ObservationKind,
+    /// Time of the observation (`ktime_get`) in nanoseconds
+    pub timestamp: i64,
+    /// Address related to the observation (module base, patched address, probed address)
+    pub address: u64,
+    /// Short description (truncated), null terminated
+    pub detail: [u8; OBSERVATION_DETAIL_LEN],
+}
+
+impl fmt::Debug for Observation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self
+            .detail
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(OBSERVATION_DETAIL_LEN);
+        write!(
+            f,
+            "[{}] {:?} {:#x} {}",
+            self.timestamp,
+            self.kind,
+            self.address,
+            crate::str::BStr::from_bytes(&self.detail[..len])
+        )
+    }
+}
+
+/// An [`Observation`] as stored in a [`Slot`]
+///
+/// It only holds integers, so a read racing with a write gets a torn value instead of an
+/// invalid one, the kind is checked when the observation is taken.
+#[derive(Clone, Copy)]
+struct RawObservation {
+    kind: u32,
+    timestamp: i64,
+    address: u64,
+    detail: [u8; OBSERVATION_DETAIL_LEN],
+}
+
+impl RawObservation {
+    /// Get the observation, `None` if the kind isn't valid
+    fn observation(&self) -> Option<Observation> {
+        Some(Observation {
+            kind: ObservationKind::from_u32(self.kind)?,
+            timestamp: self.timestamp,
+            address: self.address,
+            detail: self.detail,
+        })
+    }
+}
+
+/// A slot of the ring of observations
+///
+/// `seq` is a sequence count: bit 0 is set while the observation is written, bit 1 while
+/// it holds an observation not taken yet, and the upper bits count the writes.
+struct Slot {
+    seq: AtomicU64,
+    observation: UnsafeCell<RawObservation>,
+}
+
+/// The slot is being written
+const SLOT_WRITING: u64 = 1;
+/// The slot holds an observation
+const SLOT_VALID: u64 = 2;
+/// Increment of the write count of a slot
+const SLOT_GENERATION: u64 = 4;
+
+impl Slot {
+    const EMPTY: Self = Slot {
+        seq: AtomicU64::new(0),
+        observation: UnsafeCell::new(RawObservation {
+            kind: ObservationKind::ModuleLoad as u32,
+            timestamp: 0,
+            address: 0,
+            detail: [0; OBSERVATION_DETAIL_LEN],
+        }),
+    };
+}
+
+/// Circular buffer of the recent low-severity observations
+///
+/// The observations are recorded without allocation nor lock so this can be used from the
+/// probe handlers, in any context: a slot is claimed by moving the head, and written under
+/// its sequence count. A writer finding its slot still being written (interrupted while
+/// the ring wrapped around) drops its observation.
+///
+/// When a critical event fires the observations of the last `window_ms` are
+/// attached to it and the buffer is emptied.
+#[pin_data]
+pub struct TimeTravelBuffer {
+    slots: [Slot; TIME_TRAVEL_CAPACITY],
+    /// Number of slots claimed since the creation, the next slot to write modulo the capacity
+    head: AtomicUsize,
+    window_ns: i64,
+}
+
+// SAFETY: The observations are only accessed under the sequence count of their slot, see
+// `record` and `attach`
+unsafe impl Sync for TimeTravelBuffer {}
+
+impl TimeTravelBuffer {
+    /// Create an empty buffer keeping the observations of the last `window_ms`
+    pub fn new(window_ms: i64) -> impl PinInit<Self> {
+        pin_init!(Self {
+            slots: [const { Slot::EMPTY }; TIME_TRAVEL_CAPACITY],
+            head: AtomicUsize::new(0),
+            window_ns: window_ms * 1_000_000,
+        })
+    }
+
+    /// Record an observation, overwriting the oldest one if the buffer is full
+    pub fn record(&self, kind: ObservationKind, address: u64, detail: &[u8]) {
+        let mut observation = RawObservation {
+            kind: kind as u32,
+            timestamp: Ktime::ktime_get().to_ns(),
+            address,
+            detail: [0; OBSERVATION_DETAIL_LEN],
+        };
+        let len = detail.len().min(OBSERVATION_DETAIL_LEN - 1);
+        observation.detail[..len].copy_from_slice(&detail[..len]);
+
+        let slot = &self.slots[self.head.fetch_add(1, Ordering::Relaxed) % TIME_TRAVEL_CAPACITY];
+        let mut seq = slot.seq.load(Ordering::Relaxed);
+        loop {
+            if seq & SLOT_WRITING != 0 {
+                return;
+            }
+            match slot.seq.compare_exchange_weak(
+                seq,
+                seq | SLOT_WRITING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+        // SAFETY: We own the slot while `SLOT_WRITING` is set. A concurrent `attach` may read
+        // it, the sequence count change makes it discard what it read
+        unsafe { ptr::write_volatile(slot.observation.get(), observation) };
+        let next = (seq & !(SLOT_WRITING | SLOT_VALID)) + SLOT_GENERATION;
+        slot.seq.store(next | SLOT_VALID, Ordering::Release);
+    }
+
+    /// Attach the recent observations to `event` if it is critical, and empty the buffer
+    ///
+    /// The observations are attached from the oldest to the newest
+    ///
+    /// # Return
+    /// The number of attached observations
+    pub fn attach(&self, event: &mut Event, flags: Flags) -> Result<usize> {
+        if event.severity < Severity::Critical {
+            return Ok(0);
+        }
+
+        // Allocate before taking the observations, the push can't fail once they are taken
+        let mut context = KVec::with_capacity(TIME_TRAVEL_CAPACITY, flags)?;
+        let oldest = event.timestamp - self.window_ns;
+
+        let head = self.head.load(Ordering::Relaxed);
+        for i in 0..TIME_TRAVEL_CAPACITY {
+            let slot = &self.slots[(head + i) % TIME_TRAVEL_CAPACITY];
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq & (SLOT_WRITING | SLOT_VALID) != SLOT_VALID {
+                continue;
+            }
+            // SAFETY: The observation may be rewritten concurrently, it only holds integers
+            // and is only used if the sequence count didn't change in between
+            let observation = unsafe { ptr::read_volatile(slot.observation.get()) };
+            fence(Ordering::Acquire);
+            // Take the observation, fails if it was rewritten
+            if slot
+                .seq
+                .compare_exchange(seq, seq & !SLOT_VALID, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+            {
+                continue;
+            }
+            match observation.observation() {
+                Some(observation) if observation.timestamp >= oldest => {
+                    // Can't fail, the capacity is reserved
+                    context.push(observation, flags)?;
+                }
+                _ => (),
+            }
+        }
+
+        let count = context.len();
+        event.context = context;
+        Ok(count)
+    }
+}
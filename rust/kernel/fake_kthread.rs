@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Fake kernel threads : the user tasks disguised as kernel threads
+//!
+//! `ps` shows the kernel threads with their name in brackets, a userland rootkit helper
+//! hides among them by naming itself `[kworker/0:1]` or `kworker/0:1`. A kernel thread is
+//! created by `kthreadd`, it has no address space of its own and its `PF_KTHREAD` flag is
+//! set, none of which a user task can fake. Every thread of the system is walked with
+//! [`AllThreadsIter`], so a payload thread renamed inside a legitimate process is found as
+//! well, and the ones named like a kernel thread are flagged when:
+//! - `PF_KTHREAD` isn't set
+//! - they have an address space
+//! - their parent isn't `kthreadd`
+//!
+//! A kernel thread borrowing an address space (`kthread_use_mm`) keeps its `PF_KTHREAD`,
+//! its address space is only reported for the tasks without it.
+//!
+//! C header: [`include/linux/kthread.h`](../../../../include/linux/kthread.h)
+
+use core::fmt;
+use core::ptr;
+
+use crate::event::{Event, EventKind};
+use crate::str::{until_nul, BStr};
+use crate::task::{Task, TASK_COMM_LEN};
+use crate::task_cgroup::Attribution;
+use crate::task_iter::AllThreadsIter;
+use kernel::prelude::*;
+
+/// Prefixes of the names of the common kernel threads
+const KTHREAD_PREFIXES: [&[u8]; 15] = [
+    b"kworker/",
+    b"ksoftirqd/",
+    b"migration/",
+    b"cpuhp/",
+    b"idle_inject/",
+    b"watchdog/",
+    b"watchdogd",
+    b"rcu_",
+    b"kthreadd",
+    b"kswapd",
+    b"kcompactd",
+    b"khugepaged",
+    b"kauditd",
+    b"jbd2/",
+    b"irq/",
+];
+
+/// A task named like a kernel thread which isn't one
+pub struct FakeKthread {
+    /// Thread id of the task
+    pub pid: i32,
+    /// Process id of the task
+    pub tgid: i32,
+    /// Command name of the task, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Path of the executable of the task, empty if it has none
+    pub exe: KVec<u8>,
+    /// Path of the cgroup of the task
+    pub cgroup: KVec<u8>,
+    /// Thread id of the parent
+    pub parent: i32,
+    /// `PF_KTHREAD` is set
+    pub kthread_flag: bool,
+    /// The task has an address space without `PF_KTHREAD`
+    pub has_mm: bool,
+    /// The parent of the task isn't `kthreadd`
+    pub wrong_parent: bool,
+}
+
+/// Result of the scan
+pub struct FakeKthreadScan {
+    /// Number of threads scanned
+    pub scanned: usize,
+    /// The fake kernel threads
+    pub tasks: KVec<FakeKthread>,
+}
+
+/// The command name `comm` is the one of a kernel thread, or bracketed like `ps` shows them
+pub(crate) fn looks_like_kthread(comm: &[u8]) -> bool {
+    comm.starts_with(b"[")
+        || KTHREAD_PREFIXES
+            .iter()
+            .any(|prefix| comm.starts_with(prefix))
+}
+
+/// The task has an address space
+fn has_mm(task: &Task) -> bool {
+    // SAFETY: The task is referenced, only the pointer is read
+    !unsafe { ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).mm)) }.is_null()
+}
+
+impl FakeKthreadScan {
+    /// Scan every thread of the system
+    pub fn scan() -> Result<Self> {
+        // SAFETY: `kthreadd_task` is set once when kthreadd is created and never modified
+        let kthreadd = unsafe { bindings::kthreadd_task };
+        let mut scan = FakeKthreadScan {
+            scanned: 0,
+            tasks: KVec::new(),
+        };
+
+        for task in AllThreadsIter::new(current!().into()) {
+            scan.scanned += 1;
+            let comm = task.comm();
+            if !looks_like_kthread(until_nul(&comm)) || task.as_ptr() == kthreadd {
+                continue;
+            }
+
+            // An exited task has no parent to check anymore
+            let Some(parent) = task.real_parent() else {
+                continue;
+            };
+            let kthread_flag = task.is_kthread();
+            let has_mm = !kthread_flag && has_mm(&task);
+            let wrong_parent = parent.as_ptr() != kthreadd;
+            if kthread_flag && !wrong_parent {
+                continue;
+            }
+            scan.tasks.push(
+                FakeKthread {
+                    pid: task.pid(),
+                    tgid: task.group_leader().pid(),
+                    comm,
+                    exe: task.exe_path()?.unwrap_or_else(KVec::new),
+                    cgroup: task.cgroup_path()?,
+                    parent: parent.pid(),
+                    kthread_flag,
+                    has_mm,
+                    wrong_parent,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+        Ok(scan)
+    }
+
+    /// Create the event listing the fake kernel threads, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.tasks.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::FakeKernelThread,
+            fmt!("tasks disguised as kernel threads : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for FakeKthreadScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, task) in self.tasks.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} (pid {}, tgid {}, parent {}, exe {}, {})",
+                BStr::from_bytes(until_nul(&task.comm)),
+                task.pid,
+                task.tgid,
+                task.parent,
+                BStr::from_bytes(&task.exe),
+                Attribution(&task.cgroup)
+            )?;
+            if !task.kthread_flag {
+                f.write_str(" no PF_KTHREAD")?;
+            }
+            if task.has_mm {
+                f.write_str(" has mm")?;
+            }
+            if task.wrong_parent {
+                f.write_str(" not created by kthreadd")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Fingerprint : signatures of the code of kernel functions, stable across boots
+//!
+//! The code of a function isn't the same from one boot to another: the relative branches
+//! and the RIP-relative operands depend on its location, the absolute addresses are
+//! relocated by KASLR, and the alternatives and the runtime constants are patched at boot
+//! depending on the CPU. [`fingerprint_function`] decodes the function and hashes its
+//! normalized instructions:
+//! - the immediate of a relative branch and the displacement of a RIP-relative operand are
+//!   masked
+//! - the 8 bytes immediates (`movabs`, used by the runtime constants) and the 4 bytes
+//!   immediates and displacements holding an address of the kernel image or of a module
+//!   are masked, the other constants (like a negative error code) are kept
+//! - the nops are dropped, so a site patched with a shorter replacement and its nop padding
+//!   hashes like the replacement alone
+//!
+//! The table of the alternatives is freed with the init memory, so their sites are only
+//! normalized through these rules: a replacement which isn't a nop or a relocated operand
+//! still changes the fingerprint, as do the jump labels and the ftrace call site. The same
+//! kernel on the same CPU gets the same fingerprints, which can be compared with a
+//! baseline recorded at a previous boot.
+//!
+//! C header: [`arch/x86/include/asm/insn.h`](../../../../arch/x86/include/asm/insn.h)
+
+use core::fmt;
+
+use crate::insn::{FunctionCode, Insn, MemBase, MemOperand, RmOperand};
+use crate::module::is_kernel;
+use crate::sync::rcu;
+use kernel::prelude::*;
+
+/// Maximum length of an x86 instruction
+const MAX_INSN_SIZE: usize = 15;
+
+/// FNV-1a 64 bits offset basis
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// FNV-1a 64 bits prime
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The signature of the code of a function
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fingerprint {
+    /// FNV-1a hash of the normalized instructions
+    pub hash: u64,
+    /// Number of instructions hashed, the nops excluded
+    pub instructions: usize,
+    /// Number of bytes decoded, less than the size of the function when its end couldn't
+    /// be decoded
+    pub decoded: usize,
+}
+
+/// Hash `bytes` into `hash`
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// The instruction `insn` is a nop, the one byte `nop` or the multi bytes `nopw`/`nopl`
+/// with their operand size and segment prefixes
+fn is_nop(insn: &[u8]) -> bool {
+    let start = insn
+        .iter()
+        .position(|byte| !matches!(byte, 0x66 | 0x2e))
+        .unwrap_or(insn.len());
+    // `90` with a REX.B prefix is `xchg r8, rax`, not a nop
+    matches!(insn[start..], [0x90] | [0x0f, 0x1f, ..])
+}
+
+/// The address `addr` is in the kernel image or in a module
+fn is_kernel_or_module(addr: u64) -> bool {
+    if is_kernel(addr) {
+        return true;
+    }
+    let _guard = rcu::read_lock();
+    // SAFETY: Just an FFI call, we hold the RCU read lock as required
+    !unsafe { bindings::__module_address(addr as _) }.is_null()
+}
+
+/// The little endian field `field` of an instruction holds an address of the kernel image
+/// or of a module once sign extended, a constant in the kernel half of the address space
+/// (like a negative error code) which resolves to nothing isn't an address
+fn is_kernel_address(field: &[u8]) -> bool {
+    match *field {
+        [a, b, c, d] => is_kernel_or_module(i32::from_le_bytes([a, b, c, d]) as i64 as u64),
+        _ => false,
+    }
+}
+
+/// Normalize the instruction `insn`, whose bytes are `bytes`, into `normalized`
+fn normalize(insn: &mut Insn<'_>, bytes: &[u8], normalized: &mut [u8]) -> Result {
+    normalized.copy_from_slice(bytes);
+    let (displacement, immediate) = insn.get_operand_ranges()?;
+
+    let rip_relative = matches!(
+        insn.get_rm_operand()?,
+        Some(RmOperand::Memory(MemOperand {
+            base: Some(MemBase::Rip),
+            ..
+        }))
+    );
+    if rip_relative || is_kernel_address(&bytes[displacement.clone()]) {
+        normalized[displacement].fill(0);
+    }
+
+    if insn.is_relative_branch()?
+        || immediate.len() == 8
+        || is_kernel_address(&bytes[immediate.clone()])
+    {
+        normalized[immediate].fill(0);
+    }
+    Ok(())
+}
+
+impl Fingerprint {
+    /// Compute the fingerprint of the code of `function`
+    ///
+    /// The decoding stops at the first instruction which can't be decoded, the end of a
+    /// function can be data or padding
+    pub fn of(function: &FunctionCode) -> Result<Self> {
+        let code = function.code();
+        let mut fingerprint = Fingerprint {
+            hash: FNV_OFFSET_BASIS,
+            instructions: 0,
+            decoded: 0,
+        };
+        let mut normalized = [0u8; MAX_INSN_SIZE];
+
+        for insn in function.iter() {
+            let Ok((offset, mut insn)) = insn else {
+                break;
+            };
+            let length = insn.get_length()? as usize;
+            let bytes = &code[offset..offset + length];
+            fingerprint.decoded = offset + length;
+            if is_nop(bytes) {
+                continue;
+            }
+
+            normalize(&mut insn, bytes, &mut normalized[..length])?;
+            // The length separates the instructions, so two streams of different
+            // instructions can't hash the same bytes
+            fingerprint.hash = fnv1a(fingerprint.hash, &[length as u8]);
+            fingerprint.hash = fnv1a(fingerprint.hash, &normalized[..length]);
+            fingerprint.instructions += 1;
+        }
+        Ok(fingerprint)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:016x} ({} instructions, {} bytes)",
+            self.hash, self.instructions, self.decoded
+        )
+    }
+}
+
+/// Compute the fingerprint of the function of `size` bytes at `address`, see
+/// [`Fingerprint::of`]
+pub fn fingerprint_function(address: u64, size: usize) -> Result<Fingerprint> {
+    Fingerprint::of(&FunctionCode::read_range(address, size)?)
+}
@@ -4,14 +4,21 @@
 
 use crate::error::Result;
 use crate::init::PinInit;
+use crate::module::{symbols_lookup_address_buf, SymbolBuffer};
+use crate::percpu::PerCpuCounter;
+use crate::registers::Registers;
+use crate::stacktrace::{ProbeStacktrace, Stacktrace, MAX_PROBE_DEPTH};
 use crate::str::CStr;
 use crate::try_pin_init;
 use crate::types::{ForeignOwnable, Opaque};
-use bindings::{fprobe, pt_regs};
+use bindings::{fprobe, pt_regs, KSYM_NAME_LEN};
 use core::ffi::c_void;
 use core::marker::PhantomData;
 use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use kernel::container_of;
 use kernel::error::Error;
+use kernel::prelude::*;
 use macros::{pin_data, pinned_drop};
 
 /// Wraps the kernel's `struct fprobe`
@@ -44,39 +51,102 @@ pub trait FprobeOperations
     /// The type of the data that will be allocated at each entry of the hooked function
     /// and passed to the `entry_handler` and it's respective `exit_handler`
     type EntryData: Default + Sized;
-    /// Callback called at each traced function entry
+
+    /// Register the entry handler
+    const HAS_ENTRY_HANDLER: bool = true;
+
+    /// Register the exit handler, without it no rethook is allocated and the entry data
+    /// is never passed to the entry handler
+    const HAS_EXIT_HANDLER: bool = true;
+
+    /// Number of rethook nodes allocated at registration, the maximum number of calls
+    /// in flight whose exit handler is pending. A call beyond it doesn't get its exit
+    /// handler and is counted in [`Fprobe::nmissed`], see [`Fprobe::resize`]
+    const NR_MAXACTIVE: u32 = 50;
+
+    /// Number of frames of the stacktrace captured before each call to the entry handler
+    /// and passed to it, at most [`MAX_PROBE_DEPTH`]. 0 disables the capture
+    ///
+    /// The stacktrace is unwound from the registers of the traced function, without the
+    /// frames of the probe, and without allocating.
+    const STACKTRACE_DEPTH: usize = 0;
+
+    /// Callback called at each traced function entry, only if [`Self::HAS_ENTRY_HANDLER`]
+    /// is set
+    ///
+    /// `stack` is the stacktrace of the call if [`Self::STACKTRACE_DEPTH`] is set. Returning
+    /// `None` skip the exit handler of this call
     fn entry_handler(
-        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
-        entry_ip: usize,
-        ret_ip: usize,
-        regs: &pt_regs,
-        entry_data: Option<&mut Self::EntryData>,
-    ) -> Option<()>;
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _entry_ip: usize,
+        _ret_ip: usize,
+        _regs: Registers<'_>,
+        _stack: Option<ProbeStacktrace<'_>>,
+        _entry_data: Option<&mut Self::EntryData>,
+    ) -> Option<()> {
+        Some(())
+    }
 
-    /// Callback called at each traced function exit
+    /// Callback called at each traced function exit, only if [`Self::HAS_EXIT_HANDLER`]
+    /// is set
     fn exit_handler(
-        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
-        entry_ip: usize,
-        ret_ip: usize,
-        regs: &pt_regs,
-        entry_data: Option<&mut Self::EntryData>,
-    );
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _entry_ip: usize,
+        _ret_ip: usize,
+        _regs: Registers<'_>,
+        _entry_data: Option<&mut Self::EntryData>,
+    ) {
+    }
+}
+
+/// Statistics of a [`Fprobe`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FprobeStats {
+    /// Number of calls to the entry handler
+    pub entry_hits: u64,
+    /// Number of calls to the exit handler
+    pub exit_hits: u64,
+    /// Number of hits missed by the fprobe (recursion, rethook exhausted, ...)
+    pub missed: u64,
+}
+
+/// Mirror of `struct fprobe_rethook_node` (private to `kernel/trace/fprobe.c`), the
+/// entry data follows it in each rethook node
+#[allow(dead_code)]
+#[repr(C)]
+struct FprobeRethookNode {
+    node: bindings::rethook_node,
+    entry_ip: core::ffi::c_ulong,
+    entry_parent_ip: core::ffi::c_ulong,
+}
+
+/// Mirror of the head of `struct ftrace_hash` (private to `kernel/trace/trace.h`)
+#[allow(dead_code)]
+#[repr(C)]
+struct FtraceHashHead {
+    size_bits: core::ffi::c_ulong,
+    buckets: *mut bindings::hlist_head,
+    count: core::ffi::c_ulong,
 }
 
 /// Represent the kernel's `struct fprobe` structure
 ///
 /// # Invariants
 ///
-///     `inner` is a registered fprobe
-#[repr(transparent)]
+///     `inner` is a registered fprobe while `registered` is set, it is only cleared by
+///     [`Fprobe::remove_filter`] once the fprobe is unregistered
 #[pin_data(PinnedDrop)]
 pub struct Fprobe<T: FprobeOperations> {
+    entry_hits: PerCpuCounter,
+    exit_hits: PerCpuCounter,
+    registered: AtomicBool,
     #[pin]
     inner: Opaque<bindings::fprobe>,
     _t: PhantomData<T>,
 }
 
-// SAFETY: There is no `&self` methods
+// SAFETY: The `&self` methods only access the flags and the counters atomically, and
+// read `nmissed`
 unsafe impl<T: FprobeOperations> Sync for Fprobe<T> where T::Data: Sync {}
 
 // SAFETY: It is safe to unregister the probe on a different thread than
@@ -97,37 +167,383 @@ fn new_inner(data: T::Data) -> bindings::fprobe {
             flags: 0,
             rethook: core::ptr::null_mut::<bindings::rethook>(),
             // We just need common data between all the function not only the entry and it's corresponding exit
-            entry_data_size: core::mem::size_of::<T::EntryData>(),
-            nr_maxactive: 50,
-            entry_handler: Some(Fprobe::<T>::entry_handler_callback as _),
-            exit_handler: Some(Fprobe::<T>::exit_handler_callback as _),
+            // The entry data is stored in the rethook nodes, which are only allocated with
+            // an exit handler
+            entry_data_size: if T::HAS_EXIT_HANDLER {
+                core::mem::size_of::<T::EntryData>()
+            } else {
+                0
+            },
+            nr_maxactive: if T::HAS_EXIT_HANDLER {
+                T::NR_MAXACTIVE as _
+            } else {
+                0
+            },
+            entry_handler: if T::HAS_ENTRY_HANDLER {
+                Some(Fprobe::<T>::entry_handler_callback as _)
+            } else {
+                None
+            },
+            exit_handler: if T::HAS_EXIT_HANDLER {
+                Some(Fprobe::<T>::exit_handler_callback as _)
+            } else {
+                None
+            },
         }
     }
 
-    /// Create a new `struct fprobe` structure and register it
-    pub fn new(
-        filter: &'static CStr,
-        notfilter: Option<&'static CStr>,
+    /// Create a new `struct fprobe` structure and register it with `register`
+    ///
+    /// If the registration fail the private data is dropped
+    fn new_with<'a>(
         private_data: T::Data,
-    ) -> impl PinInit<Self, Error> {
+        register: impl FnOnce(*mut bindings::fprobe) -> Result + 'a,
+    ) -> impl PinInit<Self, Error> + 'a
+    where
+        T: 'a,
+        T::Data: 'a,
+    {
         try_pin_init!(Self {
+            // The counters are initialized first as the handlers use them once registered
+            entry_hits: PerCpuCounter::new()?,
+            exit_hits: PerCpuCounter::new()?,
+            registered: AtomicBool::new(true),
             inner <- Opaque::try_ffi_init(move |slot: *mut bindings::fprobe| {
                 // SAFETY: The initializer can write to the provided `slot`.
                 unsafe { slot.write(Self::new_inner(private_data))};
 
-                // SAFETY: We wrote the data to the fprobe structure.
                 // We have the fprobe structure pinned to our type so will be unregistred
                 // before being deallocated
                 // INVARIANT: If this return `Ok(())`, then the `slot` will contan a registred
                 // device
-                unsafe {
-                    Self::register(slot, filter, notfilter)
+                let ret = register(slot);
+                if ret.is_err() {
+                    // SAFETY: The fprobe is not registered so no one borrowed the data,
+                    // this is the only call to `from_foreign` for this `into_foreign`
+                    unsafe { T::Data::from_foreign((*slot).ops.private) };
                 }
+                ret
             }),
             _t: PhantomData,
         })
     }
 
+    /// Create a new `struct fprobe` structure and register it
+    pub fn new(
+        filter: &'static CStr,
+        notfilter: Option<&'static CStr>,
+        private_data: T::Data,
+    ) -> impl PinInit<Self, Error> {
+        // SAFETY: We wrote the data to the fprobe structure before the call
+        Self::new_with(private_data, move |slot| unsafe {
+            Self::register(slot, filter, notfilter)
+        })
+    }
+
+    /// Create a new `struct fprobe` structure and register it on a list of addresses
+    ///
+    /// The addresses must be the entry of ftrace-able functions, they are copied at
+    /// registration so the slice is only borrowed for the initialization
+    pub fn new_ips<'a>(addrs: &'a [usize], private_data: T::Data) -> impl PinInit<Self, Error> + 'a
+    where
+        T: 'a,
+        T::Data: 'a,
+    {
+        Self::new_with(private_data, move |slot| {
+            let num = i32::try_from(addrs.len()).map_err(|_| EINVAL)?;
+            // SAFETY: We wrote the data to the fprobe structure before the call, and
+            // `addrs` is valid for `num` entries. `usize` and `unsigned long` have the same
+            // layout
+            crate::error::to_result(unsafe {
+                bindings::register_fprobe_ips(slot, addrs.as_ptr() as *mut core::ffi::c_ulong, num)
+            })
+        })
+    }
+
+    /// Create a new `struct fprobe` structure and register it on a list of symbols
+    ///
+    /// Unlike [`Self::new`] no glob is expanded, each name must be an ftrace-able function
+    pub fn new_syms<'a>(
+        syms: &'a [&'a CStr],
+        private_data: T::Data,
+    ) -> impl PinInit<Self, Error> + 'a
+    where
+        T: 'a,
+        T::Data: 'a,
+    {
+        Self::new_with(private_data, move |slot| {
+            let num = i32::try_from(syms.len()).map_err(|_| EINVAL)?;
+            let mut names = KVec::with_capacity(syms.len(), GFP_KERNEL)?;
+            for sym in syms {
+                names.push(sym.as_char_ptr(), GFP_KERNEL)?;
+            }
+            // SAFETY: We wrote the data to the fprobe structure before the call, and
+            // `names` contains `num` valid null terminated strings
+            crate::error::to_result(unsafe {
+                bindings::register_fprobe_syms(slot, names.as_mut_ptr(), num)
+            })
+        })
+    }
+
+    /// Get the raw `struct fprobe`, registered as long as `self` is alive
+    pub fn as_ptr(&self) -> *mut bindings::fprobe {
+        self.inner.get()
+    }
+
+    /// Get the flags of the fprobe as an atomic
+    fn flags(&self) -> &AtomicU32 {
+        // SAFETY: The fprobe is registered by the type invariant and stay valid as long as
+        // `self`. `flags` is an aligned `unsigned int`, which has the layout of an `AtomicU32`,
+        // and is only read by the fprobe handlers once registered
+        unsafe { AtomicU32::from_ptr(core::ptr::addr_of_mut!((*self.inner.get()).flags)) }
+    }
+
+    /// Get the number of hits missed by the fprobe (`struct fprobe::nmissed`)
+    pub fn nmissed(&self) -> u64 {
+        // SAFETY: The fprobe is registered by the type invariant, `nmissed` is only
+        // incremented by the kernel so we read a snapshot of it
+        unsafe { core::ptr::read_volatile(core::ptr::addr_of!((*self.inner.get()).nmissed)) as u64 }
+    }
+
+    /// Get the number of rethook nodes, 0 without an exit handler
+    pub fn nr_maxactive(&self) -> u32 {
+        // SAFETY: The fprobe is registered by the type invariant, `nr_maxactive` is only
+        // modified by `resize` which borrows `self` mutably
+        unsafe { (*self.inner.get()).nr_maxactive as u32 }
+    }
+
+    /// Replace the rethook of the fprobe by one of `nr_maxactive` nodes
+    ///
+    /// The fprobe stays registered. Like at unregistration, the exit handlers of the calls
+    /// in flight with a node of the old rethook are skipped
+    pub fn resize(self: Pin<&mut Self>, nr_maxactive: u32) -> Result {
+        if !T::HAS_EXIT_HANDLER {
+            return Err(EINVAL);
+        }
+        let num = core::ffi::c_int::try_from(nr_maxactive).map_err(|_| EINVAL)?;
+        let fp = self.inner.get();
+
+        // SAFETY: The fprobe is registered by the type invariant, with an exit handler its
+        // rethook is allocated at registration and only replaced here
+        let (old, entry_data_size) = unsafe { ((*fp).rethook, (*fp).entry_data_size) };
+        if old.is_null() {
+            return Err(EINVAL);
+        }
+        let size = core::ffi::c_int::try_from(
+            core::mem::size_of::<FprobeRethookNode>() + entry_data_size as usize,
+        )
+        .map_err(|_| EINVAL)?;
+
+        // SAFETY: `old` is the live rethook of the fprobe, the new one gets the same data
+        // and handler, as allocated by `fprobe_init_rethook`
+        let new = crate::error::from_err_ptr(unsafe {
+            bindings::rethook_alloc((*old).data, (*old).handler, size, num)
+        })?;
+
+        // SAFETY: The entry handler of the fprobe takes its node from `fp->rethook` under
+        // RCU, and `rethook_free` waits for an RCU grace period before freeing the old
+        // rethook. The nodes in flight hold a reference on their own rethook
+        unsafe {
+            core::ptr::write_volatile(core::ptr::addr_of_mut!((*fp).rethook), new);
+            (*fp).nr_maxactive = num;
+            bindings::rethook_free(old);
+        }
+        Ok(())
+    }
+
+    /// Get the statistics of the fprobe
+    pub fn stats(&self) -> FprobeStats {
+        FprobeStats {
+            entry_hits: self.entry_hits.sum(),
+            exit_hits: self.exit_hits.sum(),
+            missed: self.nmissed(),
+        }
+    }
+
+    /// Get the number of calls to the entry handler on each possible CPU
+    pub fn entry_hits_per_cpu(&self) -> Result<KVec<(u32, u64)>> {
+        self.entry_hits.per_cpu()
+    }
+
+    /// Get the number of calls to the exit handler on each possible CPU
+    pub fn exit_hits_per_cpu(&self) -> Result<KVec<(u32, u64)>> {
+        self.exit_hits.per_cpu()
+    }
+
+    /// Soft-disable the fprobe, the handlers are not called anymore until [`Self::enable`]
+    ///
+    /// Same as `disable_fprobe` but atomic, so it can be called from any context
+    pub fn disable(&self) {
+        self.flags()
+            .fetch_or(flags::FTRACE_FL_DISABLED, Ordering::Relaxed);
+    }
+
+    /// Enable back a fprobe disabled by [`Self::disable`]
+    ///
+    /// Same as `enable_fprobe` but atomic, so it can be called from any context
+    pub fn enable(&self) {
+        self.flags()
+            .fetch_and(!flags::FTRACE_FL_DISABLED, Ordering::Relaxed);
+    }
+
+    /// The fprobe is soft-disabled
+    pub fn is_disabled(&self) -> bool {
+        self.flags().load(Ordering::Relaxed) & flags::FTRACE_FL_DISABLED != 0
+    }
+
+    /// Update the filter of the ftrace_ops of the fprobe with an ftrace filter expression
+    fn set_filter(&self, expr: &[u8]) -> Result {
+        let len = i32::try_from(expr.len()).map_err(|_| EINVAL)?;
+        // `ftrace_set_filter` may modify the buffer while parsing it
+        let mut buf = KVec::with_capacity(expr.len(), GFP_KERNEL)?;
+        buf.extend_from_slice(expr, GFP_KERNEL)?;
+
+        // SAFETY: The fprobe is registered by the type invariant so its ops are valid, the
+        // filter hash is protected by the ftrace locks. `buf` is valid for `len` bytes
+        crate::error::to_result(unsafe {
+            bindings::ftrace_set_filter(
+                core::ptr::addr_of_mut!((*self.inner.get()).ops),
+                buf.as_mut_ptr(),
+                len,
+                0,
+            )
+        })
+    }
+
+    /// The fprobe is registered, it wasn't unregistered by [`Self::remove_filter`]
+    pub fn is_registered(&self) -> bool {
+        self.registered.load(Ordering::Relaxed)
+    }
+
+    /// The filter of the ftrace_ops of the fprobe is empty, which means every function
+    /// for ftrace
+    fn filter_is_empty(&self) -> bool {
+        let _guard = crate::sync::rcu::read_lock();
+        // SAFETY: The fprobe is registered, its ops are valid and `func_hash` points to
+        // their `local_hash`. A replaced filter hash is freed after an RCU grace period and
+        // starts like a `FtraceHashHead`
+        unsafe {
+            let hash_ptr = (*self.inner.get()).ops.func_hash;
+            let hash = core::ptr::read_volatile(core::ptr::addr_of!((*hash_ptr).filter_hash))
+                .cast::<FtraceHashHead>();
+            hash.is_null() || core::ptr::read_volatile(core::ptr::addr_of!((*hash).count)) == 0
+        }
+    }
+
+    /// Get the addresses of the functions of the filter of the ftrace_ops of the fprobe
+    fn filter_functions(&self) -> Result<KVec<u64>> {
+        let mut functions = KVec::new();
+        let _guard = crate::sync::rcu::read_lock();
+        // SAFETY: The fprobe is registered, its ops are valid and `func_hash` points to
+        // their `local_hash`. A replaced filter hash is freed after an RCU grace period and
+        // starts like a `FtraceHashHead`
+        let (size_bits, buckets) = unsafe {
+            let hash_ptr = (*self.inner.get()).ops.func_hash;
+            let hash = core::ptr::read_volatile(core::ptr::addr_of!((*hash_ptr).filter_hash))
+                .cast::<FtraceHashHead>();
+            if hash.is_null() {
+                return Ok(functions);
+            }
+            ((*hash).size_bits, (*hash).buckets)
+        };
+        if buckets.is_null() {
+            return Ok(functions);
+        }
+
+        for i in 0..(1usize << size_bits) {
+            // SAFETY: The hash has `1 << size_bits` buckets, valid under RCU, see above
+            let mut node = unsafe { (*buckets.add(i)).first };
+            while !node.is_null() {
+                // SAFETY: The nodes of the hash are the `hlist` field, the first one, of a
+                // `struct ftrace_func_entry`
+                let entry = node.cast::<bindings::ftrace_func_entry>();
+                // We are under RCU, we can't allocate with GFP_KERNEL
+                // SAFETY: `entry` is in the hash, see above
+                functions.push(unsafe { (*entry).ip } as u64, GFP_ATOMIC)?;
+                // SAFETY: `node` is in the hash, see above
+                node = unsafe { (*node).next };
+            }
+        }
+        Ok(functions)
+    }
+
+    /// Every function of the filter of the ftrace_ops of the fprobe matches `filter` (a
+    /// symbol or a glob), removing it would leave an empty filter
+    fn filter_matches_all(&self, filter: &CStr) -> Result<bool> {
+        let functions = self.filter_functions()?;
+        let mut buf = KBox::new(SymbolBuffer::new(), GFP_KERNEL)?;
+        let mut name = KVec::with_capacity(KSYM_NAME_LEN as usize + 1, GFP_KERNEL)?;
+        for function in functions {
+            let Some(symbol) = symbols_lookup_address_buf(function, &mut buf) else {
+                return Ok(false);
+            };
+            name.clear();
+            name.extend_from_slice(symbol.name, GFP_KERNEL)?;
+            name.push(0, GFP_KERNEL)?;
+            // SAFETY: Just an FFI call, both strings are null terminated
+            if !unsafe { bindings::glob_match(filter.as_char_ptr(), name.as_ptr().cast()) } {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Hook the functions matching `filter` (a symbol or a glob) in addition to the
+    /// current ones
+    ///
+    /// Fails with `ENOENT` once the fprobe was unregistered by [`Self::remove_filter`]
+    pub fn add_filter(&self, filter: &CStr) -> Result {
+        if !self.is_registered() {
+            return Err(ENOENT);
+        }
+        self.set_filter(filter.as_bytes())
+    }
+
+    /// Unhook the functions matching `filter` (a symbol or a glob)
+    ///
+    /// An empty filter means all the functions for ftrace: when `filter` matches all the
+    /// hooked functions the fprobe is unregistered instead, without touching its filter,
+    /// and it can't be hooked on anything again. The fprobe is soft-disabled during the
+    /// update of the filter, its previous state is then restored.
+    pub fn remove_filter(self: Pin<&mut Self>, filter: &CStr) -> Result {
+        if !self.is_registered() {
+            return Err(ENOENT);
+        }
+        if self.filter_matches_all(filter)? {
+            return self.unregister();
+        }
+
+        let mut expr = KVec::with_capacity(filter.len() + 1, GFP_KERNEL)?;
+        expr.push(b'!', GFP_KERNEL)?;
+        expr.extend_from_slice(filter.as_bytes(), GFP_KERNEL)?;
+
+        let was_disabled = self.is_disabled();
+        self.disable();
+        let ret = self.set_filter(&expr);
+        if ret.is_ok() && self.filter_is_empty() {
+            // ftrace matched more functions than `filter_matches_all`, the fprobe stays
+            // disabled if it can't be unregistered, it would run on every function
+            return self.unregister();
+        }
+        if !was_disabled {
+            self.enable();
+        }
+        ret
+    }
+
+    /// Unregister the fprobe, see [`Self::remove_filter`]
+    fn unregister(&self) -> Result {
+        let fp = self.inner.get();
+        // SAFETY: The fprobe is registered, checked by the caller, and the caller has the
+        // only access to it
+        crate::error::to_result(unsafe { bindings::unregister_fprobe(fp) })?;
+        // SAFETY: The rethook was freed by `unregister_fprobe`, `resize` must not use it
+        unsafe { (*fp).rethook = core::ptr::null_mut() };
+        // INVARIANT: The fprobe was just unregistered
+        self.registered.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
     /// # Safety
     ///     Will be called only from C, prototype correspond to the fprobe's callback prototype
     unsafe extern "C" fn entry_handler_callback(
@@ -137,6 +553,11 @@ pub fn new(
         regs: *mut pt_regs,
         entry_data: *mut core::ffi::c_void,
     ) -> core::ffi::c_int {
+        // SAFETY: `fp` is the `inner` field of a `Fprobe<T>`, which is alive while the
+        // fprobe is registered
+        let this = unsafe { &*container_of!(fp, Self, inner) };
+        this.entry_hits.inc();
+
         let mut entry_ref = None;
 
         let entry_data = entry_data as *mut T::EntryData;
@@ -158,14 +579,52 @@ pub fn new(
 
         // SAFETY: The pointer is created at the call of our callback so no need to chack for race
         // However writting to it has side effect so we set it to non mutable
-        let regs = unsafe { &*regs };
+        let regs = Registers::new(unsafe { &*regs });
 
-        match T::entry_handler(data, entry_ip as usize, ret_ip as usize, regs, entry_ref) {
+        // The condition is a constant, the probes without stacktrace don't pay for the frames
+        let ret = if T::STACKTRACE_DEPTH == 0 {
+            T::entry_handler(
+                data,
+                entry_ip as usize,
+                ret_ip as usize,
+                regs,
+                None,
+                entry_ref,
+            )
+        } else {
+            Self::entry_handler_with_stack(
+                data,
+                entry_ip as usize,
+                ret_ip as usize,
+                regs,
+                entry_ref,
+            )
+        };
+        match ret {
             Some(()) => 0,
             None => -1,
         }
     }
 
+    /// Capture the stacktrace of the call and call the entry handler with it
+    ///
+    /// Kept out of line so the frames are only on the stack of the probes capturing them, the
+    /// capture must not allocate in this context
+    #[inline(never)]
+    fn entry_handler_with_stack(
+        data: <T::Data as ForeignOwnable>::Borrowed<'_>,
+        entry_ip: usize,
+        ret_ip: usize,
+        regs: Registers<'_>,
+        entry_data: Option<&mut T::EntryData>,
+    ) -> Option<()> {
+        let mut frames = [0u64; MAX_PROBE_DEPTH];
+        let depth = T::STACKTRACE_DEPTH.min(MAX_PROBE_DEPTH);
+        let len = Stacktrace::save_regs_into(regs.as_raw(), &mut frames[..depth]);
+        let stack = ProbeStacktrace::new(&frames[..len]);
+        T::entry_handler(data, entry_ip, ret_ip, regs, Some(stack), entry_data)
+    }
+
     /// # Safety
     ///     Will be called only from C, prototype correspond to the fprobe's callback prototype
     unsafe extern "C" fn exit_handler_callback(
@@ -175,6 +634,11 @@ pub fn new(
         regs: *mut pt_regs,
         entry_data: *mut core::ffi::c_void,
     ) {
+        // SAFETY: `fp` is the `inner` field of a `Fprobe<T>`, which is alive while the
+        // fprobe is registered
+        let this = unsafe { &*container_of!(fp, Self, inner) };
+        this.exit_hits.inc();
+
         let mut entry_ref = None;
 
         let entry_data = entry_data as *mut T::EntryData;
@@ -190,7 +654,7 @@ pub fn new(
 
         // SAFETY: The pointer is created at the call of our callback so no need to chack for race
         // However writting to it has side effect so we set it to non mutable
-        let regs = unsafe { &*regs };
+        let regs = Registers::new(unsafe { &*regs });
 
         T::exit_handler(data, entry_ip as usize, ret_ip as usize, regs, entry_ref);
     }
@@ -219,9 +683,12 @@ unsafe fn register(
 #[pinned_drop]
 impl<T: FprobeOperations> PinnedDrop for Fprobe<T> {
     fn drop(self: Pin<&mut Self>) {
-        // SAFETY: WWe know the fprobe is registered by the type invariant
-        // The doc don't really specify why this call would fail so...
-        crate::error::to_result(unsafe { bindings::unregister_fprobe(self.inner.get()) }).unwrap();
+        if self.is_registered() {
+            // SAFETY: WWe know the fprobe is registered by the type invariant
+            // The doc don't really specify why this call would fail so...
+            crate::error::to_result(unsafe { bindings::unregister_fprobe(self.inner.get()) })
+                .unwrap();
+        }
         // SAFETY: We unregistered the fprobe so no one hold a Borrowed reference to the pointer
         // and we are in the Drop Impl so this is the first and last call to `from_foreign` corresponding exactly with
         // the call to `into_foreign`
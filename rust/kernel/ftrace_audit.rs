@@ -0,0 +1,492 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Ftrace audit : the registered ftrace_ops and the functions they hook
+//!
+//! Ftrace is the most convenient way for a rootkit to hook a kernel function : an
+//! `ftrace_ops` with `FTRACE_OPS_FL_IPMODIFY` can redirect the hooked function to its own
+//! code. Each ops of `ftrace_ops_list` is listed with its callback, attributed with
+//! [`resolve_address`], and the functions of its filter hash. The ops are flagged if :
+//! - the callback is not in the text of the kernel or of a module
+//! - the ops modify the instruction pointer and are not owned by the kernel image
+//!   (livepatch and the kprobes are the legitimate users)
+//!
+//! The list and the filter hashes are read under `ftrace_lock`, the addresses are
+//! resolved once it is released.
+//!
+//! The direct calls (`FTRACE_OPS_FL_DIRECT`) bypass the ops list : the patched function
+//! calls its trampoline directly. They are listed by [`DirectAudit`], which flags the
+//! trampolines in executable memory owned by nobody.
+//!
+//! C header: [`include/linux/ftrace.h`](../../../../include/linux/ftrace.h)
+
+use core::fmt;
+
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+use crate::address::RegionType;
+use crate::address::{resolve_address, Owner};
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::symbols_lookup_name;
+use crate::str::BStr;
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+use crate::sync::rcu;
+use crate::sync::StaticCMutexGuard;
+use kernel::prelude::*;
+
+/// Maximum number of hooked functions recorded per ops, the others are only counted
+pub const MAX_FUNCTIONS: usize = 256;
+
+/// Maximum number of ops walked, to bound the walk of a corrupted list
+const MAX_OPS: usize = 1024;
+
+/// Length of the callback symbol name, longer names are truncated
+pub const SYMBOL_LEN: usize = 64;
+
+/// Mirror of `struct ftrace_hash` (private to `kernel/trace/trace.h`)
+#[allow(dead_code)]
+#[repr(C)]
+struct FtraceHash {
+    size_bits: core::ffi::c_ulong,
+    buckets: *mut bindings::hlist_head,
+    count: core::ffi::c_ulong,
+    flags: core::ffi::c_ulong,
+    rcu: bindings::callback_head,
+}
+
+/// Reason why an ops is flagged
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Finding {
+    /// The callback is not in the text of the kernel or of a module
+    ForeignCallback,
+    /// The ops modify the instruction pointer and are not owned by the kernel image
+    IpModify,
+}
+
+/// A registered `struct ftrace_ops`
+pub struct AuditedOps {
+    /// Address of the `struct ftrace_ops`
+    pub object: u64,
+    /// Address of the callback
+    pub callback: u64,
+    /// `ftrace_ops::flags`
+    pub flags: u64,
+    /// Owner of the callback
+    pub owner: Owner,
+    /// Name of the callback symbol, null terminated, empty if not found
+    pub symbol: [u8; SYMBOL_LEN],
+    /// The first [`MAX_FUNCTIONS`] hooked functions
+    pub functions: KVec<u64>,
+    /// Number of hooked functions, 0 if the filter is empty (every function is hooked)
+    pub count: u64,
+    /// Why the ops are flagged, if they are
+    pub finding: Option<Finding>,
+}
+
+impl AuditedOps {
+    /// The filter hash is empty, the ops hook every traceable function
+    pub fn hooks_all(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The ops modify the instruction pointer of the hooked functions
+    pub fn is_ipmodify(&self) -> bool {
+        self.flags & bindings::FTRACE_OPS_FL_IPMODIFY as u64 != 0
+    }
+
+    /// Attribute the callback and flag the ops
+    fn resolve(&mut self) {
+        let Ok(info) = resolve_address(self.callback) else {
+            self.finding = Some(Finding::ForeignCallback);
+            return;
+        };
+
+        self.owner = info.owner;
+        let name = info.symbol_name();
+        let len = name.len().min(SYMBOL_LEN - 1);
+        self.symbol[..len].copy_from_slice(&name[..len]);
+
+        self.finding = if !info.is_attributed() || !info.is_text() {
+            Some(Finding::ForeignCallback)
+        } else if self.is_ipmodify() && info.owner != Owner::Kernel {
+            Some(Finding::IpModify)
+        } else {
+            None
+        };
+    }
+}
+
+/// Result of the audit
+pub struct FtraceAudit {
+    /// Every registered ops
+    pub ops: KVec<AuditedOps>,
+}
+
+/// Read the functions of the filter hash of an ops
+///
+/// # Safety
+///     `ops` must be a registered ftrace_ops and `ftrace_lock` must be held
+unsafe fn filter_functions(ops: *const bindings::ftrace_ops) -> Result<(KVec<u64>, u64)> {
+    let mut functions = KVec::new();
+
+    // SAFETY: By the safety contract `ops` is valid, `func_hash` is set at registration
+    let func_hash = unsafe { (*ops).func_hash };
+    if func_hash.is_null() {
+        return Ok((functions, 0));
+    }
+    // SAFETY: `func_hash` is valid while the ops are registered, the filter hash is only
+    // replaced under `ftrace_lock`
+    let hash = unsafe { (*func_hash).filter_hash }.cast::<FtraceHash>();
+    if hash.is_null() {
+        return Ok((functions, 0));
+    }
+
+    // SAFETY: `hash` is a valid `struct ftrace_hash`, see above
+    let (size_bits, buckets, count) =
+        unsafe { ((*hash).size_bits, (*hash).buckets, (*hash).count) };
+    if buckets.is_null() {
+        return Ok((functions, count as u64));
+    }
+
+    'buckets: for i in 0..(1usize << size_bits) {
+        // SAFETY: The hash has `1 << size_bits` buckets
+        let mut node = unsafe { (*buckets.add(i)).first };
+        while !node.is_null() {
+            if functions.len() >= MAX_FUNCTIONS {
+                break 'buckets;
+            }
+            // SAFETY: The nodes of the hash are the `hlist` field, the first one, of a
+            // `struct ftrace_func_entry`
+            let entry = node.cast::<bindings::ftrace_func_entry>();
+            // SAFETY: `entry` is in the hash, see above
+            functions.push(unsafe { (*entry).ip } as u64, GFP_KERNEL)?;
+            // SAFETY: `node` is in the hash, see above
+            node = unsafe { (*node).next };
+        }
+    }
+
+    Ok((functions, count as u64))
+}
+
+impl FtraceAudit {
+    /// Walk `ftrace_ops_list` under `ftrace_lock`
+    fn walk() -> Result<KVec<AuditedOps>> {
+        let head =
+            symbols_lookup_name(c_str!("ftrace_ops_list")) as *const *mut bindings::ftrace_ops;
+        let end = symbols_lookup_name(c_str!("ftrace_list_end")) as *mut bindings::ftrace_ops;
+        if head.is_null() || end.is_null() {
+            pr_err!("Couldn't find ftrace_ops_list symbol\n");
+            return Err(ENOENT);
+        }
+
+        let mut list = KVec::new();
+
+        let _guard = StaticCMutexGuard::lock(c_str!("ftrace_lock"))?;
+
+        // SAFETY: `head` is the address of `ftrace_ops_list` which is always a valid pointer
+        let mut ops = unsafe { core::ptr::read_volatile(head) };
+        while !ops.is_null() && ops != end {
+            if list.len() >= MAX_OPS {
+                pr_warn!("Too many ftrace_ops, the walk is truncated\n");
+                break;
+            }
+
+            // SAFETY: `ops` is on the list and we hold `ftrace_lock`, it can't be unregistered
+            let (func, flags, next) = unsafe { ((*ops).func, (*ops).flags, (*ops).next) };
+            // SAFETY: Same as above
+            let (functions, count) = unsafe { filter_functions(ops)? };
+
+            list.push(
+                AuditedOps {
+                    object: ops as u64,
+                    callback: func.map_or(0, |func| func as u64),
+                    flags: flags as u64,
+                    owner: Owner::None,
+                    symbol: [0; SYMBOL_LEN],
+                    functions,
+                    count,
+                    finding: None,
+                },
+                GFP_KERNEL,
+            )?;
+
+            ops = next;
+        }
+
+        Ok(list)
+    }
+
+    /// List the registered ftrace_ops and flag the suspicious ones
+    pub fn audit() -> Result<Self> {
+        let mut ops = Self::walk()?;
+        for ops in ops.iter_mut() {
+            ops.resolve();
+        }
+        Ok(FtraceAudit { ops })
+    }
+
+    /// Get the flagged ops
+    pub fn suspicious(&self) -> impl Iterator<Item = &AuditedOps> {
+        self.ops.iter().filter(|ops| ops.finding.is_some())
+    }
+
+    /// Create the event listing the flagged ops, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.suspicious().next().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousFtraceOps,
+            fmt!("suspicious ftrace_ops : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for FtraceAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, ops) in self.suspicious().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            let len = ops
+                .symbol
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(SYMBOL_LEN);
+            write!(
+                f,
+                "{:#x} ({} {:#x} [{:?}], {:?}, ",
+                ops.object,
+                BStr::from_bytes(&ops.symbol[..len]),
+                ops.callback,
+                ops.owner,
+                ops.finding
+            )?;
+            if ops.hooks_all() {
+                f.write_str("all functions)")?;
+            } else {
+                write!(f, "{} functions)", ops.count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prototype of `ftrace_find_rec_direct`
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+type FtraceFindRecDirect = unsafe extern "C" fn(ip: core::ffi::c_ulong) -> core::ffi::c_ulong;
+
+/// Resolve `ftrace_find_rec_direct`
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+fn find_rec_direct_fn() -> Result<FtraceFindRecDirect> {
+    let find_direct = symbols_lookup_name(c_str!("ftrace_find_rec_direct")) as *const ();
+    if find_direct.is_null() {
+        pr_err!("Couldn't find ftrace_find_rec_direct symbol\n");
+        return Err(ENOENT);
+    }
+    // SAFETY: The symbol is the function `ftrace_find_rec_direct` which has this prototype
+    Ok(unsafe { core::mem::transmute::<*const (), FtraceFindRecDirect>(find_direct) })
+}
+
+/// Get the direct trampoline registered for the patch site `ip`, if any
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+pub fn direct_trampoline(ip: u64) -> Result<Option<u64>> {
+    let find_direct = find_rec_direct_fn()?;
+    let _guard = rcu::read_lock();
+    // SAFETY: Just an FFI call, the direct hash is only freed after an RCU grace period
+    let trampoline = unsafe { find_direct(ip as _) };
+    Ok((trampoline != 0).then_some(trampoline as u64))
+}
+
+/// Get the direct trampoline registered for the patch site `ip`, there is none without
+/// the direct calls
+#[cfg(not(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS))]
+pub fn direct_trampoline(_ip: u64) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Mirror of `struct ftrace_page` (private to `kernel/trace/ftrace.c`)
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+#[repr(C)]
+struct FtracePage {
+    next: *mut FtracePage,
+    records: *mut bindings::dyn_ftrace,
+    index: core::ffi::c_int,
+    order: core::ffi::c_int,
+}
+
+/// A function calling a direct trampoline instead of the ftrace ops list
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+pub struct DirectCall {
+    /// Address of the patched function
+    pub ip: u64,
+    /// Name of the patched function, null terminated, empty if not found
+    pub function: [u8; SYMBOL_LEN],
+    /// Address of the trampoline called
+    pub trampoline: u64,
+    /// The `FTRACE_OPS_FL_DIRECT` ops which registered the trampoline, 0 if none is found
+    pub ops: u64,
+    /// Owner of the trampoline
+    pub owner: Owner,
+    /// Region of the trampoline
+    pub region: RegionType,
+    /// The trampoline is in executable memory owned by nobody, neither the kernel, a
+    /// module nor a BPF program
+    pub anonymous: bool,
+}
+
+/// Result of the audit of the direct calls
+///
+/// The direct trampolines are called straight from the patched functions, the ops list
+/// is never walked for them. The BPF trampolines (fentry, fexit, ...) are the legitimate
+/// users, they are in the BPF JIT space. The records flagged `FTRACE_FL_DIRECT` are read
+/// under `ftrace_lock` and their trampoline found with `ftrace_find_rec_direct`.
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+pub struct DirectAudit {
+    /// Every direct call
+    pub calls: KVec<DirectCall>,
+}
+
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+impl DirectAudit {
+    /// Walk the ftrace records and the ops list under `ftrace_lock`
+    ///
+    /// # Return
+    /// The patched functions with their trampoline, and the direct ops with their trampoline
+    fn walk() -> Result<(KVec<(u64, u64)>, KVec<(u64, u64)>)> {
+        let pages = symbols_lookup_name(c_str!("ftrace_pages_start")) as *const *mut FtracePage;
+        if pages.is_null() {
+            pr_err!("Couldn't find ftrace_pages_start symbol\n");
+            return Err(ENOENT);
+        }
+        let find_direct = find_rec_direct_fn()?;
+        let head =
+            symbols_lookup_name(c_str!("ftrace_ops_list")) as *const *mut bindings::ftrace_ops;
+        let end = symbols_lookup_name(c_str!("ftrace_list_end")) as *mut bindings::ftrace_ops;
+        if head.is_null() || end.is_null() {
+            pr_err!("Couldn't find ftrace_ops_list symbol\n");
+            return Err(ENOENT);
+        }
+
+        let mut records = KVec::new();
+        let mut direct_ops = KVec::new();
+
+        let _guard = StaticCMutexGuard::lock(c_str!("ftrace_lock"))?;
+
+        // SAFETY: `pages` is the address of `ftrace_pages_start`, the pages are only
+        // added and freed under `ftrace_lock`
+        let mut page = unsafe { core::ptr::read_volatile(pages) };
+        while !page.is_null() {
+            // SAFETY: `page` is on the list and we hold `ftrace_lock`
+            let (recs, index, next) = unsafe { ((*page).records, (*page).index, (*page).next) };
+            for i in 0..index.max(0) as usize {
+                // SAFETY: The page holds `index` records
+                let rec = unsafe { &*recs.add(i) };
+                if rec.flags & bindings::FTRACE_FL_DIRECT as core::ffi::c_ulong == 0 {
+                    continue;
+                }
+                // SAFETY: Just an FFI call, the direct hash is only modified under
+                // `ftrace_lock` (and `direct_mutex`)
+                let trampoline = unsafe { find_direct(rec.ip) };
+                records.push((rec.ip as u64, trampoline as u64), GFP_KERNEL)?;
+            }
+            page = next;
+        }
+
+        // SAFETY: `head` is the address of `ftrace_ops_list` which is always a valid pointer
+        let mut ops = unsafe { core::ptr::read_volatile(head) };
+        let mut walked = 0;
+        while !ops.is_null() && ops != end && walked < MAX_OPS {
+            // SAFETY: `ops` is on the list and we hold `ftrace_lock`, it can't be unregistered
+            let (flags, direct_call, next) =
+                unsafe { ((*ops).flags, (*ops).direct_call, (*ops).next) };
+            if flags & bindings::FTRACE_OPS_FL_DIRECT as core::ffi::c_ulong != 0 {
+                direct_ops.push((ops as u64, direct_call as u64), GFP_KERNEL)?;
+            }
+            ops = next;
+            walked += 1;
+        }
+
+        Ok((records, direct_ops))
+    }
+
+    /// List the direct calls and flag the trampolines in anonymous executable memory
+    pub fn audit() -> Result<Self> {
+        let (records, direct_ops) = Self::walk()?;
+
+        let mut calls = KVec::with_capacity(records.len(), GFP_KERNEL)?;
+        for (ip, trampoline) in records {
+            let mut function = [0u8; SYMBOL_LEN];
+            if let Ok(info) = resolve_address(ip) {
+                let name = info.symbol_name();
+                let len = name.len().min(SYMBOL_LEN - 1);
+                function[..len].copy_from_slice(&name[..len]);
+            }
+
+            let (owner, region) = match resolve_address(trampoline) {
+                Ok(info) => (info.owner, info.region),
+                Err(_) => (Owner::None, RegionType::Unknown),
+            };
+            let anonymous = owner == Owner::None && region != RegionType::BpfJit;
+
+            calls.push(
+                DirectCall {
+                    ip,
+                    function,
+                    trampoline,
+                    ops: direct_ops
+                        .iter()
+                        .find(|(_, direct_call)| *direct_call == trampoline)
+                        .map_or(0, |(ops, _)| *ops),
+                    owner,
+                    region,
+                    anonymous,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+
+        Ok(DirectAudit { calls })
+    }
+
+    /// Get the direct calls to anonymous memory
+    pub fn suspicious(&self) -> impl Iterator<Item = &DirectCall> {
+        self.calls.iter().filter(|call| call.anonymous)
+    }
+
+    /// Create the event listing the direct calls to anonymous memory, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.suspicious().next().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousDirectCall,
+            fmt!("direct trampolines in anonymous memory : {}", self),
+        )?))
+    }
+}
+
+#[cfg(CONFIG_DYNAMIC_FTRACE_WITH_DIRECT_CALLS)]
+impl fmt::Display for DirectAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, call) in self.suspicious().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            let len = call
+                .function
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(SYMBOL_LEN);
+            write!(
+                f,
+                "{} ({:#x}) -> {:#x} [{:?}], ops {:#x}",
+                BStr::from_bytes(&call.function[..len]),
+                call.ip,
+                call.trampoline,
+                call.region,
+                call.ops
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Hidden module scanner
+//!
+//! Rootkits unlink their `struct module` from the `modules` list so they are invisible
+//! to [`ModuleIter`], but their memory is still mapped in the module space. This scanner
+//! walks the module mapping area page by page, reports the mapped memory not owned by
+//! any listed module, and looks in it for structures looking like a `struct module`.
+//!
+//! The orphan ranges are not all malicious : BPF JIT images, kprobe instruction slots
+//! and ftrace trampolines are also allocated in the module space.
+
+use core::mem::offset_of;
+
+use crate::module::{
+    is_module_space, ModMemType, ModuleIter, MODULES_END, MODULES_VADDR, MODULE_NAME_LEN,
+};
+use crate::nofault;
+use crate::page::PAGE_SIZE;
+use crate::pgtable::{lookup_address, PageLevel, PgProtFlags, Pgtable};
+use crate::wx_audit::kernel_half;
+use kernel::prelude::*;
+
+/// `POISON_POINTER_DELTA` used by `list_del`
+const POISON_POINTER_DELTA: u64 = 0xdead_0000_0000_0000;
+
+/// Maximum sane size of a module text
+const MAX_TEXT_SIZE: u64 = 256 << 20;
+
+/// A finding of the hidden module scanner
+#[derive(Clone, Copy)]
+pub enum HiddenModuleFinding {
+    /// Something looking like a `struct module` outside of the listed modules
+    Module {
+        /// Address of the structure
+        address: u64,
+        /// Name of the module, null terminated
+        name: [u8; MODULE_NAME_LEN],
+    },
+    /// Mapped memory of the module space not owned by any listed module
+    OrphanRange {
+        /// Start of the range
+        start: u64,
+        /// End of the range (excluded)
+        end: u64,
+        /// The range is mapped executable
+        executable: bool,
+    },
+}
+
+/// The memory regions of the modules reachable from the list, sorted
+struct KnownRegions(KVec<(u64, u64)>);
+
+impl KnownRegions {
+    fn new() -> Result<Self> {
+        let mut regions = KVec::new();
+        for module in ModuleIter::try_new()? {
+            for region in module.regions() {
+                regions.push((region.base, region.base + region.size as u64), GFP_KERNEL)?;
+            }
+        }
+        regions.sort_unstable();
+        Ok(KnownRegions(regions))
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        let pos = self.0.partition_point(|(start, _)| *start <= addr);
+        match pos.checked_sub(1).and_then(|i| self.0.get(i)) {
+            Some((_, end)) => addr < *end,
+            None => false,
+        }
+    }
+}
+
+fn is_kernel_or_poison_ptr(ptr: u64) -> bool {
+    ptr % 8 == 0
+        && (ptr >= kernel_half() as u64 || ptr & 0xffff_0000_0000_0000 == POISON_POINTER_DELTA)
+}
+
+fn is_module_name(name: &[u8]) -> bool {
+    let len = match name.iter().position(|c| *c == 0) {
+        Some(len) if len > 0 => len,
+        _ => return false,
+    };
+    name[..len]
+        .iter()
+        .all(|c| c.is_ascii_alphanumeric() || *c == b'_' || *c == b'-')
+}
+
+/// Check if the structure at `addr` look like a `struct module`
+///
+/// `page` is the content of the page containing `addr`, read beforehand
+fn probe_struct_module(addr: u64, page: &[u8], page_off: usize) -> Option<[u8; MODULE_NAME_LEN]> {
+    // The cheap checks on the page buffer first
+    let name_off = page_off + offset_of!(bindings::module, name);
+    let name = page.get(name_off..name_off + MODULE_NAME_LEN)?;
+    if !is_module_name(name) {
+        return None;
+    }
+
+    let state_off = page_off + offset_of!(bindings::module, state);
+    let state = u32::from_ne_bytes(page.get(state_off..state_off + 4)?.try_into().ok()?);
+    if state > bindings::module_state_MODULE_STATE_UNFORMED {
+        return None;
+    }
+
+    // Then the fields which may be on the next page
+    let list = addr as usize + offset_of!(bindings::module, list);
+    let next: u64 = nofault::read(list).ok()?;
+    let prev: u64 = nofault::read(list + 8).ok()?;
+    if !is_kernel_or_poison_ptr(next) || !is_kernel_or_poison_ptr(prev) {
+        return None;
+    }
+
+    let text = addr as usize
+        + offset_of!(bindings::module, mem)
+        + ModMemType::Text as usize * core::mem::size_of::<bindings::module_memory>();
+    let base: u64 = nofault::read(text + offset_of!(bindings::module_memory, base)).ok()?;
+    let size: u32 = nofault::read(text + offset_of!(bindings::module_memory, size)).ok()?;
+    if !is_module_space(base) || size == 0 || size as u64 > MAX_TEXT_SIZE {
+        return None;
+    }
+
+    let mut out = [0u8; MODULE_NAME_LEN];
+    out.copy_from_slice(name);
+    Some(out)
+}
+
+/// Scan the module space for memory and `struct module` not reachable from the module list
+pub fn scan() -> Result<KVec<HiddenModuleFinding>> {
+    let known = KnownRegions::new()?;
+    let mut findings = KVec::new();
+    let mut page = KBox::new([0u8; PAGE_SIZE], GFP_KERNEL)?;
+    let mut orphan: Option<(u64, u64, bool)> = None;
+
+    let mut addr = MODULES_VADDR;
+    while addr < MODULES_END {
+        let (step, executable) = match lookup_address(addr as usize) {
+            Ok(level) => {
+                let size = match level {
+                    PageLevel::Pte(_) => PAGE_SIZE as u64,
+                    _ => (PAGE_SIZE as u64) << level.order(),
+                };
+                // Go to the end of the mapping, `addr` may be in the middle of a large page
+                let step = (addr & !(size - 1)) + size - addr;
+                (step, PgProtFlags::new(level.pgprot()).is_executable())
+            }
+            Err(_) => {
+                addr += PAGE_SIZE as u64;
+                continue;
+            }
+        };
+
+        let mut page_addr = addr;
+        while page_addr < addr + step {
+            if known.contains(page_addr)
+                || nofault::copy(page_addr as usize, &mut page[..]).is_err()
+            {
+                if let Some((start, end, executable)) = orphan.take() {
+                    findings.push(
+                        HiddenModuleFinding::OrphanRange {
+                            start,
+                            end,
+                            executable,
+                        },
+                        GFP_KERNEL,
+                    )?;
+                }
+                page_addr += PAGE_SIZE as u64;
+                continue;
+            }
+
+            orphan = match orphan {
+                Some((start, end, exec)) if end == page_addr && exec == executable => {
+                    Some((start, end + PAGE_SIZE as u64, exec))
+                }
+                Some((start, end, exec)) => {
+                    findings.push(
+                        HiddenModuleFinding::OrphanRange {
+                            start,
+                            end,
+                            executable: exec,
+                        },
+                        GFP_KERNEL,
+                    )?;
+                    Some((page_addr, page_addr + PAGE_SIZE as u64, executable))
+                }
+                None => Some((page_addr, page_addr + PAGE_SIZE as u64, executable)),
+            };
+
+            // A `struct module` is in the data of the module, never in the text
+            if !executable {
+                for off in (0..PAGE_SIZE).step_by(8) {
+                    if let Some(name) = probe_struct_module(page_addr + off as u64, &page[..], off)
+                    {
+                        findings.push(
+                            HiddenModuleFinding::Module {
+                                address: page_addr + off as u64,
+                                name,
+                            },
+                            GFP_KERNEL,
+                        )?;
+                    }
+                }
+            }
+
+            page_addr += PAGE_SIZE as u64;
+        }
+
+        addr += step;
+    }
+
+    if let Some((start, end, executable)) = orphan {
+        findings.push(
+            HiddenModuleFinding::OrphanRange {
+                start,
+                end,
+                executable,
+            },
+            GFP_KERNEL,
+        )?;
+    }
+
+    Ok(findings)
+}
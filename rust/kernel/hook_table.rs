@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Hook table : snapshot of the current hooking state of the kernel
+//!
+//! Enumerate the registered ftrace_ops and kprobes with their attribution and
+//! expose them as a table that userspace can query at any moment. This way an agent
+//! connecting late can reconcile the current state without the change events it missed.
+//!
+//! C headers: [`include/linux/ftrace.h`](../../../../include/linux/ftrace.h),
+//! [`include/linux/kprobes.h`](../../../../include/linux/kprobes.h)
+
+use core::mem::offset_of;
+
+use crate::address::resolve_address;
+use crate::c_str;
+use crate::module::{symbols_lookup_name, MODULE_NAME_LEN};
+use crate::sync::rcu;
+use crate::transmute::AsBytes;
+use crate::uaccess::UserSliceWriter;
+use kernel::prelude::*;
+
+/// Size of the kprobe hash table, `KPROBE_TABLE_SIZE` in `kernel/kprobes.c`
+const KPROBE_TABLE_SIZE: usize = 1 << 6;
+
+/// Length of the owner name
+pub const OWNER_LEN: usize = MODULE_NAME_LEN;
+
+/// Length of the handler symbol name stored in an entry, longer names are truncated
+pub const SYMBOL_LEN: usize = 64;
+
+/// Kind of hook
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum HookKind {
+    /// A `struct ftrace_ops` on `ftrace_ops_list`
+    Ftrace = 0,
+    /// A `struct kprobe` on `kprobe_table`
+    Kprobe = 1,
+    /// A `struct kretprobe` (its kprobe is on `kprobe_table`)
+    Kretprobe = 2,
+}
+
+/// Raw information on a hook, as found while walking the kernel structures
+#[derive(Clone, Copy)]
+pub struct RawHook {
+    /// Kind of hook
+    pub kind: HookKind,
+    /// Address of the kernel object (`struct ftrace_ops`, `struct kprobe`)
+    pub object: u64,
+    /// Hooked address, 0 for the ftrace_ops as they can filter many functions
+    pub target: u64,
+    /// Address of the callback
+    pub handler: u64,
+    /// `ftrace_ops::flags` or `kprobe::flags`
+    pub flags: u64,
+}
+
+/// An entry of the table as exposed to userspace
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HookEntry {
+    /// The [`HookKind`]
+    pub kind: u32,
+    _pad: u32,
+    /// See [`RawHook::flags`]
+    pub flags: u64,
+    /// See [`RawHook::object`]
+    pub object: u64,
+    /// See [`RawHook::target`]
+    pub target: u64,
+    /// See [`RawHook::handler`]
+    pub handler: u64,
+    /// Name of the module owning the handler, empty if it's the kernel or unknown memory
+    pub owner: [u8; OWNER_LEN],
+    /// Name of the handler symbol, empty if not found
+    pub symbol: [u8; SYMBOL_LEN],
+}
+
+// SAFETY: `HookEntry` is `repr(C)`, only contains integers and has explicit padding
+unsafe impl AsBytes for HookEntry {}
+
+/// Header written before the entries
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HookTableHeader {
+    /// Number of entries in the kernel table
+    pub total: u32,
+    /// Number of entries written after this header (bounded by the user buffer)
+    pub count: u32,
+}
+
+// SAFETY: `HookTableHeader` is `repr(C)` and only contains integers
+unsafe impl AsBytes for HookTableHeader {}
+
+/// Copy the start of `src` to `dst` always keeping a null terminator
+fn copy_name(dst: &mut [u8], src: &[u8]) {
+    let len = src
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(src.len())
+        .min(dst.len() - 1);
+    dst[..len].copy_from_slice(&src[..len]);
+}
+
+impl HookEntry {
+    /// Create an entry, attributing the handler to its owner
+    pub fn new(raw: &RawHook) -> Self {
+        let mut entry = HookEntry {
+            kind: raw.kind as u32,
+            _pad: 0,
+            flags: raw.flags,
+            object: raw.object,
+            target: raw.target,
+            handler: raw.handler,
+            owner: [0; OWNER_LEN],
+            symbol: [0; SYMBOL_LEN],
+        };
+
+        if let Ok(info) = resolve_address(raw.handler) {
+            copy_name(&mut entry.owner, info.owner_name());
+            copy_name(&mut entry.symbol, info.symbol_name());
+        }
+
+        entry
+    }
+}
+
+/// Walk the `ftrace_ops_list`
+///
+/// Called with the RCU read lock held, so `f` must not sleep.
+/// Strictly speaking we should hold `ftrace_lock` but the list is RCU-safe for readers.
+pub fn for_each_ftrace_ops(mut f: impl FnMut(&RawHook) -> Result) -> Result {
+    let head = symbols_lookup_name(c_str!("ftrace_ops_list")) as *const *mut bindings::ftrace_ops;
+    let end = symbols_lookup_name(c_str!("ftrace_list_end")) as *mut bindings::ftrace_ops;
+    if head.is_null() || end.is_null() {
+        pr_err!("Couldn't find ftrace_ops_list symbol\n");
+        return Err(ENOENT);
+    }
+
+    let _guard = rcu::read_lock();
+
+    // SAFETY: `head` is the address of `ftrace_ops_list` which is always a valid pointer
+    let mut ops = unsafe { core::ptr::read_volatile(head) };
+    while !ops.is_null() && ops != end {
+        // SAFETY: `ops` is on the list and we hold the RCU read lock so it's not freed
+        let (func, flags, next) = unsafe { ((*ops).func, (*ops).flags, (*ops).next) };
+
+        f(&RawHook {
+            kind: HookKind::Ftrace,
+            object: ops as u64,
+            target: 0,
+            handler: func.map_or(0, |func| func as u64),
+            flags: flags as u64,
+        })?;
+
+        ops = next;
+    }
+
+    Ok(())
+}
+
+/// Walk the `kprobe_table`, aggregated probes are reported as their children
+///
+/// Called with the RCU read lock held, so `f` must not sleep.
+pub fn for_each_kprobe(mut f: impl FnMut(&RawHook) -> Result) -> Result {
+    let table = symbols_lookup_name(c_str!("kprobe_table")) as *const bindings::hlist_head;
+    if table.is_null() {
+        pr_err!("Couldn't find kprobe_table symbol\n");
+        return Err(ENOENT);
+    }
+    let aggr_pre_handler = symbols_lookup_name(c_str!("aggr_pre_handler"));
+    let pre_handler_kretprobe = symbols_lookup_name(c_str!("pre_handler_kretprobe"));
+
+    let mut report = |kp: *const bindings::kprobe| -> Result {
+        // SAFETY: `kp` is on the kprobe table and we hold the RCU read lock
+        let (addr, pre, flags) = unsafe { ((*kp).addr, (*kp).pre_handler, (*kp).flags) };
+        let pre = pre.map_or(0, |pre| pre as u64);
+
+        let (kind, handler) = if pre != 0 && pre == pre_handler_kretprobe {
+            // SAFETY: The `pre_handler` is the kretprobe one so the kprobe is embedded in a `kretprobe`
+            let rp = unsafe { crate::container_of!(kp, bindings::kretprobe, kp) };
+            // SAFETY: `rp` is valid as `kp` is
+            let handler = unsafe { (*rp).handler };
+            (HookKind::Kretprobe, handler.map_or(0, |h| h as u64))
+        } else {
+            (HookKind::Kprobe, pre)
+        };
+
+        f(&RawHook {
+            kind,
+            object: kp as u64,
+            target: addr as u64,
+            handler,
+            flags: flags as u64,
+        })
+    };
+
+    let _guard = rcu::read_lock();
+
+    for i in 0..KPROBE_TABLE_SIZE {
+        // SAFETY: `i < KPROBE_TABLE_SIZE` so we are in bounds of the table
+        let mut node = unsafe { (*table.add(i)).first };
+        while !node.is_null() {
+            let kp = node
+                .cast::<u8>()
+                .wrapping_sub(offset_of!(bindings::kprobe, hlist))
+                .cast::<bindings::kprobe>();
+
+            // SAFETY: `kp` is on the table and we hold the RCU read lock
+            let pre = unsafe { (*kp).pre_handler }.map_or(0, |pre| pre as u64);
+            if aggr_pre_handler != 0 && pre == aggr_pre_handler {
+                // An aggregated probe, the real probes are on its `list`
+                // SAFETY: `kp` is valid
+                let head = unsafe { core::ptr::addr_of!((*kp).list) };
+                // SAFETY: `head` is a valid list_head
+                let mut child = unsafe { (*head).next };
+                while !child.is_null() && child as *const _ != head {
+                    let child_kp = child
+                        .cast::<u8>()
+                        .wrapping_sub(offset_of!(bindings::kprobe, list))
+                        .cast::<bindings::kprobe>();
+                    report(child_kp)?;
+                    // SAFETY: `child` is on the list protected by RCU
+                    child = unsafe { (*child).next };
+                }
+            } else {
+                report(kp)?;
+            }
+
+            // SAFETY: `node` is on the list protected by RCU
+            node = unsafe { (*node).next };
+        }
+    }
+
+    Ok(())
+}
+
+/// Snapshot of the hooks currently registered in the kernel
+pub struct HookTable {
+    entries: KVec<HookEntry>,
+}
+
+impl HookTable {
+    /// Enumerate all the ftrace_ops and kprobes and attribute them
+    pub fn snapshot() -> Result<Self> {
+        // The walkers run under RCU so we can't allocate with GFP_KERNEL inside
+        let mut raw = KVec::new();
+        for_each_ftrace_ops(|hook| Ok(raw.push(*hook, GFP_ATOMIC)?))?;
+        for_each_kprobe(|hook| Ok(raw.push(*hook, GFP_ATOMIC)?))?;
+
+        let mut entries = KVec::with_capacity(raw.len(), GFP_KERNEL)?;
+        for hook in raw.iter() {
+            entries.push(HookEntry::new(hook), GFP_KERNEL)?;
+        }
+
+        Ok(HookTable { entries })
+    }
+
+    /// The entries of the table
+    pub fn entries(&self) -> &[HookEntry] {
+        &self.entries
+    }
+
+    /// Write the table to userspace : a [`HookTableHeader`] followed by as many entries
+    /// as the buffer can hold
+    ///
+    /// # Return
+    /// The number of entries written
+    pub fn write_to_user(&self, mut writer: UserSliceWriter) -> Result<usize> {
+        let header_size = core::mem::size_of::<HookTableHeader>();
+        let entry_size = core::mem::size_of::<HookEntry>();
+        if writer.len() < header_size {
+            return Err(EINVAL);
+        }
+
+        let count = ((writer.len() - header_size) / entry_size).min(self.entries.len());
+        writer.write(&HookTableHeader {
+            total: self.entries.len() as u32,
+            count: count as u32,
+        })?;
+
+        for entry in &self.entries[..count] {
+            writer.write(entry)?;
+        }
+
+        Ok(count)
+    }
+}
@@ -1,109 +1,232 @@
+// SPDX-License-Identifier: GPL-2.0
+
 //! INSN : In kernel decompiler
 //!
-//! C header : [`arch/x86/include/asm/insn.h`](../../../../arch/x86/include/asm/insn.h)
+//! The decoding is done by the backend of the architecture, its [`Insn`] implements
+//! [`Instruction`] so the analyses of the control flow (like the recognition of the inline
+//! hooks) don't depend on the architecture:
+//! - x86_64 : wrapper of the kernel's x86 instruction decoder
+//! - arm64 : decoder of the branches, of the literal loads and of the ftrace patch site
+//!
+//! C header : [`arch/x86/include/asm/insn.h`](../../../../arch/x86/include/asm/insn.h) and
+//! [`arch/arm64/include/asm/insn.h`](../../../../arch/arm64/include/asm/insn.h)
 
-use core::{ffi::c_void, mem::MaybeUninit};
+use core::fmt;
 use kernel::prelude::*;
 
-/// Represent the kernel's `struct insn` structure
-/// Represent the decompiled of an instruction
-pub struct Insn(bindings::insn);
+use crate::module::{symbols_lookup_name, symbols_lookup_size_offset};
+use crate::nofault;
+
+#[cfg(target_arch = "aarch64")]
+pub mod arm64;
+#[cfg(target_arch = "x86_64")]
+pub mod x86;
+
+#[cfg(target_arch = "aarch64")]
+pub use arm64::{register_name, Insn};
+#[cfg(target_arch = "x86_64")]
+pub use x86::{register_name, Insn, MemBase, MemOperand, RmOperand};
+
+/// Kind of a branch
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BranchKind {
+    /// Unconditional jump
+    Jump,
+    /// Conditional jump
+    ConditionalJump,
+    /// Call, the execution continues after it when the callee returns
+    Call,
+    /// Return
+    Return,
+}
 
-fn inat_has_immediate(attr: bindings::insn_attr_t) -> u32 {
-    attr & bindings::INAT_IMM_MASK
+/// A branch done by an instruction
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Branch {
+    /// Kind of the branch
+    pub kind: BranchKind,
+    /// The destination, `None` if it is held by a register (or a pointer which can't be
+    /// read) or for a return
+    pub target: Option<u64>,
 }
 
-fn inat_immediate_size(attr: bindings::insn_attr_t) -> u32 {
-    (attr & bindings::INAT_IMM_MASK) >> bindings::INAT_IMM_OFFS
+/// Class of an instruction, the mnemonic shown in the reports
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsnClass {
+    /// A branch
+    Branch(BranchKind),
+    /// A nop, of any length
+    Nop,
+    /// A trap (`int3`, `ud2`, `hlt`, `brk`)
+    Trap,
+    /// The landing pad of the indirect branches (`endbr64`, `bti`)
+    LandingPad,
+    /// A push on the stack
+    Push,
+    /// A pop from the stack
+    Pop,
+    /// A move between registers or memory, or of an immediate
+    Move,
+    /// An address computation (`lea`)
+    Lea,
+    /// A load from a literal pool
+    Load,
+    /// Any other instruction
+    Other,
 }
 
-impl Insn {
-    /// Create a new `struct insn` structure and initialize it.
-    /// Only one instruction is analyzed.
-    /// So buffer longer than 15 bytes will only be analyzed on the 15's first bytes.
-    pub fn new(buffer: &[u8]) -> Self {
-        let mut insn = MaybeUninit::<bindings::insn>::uninit();
-
-        // SAFETY: Just an FFI call.
-        // The buffer len is respected.
-        unsafe {
-            bindings::insn_init(
-                insn.as_mut_ptr(),
-                buffer as *const [u8] as *const c_void,
-                buffer.len() as i32,
-                1,
-            );
-        }
+impl fmt::Display for InsnClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InsnClass::Branch(BranchKind::Jump) => "jmp",
+            InsnClass::Branch(BranchKind::ConditionalJump) => "jcc",
+            InsnClass::Branch(BranchKind::Call) => "call",
+            InsnClass::Branch(BranchKind::Return) => "ret",
+            InsnClass::Nop => "nop",
+            InsnClass::Trap => "trap",
+            InsnClass::LandingPad => "landing",
+            InsnClass::Push => "push",
+            InsnClass::Pop => "pop",
+            InsnClass::Move => "mov",
+            InsnClass::Lea => "lea",
+            InsnClass::Load => "ldr",
+            InsnClass::Other => "insn",
+        })
+    }
+}
 
-        // SAFETY: According to the insn_init's API, insn is now initialized
-        let insn = unsafe { insn.assume_init() };
+/// An instruction decoded by the backend of the architecture
+///
+/// The decoding can be lazy, the instruction then borrows the buffer it is decoded from for
+/// `'a`.
+pub trait Instruction<'a>: Sized {
+    /// The operands of an instruction, as shown in the reports
+    type Operands: fmt::Display;
 
-        // insn is valid and a well initialized opcode
-        Insn(insn)
-    }
+    /// Start the decoding of the instruction at the start of `buffer`
+    fn new(buffer: &'a [u8]) -> Self;
 
-    /// Get the length of the parsed instruction
-    pub fn get_length(&mut self) -> Result<u32> {
-        // SAFETY: By the type invariant, we know that `self.0` is valid.
-        let ret = unsafe { bindings::insn_get_length(&mut self.0 as _) };
+    /// Get the length of the instruction, an error if it can't be decoded
+    fn length(&mut self) -> Result<usize>;
 
-        crate::error::to_result(ret)?;
+    /// Get the class of the instruction
+    fn class(&mut self) -> Result<InsnClass>;
 
-        Ok(self.0.length as _)
-    }
+    /// Get the operands of the instruction, the destination of a branch excluded, `None` if
+    /// there is nothing to show
+    fn operands(&mut self) -> Result<Option<Self::Operands>>;
 
-    /// Get the opcode of the parsed instruction
-    pub fn get_opcode(&mut self) -> Result<i32> {
-        // SAFETY: By the type invariant, we know that `self.0` is valid.
-        let ret = unsafe { bindings::insn_get_opcode(&mut self.0 as _) };
+    /// Get the branch done by the instruction at `address`, `None` if it isn't a branch
+    fn branch(&mut self, address: u64) -> Result<Option<Branch>>;
+}
 
-        crate::error::to_result(ret)?;
+/// Iterator over the instructions of a buffer
+///
+/// Yields each instruction with its offset in the buffer, until the end of the buffer or
+/// the first instruction which can't be decoded (an error is then yielded and the iteration
+/// stops). An instruction truncated by the end of the buffer is an error.
+pub struct InsnIter<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
 
-        // SAFETY: By the type invariant, we know that `self.0` is valid.
-        // We just initialized the opcode field and we know it didn't failed.
-        let opcode = unsafe { self.0.opcode.__bindgen_anon_1.value };
-        Ok(opcode)
+impl<'a> InsnIter<'a> {
+    /// Iterate over the instructions of `buffer`
+    pub fn new(buffer: &'a [u8]) -> Self {
+        InsnIter {
+            buffer,
+            offset: 0,
+            failed: false,
+        }
     }
 
-    /// Get the immediate of the parsed instruction
-    pub fn get_immediate(&mut self) -> Result<Option<(u64, u8)>> {
-        // SAFETY: By the type invariant, we know that `self.0` is valid.
-        // So this is just an FFI call
-        let ret = unsafe { bindings::insn_get_immediate(&mut self.0 as _) };
+    /// Get the offset of the next instruction
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
 
-        crate::error::to_result(ret)?;
+impl<'a> Iterator for InsnIter<'a> {
+    type Item = Result<(usize, Insn<'a>)>;
 
-        if inat_has_immediate(self.0.attr) == 0 {
-            return Ok(None);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.offset >= self.buffer.len() {
+            return None;
         }
 
-        // SAFETY: By the type invariant, we know that `self.0` is valid
-        match inat_immediate_size(self.0.attr) {
-            // SAFETY: By the type invariant, we know that `self.0` is valid.
-            // According to the function call we access the good union field.
-            bindings::INAT_IMM_BYTE | bindings::INAT_IMM_WORD | bindings::INAT_IMM_DWORD => unsafe {
-                Ok(Some((
-                    self.0.__bindgen_anon_1.immediate.__bindgen_anon_1.value as u32 as u64,
-                    self.0.__bindgen_anon_1.immediate.nbytes,
-                )))
-            },
-            // SAFETY: By the type invariant, we know that `self.0` is valid.
-            // According to the function call we access the good union field.
-            bindings::INAT_IMM_QWORD => unsafe {
-                let lb: u64 =
-                    self.0.__bindgen_anon_1.immediate1.__bindgen_anon_1.value as u32 as u64;
-                let hb: u64 =
-                    self.0.__bindgen_anon_2.immediate2.__bindgen_anon_1.value as u32 as u64;
-                Ok(Some((
-                    hb << 32 | lb,
-                    self.0.__bindgen_anon_1.immediate.nbytes,
-                )))
-            },
-
-            _ => {
-                pr_err!("Unknow immediate size, skipping");
-                Ok(None)
+        let mut insn = <Insn<'a> as Instruction<'a>>::new(&self.buffer[self.offset..]);
+        match insn.length() {
+            Ok(length) if length != 0 => {
+                let offset = self.offset;
+                self.offset += length;
+                Some(Ok((offset, insn)))
+            }
+            Ok(_) => {
+                self.failed = true;
+                Some(Err(EINVAL))
+            }
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
             }
         }
     }
 }
+
+/// Maximum size of a function read by [`FunctionCode`]
+const MAX_FUNCTION_SIZE: usize = 64 * 1024;
+
+/// A copy of the code of a function, its bounds resolved with kallsyms
+pub struct FunctionCode {
+    address: u64,
+    code: KVec<u8>,
+}
+
+impl FunctionCode {
+    /// Copy the code of the function containing `address`
+    pub fn read(address: u64) -> Result<Self> {
+        let (size, offset) = symbols_lookup_size_offset(address);
+        if size == 0 {
+            return Err(ENOENT);
+        }
+        Self::read_range(address - offset as u64, size)
+    }
+
+    /// Copy the `size` bytes of code at `address`, for a function whose bounds are already
+    /// known
+    pub fn read_range(address: u64, size: usize) -> Result<Self> {
+        if size > MAX_FUNCTION_SIZE {
+            return Err(E2BIG);
+        }
+
+        let mut code = KVec::from_elem(0u8, size, GFP_KERNEL)?;
+        nofault::copy(address as usize, &mut code)?;
+        Ok(FunctionCode { address, code })
+    }
+
+    /// Copy the code of the function `name`
+    pub fn lookup(name: &CStr) -> Result<Self> {
+        let address = symbols_lookup_name(name);
+        if address == 0 {
+            pr_err!("Couldn't find {:?} symbol\n", name);
+            return Err(ENOENT);
+        }
+        Self::read(address)
+    }
+
+    /// Get the address of the function
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Get the code of the function
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Iterate over the instructions of the function, see [`InsnIter`]
+    pub fn iter(&self) -> InsnIter<'_> {
+        InsnIter::new(&self.code)
+    }
+}
@@ -0,0 +1,346 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! arm64 backend : decoder of the branches and of the ftrace patch site
+//!
+//! The instructions are 4 bytes long and aligned, only the ones needed to follow the
+//! control flow of a function are decoded: the branches, the literal loads feeding an
+//! indirect branch and the instructions of the ftrace patch site.
+//!
+//! With `-fpatchable-function-entry=2` a traceable function starts (after its `bti c`)
+//! with two nops, replaced by `mov x9, x30 ; bl <ftrace trampoline>` when it is traced.
+//!
+//! C header : [`arch/arm64/include/asm/insn.h`](../../../../../arch/arm64/include/asm/insn.h)
+
+use core::{fmt, marker::PhantomData};
+use kernel::prelude::*;
+
+use super::{Branch, BranchKind, InsnClass, Instruction};
+use crate::nofault;
+
+/// Size of an instruction
+pub const INSN_SIZE: usize = 4;
+
+/// `nop`
+const NOP: u32 = 0xd503_201f;
+
+/// `mov x9, x30`, saving the return address at the ftrace patch site
+const MOV_X9_X30: u32 = 0xaa1e_03e9;
+
+/// Names of the general purpose registers, in the order of their encoding
+const REGISTER_NAMES: [&str; 32] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+    "x28", "x29", "x30", "xzr",
+];
+
+/// Get the name of the general purpose register numbered `reg` in the encoding
+pub fn register_name(reg: u8) -> &'static str {
+    REGISTER_NAMES.get(reg as usize).copied().unwrap_or("?")
+}
+
+/// Sign extend the `bits` low bits of `value`, scaled by the instruction size
+fn branch_offset(value: u32, bits: u32) -> i64 {
+    let shift = 32 - bits;
+    (((value << shift) as i32) >> shift) as i64 * INSN_SIZE as i64
+}
+
+/// A decoded instruction
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Opcode {
+    /// `b label`
+    B {
+        /// Offset of the destination from the instruction
+        offset: i64,
+    },
+    /// `bl label`
+    Bl {
+        /// Offset of the destination from the instruction
+        offset: i64,
+    },
+    /// `b.cond`, `cbz`, `cbnz`, `tbz` and `tbnz`
+    BCond {
+        /// Offset of the destination from the instruction
+        offset: i64,
+    },
+    /// `br xn`
+    Br {
+        /// The register holding the destination
+        register: u8,
+    },
+    /// `blr xn`
+    Blr {
+        /// The register holding the destination
+        register: u8,
+    },
+    /// `ret xn`
+    Ret {
+        /// The register holding the return address, `x30` by default
+        register: u8,
+    },
+    /// `ldr xt, label` or `ldr wt, label`
+    LdrLiteral {
+        /// The loaded register
+        register: u8,
+        /// Offset of the literal from the instruction
+        offset: i64,
+        /// Size of the literal, 4 or 8 bytes
+        size: u8,
+    },
+    /// `brk #immediate`, used by `BUG()`, `WARN()` and the kprobes
+    Brk {
+        /// The immediate, identifying the handler
+        immediate: u16,
+    },
+    /// `bti`, the landing pad of the indirect branches
+    Bti,
+    /// `nop`
+    Nop,
+    /// `mov x9, x30`
+    MovX9X30,
+    /// Any other instruction
+    Other,
+}
+
+impl Opcode {
+    /// Decode the instruction `word`
+    pub fn decode(word: u32) -> Self {
+        match word {
+            NOP => Opcode::Nop,
+            MOV_X9_X30 => Opcode::MovX9X30,
+            // `hint #32` to `hint #38`, the targets of bti are in bits 6 and 7
+            _ if word & 0xffff_ff3f == 0xd503_241f => Opcode::Bti,
+            _ if word & 0xfc00_0000 == 0x1400_0000 => Opcode::B {
+                offset: branch_offset(word, 26),
+            },
+            _ if word & 0xfc00_0000 == 0x9400_0000 => Opcode::Bl {
+                offset: branch_offset(word, 26),
+            },
+            _ if word & 0xff00_0010 == 0x5400_0000 || word & 0x7e00_0000 == 0x3400_0000 => {
+                Opcode::BCond {
+                    offset: branch_offset(word >> 5, 19),
+                }
+            }
+            _ if word & 0x7e00_0000 == 0x3600_0000 => Opcode::BCond {
+                offset: branch_offset(word >> 5, 14),
+            },
+            _ if word & 0xffff_fc1f == 0xd61f_0000 => Opcode::Br {
+                register: ((word >> 5) & 0x1f) as u8,
+            },
+            _ if word & 0xffff_fc1f == 0xd63f_0000 => Opcode::Blr {
+                register: ((word >> 5) & 0x1f) as u8,
+            },
+            _ if word & 0xffff_fc1f == 0xd65f_0000 => Opcode::Ret {
+                register: ((word >> 5) & 0x1f) as u8,
+            },
+            _ if word & 0xffe0_001f == 0xd420_0000 => Opcode::Brk {
+                immediate: ((word >> 5) & 0xffff) as u16,
+            },
+            // The `opc` field (bits 30 and 31) selects a 32 or 64 bits register
+            _ if word & 0xbf00_0000 == 0x1800_0000 => Opcode::LdrLiteral {
+                register: (word & 0x1f) as u8,
+                offset: branch_offset(word >> 5, 19),
+                size: if word & 0x4000_0000 != 0 { 8 } else { 4 },
+            },
+            _ => Opcode::Other,
+        }
+    }
+}
+
+/// The operands of an instruction
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operands {
+    /// A register
+    Register(u8),
+    /// Two registers, the destination then the source
+    Registers(u8, u8),
+    /// The loaded register and the offset of the literal from the instruction
+    Literal {
+        /// The loaded register
+        register: u8,
+        /// Offset of the literal from the instruction
+        offset: i64,
+    },
+    /// An immediate
+    Immediate(u16),
+    /// The encoding of an instruction which isn't decoded
+    Word(u32),
+}
+
+impl fmt::Display for Operands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operands::Register(reg) => f.write_str(register_name(reg)),
+            Operands::Registers(dst, src) => {
+                write!(f, "{}, {}", register_name(dst), register_name(src))
+            }
+            Operands::Literal { register, offset } if offset < 0 => write!(
+                f,
+                "{}, [pc-{:#x}]",
+                register_name(register),
+                offset.unsigned_abs()
+            ),
+            Operands::Literal { register, offset } => {
+                write!(f, "{}, [pc+{:#x}]", register_name(register), offset)
+            }
+            Operands::Immediate(imm) => write!(f, "#{:#x}", imm),
+            Operands::Word(word) => write!(f, "{:#010x}", word),
+        }
+    }
+}
+
+/// An instruction, decoded from the start of a buffer
+///
+/// The word is copied out of the buffer, the lifetime only keeps the same signature as the
+/// x86 backend.
+pub struct Insn<'a> {
+    word: Option<u32>,
+    _buffer: PhantomData<&'a [u8]>,
+}
+
+impl<'a> Insn<'a> {
+    /// Decode the instruction at the start of `buffer`, a buffer shorter than 4 bytes holds
+    /// no instruction
+    pub fn new(buffer: &'a [u8]) -> Self {
+        let word = buffer
+            .get(..INSN_SIZE)
+            .and_then(|word| word.try_into().ok())
+            .map(u32::from_le_bytes);
+        Insn {
+            word,
+            _buffer: PhantomData,
+        }
+    }
+
+    /// Decode the instruction at the kernel address `address`
+    ///
+    /// The instruction is read with [`nofault::read`], so `address` can come from a suspect
+    /// pointer: an unmapped address fails with `EFAULT`, a misaligned one with `EINVAL`.
+    pub fn new_from_address(address: u64) -> Result<Insn<'static>> {
+        if address % INSN_SIZE as u64 != 0 {
+            return Err(EINVAL);
+        }
+        let word = nofault::read::<u32>(address as usize)?;
+        Ok(Insn {
+            word: Some(word),
+            _buffer: PhantomData,
+        })
+    }
+
+    /// Get the length of the instruction
+    pub fn get_length(&self) -> Result<u32> {
+        self.get_word().map(|_| INSN_SIZE as u32)
+    }
+
+    /// Get the encoding of the instruction
+    pub fn get_word(&self) -> Result<u32> {
+        self.word.ok_or(EINVAL)
+    }
+
+    /// Get the decoded instruction
+    pub fn get_opcode(&self) -> Result<Opcode> {
+        self.get_word().map(Opcode::decode)
+    }
+
+    /// Get the destination of the instruction if it is a relative branch
+    ///
+    /// `address` is the address of the instruction
+    pub fn branch_target(&self, address: u64) -> Result<Option<u64>> {
+        Ok(match self.get_opcode()? {
+            Opcode::B { offset } | Opcode::Bl { offset } | Opcode::BCond { offset } => {
+                Some(address.wrapping_add(offset as u64))
+            }
+            _ => None,
+        })
+    }
+}
+
+impl<'a> Instruction<'a> for Insn<'a> {
+    type Operands = Operands;
+
+    fn new(buffer: &'a [u8]) -> Self {
+        Insn::new(buffer)
+    }
+
+    fn length(&mut self) -> Result<usize> {
+        Ok(self.get_length()? as usize)
+    }
+
+    fn class(&mut self) -> Result<InsnClass> {
+        Ok(match self.get_opcode()? {
+            Opcode::B { .. } | Opcode::Br { .. } => InsnClass::Branch(BranchKind::Jump),
+            Opcode::Bl { .. } | Opcode::Blr { .. } => InsnClass::Branch(BranchKind::Call),
+            Opcode::BCond { .. } => InsnClass::Branch(BranchKind::ConditionalJump),
+            Opcode::Ret { .. } => InsnClass::Branch(BranchKind::Return),
+            Opcode::LdrLiteral { .. } => InsnClass::Load,
+            Opcode::Brk { .. } => InsnClass::Trap,
+            Opcode::Bti => InsnClass::LandingPad,
+            Opcode::Nop => InsnClass::Nop,
+            Opcode::MovX9X30 => InsnClass::Move,
+            Opcode::Other => InsnClass::Other,
+        })
+    }
+
+    fn operands(&mut self) -> Result<Option<Operands>> {
+        Ok(match self.get_opcode()? {
+            Opcode::Br { register } | Opcode::Blr { register } | Opcode::Ret { register } => {
+                Some(Operands::Register(register))
+            }
+            Opcode::LdrLiteral {
+                register, offset, ..
+            } => Some(Operands::Literal { register, offset }),
+            Opcode::Brk { immediate } => Some(Operands::Immediate(immediate)),
+            Opcode::MovX9X30 => Some(Operands::Registers(9, 30)),
+            Opcode::Other => Some(Operands::Word(self.get_word()?)),
+            // The destination of a relative branch is shown on its own
+            Opcode::B { .. } | Opcode::Bl { .. } | Opcode::BCond { .. } => None,
+            Opcode::Bti | Opcode::Nop => None,
+        })
+    }
+
+    fn branch(&mut self, address: u64) -> Result<Option<Branch>> {
+        let InsnClass::Branch(kind) = self.class()? else {
+            return Ok(None);
+        };
+        Ok(Some(Branch {
+            kind,
+            target: self.branch_target(address)?,
+        }))
+    }
+}
+
+/// State of the ftrace patch site at the start of a function
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PatchSite {
+    /// `nop ; nop`, the function isn't traced
+    Disabled,
+    /// `mov x9, x30 ; bl destination`
+    Enabled {
+        /// Destination of the call, an ftrace trampoline
+        destination: u64,
+    },
+}
+
+/// Decode the ftrace patch site of the function at `address` starting with `code`
+///
+/// The `bti c` starting the function is skipped. `None` if the function doesn't start with
+/// a patch site in one of its states, it isn't traceable or its prologue was overwritten.
+pub fn decode_patch_site(code: &[u8], address: u64) -> Option<PatchSite> {
+    let mut words = code
+        .chunks_exact(INSN_SIZE)
+        .map(|word| Opcode::decode(u32::from_le_bytes([word[0], word[1], word[2], word[3]])));
+    let mut address = address;
+    let mut first = words.next()?;
+    if first == Opcode::Bti {
+        first = words.next()?;
+        address = address.wrapping_add(INSN_SIZE as u64);
+    }
+    match (first, words.next()?) {
+        (Opcode::Nop, Opcode::Nop) => Some(PatchSite::Disabled),
+        (Opcode::MovX9X30, Opcode::Bl { offset }) => Some(PatchSite::Enabled {
+            destination: address
+                .wrapping_add(INSN_SIZE as u64)
+                .wrapping_add(offset as u64),
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,597 @@
+//! x86 backend : wrapper of the in-kernel x86 instruction decoder
+//!
+//! C header : [`arch/x86/include/asm/insn.h`](../../../../../arch/x86/include/asm/insn.h)
+
+use core::{ffi::c_void, fmt, marker::PhantomData, mem::MaybeUninit, ops::Range, ptr};
+use kernel::prelude::*;
+
+use super::{Branch, BranchKind, InsnClass, Instruction};
+use crate::nofault;
+use crate::page::PAGE_SIZE;
+
+/// Represent the kernel's `struct insn` structure
+///
+/// The decoder keeps a pointer to the buffer the instruction is decoded from, and decodes
+/// its fields lazily, so the instruction borrows the buffer for `'a`.
+pub struct Insn<'a>(bindings::insn, PhantomData<&'a [u8]>);
+
+fn inat_has_immediate(attr: bindings::insn_attr_t) -> u32 {
+    attr & bindings::INAT_IMM_MASK
+}
+
+fn inat_immediate_size(attr: bindings::insn_attr_t) -> u32 {
+    (attr & bindings::INAT_IMM_MASK) >> bindings::INAT_IMM_OFFS
+}
+
+/// Bits of the REX prefix extending the register numbers
+const REX_R: u8 = 0x4;
+const REX_X: u8 = 0x2;
+const REX_B: u8 = 0x1;
+
+/// Names of the general purpose registers, in the order of their encoding
+const REGISTER_NAMES: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
+/// Get the name of the general purpose register numbered `reg` in the encoding
+pub fn register_name(reg: u8) -> &'static str {
+    REGISTER_NAMES.get(reg as usize).copied().unwrap_or("?")
+}
+
+/// Base of a memory operand
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemBase {
+    /// A general purpose register, see [`register_name`]
+    Register(u8),
+    /// The address of the next instruction (RIP-relative addressing)
+    Rip,
+}
+
+/// A memory operand : `base + index * scale + displacement`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemOperand {
+    /// The base, `None` for an absolute address
+    pub base: Option<MemBase>,
+    /// The index register
+    pub index: Option<u8>,
+    /// The scale of the index : 1, 2, 4 or 8
+    pub scale: u8,
+    /// The displacement, sign extended
+    pub displacement: i32,
+}
+
+impl fmt::Display for MemOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        match self.base {
+            Some(MemBase::Register(reg)) => f.write_str(register_name(reg))?,
+            Some(MemBase::Rip) => f.write_str("rip")?,
+            None => (),
+        }
+        if let Some(index) = self.index {
+            if self.base.is_some() {
+                f.write_str("+")?;
+            }
+            write!(f, "{}*{}", register_name(index), self.scale)?;
+        }
+        let relative = self.base.is_some() || self.index.is_some();
+        match self.displacement {
+            0 if relative => (),
+            disp if !relative => write!(f, "{:#x}", disp as u32)?,
+            disp if disp < 0 => write!(f, "-{:#x}", disp.unsigned_abs())?,
+            disp => write!(f, "+{:#x}", disp)?,
+        }
+        f.write_str("]")
+    }
+}
+
+/// The operand encoded by the r/m field of the ModRM byte
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RmOperand {
+    /// A general purpose register, see [`register_name`]
+    Register(u8),
+    /// A memory operand
+    Memory(MemOperand),
+}
+
+impl fmt::Display for RmOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RmOperand::Register(reg) => f.write_str(register_name(*reg)),
+            RmOperand::Memory(operand) => write!(f, "{}", operand),
+        }
+    }
+}
+
+/// The operands of an instruction, in the order of their encoding (the register, the r/m
+/// operand then the immediate) rather than in the order of the assembly syntax
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Operands {
+    /// The register of the ModRM byte, or the one encoded in the opcode
+    pub register: Option<u8>,
+    /// The operand of the r/m field of the ModRM byte
+    pub rm: Option<RmOperand>,
+    /// The immediate, sign extended
+    pub immediate: Option<i64>,
+}
+
+impl fmt::Display for Operands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        if let Some(register) = self.register {
+            f.write_str(register_name(register))?;
+            separator = ", ";
+        }
+        if let Some(rm) = self.rm {
+            write!(f, "{}{}", separator, rm)?;
+            separator = ", ";
+        }
+        match self.immediate {
+            Some(imm) if imm < 0 => write!(f, "{}$-{:#x}", separator, imm.unsigned_abs()),
+            Some(imm) => write!(f, "{}${:#x}", separator, imm),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The reg field of the ModRM byte of `opcode` is an opcode extension, not a register
+fn modrm_is_extension(opcode: &[u8]) -> bool {
+    matches!(
+        opcode,
+        [0x80..=0x83 | 0x8f | 0xc0 | 0xc1 | 0xc6 | 0xc7 | 0xd0..=0xd3 | 0xf6 | 0xf7 | 0xfe | 0xff]
+            | [0x0f, 0x00 | 0x01 | 0x18..=0x1f | 0x71..=0x73 | 0xae | 0xba | 0xc7]
+    )
+}
+
+/// Get the value of a decoded field, `None` if it is absent from the instruction
+fn field_value(field: &bindings::insn_field) -> Option<i32> {
+    // SAFETY: `value` is the view of the field used by the decoder for all the fields
+    (field.nbytes != 0).then(|| unsafe { field.__bindgen_anon_1.value })
+}
+
+impl<'a> Insn<'a> {
+    /// Create a new `struct insn` structure and initialize it.
+    /// Only one instruction is analyzed.
+    /// So buffer longer than 15 bytes will only be analyzed on the 15's first bytes.
+    pub fn new(buffer: &'a [u8]) -> Self {
+        let mut insn = MaybeUninit::<bindings::insn>::uninit();
+
+        // SAFETY: Just an FFI call.
+        // The buffer len is respected.
+        unsafe {
+            bindings::insn_init(
+                insn.as_mut_ptr(),
+                buffer as *const [u8] as *const c_void,
+                buffer.len() as i32,
+                1,
+            );
+        }
+
+        // SAFETY: According to the insn_init's API, insn is now initialized
+        let insn = unsafe { insn.assume_init() };
+
+        // insn is valid and a well initialized opcode
+        Insn(insn, PhantomData)
+    }
+
+    /// Decode the instruction at the kernel address `address`
+    ///
+    /// Up to `MAX_INSN_SIZE` bytes are copied with [`nofault::copy`] before decoding, so
+    /// `address` can come from a suspect pointer: an unmapped address fails with `EFAULT`.
+    pub fn new_from_address(address: u64) -> Result<Insn<'static>> {
+        let mut buffer = [0u8; bindings::MAX_INSN_SIZE as usize];
+        // The instruction may end right before an unmapped page
+        let length = if nofault::copy(address as usize, &mut buffer).is_ok() {
+            buffer.len()
+        } else {
+            let length = (PAGE_SIZE - address as usize % PAGE_SIZE).min(buffer.len());
+            nofault::copy(address as usize, &mut buffer[..length])?;
+            length
+        };
+
+        let mut insn = Insn::new(&buffer[..length]);
+        // Once the whole instruction is decoded, the accessors only read the decoded fields
+        insn.get_length()?;
+        let Insn(mut insn, _) = insn;
+        // `buffer` doesn't outlive this function, the decoder must not keep a pointer to it
+        insn.kaddr = ptr::null();
+        insn.end_kaddr = ptr::null();
+        insn.next_byte = ptr::null();
+        Ok(Insn(insn, PhantomData))
+    }
+
+    /// Get the length of the parsed instruction
+    pub fn get_length(&mut self) -> Result<u32> {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        let ret = unsafe { bindings::insn_get_length(&mut self.0 as _) };
+
+        crate::error::to_result(ret)?;
+
+        Ok(self.0.length as _)
+    }
+
+    /// Get the opcode of the parsed instruction
+    pub fn get_opcode(&mut self) -> Result<i32> {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        let ret = unsafe { bindings::insn_get_opcode(&mut self.0 as _) };
+
+        crate::error::to_result(ret)?;
+
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // We just initialized the opcode field and we know it didn't failed.
+        let opcode = unsafe { self.0.opcode.__bindgen_anon_1.value };
+        Ok(opcode)
+    }
+
+    /// Get the immediate of the parsed instruction
+    pub fn get_immediate(&mut self) -> Result<Option<(u64, u8)>> {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // So this is just an FFI call
+        let ret = unsafe { bindings::insn_get_immediate(&mut self.0 as _) };
+
+        crate::error::to_result(ret)?;
+
+        if inat_has_immediate(self.0.attr) == 0 {
+            return Ok(None);
+        }
+
+        // SAFETY: By the type invariant, we know that `self.0` is valid
+        match inat_immediate_size(self.0.attr) {
+            // SAFETY: By the type invariant, we know that `self.0` is valid.
+            // According to the function call we access the good union field.
+            bindings::INAT_IMM_BYTE | bindings::INAT_IMM_WORD | bindings::INAT_IMM_DWORD => unsafe {
+                Ok(Some((
+                    self.0.__bindgen_anon_1.immediate.__bindgen_anon_1.value as u32 as u64,
+                    self.0.__bindgen_anon_1.immediate.nbytes,
+                )))
+            },
+            // SAFETY: By the type invariant, we know that `self.0` is valid.
+            // According to the function call we access the good union field.
+            bindings::INAT_IMM_QWORD => unsafe {
+                let lb: u64 =
+                    self.0.__bindgen_anon_1.immediate1.__bindgen_anon_1.value as u32 as u64;
+                let hb: u64 =
+                    self.0.__bindgen_anon_2.immediate2.__bindgen_anon_1.value as u32 as u64;
+                Ok(Some((
+                    hb << 32 | lb,
+                    self.0.__bindgen_anon_1.immediate.nbytes,
+                )))
+            },
+
+            _ => {
+                pr_err!("Unknow immediate size, skipping");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Get the ModRM byte of the parsed instruction, if it has one
+    pub fn get_modrm(&mut self) -> Result<Option<u8>> {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // So this is just an FFI call
+        let ret = unsafe { bindings::insn_get_modrm(&mut self.0 as _) };
+
+        crate::error::to_result(ret)?;
+
+        Ok(field_value(&self.0.modrm).map(|modrm| modrm as u8))
+    }
+
+    /// Get the SIB byte of the parsed instruction, if it has one
+    pub fn get_sib(&mut self) -> Result<Option<u8>> {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // So this is just an FFI call
+        let ret = unsafe { bindings::insn_get_sib(&mut self.0 as _) };
+
+        crate::error::to_result(ret)?;
+
+        Ok(field_value(&self.0.sib).map(|sib| sib as u8))
+    }
+
+    /// Get the displacement of the memory operand of the parsed instruction
+    ///
+    /// # Return
+    /// The displacement sign extended and its size in bytes (1, 2 or 4)
+    pub fn get_displacement(&mut self) -> Result<Option<(i32, u8)>> {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // So this is just an FFI call
+        let ret = unsafe { bindings::insn_get_displacement(&mut self.0 as _) };
+
+        crate::error::to_result(ret)?;
+
+        Ok(field_value(&self.0.displacement)
+            .map(|displacement| (displacement, self.0.displacement.nbytes)))
+    }
+
+    /// Get the legacy prefixes of the parsed instruction (`lock`, `rep`, segment, operand
+    /// and address size overrides), each prefix is only listed once
+    pub fn get_prefixes(&mut self) -> Result<&[u8]> {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // So this is just an FFI call
+        let ret = unsafe { bindings::insn_get_prefixes(&mut self.0 as _) };
+
+        crate::error::to_result(ret)?;
+
+        // SAFETY: The decoder stores the distinct prefixes in `bytes`, the unused ones are 0
+        let bytes = unsafe { &self.0.prefixes.__bindgen_anon_1.bytes };
+        let count = bytes
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(bytes.len());
+        Ok(&bytes[..count])
+    }
+
+    /// Get the REX prefix of the parsed instruction, if it has one
+    pub fn get_rex_prefix(&mut self) -> Result<Option<u8>> {
+        self.get_prefixes()?;
+        Ok(field_value(&self.0.rex_prefix).map(|rex| rex as u8))
+    }
+
+    /// Get the VEX (2 or 3 bytes) or EVEX (4 bytes) prefix of the parsed instruction, if it
+    /// has one
+    pub fn get_vex_prefix(&mut self) -> Result<Option<&[u8]>> {
+        self.get_prefixes()?;
+        let vex = &self.0.vex_prefix;
+        if vex.nbytes == 0 {
+            return Ok(None);
+        }
+        // SAFETY: The decoder stores the bytes of the VEX prefix in `bytes`
+        let bytes = unsafe { &vex.__bindgen_anon_1.bytes };
+        Ok(bytes.get(..vex.nbytes as usize))
+    }
+
+    /// The parsed instruction is `endbr64`, the landing pad of the indirect branches with IBT
+    pub fn is_endbr64(&mut self) -> Result<bool> {
+        self.get_length()?;
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // The opcode was decoded by `get_length`
+        let opcode = unsafe { self.0.opcode.__bindgen_anon_1.bytes };
+        let modrm = field_value(&self.0.modrm);
+        let opcode_match =
+            self.0.opcode.nbytes == 2 && opcode[..2] == [0x0f, 0x1e] && modrm == Some(0xfa);
+        Ok(opcode_match && self.get_prefixes()?.contains(&0xf3))
+    }
+
+    /// Extend the 3 bits register number `reg` with the bit `rex_bit` of the REX prefix
+    fn extend_register(&self, reg: u8, rex_bit: u8) -> u8 {
+        let rex = field_value(&self.0.rex_prefix).unwrap_or(0) as u8;
+        if rex & rex_bit != 0 {
+            reg | 8
+        } else {
+            reg
+        }
+    }
+
+    /// Get the register encoded by the reg field of the ModRM byte
+    ///
+    /// For some opcodes the field is an opcode extension rather than a register
+    /// (`FF /2` is an indirect call, `FF /4` an indirect jump)
+    pub fn get_modrm_reg(&mut self) -> Result<Option<u8>> {
+        let Some(modrm) = self.get_modrm()? else {
+            return Ok(None);
+        };
+        Ok(Some(self.extend_register((modrm >> 3) & 7, REX_R)))
+    }
+
+    /// Get the operand encoded by the r/m field of the ModRM byte
+    ///
+    /// The instruction is decoded in 64 bits mode, the address size prefix is ignored
+    pub fn get_rm_operand(&mut self) -> Result<Option<RmOperand>> {
+        // The displacement is decoded after the ModRM and SIB bytes
+        let displacement = self
+            .get_displacement()?
+            .map_or(0, |(displacement, _)| displacement);
+        let Some(modrm) = field_value(&self.0.modrm).map(|modrm| modrm as u8) else {
+            return Ok(None);
+        };
+        let (mode, rm) = (modrm >> 6, modrm & 7);
+        if mode == 3 {
+            return Ok(Some(RmOperand::Register(self.extend_register(rm, REX_B))));
+        }
+
+        let operand = match field_value(&self.0.sib).map(|sib| sib as u8) {
+            Some(sib) => {
+                let (scale, index, base) = (sib >> 6, (sib >> 3) & 7, sib & 7);
+                let index = self.extend_register(index, REX_X);
+                MemOperand {
+                    // Without displacement byte, a base of rbp or r13 means no base
+                    base: (mode != 0 || base != 5)
+                        .then(|| MemBase::Register(self.extend_register(base, REX_B))),
+                    // rsp can't be an index
+                    index: (index != 4).then_some(index),
+                    scale: 1 << scale,
+                    displacement,
+                }
+            }
+            None if mode == 0 && rm == 5 => MemOperand {
+                base: Some(MemBase::Rip),
+                index: None,
+                scale: 1,
+                displacement,
+            },
+            None => MemOperand {
+                base: Some(MemBase::Register(self.extend_register(rm, REX_B))),
+                index: None,
+                scale: 1,
+                displacement,
+            },
+        };
+        Ok(Some(RmOperand::Memory(operand)))
+    }
+
+    /// The parsed instruction is a jump or a call relative to the next instruction, its
+    /// immediate is the sign extended offset of the destination
+    pub fn is_relative_branch(&mut self) -> Result<bool> {
+        self.get_opcode()?;
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // The opcode was decoded above
+        let opcode = unsafe { self.0.opcode.__bindgen_anon_1.bytes };
+        Ok(match (self.0.opcode.nbytes, opcode[0], opcode[1]) {
+            // call rel32, jmp rel32, jmp rel8
+            (1, 0xe8 | 0xe9 | 0xeb, _) => true,
+            // jcc rel8, loop and jrcxz
+            (1, 0x70..=0x7f | 0xe0..=0xe3, _) => true,
+            // jcc rel32
+            (2, 0x0f, 0x80..=0x8f) => true,
+            _ => false,
+        })
+    }
+
+    /// Get the ranges of the displacement and of the immediate in the bytes of the parsed
+    /// instruction, empty if it has none
+    ///
+    /// The range of the immediate covers both immediates of `enter` and the 8 bytes of
+    /// `movabs`
+    pub fn get_operand_ranges(&mut self) -> Result<(Range<usize>, Range<usize>)> {
+        let length = self.get_length()? as usize;
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // The immediates were decoded by `get_length`, the size of an absent one is 0
+        let immediate = unsafe {
+            self.0.__bindgen_anon_1.immediate1.nbytes as usize
+                + self.0.__bindgen_anon_2.immediate2.nbytes as usize
+        };
+        let displacement = self.0.displacement.nbytes as usize;
+        // The displacement and the immediate are the last fields of an instruction
+        let start = length.checked_sub(immediate + displacement).ok_or(EINVAL)?;
+        Ok((start..start + displacement, start + displacement..length))
+    }
+
+    /// Get the destination of the parsed instruction if it is a relative jump or call, or a
+    /// jump or call through a RIP-relative pointer
+    ///
+    /// `address` is the address of the instruction. The pointer of an indirect branch is
+    /// read with [`nofault::read`], it may have changed since the instruction was read.
+    pub fn branch_target(&mut self, address: u64) -> Result<Option<u64>> {
+        let next = address.wrapping_add(self.get_length()? as u64);
+
+        if self.is_relative_branch()? {
+            // SAFETY: By the type invariant, we know that `self.0` is valid.
+            // The immediate was decoded by `get_length`, sign extended for a relative branch
+            let rel = unsafe { self.0.__bindgen_anon_1.immediate.__bindgen_anon_1.value };
+            return Ok(Some(next.wrapping_add(rel as i64 as u64)));
+        }
+
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // The opcode was decoded by `get_length`
+        let opcode = unsafe { self.0.opcode.__bindgen_anon_1.bytes };
+        // call and jmp through a pointer are `FF /2` and `FF /4`
+        if self.0.opcode.nbytes != 1 || opcode[0] != 0xff {
+            return Ok(None);
+        }
+        let Some(modrm) = self.get_modrm()? else {
+            return Ok(None);
+        };
+        match ((modrm >> 3) & 7, self.get_rm_operand()?) {
+            (
+                2 | 4,
+                Some(RmOperand::Memory(MemOperand {
+                    base: Some(MemBase::Rip),
+                    displacement,
+                    ..
+                })),
+            ) => {
+                let pointer = next.wrapping_add(displacement as i64 as u64);
+                Ok(Some(nofault::read::<u64>(pointer as usize)?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> Instruction<'a> for Insn<'a> {
+    type Operands = Operands;
+
+    fn new(buffer: &'a [u8]) -> Self {
+        Insn::new(buffer)
+    }
+
+    fn length(&mut self) -> Result<usize> {
+        Ok(self.get_length()? as usize)
+    }
+
+    fn class(&mut self) -> Result<InsnClass> {
+        if self.is_endbr64()? {
+            return Ok(InsnClass::LandingPad);
+        }
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // The opcode was decoded by `is_endbr64`
+        let opcode = unsafe { self.0.opcode.__bindgen_anon_1.bytes };
+        let extension = self.get_modrm()?.map(|modrm| (modrm >> 3) & 7);
+        Ok(match (self.0.opcode.nbytes, opcode[0], opcode[1]) {
+            (1, 0xe8, _) => InsnClass::Branch(BranchKind::Call),
+            (1, 0xe9 | 0xeb, _) => InsnClass::Branch(BranchKind::Jump),
+            (1, 0x70..=0x7f | 0xe0..=0xe3, _) | (2, 0x0f, 0x80..=0x8f) => {
+                InsnClass::Branch(BranchKind::ConditionalJump)
+            }
+            (1, 0xc2 | 0xc3, _) => InsnClass::Branch(BranchKind::Return),
+            // `FF /2` and `FF /4`, through a register or a pointer
+            (1, 0xff, _) if extension == Some(2) => InsnClass::Branch(BranchKind::Call),
+            (1, 0xff, _) if extension == Some(4) => InsnClass::Branch(BranchKind::Jump),
+            (1, 0xff, _) if extension == Some(6) => InsnClass::Push,
+            // `90` with a REX.B prefix is `xchg r8, rax`
+            (1, 0x90, _) if self.get_rex_prefix()?.unwrap_or(0) & REX_B == 0 => InsnClass::Nop,
+            (2, 0x0f, 0x1f) => InsnClass::Nop,
+            (1, 0xcc | 0xcd | 0xf4, _) | (2, 0x0f, 0x0b) => InsnClass::Trap,
+            (1, 0x50..=0x57 | 0x68 | 0x6a, _) => InsnClass::Push,
+            (1, 0x58..=0x5f | 0x8f, _) => InsnClass::Pop,
+            (1, 0x88..=0x8b | 0xb0..=0xbf | 0xc6 | 0xc7, _) => InsnClass::Move,
+            (1, 0x8d, _) => InsnClass::Lea,
+            _ => InsnClass::Other,
+        })
+    }
+
+    fn operands(&mut self) -> Result<Option<Operands>> {
+        self.get_length()?;
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        // The opcode was decoded by `get_length`
+        let opcode = unsafe { self.0.opcode.__bindgen_anon_1.bytes };
+        let opcode = &opcode[..self.0.opcode.nbytes as usize];
+        let register = match *opcode {
+            // The register is encoded in the opcode of `push`, `pop` and `mov reg, imm`
+            [op @ (0x50..=0x5f | 0xb8..=0xbf)] => Some(self.extend_register(op & 7, REX_B)),
+            _ if modrm_is_extension(opcode) => None,
+            _ => self.get_modrm_reg()?,
+        };
+        let rm = self.get_rm_operand()?;
+        // The immediate of a relative branch is its destination, shown on its own
+        let immediate = if self.is_relative_branch()? {
+            None
+        } else {
+            // SAFETY: By the type invariant, we know that `self.0` is valid.
+            // The immediates were decoded by `get_length`
+            let (low, high) = unsafe {
+                (
+                    &self.0.__bindgen_anon_1.immediate1,
+                    &self.0.__bindgen_anon_2.immediate2,
+                )
+            };
+            // The 8 bytes immediate of `movabs` is split in two fields of 4 bytes
+            match (field_value(low), field_value(high)) {
+                (Some(low_value), Some(high_value)) if low.nbytes == 4 && high.nbytes == 4 => {
+                    Some(((high_value as i64) << 32) | low_value as u32 as i64)
+                }
+                (low, _) => low.map(|low| low as i64),
+            }
+        };
+        if register.is_none() && rm.is_none() && immediate.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Operands {
+            register,
+            rm,
+            immediate,
+        }))
+    }
+
+    fn branch(&mut self, address: u64) -> Result<Option<Branch>> {
+        let InsnClass::Branch(kind) = self.class()? else {
+            return Ok(None);
+        };
+        let target = match kind {
+            BranchKind::Return => None,
+            // An unreadable pointer only hides the destination
+            _ => self.branch_target(address).ok().flatten(),
+        };
+        Ok(Some(Branch { kind, target }))
+    }
+}
@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Kprobes (probing of arbitrary instructions)
+//!
+//! Unlike [`Fprobe`](crate::fprobe::Fprobe) which relies on ftrace and can only hook
+//! the entry of traceable functions, a kprobe can be placed on any instruction which is
+//! not blacklisted, including `notrace` functions and in the middle of a function.
+//!
+//! The `fault_handler` of `struct kprobe` was removed in Linux 5.14, so only the pre
+//! and post handlers are supported.
+//!
+//! C header: [`include/linux/kprobes.h`](../../../../include/linux/kprobes.h)
+
+use crate::error::Result;
+use crate::init::PinInit;
+use crate::registers::Registers;
+use crate::str::CStr;
+use crate::try_pin_init;
+use crate::types::{ForeignOwnable, Opaque};
+use bindings::{kprobe, pt_regs};
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use kernel::container_of;
+use kernel::error::Error;
+use macros::{pin_data, pinned_drop};
+
+/// Where to place a kprobe
+#[derive(Clone, Copy, Debug)]
+pub enum KprobeTarget {
+    /// At `offset` bytes from the start of a symbol
+    Symbol(&'static CStr, u32),
+    /// At an address
+    Address(usize),
+}
+
+/// Correspond to the kernel `pre_handler` and `post_handler` function of a kprobe
+///
+/// You need to implement this trait each time you need a new callbacks
+pub trait KprobeOperations
+where
+    Self: Sized,
+{
+    /// The global type that will be transmited to all the callbacks
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// Register the post handler
+    ///
+    /// A kprobe with a post handler can't be optimized into a jump, so it is only
+    /// registered when needed
+    const HAS_POST_HANDLER: bool = false;
+
+    /// Callback called before the probed instruction is executed
+    fn pre_handler(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        addr: usize,
+        regs: Registers<'_>,
+    );
+
+    /// Callback called after the probed instruction is single-stepped,
+    /// only if [`Self::HAS_POST_HANDLER`] is set
+    fn post_handler(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _addr: usize,
+        _regs: Registers<'_>,
+    ) {
+    }
+}
+
+/// The `struct kprobe` and the private data of a [`Kprobe`]
+#[repr(C)]
+struct KprobeInner {
+    kp: kprobe,
+    data: *const c_void,
+}
+
+/// Represent the kernel's `struct kprobe` structure
+///
+/// # Invariants
+///
+///     `inner.kp` is a registered kprobe
+#[pin_data(PinnedDrop)]
+pub struct Kprobe<T: KprobeOperations> {
+    #[pin]
+    inner: Opaque<KprobeInner>,
+    _t: PhantomData<T>,
+}
+
+// SAFETY: The `&self` methods read the address, which is not modified after registration,
+// return a raw pointer, read the flags and `nmissed`, or call `enable_kprobe` and
+// `disable_kprobe` which take `kprobe_mutex`
+unsafe impl<T: KprobeOperations> Sync for Kprobe<T> where T::Data: Sync {}
+
+// SAFETY: It is safe to unregister the probe on a different thread than
+// the one used to register
+unsafe impl<T: KprobeOperations> Send for Kprobe<T> where T::Data: Send {}
+
+impl<T: KprobeOperations> Kprobe<T> {
+    /// Create a new `struct kprobe` structure
+    ///
+    /// But it dont register it
+    fn new_inner(target: KprobeTarget, data: T::Data) -> KprobeInner {
+        // SAFETY: All zeroes is a valid `struct kprobe`, the unused fields must be zero
+        let mut kp: kprobe = unsafe { core::mem::zeroed() };
+        match target {
+            KprobeTarget::Symbol(symbol, offset) => {
+                kp.symbol_name = symbol.as_char_ptr();
+                kp.offset = offset;
+            }
+            KprobeTarget::Address(addr) => kp.addr = addr as _,
+        }
+        kp.pre_handler = Some(Kprobe::<T>::pre_handler_callback);
+        if T::HAS_POST_HANDLER {
+            kp.post_handler = Some(Kprobe::<T>::post_handler_callback);
+        }
+
+        KprobeInner {
+            kp,
+            data: data.into_foreign(),
+        }
+    }
+
+    /// Create a new `struct kprobe` structure and register it
+    pub fn new(target: KprobeTarget, private_data: T::Data) -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            inner <- Opaque::try_ffi_init(move |slot: *mut KprobeInner| {
+                // SAFETY: The initializer can write to the provided `slot`.
+                unsafe { slot.write(Self::new_inner(target, private_data)) };
+
+                // SAFETY: We wrote the data to the kprobe structure.
+                // We have the kprobe structure pinned to our type so will be unregistred
+                // before being deallocated
+                // INVARIANT: If this return `Ok(())`, then the `slot` will contain a registred
+                // kprobe
+                let ret = crate::error::to_result(unsafe {
+                    bindings::register_kprobe(core::ptr::addr_of_mut!((*slot).kp))
+                });
+                if ret.is_err() {
+                    // SAFETY: The kprobe is not registered so no one borrowed the data,
+                    // this is the only call to `from_foreign` for this `into_foreign`
+                    unsafe { T::Data::from_foreign((*slot).data as _) };
+                }
+                ret
+            }),
+            _t: PhantomData,
+        })
+    }
+
+    /// Get the address of the probed instruction, resolved at registration
+    pub fn address(&self) -> usize {
+        // SAFETY: The kprobe is registered by the type invariant, `addr` is set by
+        // `register_kprobe` and not modified after
+        unsafe { (*self.inner.get()).kp.addr as usize }
+    }
+
+    /// Get the raw `struct kprobe`, registered as long as `self` is alive
+    pub fn as_ptr(&self) -> *mut kprobe {
+        // SAFETY: `inner` is valid as long as `self`, we only compute the field address
+        unsafe { core::ptr::addr_of_mut!((*self.inner.get()).kp) }
+    }
+
+    /// Disable the kprobe, the handlers are not called anymore until [`Self::enable`]
+    ///
+    /// Takes `kprobe_mutex`, so it must be called from process context
+    pub fn disable(&self) -> Result {
+        // SAFETY: The kprobe is registered by the type invariant
+        crate::error::to_result(unsafe { bindings::disable_kprobe(self.as_ptr()) })
+    }
+
+    /// Enable back a kprobe disabled by [`Self::disable`]
+    ///
+    /// Takes `kprobe_mutex`, so it must be called from process context
+    pub fn enable(&self) -> Result {
+        // SAFETY: The kprobe is registered by the type invariant
+        crate::error::to_result(unsafe { bindings::enable_kprobe(self.as_ptr()) })
+    }
+
+    /// The kprobe is disabled
+    pub fn is_disabled(&self) -> bool {
+        // SAFETY: The kprobe is registered by the type invariant, the flags are modified
+        // under `kprobe_mutex` so we read a snapshot of them
+        let flags =
+            unsafe { core::ptr::read_volatile(core::ptr::addr_of!((*self.as_ptr()).flags)) };
+        flags & bindings::KPROBE_FLAG_DISABLED != 0
+    }
+
+    /// Get the number of hits missed by the kprobe (`struct kprobe::nmissed`), because of
+    /// a recursion
+    pub fn nmissed(&self) -> u64 {
+        // SAFETY: The kprobe is registered by the type invariant, `nmissed` is only
+        // incremented by the kernel so we read a snapshot of it
+        unsafe { core::ptr::read_volatile(core::ptr::addr_of!((*self.as_ptr()).nmissed)) as u64 }
+    }
+
+    /// Get the private data of a registered kprobe
+    ///
+    /// # Safety
+    ///
+    /// `kp` must be the `kp` field of a registered [`KprobeInner`]
+    unsafe fn data<'a>(kp: *mut kprobe) -> <T::Data as ForeignOwnable>::Borrowed<'a> {
+        // SAFETY: By the safety contract `kp` is embedded in a `KprobeInner`
+        let inner = unsafe { &*container_of!(kp, KprobeInner, kp) };
+
+        // SAFETY: The kprobe is still registered so the data is still valid
+        unsafe { T::Data::borrow(inner.data as _) }
+    }
+
+    /// # Safety
+    ///     Will be called only from C, prototype correspond to the kprobe's callback prototype
+    unsafe extern "C" fn pre_handler_callback(
+        kp: *mut kprobe,
+        regs: *mut pt_regs,
+    ) -> core::ffi::c_int {
+        // SAFETY: This callback is called only when the kprobe is still registered
+        let data = unsafe { Self::data(kp) };
+
+        // SAFETY: The pointer is created at the call of our callback so no need to check for race
+        // However writting to it has side effect so we set it to non mutable
+        let regs = Registers::new(unsafe { &*regs });
+
+        // SAFETY: `kp` is valid during the callback
+        let addr = unsafe { (*kp).addr } as usize;
+
+        T::pre_handler(data, addr, regs);
+
+        // The instruction pointer was not modified, the instruction must be single-stepped
+        0
+    }
+
+    /// # Safety
+    ///     Will be called only from C, prototype correspond to the kprobe's callback prototype
+    unsafe extern "C" fn post_handler_callback(
+        kp: *mut kprobe,
+        regs: *mut pt_regs,
+        _flags: core::ffi::c_ulong,
+    ) {
+        // SAFETY: This callback is called only when the kprobe is still registered
+        let data = unsafe { Self::data(kp) };
+
+        // SAFETY: The pointer is created at the call of our callback so no need to check for race
+        // However writting to it has side effect so we set it to non mutable
+        let regs = Registers::new(unsafe { &*regs });
+
+        // SAFETY: `kp` is valid during the callback
+        let addr = unsafe { (*kp).addr } as usize;
+
+        T::post_handler(data, addr, regs);
+    }
+}
+
+#[pinned_drop]
+impl<T: KprobeOperations> PinnedDrop for Kprobe<T> {
+    fn drop(self: Pin<&mut Self>) {
+        let inner = self.inner.get();
+
+        // SAFETY: We know the kprobe is registered by the type invariant
+        unsafe { bindings::unregister_kprobe(core::ptr::addr_of_mut!((*inner).kp)) };
+
+        // SAFETY: `unregister_kprobe` wait for the running handlers, so no one hold a
+        // Borrowed reference to the pointer, and we are in the Drop Impl so this is the
+        // first and last call to `from_foreign` corresponding exactly with the call to
+        // `into_foreign`
+        unsafe { T::Data::from_foreign((*inner).data as _) };
+    }
+}
@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Kprobe audit : the kprobes and kretprobes registered by other parties
+//!
+//! A kprobe is an easy way to hook a kernel function without patching its text : the
+//! pre handler can rewrite the registers of the probed function, or redirect it. Each
+//! active probe of `kprobe_table` is listed with its target symbol and the owner of its
+//! handler. A probe is flagged when it targets a security-critical function and its
+//! handler is :
+//! - in memory owned by nobody (a hidden module or a plain allocation)
+//! - in a module missing from the module list
+//!
+//! C header: [`include/linux/kprobes.h`](../../../../include/linux/kprobes.h)
+
+use core::fmt;
+
+use crate::address::{resolve_address, Owner};
+use crate::event::{Event, EventKind};
+use crate::hook_table::{for_each_kprobe, HookKind, RawHook};
+use crate::module::{is_module, MODULE_NAME_LEN};
+use crate::str::BStr;
+use kernel::prelude::*;
+
+/// Length of the target symbol name, longer names are truncated
+pub const SYMBOL_LEN: usize = 64;
+
+/// Functions on which a foreign probe is a strong sign of a rootkit
+const CRITICAL_TARGETS: &[&[u8]] = &[
+    b"commit_creds",
+    b"prepare_creds",
+    b"prepare_kernel_cred",
+    b"load_module",
+    b"do_init_module",
+    b"kallsyms_lookup_name",
+    b"filldir",
+    b"filldir64",
+    b"vfs_read",
+    b"vfs_write",
+    b"do_sys_openat2",
+    b"tcp4_seq_show",
+    b"tcp6_seq_show",
+    b"udp4_seq_show",
+    b"udp6_seq_show",
+    b"audit_log_start",
+];
+
+/// Prefixes of the security-critical functions (syscall entries and LSM hooks)
+const CRITICAL_PREFIXES: &[&[u8]] = &[b"__x64_sys_", b"__ia32_sys_", b"__arm64_sys_", b"security_"];
+
+/// Reason why a probe is flagged
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Finding {
+    /// The handler is in memory owned by nobody
+    HiddenMemory,
+    /// The handler is in a module missing from the module list
+    UnknownModule,
+}
+
+/// An active kprobe or kretprobe
+pub struct AuditedProbe {
+    /// Kprobe or kretprobe
+    pub kind: HookKind,
+    /// Address of the `struct kprobe`
+    pub object: u64,
+    /// Probed address
+    pub target: u64,
+    /// Name of the probed symbol, null terminated, empty if not found
+    pub target_symbol: [u8; SYMBOL_LEN],
+    /// Address of the pre handler (the handler for a kretprobe)
+    pub handler: u64,
+    /// Owner of the handler
+    pub owner: Owner,
+    /// The probed function is security-critical
+    pub critical: bool,
+    /// Why the probe is flagged, if it is
+    pub finding: Option<Finding>,
+}
+
+/// Check if a symbol is security-critical
+fn is_critical(name: &[u8]) -> bool {
+    CRITICAL_TARGETS.contains(&name)
+        || CRITICAL_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// Check if the module named `name` (null terminated) is on the module list
+fn is_listed(name: &[u8; MODULE_NAME_LEN]) -> bool {
+    let Some(len) = name.iter().position(|c| *c == 0) else {
+        return false;
+    };
+    CStr::from_bytes_with_nul(&name[..=len]).is_ok_and(is_module)
+}
+
+impl AuditedProbe {
+    /// Attribute the probe and flag it
+    fn new(raw: &RawHook) -> Self {
+        let mut probe = AuditedProbe {
+            kind: raw.kind,
+            object: raw.object,
+            target: raw.target,
+            target_symbol: [0; SYMBOL_LEN],
+            handler: raw.handler,
+            owner: Owner::None,
+            critical: false,
+            finding: None,
+        };
+
+        if let Ok(info) = resolve_address(raw.target) {
+            let name = info.symbol_name();
+            let len = name.len().min(SYMBOL_LEN - 1);
+            probe.target_symbol[..len].copy_from_slice(&name[..len]);
+            // A probe in the middle of a function is as good as one on its entry
+            probe.critical = is_critical(name);
+        }
+
+        if let Ok(info) = resolve_address(raw.handler) {
+            probe.owner = info.owner;
+        }
+
+        if probe.critical {
+            probe.finding = match &probe.owner {
+                Owner::Kernel => None,
+                Owner::Module(name) if is_listed(name) => None,
+                Owner::Module(_) => Some(Finding::UnknownModule),
+                Owner::None => Some(Finding::HiddenMemory),
+            };
+        }
+
+        probe
+    }
+
+    /// Get the name of the probed symbol (without the null terminator)
+    pub fn target_name(&self) -> &[u8] {
+        let len = self
+            .target_symbol
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(SYMBOL_LEN);
+        &self.target_symbol[..len]
+    }
+}
+
+/// Result of the audit
+pub struct KprobeAudit {
+    /// Every active probe
+    pub probes: KVec<AuditedProbe>,
+}
+
+impl KprobeAudit {
+    /// List the active kprobes and kretprobes and flag the suspicious ones
+    pub fn audit() -> Result<Self> {
+        // The walker runs under RCU so we can't allocate with GFP_KERNEL inside
+        let mut raw = KVec::new();
+        for_each_kprobe(|hook| {
+            let inactive = bindings::KPROBE_FLAG_GONE | bindings::KPROBE_FLAG_DISABLED;
+            if hook.flags & inactive as u64 == 0 {
+                raw.push(*hook, GFP_ATOMIC)?;
+            }
+            Ok(())
+        })?;
+
+        let mut probes = KVec::with_capacity(raw.len(), GFP_KERNEL)?;
+        for hook in raw.iter() {
+            probes.push(AuditedProbe::new(hook), GFP_KERNEL)?;
+        }
+
+        Ok(KprobeAudit { probes })
+    }
+
+    /// Get the flagged probes
+    pub fn suspicious(&self) -> impl Iterator<Item = &AuditedProbe> {
+        self.probes.iter().filter(|probe| probe.finding.is_some())
+    }
+
+    /// Create the event listing the flagged probes, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.suspicious().next().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousKprobe,
+            fmt!("suspicious probes on critical functions : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for KprobeAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, probe) in self.suspicious().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:?} on {} ({:#x}), handler {:#x} [{:?}], {:?}",
+                probe.kind,
+                BStr::from_bytes(probe.target_name()),
+                probe.target,
+                probe.handler,
+                probe.owner,
+                probe.finding
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -69,13 +69,105 @@
 pub mod uaccess;
 pub mod workqueue;
 
+pub mod address;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod analysis;
+#[cfg(CONFIG_BPF_SYSCALL)]
+pub mod bpf_audit;
+pub mod comm_audit;
+pub mod confinement_audit;
+pub mod control;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod control_flow;
+pub mod cred_audit;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod disasm;
+pub mod event;
+pub mod fake_kthread;
+#[cfg(target_arch = "x86_64")]
+pub mod fingerprint;
 pub mod fprobe;
+#[cfg(CONFIG_DYNAMIC_FTRACE)]
+pub mod ftrace_audit;
+#[cfg(target_arch = "x86_64")]
+pub mod hidden_module;
+pub mod hook_table;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
 pub mod insn;
+pub mod kprobe;
+pub mod kprobe_audit;
+#[cfg(CONFIG_LIVEPATCH)]
+pub mod livepatch;
+pub mod mm;
 pub mod module;
+pub mod module_integrity;
+pub mod module_metadata;
+pub mod module_params;
+pub mod module_signature;
+pub mod module_states;
+pub mod module_tampering;
+pub mod module_views;
+pub mod nofault;
+pub mod ns_audit;
+pub mod offsets;
+#[cfg(all(target_arch = "x86_64", CONFIG_UNWINDER_ORC))]
+pub mod orc_audit;
+#[cfg(all(
+    CONFIG_DYNAMIC_FTRACE,
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod patch_site;
+pub mod percpu;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
 pub mod pgtable;
+pub mod probe;
+pub mod probe_capacity;
+#[cfg(CONFIG_TRACEPOINTS)]
+pub mod process_monitor;
+#[cfg(CONFIG_ARCH_HAS_SET_MEMORY)]
+pub mod protection;
+#[cfg(target_arch = "x86_64")]
+pub mod protection_map;
+pub mod provenance;
+#[cfg(all(target_arch = "x86_64", CONFIG_MITIGATION_PAGE_TABLE_ISOLATION))]
+pub mod pti_audit;
+pub mod ptrace_monitor;
+pub mod registers;
+pub mod sampling;
+pub mod scoring;
+pub mod sensor_set;
 pub mod socket;
+#[cfg(target_arch = "x86_64")]
+pub mod stack_scan;
 pub mod stacktrace;
+pub mod symbol_map;
+#[cfg(all(
+    CONFIG_TRACEPOINTS,
+    CONFIG_HAVE_SYSCALL_TRACEPOINTS,
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod syscall_monitor;
+pub mod task_cgroup;
+pub mod task_files;
 pub mod task_iter;
+pub mod task_ns;
+pub mod task_security;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod text_alias;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod text_split;
+#[cfg(CONFIG_TRACEPOINTS)]
+pub mod tracepoint_probe;
+#[cfg(target_arch = "x86_64")]
+pub mod trap_scan;
+#[cfg(CONFIG_UPROBES)]
+pub mod uprobe;
+pub mod user_wx_audit;
+pub mod watchdog;
+#[cfg(target_arch = "x86_64")]
+pub mod write_tracker;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod wx_audit;
 
 #[doc(hidden)]
 pub use bindings;
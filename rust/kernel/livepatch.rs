@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Livepatch : the registered livepatches and the functions they replace
+//!
+//! Livepatching legitimately redirects kernel functions, which the text integrity checks
+//! would otherwise report as hooks. Each patch of `klp_patches` is listed with its module
+//! and the functions it replaces, so the patched ranges can be excluded from the
+//! baselines ([`LivepatchReport::is_patched`]).
+//!
+//! A livepatch is also a convenient way to hook a function with a signed-looking
+//! mechanism. A replacement function outside of the text of the patch module is flagged :
+//! the patch module only redirects the functions, the new code lives elsewhere.
+//!
+//! The list is walked under `klp_mutex`.
+//!
+//! C header: [`include/linux/livepatch.h`](../../../../include/linux/livepatch.h)
+
+use core::fmt;
+
+use crate::address::{resolve_address, Owner, RegionType};
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::{symbols_lookup_name, Module, MODULE_NAME_LEN};
+use crate::str::{copy_from_char_ptr, until_nul, BStr};
+use crate::sync::StaticCMutexGuard;
+use kernel::prelude::*;
+
+/// Length of the patched function name, longer names are truncated
+pub const SYMBOL_LEN: usize = 64;
+
+/// A function replaced by a livepatch
+pub struct PatchedFunction {
+    /// Name of the patched object (a module), empty for vmlinux
+    pub object: [u8; MODULE_NAME_LEN],
+    /// Name of the replaced function, null terminated
+    pub name: [u8; SYMBOL_LEN],
+    /// Address of the replaced function
+    pub old_func: u64,
+    /// Size of the replaced function
+    pub old_size: u64,
+    /// Address of the replacement function, 0 for a nop (the function is reverted)
+    pub new_func: u64,
+    /// The function is redirected (the patch is applied to it)
+    pub patched: bool,
+    /// The replacement function is not in the text of the patch module
+    pub outside_text: bool,
+}
+
+/// A registered livepatch
+pub struct Livepatch {
+    /// Address of the `struct klp_patch`
+    pub patch: u64,
+    /// Name of the patch module, null terminated
+    pub module: [u8; MODULE_NAME_LEN],
+    /// The patch is enabled
+    pub enabled: bool,
+    /// The patch replaces all the previous ones (cumulative patch)
+    pub replace: bool,
+    /// The replaced functions
+    pub functions: KVec<PatchedFunction>,
+}
+
+impl Livepatch {
+    /// Get the replacement functions outside of the text of the patch module
+    pub fn suspicious(&self) -> impl Iterator<Item = &PatchedFunction> {
+        self.functions.iter().filter(|func| func.outside_text)
+    }
+}
+
+/// Result of the check
+pub struct LivepatchReport {
+    /// The registered livepatches
+    pub patches: KVec<Livepatch>,
+}
+
+/// Check if `addr` is in the text of the module named `module`
+fn in_module_text(addr: u64, module: &[u8; MODULE_NAME_LEN]) -> bool {
+    let Ok(info) = resolve_address(addr) else {
+        return false;
+    };
+    match (info.owner, info.region) {
+        (Owner::Module(owner), RegionType::Module(mem_type)) => {
+            mem_type.is_text() && until_nul(&owner) == until_nul(module)
+        }
+        _ => false,
+    }
+}
+
+/// Read the functions of an object of a patch
+///
+/// # Safety
+///     `obj` must be an object of a registered patch and `klp_mutex` must be held
+unsafe fn read_functions(
+    obj: *const bindings::klp_object,
+    module: &[u8; MODULE_NAME_LEN],
+    functions: &mut KVec<PatchedFunction>,
+) -> Result {
+    // SAFETY: By the safety contract `obj` is valid, its name is a null terminated string
+    let object = unsafe { copy_from_char_ptr((*obj).name) };
+
+    // SAFETY: By the safety contract `obj` is valid, its functions list is only modified
+    // under `klp_mutex`
+    let head = unsafe { core::ptr::addr_of!((*obj).func_list) };
+    // SAFETY: `head` is a valid list_head
+    let mut entry = unsafe { (*head).next };
+    while !entry.is_null() && entry as *const _ != head {
+        // SAFETY: The entries of the list are the `node` field of a `struct klp_func`
+        let func = unsafe { crate::container_of!(entry, bindings::klp_func, node) };
+        // SAFETY: `func` is on the list, see above
+        let (old_name, old_func, old_size, new_func, nop, patched) = unsafe {
+            (
+                (*func).old_name,
+                (*func).old_func,
+                (*func).old_size,
+                (*func).new_func,
+                (*func).nop,
+                (*func).patched,
+            )
+        };
+
+        let new_func = if nop { 0 } else { new_func as u64 };
+        functions.push(
+            PatchedFunction {
+                object,
+                // SAFETY: `old_name` is a null terminated string living as long as `func`
+                name: unsafe { copy_from_char_ptr(old_name) },
+                old_func: old_func as u64,
+                old_size: old_size as u64,
+                new_func,
+                patched,
+                outside_text: new_func != 0 && !in_module_text(new_func, module),
+            },
+            GFP_KERNEL,
+        )?;
+
+        // SAFETY: `entry` is on the list, see above
+        entry = unsafe { (*entry).next };
+    }
+
+    Ok(())
+}
+
+impl LivepatchReport {
+    /// Walk `klp_patches` under `klp_mutex`
+    pub fn check() -> Result<Self> {
+        let head = symbols_lookup_name(c_str!("klp_patches")) as *const bindings::list_head;
+        if head.is_null() {
+            pr_err!("Couldn't find klp_patches symbol\n");
+            return Err(ENOENT);
+        }
+
+        let mut patches = KVec::new();
+
+        let _guard = StaticCMutexGuard::lock(c_str!("klp_mutex"))?;
+
+        // SAFETY: We hold `klp_mutex`, the list can't be modified
+        let mut entry = unsafe { (*head).next };
+        while !entry.is_null() && entry as *const _ != head {
+            // SAFETY: The entries of the list are the `list` field of a `struct klp_patch`
+            let patch = unsafe { crate::container_of!(entry, bindings::klp_patch, list) };
+            // SAFETY: `patch` is on the list and we hold `klp_mutex`, the patch module
+            // holds a reference on itself while the patch is registered
+            let (module, enabled, replace) = unsafe {
+                (
+                    Module::from_raw((*patch).mod_),
+                    (*patch).enabled,
+                    (*patch).replace,
+                )
+            };
+            let module = *module.raw_name();
+
+            let mut functions = KVec::new();
+            // SAFETY: `patch` is valid, see above
+            let objs = unsafe { core::ptr::addr_of!((*patch).obj_list) };
+            // SAFETY: `objs` is a valid list_head
+            let mut obj_entry = unsafe { (*objs).next };
+            while !obj_entry.is_null() && obj_entry as *const _ != objs {
+                // SAFETY: The entries of the list are the `node` field of a `struct klp_object`
+                let obj = unsafe { crate::container_of!(obj_entry, bindings::klp_object, node) };
+                // SAFETY: `obj` is an object of a registered patch and we hold `klp_mutex`
+                unsafe { read_functions(obj, &module, &mut functions)? };
+                // SAFETY: `obj_entry` is on the list, see above
+                obj_entry = unsafe { (*obj_entry).next };
+            }
+
+            patches.push(
+                Livepatch {
+                    patch: patch as u64,
+                    module,
+                    enabled,
+                    replace,
+                    functions,
+                },
+                GFP_KERNEL,
+            )?;
+
+            // SAFETY: We hold `klp_mutex`
+            entry = unsafe { (*entry).next };
+        }
+
+        Ok(LivepatchReport { patches })
+    }
+
+    /// Check if `addr` is in a function redirected by an enabled livepatch
+    ///
+    /// Modifications of the entry of these functions are expected
+    pub fn is_patched(&self, addr: u64) -> bool {
+        self.patches
+            .iter()
+            .filter(|patch| patch.enabled)
+            .flat_map(|patch| patch.functions.iter())
+            .any(|func| {
+                func.patched
+                    && (func.old_func..func.old_func + func.old_size.max(1)).contains(&addr)
+            })
+    }
+
+    /// Create the event listing the replacement functions outside of their patch module
+    /// text, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self
+            .patches
+            .iter()
+            .all(|patch| patch.suspicious().next().is_none())
+        {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousLivepatch,
+            fmt!("livepatches redirecting outside of their module : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for LivepatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for patch in self.patches.iter() {
+            for func in patch.suspicious() {
+                if !first {
+                    f.write_str(", ")?;
+                }
+                first = false;
+                write!(
+                    f,
+                    "{} ({} -> {:#x})",
+                    BStr::from_bytes(until_nul(&patch.module)),
+                    BStr::from_bytes(until_nul(&func.name)),
+                    func.new_func
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory descriptors : the address space of a task.
+//!
+//! An [`ARef<Mm>`] holds a reference on the users of a `struct mm_struct` (`mmget`), so its
+//! address space and its page tables are not torn down while it exists. The VMAs and the
+//! page tables of the user half are only stable while the mmap lock is held, through a
+//! [`MmapReadGuard`].
+//!
+//! C header: [`include/linux/mm_types.h`](srctree/include/linux/mm_types.h) and
+//! [`include/linux/mmap_lock.h`](srctree/include/linux/mmap_lock.h)
+
+use core::ptr;
+
+use crate::fs::File;
+use crate::task::Task;
+use crate::types::{ARef, AlwaysRefCounted, NotThreadSafe, Opaque};
+
+/// Wraps the kernel's `struct mm_struct`, with a reference on its users.
+///
+/// # Invariants
+///
+/// Instances of this type are always reference-counted with `mmget`, so `mm_users` is
+/// nonzero while they exist.
+#[repr(transparent)]
+pub struct Mm {
+    inner: Opaque<bindings::mm_struct>,
+}
+
+// SAFETY: `mmput` can be called from any thread where sleeping is allowed, like the drop of
+// the other reference-counted types.
+unsafe impl Send for Mm {}
+
+// SAFETY: The fields read through a shared reference are either constant or protected by the
+// mmap lock.
+unsafe impl Sync for Mm {}
+
+impl Mm {
+    /// Returns a raw pointer to the inner C struct.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut bindings::mm_struct {
+        self.inner.get()
+    }
+
+    /// Get the address space of the kernel, `init_mm`.
+    pub fn init_mm() -> &'static Mm {
+        // SAFETY: `init_mm` is static and its users never drop to zero.
+        // CAST: `Mm` is a `repr(transparent)` wrapper around `bindings::mm_struct`.
+        unsafe { &*ptr::addr_of_mut!(bindings::init_mm).cast::<Mm>() }
+    }
+
+    /// Get the address space of `task`, `None` for a kernel thread or an exiting task.
+    pub fn of_task(task: &Task) -> Option<ARef<Mm>> {
+        // SAFETY: It's always safe to call `get_task_mm` on a valid task.
+        let mm = unsafe { bindings::get_task_mm(task.as_ptr()) };
+        // SAFETY: `get_task_mm` took a reference on the users of `mm`, owned by the `ARef`.
+        // CAST: `Mm` is a `repr(transparent)` wrapper around `bindings::mm_struct`.
+        ptr::NonNull::new(mm).map(|mm| unsafe { ARef::from_raw(mm.cast::<Mm>()) })
+    }
+
+    /// Lock the mmap lock for reading, sleeping until it is available.
+    pub fn mmap_read_lock(&self) -> MmapReadGuard<'_> {
+        // SAFETY: By the type invariants, `self` is a valid mm.
+        unsafe { bindings::mmap_read_lock(self.as_ptr()) };
+        // INVARIANT: The lock was just taken above.
+        MmapReadGuard {
+            mm: self,
+            _not_send: NotThreadSafe,
+        }
+    }
+
+    /// Try to lock the mmap lock for reading, `None` if it is held for writing.
+    pub fn mmap_read_trylock(&self) -> Option<MmapReadGuard<'_>> {
+        // SAFETY: By the type invariants, `self` is a valid mm.
+        if !unsafe { bindings::mmap_read_trylock(self.as_ptr()) } {
+            return None;
+        }
+        // INVARIANT: The lock was just taken above.
+        Some(MmapReadGuard {
+            mm: self,
+            _not_send: NotThreadSafe,
+        })
+    }
+}
+
+// SAFETY: The type invariants guarantee that `Mm` is always refcounted.
+unsafe impl AlwaysRefCounted for Mm {
+    #[inline]
+    fn inc_ref(&self) {
+        // SAFETY: The existence of a shared reference means that `mm_users` is nonzero.
+        unsafe { bindings::mmget(self.as_ptr()) };
+    }
+
+    #[inline]
+    unsafe fn dec_ref(obj: ptr::NonNull<Mm>) {
+        // SAFETY: The safety requirements guarantee that `mm_users` is nonzero.
+        unsafe { bindings::mmput(obj.cast().as_ptr()) };
+    }
+}
+
+/// Evidence that the mmap lock of an [`Mm`] is held for reading.
+///
+/// The type is explicitly not `Send` because a rwsem must be unlocked by its owner.
+///
+/// # Invariants
+///
+/// The mmap lock of `mm` is held for reading while the guard exists.
+pub struct MmapReadGuard<'a> {
+    mm: &'a Mm,
+    _not_send: NotThreadSafe,
+}
+
+impl MmapReadGuard<'_> {
+    /// Get the locked address space.
+    pub fn mm(&self) -> &Mm {
+        self.mm
+    }
+
+    /// Get the VMA containing `address`.
+    pub fn vma_lookup(&self, address: usize) -> Option<&VmArea> {
+        // SAFETY: By the type invariants, the mmap lock is held.
+        let vma = unsafe { bindings::vma_lookup(self.mm.as_ptr(), address as _) };
+        // SAFETY: The VMA is not freed while the mmap lock is held, which outlives the
+        // returned reference.
+        (!vma.is_null()).then(|| unsafe { VmArea::from_ptr(vma) })
+    }
+
+    /// Get the first VMA ending after `address`, it may start after `address`.
+    pub fn find_vma(&self, address: usize) -> Option<&VmArea> {
+        // SAFETY: By the type invariants, the mmap lock is held.
+        let vma = unsafe { bindings::find_vma(self.mm.as_ptr(), address as _) };
+        // SAFETY: The VMA is not freed while the mmap lock is held, which outlives the
+        // returned reference.
+        (!vma.is_null()).then(|| unsafe { VmArea::from_ptr(vma) })
+    }
+}
+
+impl Drop for MmapReadGuard<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the lock is held for reading.
+        unsafe { bindings::mmap_read_unlock(self.mm.as_ptr()) };
+    }
+}
+
+/// Wraps the kernel's `struct vm_area_struct`, a range of an address space.
+///
+/// # Invariants
+///
+/// The VMA is only accessed while the mmap lock of its mm is held.
+#[repr(transparent)]
+pub struct VmArea {
+    inner: Opaque<bindings::vm_area_struct>,
+    _not_send: NotThreadSafe,
+}
+
+impl VmArea {
+    /// Creates a reference to a [`VmArea`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` is valid and that the mmap lock of its mm is held for
+    /// the lifetime of the returned [`VmArea`] reference.
+    unsafe fn from_ptr<'a>(ptr: *const bindings::vm_area_struct) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `VmArea` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns a raw pointer to the inner C struct.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut bindings::vm_area_struct {
+        self.inner.get()
+    }
+
+    /// Start of the VMA.
+    pub fn start(&self) -> usize {
+        // SAFETY: By the type invariants, the mmap lock is held so the field is stable.
+        unsafe { (*self.as_ptr()).__bindgen_anon_1.__bindgen_anon_1.vm_start as _ }
+    }
+
+    /// End of the VMA, excluded.
+    pub fn end(&self) -> usize {
+        // SAFETY: By the type invariants, the mmap lock is held so the field is stable.
+        unsafe { (*self.as_ptr()).__bindgen_anon_1.__bindgen_anon_1.vm_end as _ }
+    }
+
+    /// The `VM_*` flags of the VMA.
+    pub fn flags(&self) -> u64 {
+        // SAFETY: By the type invariants, the mmap lock is held so the field is stable.
+        unsafe { (*self.as_ptr()).__bindgen_anon_2.vm_flags as _ }
+    }
+
+    /// The VMA is not backed by a file.
+    pub fn is_anonymous(&self) -> bool {
+        // SAFETY: By the type invariants, the mmap lock is held so the field is stable.
+        unsafe { (*self.as_ptr()).vm_file.is_null() }
+    }
+
+    /// The file backing the VMA, if any.
+    pub fn file(&self) -> Option<&File> {
+        // SAFETY: By the type invariants, the mmap lock is held so the field is stable.
+        let file = unsafe { (*self.as_ptr()).vm_file };
+        // SAFETY: The VMA holds a reference on its file, which outlives the returned reference.
+        (!file.is_null()).then(|| unsafe { File::from_raw_file(file) })
+    }
+}
+
+/// A snapshot of a VMA, usable once the mmap lock is released.
+pub struct VmaInfo {
+    /// Start of the VMA.
+    pub start: usize,
+    /// End of the VMA, excluded.
+    pub end: usize,
+    /// The `VM_*` flags of the VMA.
+    pub flags: u64,
+    /// The file backing the VMA, if any.
+    pub file: Option<ARef<File>>,
+}
+
+impl VmaInfo {
+    /// Take a snapshot of `vma`.
+    pub fn of(vma: &VmArea) -> Self {
+        Self {
+            start: vma.start(),
+            end: vma.end(),
+            flags: vma.flags(),
+            file: vma.file().map(ARef::from),
+        }
+    }
+
+    /// The VMA can be read.
+    pub fn is_readable(&self) -> bool {
+        self.flags & bindings::VM_READ as u64 != 0
+    }
+
+    /// The VMA can be written.
+    pub fn is_writable(&self) -> bool {
+        self.flags & bindings::VM_WRITE as u64 != 0
+    }
+
+    /// The VMA can be executed.
+    pub fn is_executable(&self) -> bool {
+        self.flags & bindings::VM_EXEC as u64 != 0
+    }
+
+    /// The VMA is shared with the other mappings of its file.
+    pub fn is_shared(&self) -> bool {
+        self.flags & bindings::VM_SHARED as u64 != 0
+    }
+}
+
+/// Iterator over the VMAs of an address space, in increasing order, holding its mmap lock for
+/// reading.
+///
+/// # Invariants
+///
+/// The mmap lock of `mm` is held for reading while the iterator exists.
+pub struct VmaIter {
+    mm: ARef<Mm>,
+    /// The next VMA ends after this address.
+    address: usize,
+    _not_send: NotThreadSafe,
+}
+
+impl VmaIter {
+    /// Lock the mmap lock of `mm` for reading, sleeping until it is available, and iterate over
+    /// its VMAs.
+    pub fn new(mm: ARef<Mm>) -> Self {
+        // SAFETY: By the type invariants of `Mm`, `mm` is a valid mm.
+        unsafe { bindings::mmap_read_lock(mm.as_ptr()) };
+        // INVARIANT: The lock was just taken above.
+        Self {
+            mm,
+            address: 0,
+            _not_send: NotThreadSafe,
+        }
+    }
+}
+
+impl Iterator for VmaIter {
+    type Item = VmaInfo;
+
+    fn next(&mut self) -> Option<VmaInfo> {
+        // SAFETY: By the type invariants, the mmap lock is held.
+        let vma = unsafe { bindings::find_vma(self.mm.as_ptr(), self.address as _) };
+        if vma.is_null() {
+            return None;
+        }
+        // SAFETY: The VMA is not freed while the mmap lock is held, the reference doesn't outlive
+        // this call.
+        let vma = unsafe { VmArea::from_ptr(vma) };
+        self.address = vma.end();
+        Some(VmaInfo::of(vma))
+    }
+}
+
+impl Drop for VmaIter {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the lock is held for reading.
+        unsafe { bindings::mmap_read_unlock(self.mm.as_ptr()) };
+    }
+}
+
+impl Task {
+    /// Iterate over the VMAs of the address space of the task, `None` for a kernel thread or an
+    /// exiting task.
+    ///
+    /// The mmap lock is held until the iterator is dropped.
+    pub fn vmas(&self) -> Option<VmaIter> {
+        Mm::of_task(self).map(VmaIter::new)
+    }
+}
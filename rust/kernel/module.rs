@@ -3,45 +3,283 @@
 //! C headers: [`include/linux/module.h`](../../../../include/linux/module.h)
 
 use crate::alloc::allocator::Kmalloc;
+use crate::alloc::IntoIter;
+use crate::offsets::Field;
 use crate::str::CStr;
-use crate::types::{ARef, AlwaysRefCounted, Opaque};
+use crate::symbol_map;
+use crate::sync::{new_mutex, rcu, Mutex, StaticCMutexGuard};
+use crate::types::{ARef, AlwaysRefCounted, ForeignOwnable, Opaque};
 use crate::{c_str, container_of};
 use bindings::KSYM_NAME_LEN;
 use core::ffi::c_ulong;
+use core::marker::PhantomData;
 use core::mem::transmute;
-use core::ptr::addr_of;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
 use kernel::prelude::*;
 
+/// Maximum length of a module name, including the null terminator (`MODULE_NAME_LEN`)
+pub const MODULE_NAME_LEN: usize = 64 - core::mem::size_of::<c_ulong>();
+
 type OptModName = Option<KVec<u8>>;
 type OptSymName = Option<KVec<u8>>;
 
-/// Get the symbol type :
+/// Type of a kernel symbol, as reported by `/proc/kallsyms`
+///
+/// Some of the common types :
 /// - 'T' : exported text symbol
 /// - 't' : non-exported text symbol
 /// - 'D' : exported data symbol
 /// - 'd' : non-exported data symbol
-/// How to do it :
-///     First we get the symbol index (position) in the kallsyms array : get_symbol_pos
-///     Next we get the offset in the compressed stream : get_symbol_offset
-///     Finally we get the type with : kallsyms_get_symbol_type, it seems like this function as of linux 6.14-rc4 has a small bug (which never trigger but anyway this is not good)
-///     ^
-///     |
-///    This is the theory, in practice thoses symbols are either inlined or not accessible which make the process a "bit" messier  
-/*fn symbol_type() -> Result<char> {
+///
+/// Since linux 6.2 the type is stored as the first character of the compressed
+/// symbol name, so we get it for free while decompressing the symbol.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SymbolType(u8);
+
+impl SymbolType {
+    /// Create a symbol type from the raw kallsyms type character
+    pub const fn from_raw(c: u8) -> Self {
+        SymbolType(c)
+    }
+
+    /// Get the raw type character
+    pub const fn as_char(&self) -> char {
+        self.0 as char
+    }
+
+    /// The symbol is global (exported), the type character is uppercase
+    pub const fn is_exported(&self) -> bool {
+        self.0.is_ascii_uppercase()
+    }
+
+    /// The symbol is in a text section
+    pub const fn is_text(&self) -> bool {
+        matches!(self.0, b'T' | b't')
+    }
+
+    /// The symbol is in a data section (initialized, read-only or bss)
+    pub const fn is_data(&self) -> bool {
+        matches!(self.0, b'D' | b'd' | b'R' | b'r' | b'B' | b'b')
+    }
+
+    /// The symbol is in a read-only data section
+    pub const fn is_rodata(&self) -> bool {
+        matches!(self.0, b'R' | b'r')
+    }
+}
+
+/// Prototype of `kallsyms_on_each_symbol`
+type KallsymsOnEachSymbol = unsafe extern "C" fn(
+    Option<unsafe extern "C" fn(*mut core::ffi::c_void, *const core::ffi::c_char, c_ulong) -> i32>,
+    *mut core::ffi::c_void,
+) -> i32;
+
+/// Mirror of `struct kernel_symbol` (private to `kernel/module/internal.h`), an entry
+/// of the `__ksymtab` and `__ksymtab_gpl` sections
+#[cfg(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS)]
+#[allow(dead_code)]
+#[repr(C)]
+struct KernelSymbol {
+    value_offset: i32,
+    name_offset: i32,
+    namespace_offset: i32,
+}
+
+/// Mirror of `struct kernel_symbol` (private to `kernel/module/internal.h`), an entry
+/// of the `__ksymtab` and `__ksymtab_gpl` sections
+#[cfg(not(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS))]
+#[allow(dead_code)]
+#[repr(C)]
+struct KernelSymbol {
+    value: c_ulong,
+    name: *const core::ffi::c_char,
+    namespace: *const core::ffi::c_char,
+}
+
+impl KernelSymbol {
+    /// Resolve a field holding an offset relative to itself, as `offset_to_ptr()`
+    #[cfg(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS)]
+    fn offset_to_ptr(field: &i32) -> u64 {
+        (field as *const i32 as u64).wrapping_add(*field as i64 as u64)
+    }
+
+    /// Address of the exported symbol
+    fn value(&self) -> u64 {
+        #[cfg(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS)]
+        {
+            Self::offset_to_ptr(&self.value_offset)
+        }
+        #[cfg(not(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS))]
+        {
+            self.value as u64
+        }
+    }
+
+    /// Name of the exported symbol
+    fn name(&self) -> *const core::ffi::c_char {
+        #[cfg(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS)]
+        {
+            Self::offset_to_ptr(&self.name_offset) as _
+        }
+        #[cfg(not(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS))]
+        {
+            self.name
+        }
+    }
+}
+
+/// Bounds of the sections of the kernel image and its exported symbols, to compute
+/// the symbol types when they are not given by the backend
+struct KernelSections {
+    text: (u64, u64),
+    rodata: (u64, u64),
+    data: (u64, u64),
+    bss: (u64, u64),
+    /// Address and name of the symbols of the kernel ksymtab, sorted by address
+    exported: KVec<(u64, &'static CStr)>,
+}
 
-}*/
+impl KernelSections {
+    fn new() -> Result<Self> {
+        let range =
+            |start: &CStr, end: &CStr| (symbols_lookup_name(start), symbols_lookup_name(end));
 
+        let mut exported = KVec::new();
+        for (start, end) in [
+            range(c_str!("__start___ksymtab"), c_str!("__stop___ksymtab")),
+            range(
+                c_str!("__start___ksymtab_gpl"),
+                c_str!("__stop___ksymtab_gpl"),
+            ),
+        ] {
+            if start == 0 || end < start {
+                continue;
+            }
+            let count = (end - start) as usize / core::mem::size_of::<KernelSymbol>();
+            // SAFETY: The section between `start` and `end` is an array of
+            // `struct kernel_symbol` of the kernel image, never freed
+            let symbols =
+                unsafe { core::slice::from_raw_parts(start as *const KernelSymbol, count) };
+            exported.reserve(count, GFP_KERNEL)?;
+            for sym in symbols {
+                // SAFETY: The name of an exported symbol is a null terminated string of
+                // the kernel image, never freed
+                let name = unsafe { CStr::from_char_ptr(sym.name()) };
+                exported.push((sym.value(), name), GFP_KERNEL)?;
+            }
+        }
+        exported.sort_unstable_by_key(|(address, _)| *address);
+
+        Ok(KernelSections {
+            text: range(c_str!("_stext"), c_str!("_etext")),
+            rodata: range(c_str!("__start_rodata"), c_str!("__end_rodata")),
+            data: range(c_str!("_sdata"), c_str!("_edata")),
+            bss: range(c_str!("__bss_start"), c_str!("__bss_stop")),
+            exported,
+        })
+    }
+
+    /// The symbol is in the ksymtab, comparing the name to not count its aliases
+    fn is_exported(&self, name: &[u8], address: u64) -> bool {
+        let first = self.exported.partition_point(|(a, _)| *a < address);
+        self.exported[first..]
+            .iter()
+            .take_while(|(a, _)| *a == address)
+            .any(|(_, n)| n.as_bytes() == name)
+    }
+
+    /// Approximate the type of a symbol from the section containing it, it is uppercase
+    /// when the symbol is in the ksymtab
+    fn sym_type(&self, name: &[u8], address: u64) -> SymbolType {
+        let inside = |(start, end): (u64, u64)| start != 0 && (start..end).contains(&address);
+        let raw = if inside(self.text) {
+            b't'
+        } else if inside(self.rodata) {
+            b'r'
+        } else if inside(self.data) {
+            b'd'
+        } else if inside(self.bss) {
+            b'b'
+        } else {
+            b'?'
+        };
+        if raw != b'?' && self.is_exported(name, address) {
+            SymbolType::from_raw(raw.to_ascii_uppercase())
+        } else {
+            SymbolType::from_raw(raw)
+        }
+    }
+}
+
+/// How the kernel symbols are enumerated
+enum SymbolBackend {
+    /// Through `kallsyms_on_each_symbol`, which doesn't depend on the kallsyms encoding
+    OnEachSymbol {
+        on_each_symbol: KallsymsOnEachSymbol,
+        sections: KernelSections,
+    },
+    /// By decompressing the kallsyms tables ourselves
+    Expand {
+        kallsyms_name: *const u8,
+        kallsyms_token_index: *const u16,
+        kallsyms_token_table: *const u8,
+        kallsyms_sym_address: extern "C" fn(i32) -> u64,
+    },
+}
+
+/// The closure and the state of an [`SymbolInfo::on_each`] call through
+/// `kallsyms_on_each_symbol`
+struct OnEachContext<'a, F> {
+    f: &'a mut F,
+    buffer: &'a mut [u8; KSYM_NAME_LEN as _],
+    sections: &'a KernelSections,
+    ret: Result<()>,
+}
+
+/// Enumerate the kernel symbols
+///
+/// `kallsyms_on_each_symbol` is used when available, otherwise the kallsyms tables are
+/// decompressed manually, which is fragile across kernel versions.
 pub struct SymbolInfo {
     kallsyms_num_syms: u32,
-    kallsyms_name: *const u8,
-    kallsyms_token_index: *const u16,
-    kallsyms_token_table: *const u8,
-    kallsyms_sym_address: extern "C" fn(i32) -> u64,
+    backend: SymbolBackend,
 }
 
 impl SymbolInfo {
     /// Create a new instance in a faillible way
+    ///
+    /// Use `kallsyms_on_each_symbol` if the symbol is available, the manual
+    /// decompression otherwise
     pub fn try_new() -> Result<Self> {
+        let on_each_symbol = symbols_lookup_name(c_str!("kallsyms_on_each_symbol")) as *const ();
+        if on_each_symbol.is_null() {
+            return Self::try_new_expand();
+        }
+
+        let pkallsyms_num_syms: *const u32 = symbols_lookup_name(c_str!("kallsyms_num_syms")) as _;
+        let kallsyms_num_syms = if pkallsyms_num_syms.is_null() {
+            0
+        } else {
+            // SAFETY: `kallsyms_num_syms` is a constant of the kernel image
+            unsafe { *pkallsyms_num_syms }
+        };
+
+        Ok(SymbolInfo {
+            kallsyms_num_syms,
+            backend: SymbolBackend::OnEachSymbol {
+                // SAFETY: The symbol is the function `kallsyms_on_each_symbol` which has
+                // this prototype
+                on_each_symbol: unsafe {
+                    transmute::<*const (), KallsymsOnEachSymbol>(on_each_symbol)
+                },
+                sections: KernelSections::new()?,
+            },
+        })
+    }
+
+    /// Create a new instance decompressing the kallsyms tables manually
+    pub fn try_new_expand() -> Result<Self> {
         let kallsyms_name: *const u8 = symbols_lookup_name(c_str!("kallsyms_names")) as _;
         if kallsyms_name.is_null() {
             pr_err!("Couldn't find kallsyms_name symbol\n");
@@ -80,20 +318,34 @@ pub fn try_new() -> Result<Self> {
 
         Ok(SymbolInfo {
             kallsyms_num_syms,
-            kallsyms_name,
-            kallsyms_token_index,
-            kallsyms_token_table,
-            kallsyms_sym_address,
+            backend: SymbolBackend::Expand {
+                kallsyms_name,
+                kallsyms_token_index,
+                kallsyms_token_table,
+                kallsyms_sym_address,
+            },
         })
     }
 
     /// Does pretty much the same thing as `kallsyms_expand_symbol()` expect it copy also the type information as I need it
+    ///
+    /// Return the offset of the next symbol and the type of the expanded symbol
     fn expand_symbols(
         &self,
         mut off: usize,
         buffer: &mut [u8; KSYM_NAME_LEN as _],
-    ) -> Result<usize> {
-        let mut data: *const u8 = self.kallsyms_name.wrapping_add(off);
+    ) -> Result<(usize, SymbolType)> {
+        let SymbolBackend::Expand {
+            kallsyms_name,
+            kallsyms_token_index,
+            kallsyms_token_table,
+            ..
+        } = self.backend
+        else {
+            return Err(EINVAL);
+        };
+
+        let mut data: *const u8 = kallsyms_name.wrapping_add(off);
         let mut len: usize = unsafe { *data } as _;
 
         data = data.wrapping_add(1);
@@ -113,8 +365,8 @@ fn expand_symbols(
 
         'outer: while len != 0 {
             // We get a pointer to a token and we copy the token to the buffer, that way we decompress the symbol
-            let token_index = unsafe { *self.kallsyms_token_index.wrapping_add(*data as _) };
-            let mut ptoken = self.kallsyms_token_table.wrapping_add(token_index as _);
+            let token_index = unsafe { *kallsyms_token_index.wrapping_add(*data as _) };
+            let mut ptoken = kallsyms_token_table.wrapping_add(token_index as _);
 
             while unsafe { *ptoken } != 0 {
                 if let Some(r) = buffer.get_mut(i) {
@@ -132,30 +384,493 @@ fn expand_symbols(
 
         let buffer_len = buffer.len();
 
+        // The first decompressed character is the symbol type
+        let sym_type = if i != 0 {
+            SymbolType::from_raw(buffer[0])
+        } else {
+            SymbolType::from_raw(b'?')
+        };
+
         if let Some(r) = buffer.get_mut(i) {
             *r = 0;
         } else if let Some(r) = buffer.get_mut(buffer_len - 1) {
             *r = 0;
         }
 
-        return Ok(off);
+        return Ok((off, sym_type));
     }
 
     /// Iterate over all the kernel symbols calling on each the passed closure
     /// the furnished argument to the closure are :
     ///     - The buffer containing the name of the symbol (prepanded with the symbol section)
     ///     - The address of the symbol
-    pub fn on_each(&self, f: impl Fn(&[u8; KSYM_NAME_LEN as _], u64) -> Result<()>) -> Result<()> {
-        let mut off = 0;
+    ///     - The type of the symbol
+    ///
+    /// With the `kallsyms_on_each_symbol` backend the type is approximated from the
+    /// section containing the symbol and the ksymtab, see [`KernelSections::sym_type`].
+    pub fn on_each<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8; KSYM_NAME_LEN as _], u64, SymbolType) -> Result<()>,
+    {
         let mut buffer = KBox::new([0_u8; KSYM_NAME_LEN as _], GFP_KERNEL)?;
-        for i in 0..self.kallsyms_num_syms {
-            off = self.expand_symbols(off, &mut buffer)?;
 
-            let address = (self.kallsyms_sym_address)(i as _);
+        let (on_each_symbol, sections) = match &self.backend {
+            SymbolBackend::OnEachSymbol {
+                on_each_symbol,
+                sections,
+            } => (*on_each_symbol, sections),
+            SymbolBackend::Expand {
+                kallsyms_sym_address,
+                ..
+            } => {
+                let mut off = 0;
+                for i in 0..self.kallsyms_num_syms {
+                    let (next_off, sym_type) = self.expand_symbols(off, &mut buffer)?;
+                    off = next_off;
+
+                    let address = kallsyms_sym_address(i as _);
+
+                    f(&buffer, address, sym_type)?;
+                }
+                return Ok(());
+            }
+        };
+
+        let mut context = OnEachContext {
+            f: &mut f,
+            buffer: &mut buffer,
+            sections,
+            ret: Ok(()),
+        };
+        // SAFETY: Just an FFI call, `context` outlive the call and has the type expected
+        // by the callback
+        unsafe {
+            on_each_symbol(
+                Some(Self::on_each_callback::<F>),
+                &mut context as *mut OnEachContext<'_, F> as *mut core::ffi::c_void,
+            )
+        };
+        context.ret
+    }
+
+    /// # Safety
+    ///     Will be called only from C, prototype correspond to the `kallsyms_on_each_symbol`
+    ///     callback prototype, `data` point to an `OnEachContext<F>`
+    unsafe extern "C" fn on_each_callback<F>(
+        data: *mut core::ffi::c_void,
+        name: *const core::ffi::c_char,
+        address: c_ulong,
+    ) -> i32
+    where
+        F: FnMut(&[u8; KSYM_NAME_LEN as _], u64, SymbolType) -> Result<()>,
+    {
+        // SAFETY: `data` is the `OnEachContext` given to `kallsyms_on_each_symbol` by
+        // `on_each`, which is alive and not accessed elsewhere during the call
+        let context = unsafe { &mut *(data as *mut OnEachContext<'_, F>) };
+        // SAFETY: `name` is a null terminated string valid during the call
+        let name = unsafe { CStr::from_char_ptr(name) }.as_bytes();
+
+        // Same layout as the manual backend : the type, then the null terminated name
+        let sym_type = context.sections.sym_type(name, address as u64);
+        let len = name.len().min(context.buffer.len() - 2);
+        context.buffer[0] = sym_type.as_char() as u8;
+        context.buffer[1..=len].copy_from_slice(&name[..len]);
+        context.buffer[len + 1] = 0;
+
+        match (context.f)(context.buffer, address as u64, sym_type) {
+            Ok(()) => 0,
+            Err(e) => {
+                context.ret = Err(e);
+                // Stop the iteration
+                1
+            }
+        }
+    }
+}
+
+/// A symbol stored in a [`SymbolIndex`]
+#[derive(Clone, Copy)]
+pub struct IndexedSymbol {
+    /// Address of the symbol
+    pub address: u64,
+    /// Type of the symbol
+    pub sym_type: SymbolType,
+    name_off: u32,
+    name_len: u32,
+}
+
+/// Sorted, binary-searchable copy of the kernel and modules symbols
+///
+/// Built once from [`SymbolInfo`], so the lookups doesn't need to decompress
+/// the whole kallsyms stream each time.
+pub struct SymbolIndex {
+    /// The symbols sorted by address
+    symbols: KVVec<IndexedSymbol>,
+    /// Index in `symbols` sorted by name
+    by_name: KVVec<u32>,
+    /// Pool containing all the names (not null terminated)
+    names: KVVec<u8>,
+}
+
+impl SymbolIndex {
+    /// Build the index by iterating over all the kernel and modules symbols
+    pub fn build(info: &SymbolInfo) -> Result<Self> {
+        let mut symbols = KVVec::with_capacity(info.kallsyms_num_syms as _, GFP_KERNEL)?;
+        let mut names = KVVec::new();
+
+        info.on_each(|buffer, address, sym_type| {
+            // The first character is the type, the name follow it
+            let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+            let name = buffer.get(1..len).unwrap_or(&[]);
+
+            let name_off = names.len() as u32;
+            names.extend_from_slice(name, GFP_KERNEL)?;
+
+            symbols.push(
+                IndexedSymbol {
+                    address,
+                    sym_type,
+                    name_off,
+                    name_len: name.len() as u32,
+                },
+                GFP_KERNEL,
+            )?;
+            Ok(())
+        })?;
+
+        // The symbols of the modules are in their own ELF symbol table
+        for module in ModuleIter::try_new()? {
+            // The table is switched to the core symbols after the module is live, the
+            // initializing table is then freed after a grace period: it is only read
+            // under RCU. The space is reserved before, the table can only shrink
+            let (count, names_len) = {
+                let _guard = rcu::read_lock();
+                if module.state() != ModuleState::Live {
+                    continue;
+                }
+                // SAFETY: We hold the RCU read lock
+                unsafe { module.symbols() }.fold((0, 0), |(count, len), sym| {
+                    (count + 1, len + sym.name.as_bytes().len())
+                })
+            };
+            symbols.reserve(count, GFP_KERNEL)?;
+            names.reserve(names_len, GFP_KERNEL)?;
 
-            f(&buffer, address)?;
+            let _guard = rcu::read_lock();
+            // SAFETY: We hold the RCU read lock
+            for sym in unsafe { module.symbols() } {
+                let name = sym.name.as_bytes();
+                let name_off = names.len() as u32;
+                names.extend_from_slice(name, GFP_ATOMIC)?;
+
+                symbols.push(
+                    IndexedSymbol {
+                        address: sym.address,
+                        sym_type: sym.sym_type,
+                        name_off,
+                        name_len: name.len() as u32,
+                    },
+                    GFP_ATOMIC,
+                )?;
+            }
+        }
+
+        Self::from_parts(symbols, names)
+    }
+
+    /// Sort the symbols and create the name index
+    fn from_parts(mut symbols: KVVec<IndexedSymbol>, names: KVVec<u8>) -> Result<Self> {
+        symbols.sort_unstable_by_key(|sym| sym.address);
+
+        let mut by_name = KVVec::with_capacity(symbols.len(), GFP_KERNEL)?;
+        for i in 0..symbols.len() {
+            by_name.push(i as u32, GFP_KERNEL)?;
         }
-        Ok(())
+
+        let name_of = |i: &u32| {
+            let sym = &symbols[*i as usize];
+            &names[sym.name_off as usize..(sym.name_off + sym.name_len) as usize]
+        };
+        by_name.sort_unstable_by(|a, b| name_of(a).cmp(name_of(b)));
+
+        Ok(SymbolIndex {
+            symbols,
+            by_name,
+            names,
+        })
+    }
+
+    /// Number of symbols in the index
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// The index doesn't contain any symbol
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Get the name of an indexed symbol (without the null terminator)
+    pub fn name(&self, sym: &IndexedSymbol) -> &[u8] {
+        self.names
+            .get(sym.name_off as usize..(sym.name_off + sym.name_len) as usize)
+            .unwrap_or(&[])
+    }
+
+    /// Find the symbol containing `addr`
+    ///
+    /// # Return
+    /// The nearest symbol whose address is lower or equal to `addr` and the offset of `addr` from it
+    pub fn lookup_addr(&self, addr: u64) -> Option<(&IndexedSymbol, u64)> {
+        let pos = self.symbols.partition_point(|sym| sym.address <= addr);
+        let sym = self.symbols.get(pos.checked_sub(1)?)?;
+        Some((sym, addr - sym.address))
+    }
+
+    /// Find a symbol by its name
+    pub fn lookup_name(&self, name: &[u8]) -> Option<&IndexedSymbol> {
+        let i = self
+            .by_name
+            .binary_search_by(|i| self.name(&self.symbols[*i as usize]).cmp(name))
+            .ok()?;
+        self.symbols.get(self.by_name[i] as usize)
+    }
+
+    /// Get all the symbols whose address is in `[start, end)`
+    pub fn range(&self, start: u64, end: u64) -> &[IndexedSymbol] {
+        let first = self.symbols.partition_point(|sym| sym.address < start);
+        let last = self.symbols.partition_point(|sym| sym.address < end);
+        self.symbols.get(first..last).unwrap_or(&[])
+    }
+}
+
+/// A [`SymbolIndex`] rebuilt lazily after each module load or unload
+///
+/// # Invariants
+///
+///     `nb` is registered on the module notifier chain
+#[pin_data(PinnedDrop)]
+pub struct SymbolIndexCache {
+    #[pin]
+    index: Mutex<Option<SymbolIndex>>,
+    stale: AtomicBool,
+    #[pin]
+    nb: Opaque<bindings::notifier_block>,
+}
+
+// SAFETY: The notifier block is only used by the notifier chain which has its own locking,
+// the index is protected by a mutex and `stale` is atomic
+unsafe impl Sync for SymbolIndexCache {}
+
+// SAFETY: The notifier can be unregistered from any thread
+unsafe impl Send for SymbolIndexCache {}
+
+impl SymbolIndexCache {
+    /// Create a new cache, the index will be built at the first access
+    pub fn new() -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            index <- new_mutex!(None),
+            stale: AtomicBool::new(true),
+            // The notifier is registered last so that the callback see the other fields initialized
+            nb <- Opaque::try_ffi_init(|slot: *mut bindings::notifier_block| {
+                // SAFETY: The initializer can write to the provided `slot`.
+                unsafe {
+                    slot.write(bindings::notifier_block {
+                        notifier_call: Some(Self::module_notifier_callback),
+                        next: core::ptr::null_mut(),
+                        priority: 0,
+                    })
+                };
+
+                // SAFETY: `slot` is a filled notifier block pinned in our structure, it will
+                // be unregistered in the drop
+                // INVARIANT: if this return `Ok(())` the notifier block is registered
+                crate::error::to_result(unsafe { bindings::register_module_notifier(slot) })
+            }),
+        })
+    }
+
+    /// # Safety
+    ///     Will be called only from C, prototype correspond to the notifier's callback prototype
+    unsafe extern "C" fn module_notifier_callback(
+        nb: *mut bindings::notifier_block,
+        action: core::ffi::c_ulong,
+        _data: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: The notifier block is embedded in a `SymbolIndexCache` which is alive while
+        // the notifier is registered
+        let this = unsafe { &*container_of!(nb, Self, nb) };
+
+        let action = action as u32;
+        if action == bindings::module_state_MODULE_STATE_LIVE
+            || action == bindings::module_state_MODULE_STATE_GOING
+        {
+            this.stale.store(true, Ordering::Release);
+        }
+
+        bindings::NOTIFY_DONE as _
+    }
+
+    /// Mark the index as stale, it will be rebuilt at the next access
+    pub fn invalidate(&self) {
+        self.stale.store(true, Ordering::Release);
+    }
+
+    /// Call `f` with an up to date index, rebuilding it if needed
+    pub fn with<R>(&self, f: impl FnOnce(&SymbolIndex) -> R) -> Result<R> {
+        let mut guard = self.index.lock();
+
+        if self.stale.swap(false, Ordering::AcqRel) || guard.is_none() {
+            // The flag is cleared before the build so a module loaded meanwhile marks the
+            // new index stale, and set again by any failure so the old index is never used
+            match SymbolInfo::try_new().and_then(|info| SymbolIndex::build(&info)) {
+                Ok(index) => *guard = Some(index),
+                Err(e) => {
+                    self.stale.store(true, Ordering::Release);
+                    return Err(e);
+                }
+            }
+        }
+
+        match guard.as_ref() {
+            Some(index) => Ok(f(index)),
+            None => Err(EINVAL),
+        }
+    }
+}
+
+#[pinned_drop]
+impl PinnedDrop for SymbolIndexCache {
+    fn drop(self: Pin<&mut Self>) {
+        // SAFETY: The notifier block is registered by the type invariant
+        unsafe { bindings::unregister_module_notifier(self.nb.get()) };
+    }
+}
+
+/// Callbacks of a [`ModuleNotifier`], called on the module state changes
+///
+/// The callbacks are called in process context with the `module_mutex` not held,
+/// they can sleep. The default implementations do nothing.
+pub trait ModuleNotifierOperations
+where
+    Self: Sized,
+{
+    /// The global type that will be transmited to all the callbacks
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// The module is loaded and relocated but its init function is not called yet
+    /// (`MODULE_STATE_COMING`)
+    fn coming(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _module: &Module) {}
+
+    /// The init function of the module returned successfully (`MODULE_STATE_LIVE`)
+    fn live(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _module: &Module) {}
+
+    /// The module is being unloaded, or its init failed (`MODULE_STATE_GOING`)
+    fn going(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _module: &Module) {}
+}
+
+/// The notifier block and the private data of a [`ModuleNotifier`]
+#[repr(C)]
+struct ModuleNotifierInner {
+    nb: bindings::notifier_block,
+    data: *const core::ffi::c_void,
+}
+
+/// A callback on the module notifier chain (`register_module_notifier`)
+///
+/// # Invariants
+///
+///     `inner` is registered on the module notifier chain
+#[pin_data(PinnedDrop)]
+pub struct ModuleNotifier<T: ModuleNotifierOperations> {
+    #[pin]
+    inner: Opaque<ModuleNotifierInner>,
+    _t: PhantomData<T>,
+}
+
+// SAFETY: There is no `&self` methods
+unsafe impl<T: ModuleNotifierOperations> Sync for ModuleNotifier<T> where T::Data: Sync {}
+
+// SAFETY: The notifier can be unregistered from any thread
+unsafe impl<T: ModuleNotifierOperations> Send for ModuleNotifier<T> where T::Data: Send {}
+
+impl<T: ModuleNotifierOperations> ModuleNotifier<T> {
+    /// Create a new module notifier and register it
+    pub fn new(data: T::Data) -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            inner <- Opaque::try_ffi_init(move |slot: *mut ModuleNotifierInner| {
+                let data = data.into_foreign();
+
+                // SAFETY: The initializer can write to the provided `slot`.
+                unsafe {
+                    slot.write(ModuleNotifierInner {
+                        nb: bindings::notifier_block {
+                            notifier_call: Some(Self::notifier_callback),
+                            next: core::ptr::null_mut(),
+                            priority: 0,
+                        },
+                        data: data as _,
+                    })
+                };
+
+                // SAFETY: `slot` is a filled notifier block pinned in our structure, it will
+                // be unregistered in the drop
+                // INVARIANT: if this return `Ok(())` the notifier block is registered
+                let ret = crate::error::to_result(unsafe {
+                    bindings::register_module_notifier(core::ptr::addr_of_mut!((*slot).nb))
+                });
+                if ret.is_err() {
+                    // SAFETY: The notifier is not registered so no one borrowed the data,
+                    // this is the only call to `from_foreign` for this `into_foreign`
+                    unsafe { T::Data::from_foreign(data) };
+                }
+                ret
+            }),
+            _t: PhantomData,
+        })
+    }
+
+    /// # Safety
+    ///     Will be called only from C, prototype correspond to the notifier's callback prototype
+    unsafe extern "C" fn notifier_callback(
+        nb: *mut bindings::notifier_block,
+        action: core::ffi::c_ulong,
+        module: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: The notifier block is embedded in a `ModuleNotifierInner` which is alive
+        // while the notifier is registered
+        let inner = unsafe { &*container_of!(nb, ModuleNotifierInner, nb) };
+
+        // SAFETY: The notifier is still registered so the data is still valid
+        let data = unsafe { T::Data::borrow(inner.data as _) };
+
+        // SAFETY: The module notifier chain is called with the `struct module` being
+        // loaded or unloaded, which is valid for the duration of the call
+        let module = unsafe { Module::from_raw(module as *const bindings::module) };
+
+        match action as u32 {
+            bindings::module_state_MODULE_STATE_COMING => T::coming(data, module),
+            bindings::module_state_MODULE_STATE_LIVE => T::live(data, module),
+            bindings::module_state_MODULE_STATE_GOING => T::going(data, module),
+            _ => (),
+        }
+
+        bindings::NOTIFY_DONE as _
+    }
+}
+
+#[pinned_drop]
+impl<T: ModuleNotifierOperations> PinnedDrop for ModuleNotifier<T> {
+    fn drop(self: Pin<&mut Self>) {
+        let inner = self.inner.get();
+
+        // SAFETY: The notifier block is registered by the type invariant
+        unsafe { bindings::unregister_module_notifier(core::ptr::addr_of_mut!((*inner).nb)) };
+
+        // SAFETY: We unregistered the notifier, the chain is protected by RCU so no callback
+        // borrow the data anymore, and this is the only call to `from_foreign` corresponding
+        // to the `into_foreign` of the creation
+        unsafe { T::Data::from_foreign((*inner).data as _) };
     }
 }
 
@@ -224,8 +939,106 @@ pub fn symbols_lookup_address(
     }
 }
 
+/// Caller-provided storage for [`symbols_lookup_address_buf`]
+///
+/// Big enough for any symbol and module name, can be kept in a static or per-cpu
+/// variable so the lookup doesn't allocate nor use much stack.
+pub struct SymbolBuffer {
+    name: [u8; KSYM_NAME_LEN as usize],
+    module: [u8; MODULE_NAME_LEN],
+}
+
+impl SymbolBuffer {
+    /// Create an empty buffer
+    pub const fn new() -> Self {
+        SymbolBuffer {
+            name: [0; KSYM_NAME_LEN as usize],
+            module: [0; MODULE_NAME_LEN],
+        }
+    }
+}
+
+impl Default for SymbolBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A symbol found by [`symbols_lookup_address_buf`], borrowing the [`SymbolBuffer`]
+#[derive(Clone, Copy, Debug)]
+pub struct ResolvedSymbol<'a> {
+    /// Name of the symbol (without the null terminator)
+    pub name: &'a [u8],
+    /// Name of the module containing the symbol, `None` if in the kernel image
+    pub module: Option<&'a [u8]>,
+    /// The offset of the address in respect to the symbol
+    pub offset: u64,
+    /// The size of the symbol
+    pub size: u64,
+}
+
+/// Lookup an address for it's associated symbol without allocating
+///
+/// Same as [`symbols_lookup_address`] but the names are written in `buf`, so it can be
+/// used in atomic context (fprobe handlers, ...).
+///
+/// # Return
+/// The symbol, or `None` if no symbol contains the address
+pub fn symbols_lookup_address_buf(addr: u64, buf: &mut SymbolBuffer) -> Option<ResolvedSymbol<'_>> {
+    let mut modname: *mut i8 = core::ptr::null_mut::<i8>();
+    let mut offset: c_ulong = 0;
+    let mut size: c_ulong = 0;
+
+    // The module name point in the `struct module`, keep it alive while copying it
+    let _guard = rcu::read_lock();
+
+    // SAFETY: Just an FFI call, `buf.name` is `KSYM_NAME_LEN` long as required
+    let found = unsafe {
+        bindings::kallsyms_lookup(
+            addr as c_ulong,
+            &mut size as *mut c_ulong,
+            &mut offset as *mut c_ulong,
+            &mut modname as *mut *mut i8,
+            buf.name.as_mut_ptr() as *mut i8,
+        )
+    };
+    if found.is_null() {
+        return None;
+    }
+
+    let module_len = if modname.is_null() {
+        None
+    } else {
+        // SAFETY: `modname` point to the name of a module, which can't be freed while we
+        // hold the RCU read lock
+        let src = unsafe { CStr::from_char_ptr(modname) }.as_bytes();
+        let len = src.len().min(MODULE_NAME_LEN - 1);
+        buf.module[..len].copy_from_slice(&src[..len]);
+        Some(len)
+    };
+
+    let name_len = buf
+        .name
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(buf.name.len());
+    Some(ResolvedSymbol {
+        name: &buf.name[..name_len],
+        module: module_len.map(|len| &buf.module[..len]),
+        offset: offset as u64,
+        size: size as u64,
+    })
+}
+
 /// Lookup for the symbol address
+///
+/// In the kallsyms-free mode only the map supplied by userspace is used, see
+/// [`crate::symbol_map`]. Return 0 if the symbol is not found.
 pub fn symbols_lookup_name(name: &CStr) -> u64 {
+    if symbol_map::is_kallsyms_free() {
+        return symbol_map::lookup(name).unwrap_or(0);
+    }
+
     // SAFETY: Just an FFI call
     unsafe { bindings::kallsyms_lookup_name(name.as_char_ptr()) }
 }
@@ -272,8 +1085,277 @@ pub fn is_kernel(addr: u64) -> bool {
     (addr >= stext && addr < end) || (addr >= init_begin && addr < init_end)
 }
 
+/// Size of the kernel image mapping (`KERNEL_IMAGE_SIZE`)
+#[cfg(all(target_arch = "x86_64", CONFIG_RANDOMIZE_BASE))]
+const KERNEL_IMAGE_SIZE: u64 = 1 << 30;
+/// Size of the kernel image mapping (`KERNEL_IMAGE_SIZE`)
+#[cfg(all(target_arch = "x86_64", not(CONFIG_RANDOMIZE_BASE)))]
+const KERNEL_IMAGE_SIZE: u64 = 512 << 20;
+
+/// Start of the module mapping space (`MODULES_VADDR`)
+#[cfg(target_arch = "x86_64")]
+pub const MODULES_VADDR: u64 = 0xffff_ffff_8000_0000 + KERNEL_IMAGE_SIZE;
+
+/// End of the module mapping space (`MODULES_END`)
+#[cfg(target_arch = "x86_64")]
+pub const MODULES_END: u64 = 0xffff_ffff_ff00_0000;
+
+/// Smallest virtual address size the kernel can run with (`VA_BITS_MIN`)
+#[cfg(target_arch = "aarch64")]
+const VA_BITS_MIN: u32 = if bindings::CONFIG_ARM64_VA_BITS > 48 {
+    48
+} else {
+    bindings::CONFIG_ARM64_VA_BITS
+};
+
+/// Start of the module mapping space (`MODULES_VADDR`), the end of the linear map
+#[cfg(target_arch = "aarch64")]
+pub const MODULES_VADDR: u64 = (-(1_i64 << (VA_BITS_MIN - 1))) as u64;
+
+/// End of the module mapping space (`MODULES_END`), the module space is 2G wide
+#[cfg(target_arch = "aarch64")]
+pub const MODULES_END: u64 = MODULES_VADDR + (2 << 30);
+
 /// Check if the address is in the kernel's module space
-/// Using the
+///
+/// This is the whole area where modules can be mapped, not only the loaded modules,
+/// on x86_64 and arm64 the BPF JIT images and other executable allocations are also there.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub fn is_module_space(addr: u64) -> bool {
+    (MODULES_VADDR..MODULES_END).contains(&addr)
+}
+
+/// Check if the address is inside a BPF JIT image
+///
+/// The JIT images are allocated in the module space, so in addition to the range we
+/// need to check the BPF program tree
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub fn is_bpf_jit_space(addr: u64) -> bool {
+    #[cfg(CONFIG_BPF_JIT)]
+    {
+        // SAFETY: Just an FFI call, the lookup is done under RCU by the callee
+        is_module_space(addr) && unsafe { bindings::is_bpf_text_address(addr as _) }
+    }
+    #[cfg(not(CONFIG_BPF_JIT))]
+    {
+        let _ = addr;
+        false
+    }
+}
+
+/// Check if the address is in the vmalloc space
+pub fn is_vmalloc_addr(addr: u64) -> bool {
+    // SAFETY: Just an FFI call, the address is only compared to the vmalloc range
+    unsafe { bindings::is_vmalloc_addr(addr as *const core::ffi::c_void) }
+}
+
+/// Type of a module memory region, correspond to the C `enum mod_mem_type`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ModMemType {
+    /// Module code
+    Text = bindings::mod_mem_type_MOD_TEXT,
+    /// Module writable data
+    Data = bindings::mod_mem_type_MOD_DATA,
+    /// Module read-only data
+    Rodata = bindings::mod_mem_type_MOD_RODATA,
+    /// Module data read-only after init
+    RoAfterInit = bindings::mod_mem_type_MOD_RO_AFTER_INIT,
+    /// Module init code, freed after init
+    InitText = bindings::mod_mem_type_MOD_INIT_TEXT,
+    /// Module init data, freed after init
+    InitData = bindings::mod_mem_type_MOD_INIT_DATA,
+    /// Module init read-only data, freed after init
+    InitRodata = bindings::mod_mem_type_MOD_INIT_RODATA,
+}
+
+impl ModMemType {
+    /// All the region types, in the order of `struct module::mem`
+    pub const ALL: [ModMemType; bindings::mod_mem_type_MOD_MEM_NUM_TYPES as usize] = [
+        ModMemType::Text,
+        ModMemType::Data,
+        ModMemType::Rodata,
+        ModMemType::RoAfterInit,
+        ModMemType::InitText,
+        ModMemType::InitData,
+        ModMemType::InitRodata,
+    ];
+
+    /// The region contains code
+    pub const fn is_text(&self) -> bool {
+        matches!(self, ModMemType::Text | ModMemType::InitText)
+    }
+
+    /// The region is freed once the module is initialized
+    pub const fn is_init(&self) -> bool {
+        matches!(
+            self,
+            ModMemType::InitText | ModMemType::InitData | ModMemType::InitRodata
+        )
+    }
+}
+
+/// A module memory region
+#[derive(Clone, Copy, Debug)]
+pub struct ModRegion {
+    /// Type of the region
+    pub mem_type: ModMemType,
+    /// Start address of the region
+    pub base: u64,
+    /// Size of the region in bytes
+    pub size: usize,
+}
+
+impl ModRegion {
+    /// The address is inside the region
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.base && addr - self.base < self.size as u64
+    }
+}
+
+/// State of a module (`enum module_state`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ModuleState {
+    /// Normal state
+    Live,
+    /// Full formed, running module_init
+    Coming,
+    /// Going away
+    Going,
+    /// Still setting it up
+    Unformed,
+    /// Value outside of the enum, the structure is corrupted
+    Unknown(u32),
+}
+
+impl ModuleState {
+    /// Convert from the C `enum module_state`
+    pub fn from_raw(state: u32) -> Self {
+        match state {
+            bindings::module_state_MODULE_STATE_LIVE => ModuleState::Live,
+            bindings::module_state_MODULE_STATE_COMING => ModuleState::Coming,
+            bindings::module_state_MODULE_STATE_GOING => ModuleState::Going,
+            bindings::module_state_MODULE_STATE_UNFORMED => ModuleState::Unformed,
+            state => ModuleState::Unknown(state),
+        }
+    }
+}
+
+/// Taint flags of a module (`struct module::taints`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ModuleTaints(c_ulong);
+
+impl ModuleTaints {
+    /// Get the raw bitmask, indexed by the `TAINT_*` flags
+    pub fn bits(&self) -> c_ulong {
+        self.0
+    }
+
+    /// Check if the `TAINT_*` flag `flag` is set
+    pub fn has(&self, flag: u32) -> bool {
+        flag < c_ulong::BITS && self.0 & (1 << flag) != 0
+    }
+
+    /// Module with a non GPL compatible license
+    pub fn is_proprietary(&self) -> bool {
+        self.has(bindings::TAINT_PROPRIETARY_MODULE)
+    }
+
+    /// Module loaded without a valid signature
+    pub fn is_unsigned(&self) -> bool {
+        self.has(bindings::TAINT_UNSIGNED_MODULE)
+    }
+
+    /// Out-of-tree module
+    pub fn is_out_of_tree(&self) -> bool {
+        self.has(bindings::TAINT_OOT_MODULE)
+    }
+
+    /// Module force loaded (ignoring the version magic)
+    pub fn is_forced(&self) -> bool {
+        self.has(bindings::TAINT_FORCED_MODULE)
+    }
+}
+
+/// A symbol of a module, see [`Module::symbols`]
+#[derive(Clone, Copy)]
+pub struct ModuleSymbol<'a> {
+    /// Name of the symbol
+    pub name: &'a CStr,
+    /// Address of the symbol
+    pub address: u64,
+    /// Size of the symbol (`st_size`)
+    pub size: u64,
+    /// Type of the symbol, as computed by the module loader
+    pub sym_type: SymbolType,
+}
+
+/// Iterator over the symbols of a module (`struct module::kallsyms`)
+pub struct ModuleSymbols<'a> {
+    kallsyms: *const bindings::mod_kallsyms,
+    index: u32,
+    num: u32,
+    /// Yield the undefined symbols instead of the defined ones
+    imports: bool,
+    _module: PhantomData<&'a Module>,
+}
+
+impl<'a> Iterator for ModuleSymbols<'a> {
+    type Item = ModuleSymbol<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.num {
+            let i = self.index as usize;
+            self.index += 1;
+
+            // SAFETY: `kallsyms` is valid as long as the module is, see `Module::symbols`,
+            // and `i` is lower than `num_symtab`
+            let (sym, strtab, sym_type) = unsafe {
+                let kallsyms = &*self.kallsyms;
+                (
+                    *kallsyms.symtab.add(i),
+                    kallsyms.strtab,
+                    *kallsyms.typetab.add(i),
+                )
+            };
+
+            // The first symbol is the null symbol, and the undefined symbols are not
+            // in the module
+            if sym.st_name == 0 || (sym.st_shndx == bindings::SHN_UNDEF as u16) != self.imports {
+                continue;
+            }
+
+            return Some(ModuleSymbol {
+                // SAFETY: `st_name` is an offset in the string table of the module, which
+                // contains null terminated strings
+                name: unsafe { CStr::from_char_ptr(strtab.add(sym.st_name as usize)) },
+                address: sym.st_value,
+                size: sym.st_size,
+                sym_type: SymbolType::from_raw(sym_type as u8),
+            });
+        }
+        None
+    }
+}
+
+/// A parameter of a module (`struct kernel_param`)
+pub struct ModuleParam<'a> {
+    /// Name of the parameter
+    pub name: &'a CStr,
+    /// The sysfs permissions of the parameter, 0 if not exposed in sysfs
+    pub perm: u16,
+    /// Address of the `struct kernel_param_ops` handling the parameter
+    pub ops: u64,
+}
+
+impl ModuleParam<'_> {
+    /// The parameter should have an attribute in `/sys/module/<name>/parameters`
+    pub fn has_sysfs(&self) -> bool {
+        self.perm != 0
+    }
+}
+
+/// Represent a kernel module (`struct module`)
 #[repr(transparent)]
 pub struct Module {
     inner: Opaque<bindings::module>,
@@ -297,20 +1379,198 @@ pub const fn as_ptr(&self) -> *mut bindings::module {
         self.inner.get()
     }
 
+    /// Borrow a `struct module` without taking a reference
+    ///
+    /// # Safety
+    ///
+    /// `module` must point to a valid `struct module` which stay alive for `'a`
+    /// (by holding a reference, a lock protecting the module or in a module notifier).
+    pub unsafe fn from_raw<'a>(module: *const bindings::module) -> &'a Self {
+        // SAFETY: `bindings::module` and `Module` have the same memory representation,
+        // and the pointer is valid for `'a` by the safety contract
+        unsafe { &*(module as *const Module) }
+    }
+
+    /// Get the name of the module
+    ///
+    /// The name is set at load time and never modified after, it is valid as long as
+    /// the module is. If the name is not null terminated (corrupted structure) an empty
+    /// name is returned.
+    pub fn name(&self) -> &CStr {
+        let name = self.raw_name();
+
+        match name.iter().position(|c| *c == 0) {
+            // SAFETY: The slice end with its first null byte
+            Some(len) => unsafe { CStr::from_bytes_with_nul_unchecked(&name[..=len]) },
+            None => c_str!(""),
+        }
+    }
+
+    /// Get the raw `name` field of the module, which may not be null terminated
+    pub fn raw_name(&self) -> &[u8; MODULE_NAME_LEN] {
+        // SAFETY: ptr is non null, point to valid data and is aligned
+        // according to the type invariant and the C guarantees
+        let name: *const [u8; MODULE_NAME_LEN] = unsafe { Field::ModuleName.ptr(self.inner.get()) };
+        // SAFETY: `module.name` is valid for the lifetime of the module, which is
+        // the lifetime of `&self`
+        unsafe { &*name }
+    }
+
+    /// Get a copy of the name of the module (without the null terminator)
+    pub fn name_owned(&self) -> Result<KVec<u8>> {
+        let mut name = KVec::new();
+        name.extend_from_slice(self.name().as_bytes(), GFP_KERNEL)?;
+        Ok(name)
+    }
+
     /// Print the name of the module
     pub fn print_name(&self) {
+        pr_info!("Module : {:?}\n", self.name());
+    }
+
+    /// Get the state of the module
+    pub fn state(&self) -> ModuleState {
+        // SAFETY: ptr point to a valid module by the type invariant. The state may change
+        // concurrently, we only read a snapshot of it
+        let state =
+            unsafe { core::ptr::read_volatile(Field::ModuleState.ptr::<_, u32>(self.inner.get())) };
+        ModuleState::from_raw(state)
+    }
+
+    /// Get the taint flags of the module
+    pub fn taints(&self) -> ModuleTaints {
+        // SAFETY: ptr point to a valid module by the type invariant, `taints` is only
+        // modified with atomic bit operations
+        ModuleTaints(unsafe { core::ptr::read_volatile(&(*self.inner.get()).taints) })
+    }
+
+    /// The signature of the module was verified at load time (`struct module::sig_ok`)
+    ///
+    /// Always `false` when the kernel doesn't support module signing
+    pub fn sig_ok(&self) -> bool {
+        #[cfg(CONFIG_MODULE_SIG)]
+        {
+            // SAFETY: ptr point to a valid module by the type invariant, `sig_ok` is set
+            // at load time and never modified after
+            unsafe { (*self.inner.get()).sig_ok }
+        }
+        #[cfg(not(CONFIG_MODULE_SIG))]
+        {
+            false
+        }
+    }
+
+    /// Get the current reference count of the module
+    ///
+    /// The count include the references taken by the checks themselves (each module of
+    /// a [`ModuleIter`] holds one). A module pinned by an abnormal count can't be unloaded.
+    #[cfg(CONFIG_MODULE_UNLOAD)]
+    pub fn refcount(&self) -> i32 {
+        // SAFETY: Just an FFI call, ptr point to a valid module by the type invariant
+        unsafe { bindings::module_refcount(self.inner.get()) }
+    }
+
+    /// Iterate over the symbols of the module (`struct module::kallsyms`)
+    ///
+    /// While the module is initializing the table contains all the ELF symbols, it is
+    /// then switched to the core symbols only, and the initializing table is freed.
+    ///
+    /// # Safety
+    ///
+    /// The table must not be freed while iterating. The switch is published with
+    /// `rcu_assign_pointer` and the initializing table is freed after a grace period, so
+    /// the iterator must be used under the RCU read lock (without sleeping), with
+    /// `module_mutex` held, or from a [`ModuleNotifier`] callback.
+    pub unsafe fn symbols(&self) -> ModuleSymbols<'_> {
+        self.kallsyms(false)
+    }
+
+    /// Iterate over the symbols imported by the module (the undefined symbols of its
+    /// `struct module::kallsyms`)
+    ///
+    /// The address of an imported symbol is the one it was resolved to by the loader.
+    /// Only the initializing table contains the undefined symbols, so the iterator is
+    /// empty once the module is live.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Module::symbols`], it must be used from a [`ModuleNotifier`] `coming`
+    /// callback to see the imports.
+    pub unsafe fn imports(&self) -> ModuleSymbols<'_> {
+        self.kallsyms(true)
+    }
+
+    fn kallsyms(&self, imports: bool) -> ModuleSymbols<'_> {
+        // SAFETY: ptr point to a valid module by the type invariant, the `kallsyms`
+        // pointer is updated with `rcu_assign_pointer` so we read it once
+        let kallsyms = unsafe { core::ptr::read_volatile(&(*self.inner.get()).kallsyms) };
+        let num = if kallsyms.is_null() {
+            0
+        } else {
+            // SAFETY: `kallsyms` is not null and valid, see above
+            unsafe { (*kallsyms).num_symtab as u32 }
+        };
+
+        ModuleSymbols {
+            kallsyms,
+            index: 0,
+            num,
+            imports,
+            _module: PhantomData,
+        }
+    }
+
+    /// Iterate over the parameters of the module (`struct module::kp`)
+    ///
+    /// The parameter array is part of the module image, it is valid as long as the module
+    /// is. Parameters without a name (corrupted array) are skipped.
+    pub fn params(&self) -> impl Iterator<Item = ModuleParam<'_>> + '_ {
         let ptr = self.inner.get();
+        // SAFETY: ptr point to a valid module by the type invariant, `kp` and `num_kp`
+        // are set at load time and never modified after
+        let (kp, num_kp) = unsafe { ((*ptr).kp, (*ptr).num_kp) };
+        let num_kp = if kp.is_null() { 0 } else { num_kp as usize };
 
-        // SAFETY: ptr is non null, point to valid data and is aligned
-        // according to the type invariant and the C guarantees
-        let name_bytes = unsafe { addr_of!((*ptr).name) as *const i8 };
+        (0..num_kp).filter_map(move |i| {
+            // SAFETY: `kp` point to an array of `num_kp` parameters, see above
+            let param = unsafe { &*kp.add(i) };
+            if param.name.is_null() {
+                return None;
+            }
+            Some(ModuleParam {
+                // SAFETY: The name of a parameter is a null terminated string of the module
+                name: unsafe { CStr::from_char_ptr(param.name) },
+                perm: param.perm,
+                ops: param.ops as u64,
+            })
+        })
+    }
+
+    /// Iterate over the memory regions of the module (`struct module::mem`)
+    ///
+    /// Empty regions (for example the init ones once the module is loaded) are skipped
+    pub fn regions(&self) -> impl Iterator<Item = ModRegion> + '_ {
+        let ptr = self.inner.get();
 
-        // SAFETY: `module.name` is valid for the lifetime of the module,
-        // we hold a refcount of the module so it is valid for the duration of this call
-        // and we have that name is not null and is a constant null terminated string
-        let name = unsafe { CStr::from_char_ptr(name_bytes) };
+        ModMemType::ALL.into_iter().filter_map(move |mem_type| {
+            // SAFETY: ptr point to a valid module by the type invariant, and `mem_type`
+            // is in bounds of `mem`. The `base` and `size` fields are only modified while
+            // the module is loading or unloading.
+            let mem = unsafe { &(*ptr).mem[mem_type as usize] };
+            if mem.base.is_null() || mem.size == 0 {
+                return None;
+            }
+            Some(ModRegion {
+                mem_type,
+                base: mem.base as u64,
+                size: mem.size as usize,
+            })
+        })
+    }
 
-        pr_info!("Module : {:?}\n", name);
+    /// Get the region of the module containing `addr` if any
+    pub fn contains_addr(&self, addr: u64) -> Option<ModRegion> {
+        self.regions().find(|region| region.contains(addr))
     }
 }
 
@@ -345,17 +1605,77 @@ fn from(value: &ThisModule) -> Self {
     }
 }
 
+/// Maximum number of modules in a [`ModuleIter`] snapshot
+///
+/// Bound the walk in case the list was corrupted into a cycle
+pub const MODULE_SNAPSHOT_MAX: usize = 8192;
+
 /// Iterator on all the module in the module linked list
+///
+/// The list is walked once at creation under `module_mutex` and a reference on each
+/// module is taken, the iteration is done on this snapshot after the mutex is released.
+/// This way the modules can't be freed under the iterator while the caller is free to
+/// sleep, load modules or create another [`ModuleIter`]. Modules being unloaded when the
+/// snapshot is taken are skipped.
 pub struct ModuleIter {
-    cur: Option<ARef<Module>>,
-    head: *mut bindings::list_head,
+    modules: IntoIter<ARef<Module>, Kmalloc>,
 }
 
 impl ModuleIter {
-    /// Create a new instance, this is safe because we can only read
+    /// Take a snapshot of the module list under `module_mutex`
+    ///
+    /// If the snapshot can't be taken the error is logged and the iterator is empty, use
+    /// [`ModuleIter::try_new`] to get the error.
     pub fn new() -> Self {
+        Self::try_new().unwrap_or_else(|e| {
+            pr_err!("Couldn't take a snapshot of the module list : {:?}\n", e);
+            ModuleIter {
+                modules: KVec::new().into_iter(),
+            }
+        })
+    }
+
+    /// Take a snapshot of the module list under `module_mutex` in a faillible way
+    pub fn try_new() -> Result<Self> {
         let head = symbols_lookup_name(c_str!("modules")) as *mut bindings::list_head;
-        ModuleIter { cur: None, head }
+        if head.is_null() {
+            pr_err!("Couldn't find modules symbol\n");
+            return Err(ENOENT);
+        }
+
+        let mut modules = KVec::new();
+
+        let guard = StaticCMutexGuard::lock(c_str!("module_mutex"))?;
+
+        // SAFETY: We have by the C API that `head.next` is valid
+        let mut next = unsafe { (*head).next };
+        while next != head && !next.is_null() {
+            if modules.len() >= MODULE_SNAPSHOT_MAX {
+                pr_warn!("Too many modules, the snapshot is truncated\n");
+                break;
+            }
+
+            // SAFETY: We are on the module's linked list so excepting the head they are all in `module` struct
+            let module: *mut bindings::module =
+                unsafe { Field::ModuleList.container::<bindings::module, _>(next) as *mut _ };
+
+            // SAFETY: `module` is on the list and we hold `module_mutex` so it is not freed
+            if unsafe { bindings::try_module_get(module) } {
+                // SAFETY: We just took a reference on the module, it is owned by the `ARef`
+                let module =
+                    unsafe { ARef::from_raw(NonNull::new_unchecked(module.cast::<Module>())) };
+                modules.push(module, GFP_KERNEL)?;
+            }
+
+            // SAFETY: `module` is still valid under `module_mutex`
+            next = unsafe { (*Field::ModuleList.ptr::<_, bindings::list_head>(module)).next };
+        }
+
+        guard.unlock();
+
+        Ok(ModuleIter {
+            modules: modules.into_iter(),
+        })
     }
 }
 
@@ -369,25 +1689,6 @@ impl Iterator for ModuleIter {
     type Item = ARef<Module>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = match self.cur {
-            // SAFETY: We have by the C API that `head.next` is valid
-            None => unsafe { &*self.head }.next,
-            // SAFETY: We have by the C API that `list.next` is valid
-            // actually we need to hold module_mutex to guaranty this but holding
-            // a C mutex from the Rust side is not supported for now)
-            Some(ref module) => unsafe { (*module.as_ptr()).list }.next,
-        };
-
-        if next == self.head {
-            None
-        } else {
-            // SAFETY: We are on the module's linked list so excepting the head they are all in `module` struct
-            let next_mod = unsafe { container_of!(next, bindings::module, list) as *mut _ };
-            // SAFETY: We can always call this function
-            let next_mod = unsafe { Module::get_module(next_mod) };
-            next_mod.inc_ref();
-            self.cur = Some(next_mod.clone());
-            Some(next_mod)
-        }
+        self.modules.next()
     }
 }
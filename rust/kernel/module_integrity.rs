@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Module integrity : per-module text baseline and verification
+//!
+//! The text of each module is cut in chunks whose digests are recorded the first
+//! time the module is seen. Later verifications compare the current text against
+//! these digests and report the modified byte ranges, which is how patched
+//! out-of-tree module code is detected.
+//!
+//! Note that the kernel itself legitimately patches module text (alternatives,
+//! jump labels, static calls, ftrace), so a modified range must be triaged
+//! before being considered malicious.
+//!
+//! The baselines of the unloaded modules are retired and kept for a grace period,
+//! during which a different module loaded over the same text range is reported.
+
+use crate::event::{Event, EventKind};
+use crate::module::{ModMemType, Module, MODULE_NAME_LEN};
+use crate::rbtree::RBTree;
+use crate::str::{until_nul, BStr};
+use crate::time::Ktime;
+use kernel::prelude::*;
+
+/// Granularity of the verification, in bytes
+pub const CHUNK_SIZE: usize = 64;
+
+/// Seed of the digests
+const DIGEST_SEED: u64 = 0x726b_6368_6b00_0001;
+
+/// Default grace period of the baselines of the unloaded modules, in nanoseconds
+pub const DEFAULT_GRACE_NS: i64 = 60 * 1_000_000_000;
+
+/// Size of the build id (`BUILD_ID_SIZE_MAX`)
+const BUILD_ID_SIZE: usize = 20;
+
+/// A range of modified bytes, relative to the start of the module text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModifiedRange {
+    /// Offset of the first modified chunk from the text base
+    pub offset: usize,
+    /// Length of the modified range
+    pub len: usize,
+}
+
+/// Identify a module : its name and its build id
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ModuleKey {
+    name: [u8; MODULE_NAME_LEN],
+    build_id: [u8; BUILD_ID_SIZE],
+}
+
+impl ModuleKey {
+    fn new(module: &Module) -> Self {
+        let mut name = [0u8; MODULE_NAME_LEN];
+        let src = module.name().as_bytes();
+        name[..src.len()].copy_from_slice(src);
+
+        #[allow(unused_mut)]
+        let mut build_id = [0u8; BUILD_ID_SIZE];
+        #[cfg(CONFIG_STACKTRACE_BUILD_ID)]
+        {
+            // SAFETY: We hold a reference to the module and the build id is set at load time
+            let src = unsafe { &(*module.as_ptr()).build_id };
+            for (dst, src) in build_id.iter_mut().zip(src.iter()) {
+                *dst = *src as u8;
+            }
+        }
+
+        ModuleKey { name, build_id }
+    }
+}
+
+/// Baseline of one module text
+struct ModuleBaseline {
+    base: u64,
+    size: usize,
+    digests: KVVec<u64>,
+    /// Time of the unload of the module (`ktime_get`), `None` while it is loaded
+    retired_at: Option<i64>,
+}
+
+/// Compute the digest of a chunk
+fn digest(chunk: &[u8]) -> u64 {
+    // SAFETY: Just an FFI call, the pointer and length come from a valid slice
+    unsafe { bindings::xxh64(chunk.as_ptr().cast(), chunk.len(), DIGEST_SEED) }
+}
+
+/// Get the text of a module as a slice
+///
+/// # Safety
+///
+/// The returned slice is only valid while the module is loaded.
+unsafe fn module_text(module: &Module) -> Option<(u64, &[u8])> {
+    let text = module
+        .regions()
+        .find(|region| region.mem_type == ModMemType::Text)?;
+
+    // SAFETY: The text region is mapped and readable while the module is loaded
+    let bytes = unsafe { core::slice::from_raw_parts(text.base as *const u8, text.size) };
+    Some((text.base, bytes))
+}
+
+impl ModuleBaseline {
+    fn new(module: &Module) -> Result<Self> {
+        // SAFETY: We hold a reference to the module so it is loaded during this call
+        let (base, text) = unsafe { module_text(module) }.ok_or(ENOENT)?;
+
+        let mut digests = KVVec::with_capacity(text.len().div_ceil(CHUNK_SIZE), GFP_KERNEL)?;
+        for chunk in text.chunks(CHUNK_SIZE) {
+            digests.push(digest(chunk), GFP_KERNEL)?;
+        }
+
+        Ok(ModuleBaseline {
+            base,
+            size: text.len(),
+            digests,
+            retired_at: None,
+        })
+    }
+
+    fn verify(&self, module: &Module) -> Result<KVec<ModifiedRange>> {
+        // SAFETY: We hold a reference to the module so it is loaded during this call
+        let (_, text) = unsafe { module_text(module) }.ok_or(ENOENT)?;
+        let mut ranges: KVec<ModifiedRange> = KVec::new();
+
+        for (i, (chunk, expected)) in text.chunks(CHUNK_SIZE).zip(self.digests.iter()).enumerate() {
+            if digest(chunk) == *expected {
+                continue;
+            }
+
+            let offset = i * CHUNK_SIZE;
+            match ranges.last_mut() {
+                // Merge with the previous range if contiguous
+                Some(last) if last.offset + last.len == offset => last.len += chunk.len(),
+                _ => ranges.push(
+                    ModifiedRange {
+                        offset,
+                        len: chunk.len(),
+                    },
+                    GFP_KERNEL,
+                )?,
+            }
+        }
+
+        Ok(ranges)
+    }
+}
+
+/// Store of the module text baselines, keyed by module name and build id
+pub struct ModuleIntegrity {
+    baselines: RBTree<ModuleKey, ModuleBaseline>,
+}
+
+impl ModuleIntegrity {
+    /// Create an empty store
+    pub fn new() -> Self {
+        ModuleIntegrity {
+            baselines: RBTree::new(),
+        }
+    }
+
+    /// Record the baseline of a module, replacing any previous one
+    pub fn snapshot(&mut self, module: &Module) -> Result {
+        let baseline = ModuleBaseline::new(module)?;
+        self.baselines
+            .try_create_and_insert(ModuleKey::new(module), baseline, GFP_KERNEL)?;
+        Ok(())
+    }
+
+    /// Record the baseline of all the modules currently loaded
+    pub fn snapshot_all(&mut self) -> Result {
+        for module in crate::module::ModuleIter::try_new()? {
+            self.snapshot(&module)?;
+        }
+        Ok(())
+    }
+
+    /// Verify the text of a module against its baseline
+    ///
+    /// If the module was never seen, or was reloaded at a different address
+    /// (which changes its relocated text) the baseline is recorded and no range is reported.
+    ///
+    /// # Return
+    /// The list of modified byte ranges, empty if the text is intact
+    pub fn verify_module(&mut self, module: &Module) -> Result<KVec<ModifiedRange>> {
+        let key = ModuleKey::new(module);
+        // SAFETY: We hold a reference to the module so it is loaded during this call
+        let (base, text) = unsafe { module_text(module) }.ok_or(ENOENT)?;
+
+        match self.baselines.get(&key) {
+            Some(baseline) if baseline.base == base && baseline.size == text.len() => {
+                baseline.verify(module)
+            }
+            _ => {
+                self.snapshot(module)?;
+                Ok(KVec::new())
+            }
+        }
+    }
+
+    /// Forget the baseline of a module
+    pub fn forget(&mut self, module: &Module) {
+        self.baselines.remove(&ModuleKey::new(module));
+    }
+
+    /// Retire the baseline of a module being unloaded
+    ///
+    /// The baseline is kept until the grace period ends (see [`Self::collect`]), so a
+    /// reuse of its text range can be detected by [`Self::on_load`]
+    pub fn retire(&mut self, module: &Module) {
+        if let Some(baseline) = self.baselines.get_mut(&ModuleKey::new(module)) {
+            baseline.retired_at = Some(Ktime::ktime_get().to_ns());
+        }
+    }
+
+    /// Drop the baselines retired for more than `grace_ns`
+    pub fn collect(&mut self, grace_ns: i64) {
+        let oldest = Ktime::ktime_get().to_ns() - grace_ns;
+
+        let mut cursor = self.baselines.cursor_front();
+        while let Some(current) = cursor {
+            let expired = matches!(current.current().1.retired_at, Some(t) if t < oldest);
+            cursor = if expired {
+                current.remove_current().0
+            } else {
+                current.move_next()
+            };
+        }
+    }
+
+    /// Record the baseline of a module being loaded
+    ///
+    /// If the text range of the module overlaps the one of a different module retired
+    /// during the grace period, with a different content, an event is returned.
+    pub fn on_load(&mut self, module: &Module) -> Result<Option<Event>> {
+        let key = ModuleKey::new(module);
+        let baseline = ModuleBaseline::new(module)?;
+        let end = baseline.base + baseline.size as u64;
+
+        let mut event = None;
+        for (retired_key, retired) in self.baselines.iter() {
+            if *retired_key == key || retired.retired_at.is_none() {
+                continue;
+            }
+            let retired_end = retired.base + retired.size as u64;
+            if retired.base >= end || baseline.base >= retired_end {
+                continue;
+            }
+            if retired.digests[..] == baseline.digests[..] {
+                continue;
+            }
+
+            event = Some(Event::new(
+                EventKind::ModuleTextReuse,
+                fmt!(
+                    "module {} loaded at {:#x} over the text of the unloaded module {}",
+                    module.name(),
+                    baseline.base,
+                    BStr::from_bytes(until_nul(&retired_key.name))
+                ),
+            )?);
+            break;
+        }
+
+        self.baselines
+            .try_create_and_insert(key, baseline, GFP_KERNEL)?;
+        Ok(event)
+    }
+}
+
+impl Default for ModuleIntegrity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
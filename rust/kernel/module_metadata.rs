@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Module metadata : ELF metadata of the modules captured at load time
+//!
+//! A rootkit usually resolves a few telltale symbols (`kallsyms_lookup_name`,
+//! `set_memory_rw`, the syscall table, ...) while it is loaded, then hides. The symbols
+//! imported by each module are recorded from a module notifier at `MODULE_STATE_COMING`,
+//! while the full ELF symbol table is still available, so later scans can report the
+//! suspicious imports even once the module is hidden.
+//!
+//! The section addresses are completed at `MODULE_STATE_LIVE`, once the sysfs section
+//! attributes exist. The relocation sections are applied and freed by the loader before
+//! the notifier is called, they can't be recorded.
+//!
+//! C header: [`include/linux/module.h`](../../../../include/linux/module.h)
+
+use core::fmt;
+
+use crate::event::{Event, EventKind};
+use crate::module::{ModRegion, Module, ModuleNotifierOperations, MODULE_NAME_LEN};
+use crate::rbtree::RBTree;
+use crate::str::{until_nul, BStr};
+use crate::sync::{new_mutex, Arc, ArcBorrow, Mutex};
+use kernel::prelude::*;
+
+/// Maximum length of a recorded symbol name, longer names are truncated
+pub const IMPORT_NAME_LEN: usize = 64;
+
+/// Maximum length of a recorded section name, longer names are truncated
+pub const SECTION_NAME_LEN: usize = 32;
+
+/// Symbols seldom imported by a legitimate module
+const SUSPICIOUS_IMPORTS: &[&[u8]] = &[
+    b"kallsyms_lookup_name",
+    b"kallsyms_on_each_symbol",
+    b"set_memory_rw",
+    b"set_memory_x",
+    b"text_poke",
+    b"text_poke_bp",
+    b"sys_call_table",
+    b"ia32_sys_call_table",
+    b"x64_sys_call",
+    b"register_kprobe",
+    b"commit_creds",
+    b"prepare_kernel_cred",
+];
+
+/// Prefixes of the syscall entry points, which are not called by legitimate modules
+const SUSPICIOUS_PREFIXES: &[&[u8]] = &[b"__x64_sys_", b"__ia32_sys_", b"__arm64_sys_"];
+
+/// Mirror of `struct module_sect_attr` (private to `kernel/module/sysfs.c`)
+#[cfg(all(CONFIG_KALLSYMS, CONFIG_SYSFS))]
+#[repr(C)]
+struct ModuleSectAttr {
+    battr: bindings::bin_attribute,
+    address: core::ffi::c_ulong,
+}
+
+/// Mirror of `struct module_sect_attrs` (private to `kernel/module/sysfs.c`)
+#[cfg(all(CONFIG_KALLSYMS, CONFIG_SYSFS))]
+#[allow(dead_code)]
+#[repr(C)]
+struct ModuleSectAttrs {
+    grp: bindings::attribute_group,
+    nsections: core::ffi::c_uint,
+    attrs: [ModuleSectAttr; 0],
+}
+
+/// A symbol imported by a module
+#[derive(Clone, Copy)]
+pub struct Import {
+    /// Name of the symbol, null terminated
+    pub name: [u8; IMPORT_NAME_LEN],
+    /// Address the symbol was resolved to
+    pub address: u64,
+}
+
+impl Import {
+    /// Get the name of the symbol (without the null terminator)
+    pub fn name(&self) -> &[u8] {
+        until_nul(&self.name)
+    }
+
+    /// The symbol is commonly used by rootkits
+    pub fn is_suspicious(&self) -> bool {
+        let name = self.name();
+        SUSPICIOUS_IMPORTS.contains(&name)
+            || SUSPICIOUS_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+    }
+}
+
+/// A section of a module
+#[derive(Clone, Copy)]
+pub struct Section {
+    /// Name of the section, null terminated
+    pub name: [u8; SECTION_NAME_LEN],
+    /// Address of the section
+    pub address: u64,
+}
+
+/// The metadata of a module
+pub struct ModuleMetadata {
+    /// Symbols imported by the module
+    pub imports: KVec<Import>,
+    /// Allocated sections of the module, empty until the module is live
+    pub sections: KVec<Section>,
+    /// Memory regions of the module when it was loaded
+    pub regions: KVec<ModRegion>,
+}
+
+impl ModuleMetadata {
+    /// Capture the metadata of a module being loaded
+    ///
+    /// # Safety
+    ///
+    /// Must be called from the `coming` callback of a [`ModuleNotifier`](crate::module::ModuleNotifier) for `module`.
+    unsafe fn capture(module: &Module) -> Result<Self> {
+        let mut imports = KVec::new();
+        // SAFETY: We are in the `coming` callback by the safety contract
+        for sym in unsafe { module.imports() } {
+            imports.push(
+                Import {
+                    name: copy_bytes(sym.name.as_bytes()),
+                    address: sym.address,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+
+        let mut regions = KVec::new();
+        for region in module.regions() {
+            regions.push(region, GFP_KERNEL)?;
+        }
+
+        Ok(ModuleMetadata {
+            imports,
+            sections: KVec::new(),
+            regions,
+        })
+    }
+
+    /// Record the sections of a live module from its sysfs section attributes
+    #[cfg(all(CONFIG_KALLSYMS, CONFIG_SYSFS))]
+    fn capture_sections(&mut self, module: &Module) -> Result {
+        // SAFETY: The module is valid during the notifier call, `sect_attrs` is set before
+        // the module is live and freed when it is unloaded
+        let attrs = unsafe { (*module.as_ptr()).sect_attrs }.cast::<ModuleSectAttrs>();
+        if attrs.is_null() {
+            return Ok(());
+        }
+
+        // SAFETY: `attrs` is a valid `struct module_sect_attrs` with `nsections` entries
+        let nsections = unsafe { (*attrs).nsections } as usize;
+        self.sections = KVec::with_capacity(nsections, GFP_KERNEL)?;
+        for i in 0..nsections {
+            // SAFETY: `i` is lower than `nsections`, see above
+            let attr = unsafe {
+                &*core::ptr::addr_of!((*attrs).attrs)
+                    .cast::<ModuleSectAttr>()
+                    .add(i)
+            };
+            let name = if attr.battr.attr.name.is_null() {
+                [0u8; SECTION_NAME_LEN]
+            } else {
+                // SAFETY: The name of the attribute is the null terminated section name
+                copy_bytes(unsafe { CStr::from_char_ptr(attr.battr.attr.name) }.as_bytes())
+            };
+            self.sections.push(
+                Section {
+                    name,
+                    address: attr.address as u64,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(all(CONFIG_KALLSYMS, CONFIG_SYSFS)))]
+    fn capture_sections(&mut self, _module: &Module) -> Result {
+        Ok(())
+    }
+
+    /// Get the suspicious imports of the module
+    pub fn suspicious_imports(&self) -> impl Iterator<Item = &Import> {
+        self.imports.iter().filter(|import| import.is_suspicious())
+    }
+}
+
+fn copy_bytes<const N: usize>(src: &[u8]) -> [u8; N] {
+    let mut dst = [0u8; N];
+    let len = src.len().min(N - 1);
+    dst[..len].copy_from_slice(&src[..len]);
+    dst
+}
+
+/// Store of the metadata of the loaded modules, keyed by module name
+///
+/// Filled by a [`ModuleNotifier<MetadataRecorder>`](crate::module::ModuleNotifier),
+/// so only the modules loaded after its registration are known.
+#[pin_data]
+pub struct MetadataStore {
+    #[pin]
+    records: Mutex<RBTree<[u8; MODULE_NAME_LEN], ModuleMetadata>>,
+}
+
+impl MetadataStore {
+    /// Create an empty store
+    pub fn new() -> impl PinInit<Self> {
+        pin_init!(Self {
+            records <- new_mutex!(RBTree::new()),
+        })
+    }
+
+    fn key(module: &Module) -> [u8; MODULE_NAME_LEN] {
+        copy_bytes(module.name().as_bytes())
+    }
+
+    /// List the suspicious imports of the recorded modules
+    pub fn report(&self) -> Result<MetadataReport> {
+        let mut report = MetadataReport {
+            suspicious: KVec::new(),
+        };
+
+        let records = self.records.lock();
+        for (module, metadata) in records.iter() {
+            for import in metadata.suspicious_imports() {
+                report.suspicious.push(
+                    SuspiciousImport {
+                        module: *module,
+                        import: *import,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Call `f` with the metadata of a module, if recorded
+    pub fn with_metadata<R>(&self, name: &CStr, f: impl FnOnce(&ModuleMetadata) -> R) -> Option<R> {
+        let records = self.records.lock();
+        records.get(&copy_bytes(name.as_bytes())).map(f)
+    }
+}
+
+/// The [`ModuleNotifierOperations`] filling a [`MetadataStore`]
+pub struct MetadataRecorder;
+
+impl ModuleNotifierOperations for MetadataRecorder {
+    type Data = Arc<MetadataStore>;
+
+    fn coming(store: ArcBorrow<'_, MetadataStore>, module: &Module) {
+        // SAFETY: We are in the `coming` callback of the notifier
+        let ret = unsafe { ModuleMetadata::capture(module) }.and_then(|metadata| {
+            store.records.lock().try_create_and_insert(
+                MetadataStore::key(module),
+                metadata,
+                GFP_KERNEL,
+            )?;
+            Ok(())
+        });
+        if ret.is_err() {
+            pr_warn!("Couldn't record the metadata of module {}\n", module.name());
+        }
+    }
+
+    fn live(store: ArcBorrow<'_, MetadataStore>, module: &Module) {
+        let mut records = store.records.lock();
+        if let Some(metadata) = records.get_mut(&MetadataStore::key(module)) {
+            if metadata.capture_sections(module).is_err() {
+                pr_warn!("Couldn't record the sections of module {}\n", module.name());
+            }
+        }
+    }
+
+    fn going(store: ArcBorrow<'_, MetadataStore>, module: &Module) {
+        store.records.lock().remove(&MetadataStore::key(module));
+    }
+}
+
+/// A suspicious symbol imported by a module
+pub struct SuspiciousImport {
+    /// Name of the module, null terminated
+    pub module: [u8; MODULE_NAME_LEN],
+    /// The imported symbol
+    pub import: Import,
+}
+
+/// Result of [`MetadataStore::report`]
+pub struct MetadataReport {
+    /// The suspicious imports
+    pub suspicious: KVec<SuspiciousImport>,
+}
+
+impl MetadataReport {
+    /// Create the event listing the suspicious imports, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.suspicious.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousImport,
+            fmt!("modules importing suspicious symbols : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for MetadataReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, s) in self.suspicious.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} ({} at {:#x})",
+                BStr::from_bytes(until_nul(&s.module)),
+                BStr::from_bytes(s.import.name()),
+                s.import.address
+            )?;
+        }
+        Ok(())
+    }
+}
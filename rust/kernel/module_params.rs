@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Module parameters : cross-check of the parameters against their sysfs attributes
+//!
+//! Each parameter of a module with non-zero permissions get an attribute in
+//! `/sys/module/<name>/parameters`. A rootkit can hide its control knobs by removing
+//! these attributes while the `struct kernel_param` array, which is part of the module
+//! image, still describes them.
+//!
+//! C header: [`include/linux/moduleparam.h`](../../../../include/linux/moduleparam.h)
+
+use core::fmt;
+
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::{Module, ModuleIter, ModuleState, MODULE_NAME_LEN};
+use crate::str::{until_nul, BStr};
+use kernel::prelude::*;
+
+/// Maximum length of a parameter name kept in the report, longer names are truncated
+pub const PARAM_NAME_LEN: usize = 64;
+
+/// A parameter without its sysfs attribute
+pub struct HiddenParam {
+    /// Name of the module, null terminated
+    pub module: [u8; MODULE_NAME_LEN],
+    /// Name of the parameter, null terminated
+    pub param: [u8; PARAM_NAME_LEN],
+}
+
+/// Result of the cross-check
+pub struct ParamReport {
+    /// Parameters missing from sysfs
+    pub hidden: KVec<HiddenParam>,
+}
+
+fn copy_bytes<const N: usize>(src: &[u8]) -> [u8; N] {
+    let mut dst = [0u8; N];
+    let len = src.len().min(N - 1);
+    dst[..len].copy_from_slice(&src[..len]);
+    dst
+}
+
+/// A reference on a kernfs node, released on drop
+struct KernfsNode(*mut bindings::kernfs_node);
+
+impl KernfsNode {
+    /// Find the child `name` of `parent`
+    fn find(parent: *mut bindings::kernfs_node, name: &CStr) -> Option<Self> {
+        if parent.is_null() {
+            return None;
+        }
+        // SAFETY: Just an FFI call, `parent` is a valid kernfs node held by the caller
+        let node = unsafe {
+            bindings::kernfs_find_and_get_ns(parent, name.as_char_ptr(), core::ptr::null())
+        };
+        (!node.is_null()).then_some(KernfsNode(node))
+    }
+}
+
+impl Drop for KernfsNode {
+    fn drop(&mut self) {
+        // SAFETY: The reference was taken by `kernfs_find_and_get_ns`
+        unsafe { bindings::kernfs_put(self.0) };
+    }
+}
+
+impl ParamReport {
+    /// Check that the visible parameters of every module have their sysfs attribute
+    pub fn check() -> Result<Self> {
+        let mut report = ParamReport {
+            hidden: KVec::new(),
+        };
+
+        // The sysfs attributes are created while the module is loading and removed while
+        // it is unloading, only the live modules have all of them
+        for module in ModuleIter::try_new()?.filter(|module| module.state() == ModuleState::Live) {
+            report.check_module(&module)?;
+        }
+
+        Ok(report)
+    }
+
+    fn check_module(&mut self, module: &Module) -> Result {
+        // SAFETY: The module is valid, its kobject is alive as long as the module is
+        let sd = unsafe { (*module.as_ptr()).mkobj.kobj.sd };
+        let params = KernfsNode::find(sd, c_str!("parameters"));
+
+        for param in module.params().filter(|param| param.has_sysfs()) {
+            let found = params
+                .as_ref()
+                .and_then(|params| KernfsNode::find(params.0, param.name))
+                .is_some();
+            if !found {
+                self.hidden.push(
+                    HiddenParam {
+                        module: copy_bytes(module.name().as_bytes()),
+                        param: copy_bytes(param.name.as_bytes()),
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the event listing the hidden parameters, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.hidden.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::HiddenModuleParam,
+            fmt!("module parameters missing from sysfs : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for ParamReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, hidden) in self.hidden.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{}.{}",
+                BStr::from_bytes(until_nul(&hidden.module)),
+                BStr::from_bytes(until_nul(&hidden.param))
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Module signature : report the modules loaded without a valid signature
+//!
+//! When the kernel supports module signing, each module records whether its signature
+//! was verified. Without enforcement (`module.sig_enforce`) unsigned modules are still
+//! loaded and only taint the kernel, which is the usual way rootkits are delivered.
+//! With enforcement an unsigned module should never be loaded at all.
+
+use core::fmt;
+
+use crate::event::{Event, EventKind};
+use crate::module::{ModuleIter, MODULE_NAME_LEN};
+use crate::str::BStr;
+use kernel::prelude::*;
+
+/// A module loaded without a valid signature
+pub struct UnsignedModule {
+    /// Name of the module, null terminated
+    pub name: [u8; MODULE_NAME_LEN],
+    /// The module taints the kernel as out-of-tree
+    pub out_of_tree: bool,
+}
+
+/// Result of the signature check
+pub struct SignatureReport {
+    /// The kernel supports module signing (`CONFIG_MODULE_SIG`)
+    pub signing_configured: bool,
+    /// The signature is enforced, unsigned modules are refused
+    pub enforced: bool,
+    /// The loaded modules without a valid signature
+    pub unsigned: KVec<UnsignedModule>,
+}
+
+/// Check if the signature of the modules is enforced
+fn is_sig_enforced() -> bool {
+    #[cfg(CONFIG_MODULE_SIG)]
+    {
+        // SAFETY: Just an FFI call
+        unsafe { bindings::is_module_sig_enforced() }
+    }
+    #[cfg(not(CONFIG_MODULE_SIG))]
+    {
+        false
+    }
+}
+
+impl SignatureReport {
+    /// List the loaded modules without a valid signature
+    ///
+    /// Nothing is reported when the kernel doesn't support module signing
+    pub fn check() -> Result<Self> {
+        let mut report = SignatureReport {
+            signing_configured: cfg!(CONFIG_MODULE_SIG),
+            enforced: is_sig_enforced(),
+            unsigned: KVec::new(),
+        };
+        if !report.signing_configured {
+            return Ok(report);
+        }
+
+        for module in ModuleIter::try_new()? {
+            if module.sig_ok() {
+                continue;
+            }
+
+            let mut name = [0u8; MODULE_NAME_LEN];
+            let src = module.name().as_bytes();
+            name[..src.len()].copy_from_slice(src);
+            report.unsigned.push(
+                UnsignedModule {
+                    name,
+                    out_of_tree: module.taints().is_out_of_tree(),
+                },
+                GFP_KERNEL,
+            )?;
+        }
+
+        Ok(report)
+    }
+
+    /// Create the event listing the unsigned modules, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.unsigned.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::UnsignedModule,
+            fmt!("modules without a valid signature : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for SignatureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, module) in self.unsigned.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            let len = module
+                .name
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(MODULE_NAME_LEN);
+            write!(f, "{}", BStr::from_bytes(&module.name[..len]))?;
+            if module.out_of_tree {
+                f.write_str(" (out-of-tree)")?;
+            }
+        }
+        if self.enforced {
+            f.write_str(" [sig_enforce active]")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Module states : detection of the anomalies of the module list
+//!
+//! Manipulating the module list by hand (DKOM) often leaves side effects behind :
+//! - a module stuck in `MODULE_STATE_UNFORMED` or `MODULE_STATE_GOING`, which are
+//!   transient states the loader never keeps for long
+//! - a module with an empty name, or the same name as another module
+//! - a list entry whose `prev` pointer doesn't point back to the previous entry
+//! - a state outside of `enum module_state`
+//!
+//! The list is walked directly under `module_mutex`, without taking references, so the
+//! modules being unloaded are seen too.
+
+use core::fmt;
+
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::{
+    symbols_lookup_name, Module, ModuleState, MODULE_NAME_LEN, MODULE_SNAPSHOT_MAX,
+};
+use crate::offsets::Field;
+use crate::str::{until_nul, BStr};
+use crate::sync::StaticCMutexGuard;
+use crate::time::Ktime;
+use kernel::prelude::*;
+
+/// Default time after which a module in a transient state is reported
+pub const DEFAULT_THRESHOLD_NS: i64 = 30 * 1_000_000_000;
+
+/// An anomaly of a module
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Anomaly {
+    /// The module stay in a transient state (`Unformed` or `Going`) for too long
+    Lingering {
+        /// The transient state
+        state: ModuleState,
+        /// For how long the module is in this state, in nanoseconds
+        duration_ns: i64,
+    },
+    /// The state is outside of `enum module_state`
+    UnknownState(u32),
+    /// The name of the module is empty
+    EmptyName,
+    /// Another module has the same name
+    DuplicateName,
+    /// `next->prev` doesn't point back to the entry of the module
+    BrokenLinks,
+}
+
+/// A module with an anomaly
+pub struct ModuleAnomaly {
+    /// Address of the `struct module`
+    pub module: u64,
+    /// Name of the module, null terminated
+    pub name: [u8; MODULE_NAME_LEN],
+    /// The anomaly
+    pub anomaly: Anomaly,
+}
+
+/// A module seen in a transient state by a previous check
+struct Transient {
+    module: u64,
+    state: ModuleState,
+    since: i64,
+}
+
+/// A module of the list, as seen during the walk
+struct Seen {
+    module: u64,
+    name: [u8; MODULE_NAME_LEN],
+    state: ModuleState,
+}
+
+/// Monitor of the module states
+///
+/// The lingering modules can only be detected across several checks, the monitor keeps
+/// the modules seen in a transient state between two calls to [`StateMonitor::check`].
+pub struct StateMonitor {
+    threshold_ns: i64,
+    transients: KVec<Transient>,
+}
+
+/// Result of a check
+pub struct StateReport {
+    /// The anomalies found
+    pub anomalies: KVec<ModuleAnomaly>,
+}
+
+impl StateMonitor {
+    /// Create a monitor reporting the modules in a transient state for more than
+    /// `threshold_ns` nanoseconds
+    pub fn new(threshold_ns: i64) -> Self {
+        StateMonitor {
+            threshold_ns,
+            transients: KVec::new(),
+        }
+    }
+
+    /// Walk the module list under `module_mutex`
+    ///
+    /// # Return
+    /// The modules of the list, and the ones whose list entry is inconsistent
+    fn walk(anomalies: &mut KVec<ModuleAnomaly>) -> Result<KVec<Seen>> {
+        let head = symbols_lookup_name(c_str!("modules")) as *mut bindings::list_head;
+        if head.is_null() {
+            pr_err!("Couldn't find modules symbol\n");
+            return Err(ENOENT);
+        }
+
+        let mut seen = KVec::new();
+
+        let _guard = StaticCMutexGuard::lock(c_str!("module_mutex"))?;
+
+        let mut prev = head;
+        // SAFETY: We hold `module_mutex`, the list can't be modified
+        let mut entry = unsafe { (*head).next };
+        while entry != head && !entry.is_null() {
+            if seen.len() >= MODULE_SNAPSHOT_MAX {
+                pr_warn!("Too many modules, the walk is truncated\n");
+                break;
+            }
+
+            // SAFETY: Excepting the head the entries of the list are in a `struct module`
+            let ptr: *const bindings::module =
+                unsafe { Field::ModuleList.container::<bindings::module, _>(entry) };
+            // SAFETY: The module is on the list and we hold `module_mutex`, it can't be freed
+            let module = unsafe { Module::from_raw(ptr) };
+
+            let mut name = [0u8; MODULE_NAME_LEN];
+            let src = module.name().as_bytes();
+            name[..src.len()].copy_from_slice(src);
+
+            // SAFETY: We hold `module_mutex`
+            if unsafe { (*entry).prev } != prev {
+                anomalies.push(
+                    ModuleAnomaly {
+                        module: ptr as u64,
+                        name,
+                        anomaly: Anomaly::BrokenLinks,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+
+            seen.push(
+                Seen {
+                    module: ptr as u64,
+                    name,
+                    state: module.state(),
+                },
+                GFP_KERNEL,
+            )?;
+
+            prev = entry;
+            // SAFETY: We hold `module_mutex`
+            entry = unsafe { (*entry).next };
+        }
+
+        Ok(seen)
+    }
+
+    /// Check the module list
+    pub fn check(&mut self) -> Result<StateReport> {
+        let now = Ktime::ktime_get().to_ns();
+        let mut anomalies = KVec::new();
+        let mut seen = Self::walk(&mut anomalies)?;
+        let mut transients = KVec::new();
+
+        for module in seen.iter() {
+            let mut push = |anomaly| {
+                anomalies.push(
+                    ModuleAnomaly {
+                        module: module.module,
+                        name: module.name,
+                        anomaly,
+                    },
+                    GFP_KERNEL,
+                )
+            };
+
+            match module.state {
+                ModuleState::Unformed | ModuleState::Going => {
+                    let since = self
+                        .transients
+                        .iter()
+                        .find(|t| t.module == module.module && t.state == module.state)
+                        .map_or(now, |t| t.since);
+                    if now - since > self.threshold_ns {
+                        push(Anomaly::Lingering {
+                            state: module.state,
+                            duration_ns: now - since,
+                        })?;
+                    }
+                    transients.push(
+                        Transient {
+                            module: module.module,
+                            state: module.state,
+                            since,
+                        },
+                        GFP_KERNEL,
+                    )?;
+                }
+                ModuleState::Unknown(state) => push(Anomaly::UnknownState(state))?,
+                ModuleState::Live | ModuleState::Coming => {}
+            }
+
+            if until_nul(&module.name).is_empty() {
+                push(Anomaly::EmptyName)?;
+            }
+        }
+        self.transients = transients;
+
+        seen.sort_unstable_by(|a, b| until_nul(&a.name).cmp(until_nul(&b.name)));
+        for pair in seen.windows(2) {
+            let name = until_nul(&pair[1].name);
+            if !name.is_empty() && name == until_nul(&pair[0].name) {
+                anomalies.push(
+                    ModuleAnomaly {
+                        module: pair[1].module,
+                        name: pair[1].name,
+                        anomaly: Anomaly::DuplicateName,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+
+        Ok(StateReport { anomalies })
+    }
+}
+
+impl StateReport {
+    /// Create the event listing the anomalies, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.anomalies.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::AnomalousModuleState,
+            fmt!("anomalies of the module list : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for StateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, a) in self.anomalies.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} ({:#x}, {:?})",
+                BStr::from_bytes(until_nul(&a.name)),
+                a.module,
+                a.anomaly
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Module tampering : validation of the fields of each `struct module`
+//!
+//! Some rootkits scrub the fields of their `struct module` to evade the scanners
+//! matching on them (Reptile style), or leave a half-initialized structure behind.
+//! Each module reachable from the list is checked for :
+//! - a name which is null terminated, non-empty printable ASCII
+//! - a text region with a non-null, page aligned base and a non-zero size, inside the
+//!   module area
+//! - a sysfs kobject pointing back to the module, with the same name, once the module
+//!   is past `MODULE_STATE_COMING`
+
+use core::fmt;
+
+use crate::event::{Event, EventKind};
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use crate::module::is_module_space;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+use crate::module::is_vmalloc_addr;
+use crate::module::{ModMemType, Module, ModuleIter, ModuleState, MODULE_NAME_LEN};
+use crate::page::PAGE_SIZE;
+use crate::str::BStr;
+use kernel::prelude::*;
+
+/// An inconsistent field of a `struct module`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tampering {
+    /// The name is empty, not null terminated or contains non printable characters
+    BadName,
+    /// The text region is empty, misaligned or outside of the module area
+    BadText {
+        /// Base of the text region
+        base: u64,
+        /// Size of the text region
+        size: u64,
+    },
+    /// The kobject doesn't point back to the module
+    BadKobject,
+    /// The kobject name differs from the module name
+    KobjectNameMismatch,
+}
+
+/// A module with an inconsistent field
+pub struct TamperedModule {
+    /// Address of the `struct module`
+    pub module: u64,
+    /// Raw name of the module, printed escaped
+    pub name: [u8; MODULE_NAME_LEN],
+    /// The inconsistency
+    pub tampering: Tampering,
+}
+
+/// Result of the check
+pub struct TamperingReport {
+    /// The inconsistencies found
+    pub tampered: KVec<TamperedModule>,
+}
+
+/// Check if an address is in the area where modules are mapped
+fn in_module_area(addr: u64) -> bool {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    {
+        is_module_space(addr)
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        is_vmalloc_addr(addr)
+    }
+}
+
+fn check_name(module: &Module) -> Option<Tampering> {
+    let name = module.raw_name();
+    let valid = match name.iter().position(|c| *c == 0) {
+        Some(0) | None => false,
+        Some(len) => name[..len].iter().all(|c| c.is_ascii_graphic()),
+    };
+    (!valid).then_some(Tampering::BadName)
+}
+
+fn check_text(module: &Module) -> Option<Tampering> {
+    // SAFETY: We hold a reference to the module, `mem` is only modified while the module
+    // is loading or unloading
+    let text = unsafe { &(*module.as_ptr()).mem[ModMemType::Text as usize] };
+    let base = text.base as u64;
+    let size = text.size as u64;
+
+    let valid = base != 0
+        && size != 0
+        && base % PAGE_SIZE as u64 == 0
+        && in_module_area(base)
+        && base.checked_add(size - 1).is_some_and(in_module_area);
+    (!valid).then_some(Tampering::BadText { base, size })
+}
+
+fn check_kobject(module: &Module) -> Option<Tampering> {
+    // The sysfs kobject is only set up at the end of the loading, after the module
+    // became `MODULE_STATE_COMING`
+    if matches!(module.state(), ModuleState::Coming | ModuleState::Unformed) {
+        return None;
+    }
+
+    // SAFETY: We hold a reference to the module, its kobject is alive as long as it is
+    let (mod_, kobj_name) = unsafe {
+        let mkobj = &(*module.as_ptr()).mkobj;
+        (mkobj.mod_, mkobj.kobj.name)
+    };
+
+    if mod_ != module.as_ptr() {
+        return Some(Tampering::BadKobject);
+    }
+    if kobj_name.is_null() {
+        return Some(Tampering::KobjectNameMismatch);
+    }
+    // SAFETY: The kobject name is a null terminated string
+    let kobj_name = unsafe { CStr::from_char_ptr(kobj_name) };
+    (kobj_name.as_bytes() != module.name().as_bytes()).then_some(Tampering::KobjectNameMismatch)
+}
+
+impl TamperingReport {
+    /// Validate the fields of each module of the list
+    pub fn check() -> Result<Self> {
+        let mut report = TamperingReport {
+            tampered: KVec::new(),
+        };
+
+        for module in ModuleIter::try_new()? {
+            let checks = [
+                check_name(&module),
+                check_text(&module),
+                check_kobject(&module),
+            ];
+            for tampering in checks.into_iter().flatten() {
+                report.tampered.push(
+                    TamperedModule {
+                        module: module.as_ptr() as u64,
+                        name: *module.raw_name(),
+                        tampering,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Create the event listing the inconsistencies, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.tampered.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::ModuleTampering,
+            fmt!("tampered module structures : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for TamperingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, t) in self.tampered.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            let len = t
+                .name
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(MODULE_NAME_LEN);
+            write!(
+                f,
+                "{} ({:#x}, {:?})",
+                BStr::from_bytes(&t.name[..len]),
+                t.module,
+                t.tampering
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Module views : cross-check of the module list against the other kernel views of the modules
+//!
+//! Unlinking a module from the `modules` list is not enough to hide it completely,
+//! the kernel keeps track of the modules in other places :
+//! - the `/sys/module` kobjects (`module_kset`)
+//! - the dynamic debug tables (`ddebug_tables`)
+//! - the tracepoint module list (`tp_module_list`)
+//!
+//! A module present in one of these secondary views but missing from the
+//! primary list is a strong indicator of a hidden module.
+
+use core::fmt;
+use core::mem::offset_of;
+
+use crate::c_str;
+use crate::event::{Event, EventKind};
+#[cfg(all(
+    CONFIG_DYNAMIC_DEBUG,
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use crate::module::is_module_space;
+use crate::module::{symbols_lookup_name, Module, ModuleIter, MODULE_NAME_LEN};
+use crate::offsets::Field;
+use crate::str::{copy_from_char_ptr, until_nul, BStr};
+use crate::sync::StaticCMutexGuard;
+use kernel::prelude::*;
+
+/// Mirror of `struct ddebug_table` (private to `lib/dynamic_debug.c`)
+#[cfg(all(
+    CONFIG_DYNAMIC_DEBUG,
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[allow(dead_code)]
+#[repr(C)]
+struct DdebugTable {
+    link: bindings::list_head,
+    maps: bindings::list_head,
+    mod_name: *const core::ffi::c_char,
+    num_ddebugs: core::ffi::c_uint,
+    ddebugs: *mut core::ffi::c_void,
+}
+
+/// The secondary views of the modules
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ModuleView {
+    /// `/sys/module` kobjects
+    Sysfs,
+    /// Dynamic debug tables
+    DynamicDebug,
+    /// Tracepoint module list
+    Tracepoint,
+}
+
+/// A module present in a secondary view but not in the module list
+pub struct Discrepancy {
+    /// The view where the module was found
+    pub view: ModuleView,
+    /// Name of the module, null terminated
+    pub name: [u8; MODULE_NAME_LEN],
+    /// Address of the `struct module` if known by the view, 0 otherwise
+    pub module: u64,
+}
+
+/// Check if a `struct module` is being unloaded, it may have left the list already
+///
+/// # Safety
+///
+/// `module` must point to a valid `struct module`
+unsafe fn is_going(module: *const bindings::module) -> bool {
+    // SAFETY: By the safety contract `module` is valid
+    let state: u32 = unsafe { *Field::ModuleState.ptr::<_, u32>(module) };
+    state == bindings::module_state_MODULE_STATE_GOING
+}
+
+/// The modules reachable from the module list
+struct Listed {
+    pointers: KVec<u64>,
+    names: KVec<[u8; MODULE_NAME_LEN]>,
+}
+
+impl Listed {
+    fn new() -> Result<Self> {
+        let mut pointers = KVec::new();
+        let mut names = KVec::new();
+        for module in ModuleIter::try_new()? {
+            pointers.push(module.as_ptr() as u64, GFP_KERNEL)?;
+            // SAFETY: The name of a module is a null terminated string
+            let name = unsafe { copy_from_char_ptr(module.name().as_char_ptr()) };
+            names.push(name, GFP_KERNEL)?;
+        }
+        pointers.sort_unstable();
+        Ok(Listed { pointers, names })
+    }
+
+    fn has_pointer(&self, module: u64) -> bool {
+        self.pointers.binary_search(&module).is_ok()
+    }
+
+    #[cfg(all(
+        CONFIG_DYNAMIC_DEBUG,
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn has_name(&self, name: &[u8; MODULE_NAME_LEN]) -> bool {
+        self.names
+            .iter()
+            .any(|listed| until_nul(listed) == until_nul(name))
+    }
+}
+
+/// Result of the cross-check
+pub struct ModuleViewReport {
+    /// Modules missing from the module list
+    pub discrepancies: KVec<Discrepancy>,
+}
+
+impl ModuleViewReport {
+    /// Compare the module list against the secondary views
+    pub fn check() -> Result<Self> {
+        let listed = Listed::new()?;
+        let mut report = ModuleViewReport {
+            discrepancies: KVec::new(),
+        };
+
+        report.check_sysfs(&listed)?;
+        report.check_tracepoints(&listed)?;
+        #[cfg(all(
+            CONFIG_DYNAMIC_DEBUG,
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        report.check_ddebug(&listed)?;
+
+        Ok(report)
+    }
+
+    /// Walk the `module_kset` kobjects, the ones of loaded modules point to their `struct module`
+    fn check_sysfs(&mut self, listed: &Listed) -> Result {
+        let pkset = symbols_lookup_name(c_str!("module_kset")) as *const *mut bindings::kset;
+        if pkset.is_null() {
+            pr_err!("Couldn't find module_kset symbol\n");
+            return Err(ENOENT);
+        }
+        // SAFETY: `pkset` is the address of `module_kset`, set at boot
+        let kset = unsafe { *pkset };
+        if kset.is_null() {
+            return Err(ENOENT);
+        }
+
+        let mut found: KVec<(u64, [u8; MODULE_NAME_LEN])> = KVec::new();
+
+        // SAFETY: `kset` is valid, its list is protected by its spinlock
+        unsafe { bindings::spin_lock(core::ptr::addr_of_mut!((*kset).list_lock)) };
+        // SAFETY: `kset` is valid
+        let head = unsafe { core::ptr::addr_of_mut!((*kset).list) };
+        // SAFETY: We hold the list lock
+        let mut entry = unsafe { (*head).next };
+        let mut ret: Result = Ok(());
+        while entry != head {
+            let kobj = entry
+                .cast::<u8>()
+                .wrapping_sub(offset_of!(bindings::kobject, entry))
+                .cast::<bindings::kobject>();
+            // All the kobjects of `module_kset` are embedded in a `module_kobject`
+            let mk = kobj
+                .cast::<u8>()
+                .wrapping_sub(offset_of!(bindings::module_kobject, kobj))
+                .cast::<bindings::module_kobject>();
+
+            // SAFETY: We hold the list lock so the kobject is alive, as is the module
+            // which remove its kobject before being freed
+            let module = unsafe { (*mk).mod_ };
+            // SAFETY: See above
+            if !module.is_null() && !unsafe { is_going(module) } {
+                // SAFETY: See above
+                if let Err(e) = found.push(
+                    (module as u64, unsafe { copy_from_char_ptr((*kobj).name) }),
+                    GFP_ATOMIC,
+                ) {
+                    ret = Err(e.into());
+                    break;
+                }
+            }
+
+            // SAFETY: We hold the list lock
+            entry = unsafe { (*entry).next };
+        }
+        // SAFETY: We locked it above
+        unsafe { bindings::spin_unlock(core::ptr::addr_of_mut!((*kset).list_lock)) };
+        ret?;
+
+        for (module, name) in found.iter() {
+            if !listed.has_pointer(*module) {
+                self.discrepancies.push(
+                    Discrepancy {
+                        view: ModuleView::Sysfs,
+                        name: *name,
+                        module: *module,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk the `tp_module_list`, each entry point to a `struct module` with tracepoints
+    fn check_tracepoints(&mut self, listed: &Listed) -> Result {
+        let head = symbols_lookup_name(c_str!("tp_module_list")) as *mut bindings::list_head;
+        if head.is_null() {
+            pr_err!("Couldn't find tp_module_list symbol\n");
+            return Err(ENOENT);
+        }
+
+        let _guard = StaticCMutexGuard::lock(c_str!("tracepoint_module_list_mutex"))?;
+        // SAFETY: We hold the mutex protecting the list
+        let mut entry = unsafe { (*head).next };
+        while entry != head {
+            let tp_mod = entry
+                .cast::<u8>()
+                .wrapping_sub(offset_of!(bindings::tp_module, list))
+                .cast::<bindings::tp_module>();
+            // SAFETY: The entry is removed from the list before the module is freed
+            let module = unsafe { (*tp_mod).mod_ };
+
+            // SAFETY: See above
+            if !module.is_null()
+                && !unsafe { is_going(module) }
+                && !listed.has_pointer(module as u64)
+            {
+                // SAFETY: See above
+                let name = unsafe { Module::from_raw(module) }.name();
+                // SAFETY: The name of a module is a null terminated string
+                let name = unsafe { copy_from_char_ptr(name.as_char_ptr()) };
+                self.discrepancies.push(
+                    Discrepancy {
+                        view: ModuleView::Tracepoint,
+                        name,
+                        module: module as u64,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+
+            // SAFETY: We hold the mutex
+            entry = unsafe { (*entry).next };
+        }
+        Ok(())
+    }
+
+    /// Walk the `ddebug_tables`, only the tables whose descriptors are in the module space
+    /// belong to a loaded module, the other are built-in
+    #[cfg(all(
+        CONFIG_DYNAMIC_DEBUG,
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn check_ddebug(&mut self, listed: &Listed) -> Result {
+        let head = symbols_lookup_name(c_str!("ddebug_tables")) as *mut bindings::list_head;
+        if head.is_null() {
+            pr_err!("Couldn't find ddebug_tables symbol\n");
+            return Err(ENOENT);
+        }
+
+        let _guard = StaticCMutexGuard::lock(c_str!("ddebug_lock"))?;
+        // SAFETY: We hold the mutex protecting the list
+        let mut entry = unsafe { (*head).next };
+        while entry != head {
+            let table = entry.cast::<DdebugTable>();
+            // SAFETY: The table is valid while on the list
+            let (ddebugs, mod_name) = unsafe { ((*table).ddebugs as u64, (*table).mod_name) };
+
+            if is_module_space(ddebugs) {
+                // SAFETY: The name is a null terminated string living as long as the table
+                let name = unsafe { copy_from_char_ptr(mod_name) };
+                if !listed.has_name(&name) {
+                    self.discrepancies.push(
+                        Discrepancy {
+                            view: ModuleView::DynamicDebug,
+                            name,
+                            module: 0,
+                        },
+                        GFP_KERNEL,
+                    )?;
+                }
+            }
+
+            // SAFETY: We hold the mutex
+            entry = unsafe { (*entry).next };
+        }
+        Ok(())
+    }
+
+    /// Create the event listing the discrepancies, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.discrepancies.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::ModuleViewMismatch,
+            fmt!("modules missing from the module list : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for ModuleViewReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, d) in self.discrepancies.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} ({:?}, {:#x})",
+                BStr::from_bytes(until_nul(&d.name)),
+                d.view,
+                d.module
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Nofault : fault-safe reads of kernel memory
+//!
+//! Read kernel addresses that come from suspect pointers (which may be unmapped)
+//! without oopsing, using `copy_from_kernel_nofault`.
+//!
+//! C header: [`include/linux/uaccess.h`](../../../../include/linux/uaccess.h)
+
+use core::mem::MaybeUninit;
+
+use crate::transmute::FromBytes;
+use kernel::prelude::*;
+
+/// Copy `buf.len()` bytes from the kernel address `addr` to `buf`
+///
+/// Fail with `EFAULT` if any part of the source is not mapped or readable.
+pub fn copy(addr: usize, buf: &mut [u8]) -> Result {
+    // SAFETY: `buf` is a valid writable buffer of `buf.len()` bytes, the source
+    // is checked by `copy_from_kernel_nofault` itself
+    let ret = unsafe {
+        bindings::copy_from_kernel_nofault(buf.as_mut_ptr().cast(), addr as _, buf.len())
+    };
+    if ret != 0 {
+        return Err(EFAULT);
+    }
+    Ok(())
+}
+
+/// Read a value of type `T` at the kernel address `addr`
+pub fn read<T: FromBytes>(addr: usize) -> Result<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+
+    // SAFETY: `value` is valid for writes of `size_of::<T>()` bytes
+    let ret = unsafe {
+        bindings::copy_from_kernel_nofault(
+            value.as_mut_ptr().cast(),
+            addr as _,
+            core::mem::size_of::<T>(),
+        )
+    };
+    if ret != 0 {
+        return Err(EFAULT);
+    }
+
+    // SAFETY: The copy succeeded so all the bytes are initialized and
+    // every bit pattern is valid for `T` as it implements `FromBytes`
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Check that the kernel address `addr` is readable
+pub fn is_readable(addr: usize) -> bool {
+    read::<u8>(addr).is_ok()
+}
@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Namespace audit : the tasks hidden or isolated behind namespaces
+//!
+//! Every thread of the system is walked with [`AllThreadsIter`] and flagged when:
+//! - it isn't visible from `/proc` in one of its pid namespaces: its pid there doesn't
+//!   lead back to it, it was detached from the pid hash to hide it
+//! - it is the first process of its namespaces (its parent isn't in them) and its parent
+//!   is neither a container runtime, a sandbox, the service manager nor a kernel thread.
+//!   The runtimes are matched on the executable of the parent, its command name can be
+//!   set to anything with `prctl(PR_SET_NAME)`
+//! - its credentials belong to a user namespace owned by an unprivileged user but carry the
+//!   root id of the host with capabilities in the namespace, a mapping the owner can't have
+//!   written. The host root entering the namespace with `setns` is excluded
+//!
+//! The hiding done by hooking the readdir of `/proc` leaves the pid hash intact, it is
+//! left to the hook detection. A user isolating a program with `unshare` directly is
+//! flagged as well.
+//!
+//! C header: [`include/linux/pid_namespace.h`](../../../../include/linux/pid_namespace.h)
+
+use core::fmt;
+use core::ptr;
+
+use crate::event::{Event, EventKind};
+use crate::str::{until_nul, BStr};
+use crate::sync::rcu;
+use crate::task::{Task, TaskCreds, TASK_COMM_LEN};
+use crate::task_cgroup::Attribution;
+use crate::task_iter::AllThreadsIter;
+use crate::task_ns::{find_task_in, Namespaces};
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Prefixes of the executable names of the container runtimes and of the sandboxes creating
+/// namespaces for their children
+const RUNTIME_PREFIXES: [&[u8]; 14] = [
+    b"containerd-shim",
+    b"runc",
+    b"crun",
+    b"conmon",
+    b"dockerd",
+    b"docker-init",
+    b"lxc-start",
+    b"systemd-nspawn",
+    b"bwrap",
+    b"flatpak",
+    b"firejail",
+    b"snap-confine",
+    b"chrome",
+    b"firefox",
+];
+
+/// An anomaly of the namespaces of a task
+pub enum NsAnomaly {
+    /// The task isn't visible from `/proc` in one of its pid namespaces
+    HiddenFromProc {
+        /// Depth of the pid namespace, 0 for the initial one
+        level: u32,
+        /// The pid of the task in the namespace
+        nr: i32,
+    },
+    /// The task entered namespaces its parent isn't in, not through a container runtime
+    UnknownIsolation {
+        /// Thread id of the parent
+        parent: i32,
+        /// Path of the executable of the parent, empty if it has none
+        parent_exe: KVec<u8>,
+        /// The namespaces of the parent
+        parent_namespaces: Namespaces,
+    },
+    /// The task is root on the host with capabilities inside a user namespace owned by an
+    /// unprivileged user
+    HostRootInUserNs {
+        /// Owner of the user namespace
+        owner: u32,
+        /// The effective capabilities of the task in the namespace
+        caps: u64,
+    },
+}
+
+/// A task whose namespaces are anomalous
+pub struct NsFinding {
+    /// Thread id of the task
+    pub pid: i32,
+    /// Process id of the task
+    pub tgid: i32,
+    /// Command name of the task, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Path of the executable of the task, empty if it has none
+    pub exe: KVec<u8>,
+    /// Path of the cgroup of the task
+    pub cgroup: KVec<u8>,
+    /// The namespaces of the task
+    pub namespaces: Namespaces,
+    /// The anomaly
+    pub anomaly: NsAnomaly,
+}
+
+/// Result of the audit
+pub struct NsAudit {
+    /// Number of threads audited
+    pub scanned: usize,
+    /// The anomalous namespaces
+    pub findings: KVec<NsFinding>,
+}
+
+/// Check if the user namespace `user` of `task` was entered with `setns` rather than
+/// created by its owner `owner`
+///
+/// The topmost ancestor of `task` in the namespace created it with `unshare` or `clone`,
+/// keeping the effective id of the owner, or entered it with `setns` keeping its own ids.
+fn entered_with_setns(task: &Task, user: u32, owner: u32) -> bool {
+    let mut top: ARef<Task> = task.into();
+    for ancestor in task.ancestors() {
+        if ancestor.namespaces().user != user {
+            break;
+        }
+        top = ancestor;
+    }
+    top.creds().euid != owner
+}
+
+/// Check if the credentials of `task` carry the root id of the host and capabilities in a
+/// user namespace owned by an unprivileged user
+///
+/// The capabilities of a task only apply to its user namespace, where the owner can grant
+/// them all. What the owner can't do is to map the root of the host, whose objects the task
+/// then acts upon with these capabilities.
+fn host_root_in_user_ns(task: &Task, namespaces: &Namespaces) -> Option<NsAnomaly> {
+    let (owner, creds) = {
+        let _guard = rcu::read_lock();
+        // SAFETY: The task is referenced, `real_cred` is protected by RCU and the
+        // credentials hold a reference on their user namespace. The fields never change
+        // once committed
+        unsafe {
+            let cred = ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).real_cred));
+            let user_ns = (*cred).user_ns;
+            if user_ns == ptr::addr_of_mut!(bindings::init_user_ns) {
+                return None;
+            }
+            ((*user_ns).owner.val, TaskCreds::from_raw(cred))
+        }
+    };
+    if owner == 0 || creds.euid != 0 || creds.cap_effective == 0 {
+        return None;
+    }
+    if entered_with_setns(task, namespaces.user, owner) {
+        return None;
+    }
+    Some(NsAnomaly::HostRootInUserNs {
+        owner,
+        caps: creds.cap_effective,
+    })
+}
+
+/// Get the first pid namespace of `task` in which `/proc` doesn't show it, if any
+fn hidden_from_proc(task: &Task) -> Result<Option<NsAnomaly>> {
+    let pids = task.ns_pids()?;
+    if pids.is_empty() {
+        // Unhashed from every namespace
+        return Ok(Some(NsAnomaly::HiddenFromProc {
+            level: 0,
+            nr: task.pid(),
+        }));
+    }
+    Ok(pids
+        .iter()
+        .find(|pid| {
+            !find_task_in(&pid.ns, pid.nr).is_some_and(|found| found.as_ptr() == task.as_ptr())
+        })
+        .map(|pid| NsAnomaly::HiddenFromProc {
+            level: pid.level,
+            nr: pid.nr,
+        }))
+}
+
+/// Check if the process `task` entered namespaces its parent isn't in without a runtime
+fn unknown_isolation(task: &Task, namespaces: &Namespaces) -> Result<Option<NsAnomaly>> {
+    let Some(parent) = task.real_parent() else {
+        return Ok(None);
+    };
+    // The idle task, init and the kernel threads (usermode helpers) create namespaces
+    if parent.pid() <= 1 || parent.is_kthread() || task.is_kthread() {
+        return Ok(None);
+    }
+    // An exiting task already released its namespaces
+    if task.has_exited() || parent.has_exited() {
+        return Ok(None);
+    }
+    let parent_namespaces = parent.namespaces();
+    if !namespaces.isolated_from(&parent_namespaces) {
+        return Ok(None);
+    }
+    let parent_exe = parent.exe_path()?.unwrap_or_else(KVec::new);
+    let name = parent_exe
+        .rsplit(|c| *c == b'/')
+        .next()
+        .unwrap_or(&parent_exe);
+    if RUNTIME_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+    {
+        return Ok(None);
+    }
+    Ok(Some(NsAnomaly::UnknownIsolation {
+        parent: parent.pid(),
+        parent_exe,
+        parent_namespaces,
+    }))
+}
+
+impl NsAudit {
+    /// Audit the namespaces of every thread of the system
+    pub fn audit() -> Result<Self> {
+        let mut audit = NsAudit {
+            scanned: 0,
+            findings: KVec::new(),
+        };
+
+        for task in AllThreadsIter::new(current!().into()) {
+            audit.scanned += 1;
+            let namespaces = task.namespaces();
+
+            // A task is unhashed when it is reaped, once it exited
+            if !task.has_exited() {
+                if let Some(anomaly) = hidden_from_proc(&task)? {
+                    audit.push(&task, namespaces, anomaly)?;
+                }
+            }
+            // The threads share the namespaces of their process
+            if task.group_leader().as_ptr() == task.as_ptr() {
+                if let Some(anomaly) = unknown_isolation(&task, &namespaces)? {
+                    audit.push(&task, namespaces, anomaly)?;
+                }
+            }
+            if let Some(anomaly) = host_root_in_user_ns(&task, &namespaces) {
+                audit.push(&task, namespaces, anomaly)?;
+            }
+        }
+        Ok(audit)
+    }
+
+    fn push(&mut self, task: &Task, namespaces: Namespaces, anomaly: NsAnomaly) -> Result {
+        self.findings.push(
+            NsFinding {
+                pid: task.pid(),
+                tgid: task.group_leader().pid(),
+                comm: task.comm(),
+                exe: task.exe_path()?.unwrap_or_else(KVec::new),
+                cgroup: task.cgroup_path()?,
+                namespaces,
+                anomaly,
+            },
+            GFP_KERNEL,
+        )?;
+        Ok(())
+    }
+
+    /// Create the event listing the anomalous namespaces, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.findings.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::NamespaceAnomaly,
+            fmt!("anomalous namespaces of tasks : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for NsAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NsAnomaly::HiddenFromProc { level, nr } => write!(
+                f,
+                "hidden from /proc as {} in the pid namespace of level {}",
+                nr, level
+            ),
+            NsAnomaly::UnknownIsolation {
+                parent,
+                parent_exe,
+                parent_namespaces,
+            } => write!(
+                f,
+                "isolated from its parent {} ({}) [{}]",
+                BStr::from_bytes(parent_exe),
+                parent,
+                parent_namespaces
+            ),
+            NsAnomaly::HostRootInUserNs { owner, caps } => write!(
+                f,
+                "host root with capabilities {:#x} in a user namespace owned by {}",
+                caps, owner
+            ),
+        }
+    }
+}
+
+impl fmt::Display for NsAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, finding) in self.findings.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} (pid {}, tgid {}, exe {}, {}) [{}] {}",
+                BStr::from_bytes(until_nul(&finding.comm)),
+                finding.pid,
+                finding.tgid,
+                BStr::from_bytes(&finding.exe),
+                Attribution(&finding.cgroup),
+                finding.namespaces,
+                finding.anomaly
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Structure offsets database
+//!
+//! Some kernel structures are accessed through raw offsets (fields of `struct module`,
+//! `files_struct`, inet hash tables, ...). By default the offsets are the one computed by
+//! bindgen at compile time, but they can be overriden at runtime by a database supplied
+//! by userspace for the running kernel version. That way the same binary can be used
+//! across minor kernel updates.
+//!
+//! # Database format
+//!
+//! All the integers are native endian :
+//! - [`DbHeader`] : magic, version, kernel release the database was generated for, entry count
+//! - `count` times [`DbEntry`] : field identifier (see [`Field`]) and its offset
+
+use core::mem::{align_of, offset_of, size_of};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::transmute::FromBytes;
+use crate::uaccess::UserSliceReader;
+use kernel::prelude::*;
+
+/// Magic number at the start of the database (`"RKOF"`)
+pub const DB_MAGIC: u32 = 0x464f_4b52;
+
+/// Current version of the database format
+pub const DB_VERSION: u32 = 1;
+
+/// Maximum size of a database, to bound the allocation when reading it from userspace
+pub const DB_MAX_SIZE: usize = 4096;
+
+/// Length of the kernel release string (as in `struct new_utsname`)
+pub(crate) const RELEASE_LEN: usize = 65;
+
+/// Header of the database
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DbHeader {
+    /// Must be [`DB_MAGIC`]
+    pub magic: u32,
+    /// Must be [`DB_VERSION`]
+    pub version: u32,
+    /// The `uname -r` of the kernel the offsets were extracted from, null terminated
+    pub release: [u8; RELEASE_LEN],
+    _pad: [u8; 3],
+    /// The number of [`DbEntry`] following the header
+    pub count: u32,
+}
+
+// SAFETY: `DbHeader` only contains integers and arrays of integers, every bit pattern is valid
+unsafe impl FromBytes for DbHeader {}
+
+/// An entry of the database
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DbEntry {
+    /// The [`Field`] identifier
+    pub field: u32,
+    /// The offset of the field in its structure
+    pub offset: u32,
+}
+
+// SAFETY: `DbEntry` only contains integers, every bit pattern is valid
+unsafe impl FromBytes for DbEntry {}
+
+/// The fields whose offsets can be supplied at runtime
+///
+/// The discriminant is the identifier used in the database and must never change.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum Field {
+    /// `struct module::list`
+    ModuleList = 0,
+    /// `struct module::name`
+    ModuleName = 1,
+    /// `struct module::state`
+    ModuleState = 2,
+    /// `struct files_struct::fdt`
+    FilesStructFdt = 3,
+    /// `struct fdtable::max_fds`
+    FdtableMaxFds = 4,
+    /// `struct fdtable::fd`
+    FdtableFd = 5,
+    /// `struct inet_hashinfo::ehash`
+    InetHashinfoEhash = 6,
+    /// `struct inet_hashinfo::ehash_mask`
+    InetHashinfoEhashMask = 7,
+}
+
+/// Number of [`Field`]
+const FIELD_COUNT: usize = 8;
+
+/// Value of an entry not overriden by the database
+const UNSET: usize = usize::MAX;
+
+/// The offsets loaded from the database, indexed by [`Field`]
+static OVERRIDES: [AtomicUsize; FIELD_COUNT] = [const { AtomicUsize::new(UNSET) }; FIELD_COUNT];
+
+/// Get the size of the structure `S`, and the size and alignment of its field `T`
+fn layout<S, T>(_field: fn(&S) -> &T) -> (usize, usize, usize) {
+    (size_of::<S>(), size_of::<T>(), align_of::<T>())
+}
+
+impl Field {
+    /// Get the field corresponding to a database identifier
+    pub fn from_id(id: u32) -> Option<Self> {
+        Some(match id {
+            0 => Field::ModuleList,
+            1 => Field::ModuleName,
+            2 => Field::ModuleState,
+            3 => Field::FilesStructFdt,
+            4 => Field::FdtableMaxFds,
+            5 => Field::FdtableFd,
+            6 => Field::InetHashinfoEhash,
+            7 => Field::InetHashinfoEhashMask,
+            _ => return None,
+        })
+    }
+
+    /// The offset computed by bindgen at compile time
+    pub const fn builtin_offset(self) -> usize {
+        match self {
+            Field::ModuleList => offset_of!(bindings::module, list),
+            Field::ModuleName => offset_of!(bindings::module, name),
+            Field::ModuleState => offset_of!(bindings::module, state),
+            Field::FilesStructFdt => offset_of!(bindings::files_struct, fdt),
+            Field::FdtableMaxFds => offset_of!(bindings::fdtable, max_fds),
+            Field::FdtableFd => offset_of!(bindings::fdtable, fd),
+            Field::InetHashinfoEhash => offset_of!(bindings::inet_hashinfo, ehash),
+            Field::InetHashinfoEhashMask => offset_of!(bindings::inet_hashinfo, ehash_mask),
+        }
+    }
+
+    /// The size of the structure, and the size and alignment of the field, as compiled
+    fn layout(self) -> (usize, usize, usize) {
+        match self {
+            Field::ModuleList => layout(|s: &bindings::module| &s.list),
+            Field::ModuleName => layout(|s: &bindings::module| &s.name),
+            Field::ModuleState => layout(|s: &bindings::module| &s.state),
+            Field::FilesStructFdt => layout(|s: &bindings::files_struct| &s.fdt),
+            Field::FdtableMaxFds => layout(|s: &bindings::fdtable| &s.max_fds),
+            Field::FdtableFd => layout(|s: &bindings::fdtable| &s.fd),
+            Field::InetHashinfoEhash => layout(|s: &bindings::inet_hashinfo| &s.ehash),
+            Field::InetHashinfoEhashMask => layout(|s: &bindings::inet_hashinfo| &s.ehash_mask),
+        }
+    }
+
+    /// Check that `offset` is a valid offset of the field: aligned, and the whole field is
+    /// in bounds of its structure
+    fn check_offset(self, offset: usize) -> Result<usize> {
+        let (structure, size, align) = self.layout();
+        if offset % align != 0 || !offset.checked_add(size).is_some_and(|end| end <= structure) {
+            return Err(EINVAL);
+        }
+        Ok(offset)
+    }
+
+    /// The offset of the field for the running kernel
+    ///
+    /// Use the value of the database if one was loaded, otherwise the bindgen one. The
+    /// loaded offsets are checked to be in bounds of the structure
+    pub fn offset(self) -> usize {
+        match OVERRIDES[self as usize].load(Ordering::Relaxed) {
+            UNSET => self.builtin_offset(),
+            offset => offset,
+        }
+    }
+
+    /// Get a pointer to the field of the structure pointed by `base`
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to a valid instance of the structure containing this field.
+    pub unsafe fn ptr<S, T>(self, base: *const S) -> *const T {
+        // SAFETY: By the safety contract `base` is a valid structure and the offset
+        // is in bounds of this structure
+        unsafe { base.cast::<u8>().add(self.offset()).cast() }
+    }
+
+    /// Get the pointer to the structure from a pointer to this field (`container_of`)
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to this field inside a valid instance of its structure.
+    pub unsafe fn container<S, T>(self, ptr: *const T) -> *const S {
+        // SAFETY: By the safety contract the resulting pointer is in the same allocation
+        unsafe { ptr.cast::<u8>().sub(self.offset()).cast() }
+    }
+}
+
+/// Get the release of the running kernel
+fn running_release() -> &'static [u8] {
+    // SAFETY: `init_uts_ns` is a static of the kernel and the release never changes
+    let release = unsafe { &*core::ptr::addr_of!(bindings::init_uts_ns.name.release) };
+    // SAFETY: `c_char` and `u8` have the same layout
+    let release: &[u8] = unsafe { &*(release as *const [core::ffi::c_char] as *const [u8]) };
+    let len = release
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(release.len());
+    &release[..len]
+}
+
+/// The null terminated release of a table supplied by userspace is the running one
+pub(crate) fn release_matches(release: &[u8; RELEASE_LEN]) -> bool {
+    let len = release.iter().position(|c| *c == 0).unwrap_or(RELEASE_LEN);
+    &release[..len] == running_release()
+}
+
+/// Read the header `H` at the start of a table supplied by userspace
+///
+/// # Return
+/// The header and the bytes following it
+pub(crate) fn read_header<H: FromBytes>(data: &[u8]) -> Result<(H, &[u8])> {
+    let header_size = size_of::<H>();
+    let header_bytes = data.get(..header_size).ok_or(EINVAL)?;
+    // SAFETY: The slice has the size of `H` and every bit pattern is valid,
+    // `read_unaligned` doesn't require alignment
+    let header = unsafe { header_bytes.as_ptr().cast::<H>().read_unaligned() };
+    Ok((header, &data[header_size..]))
+}
+
+/// Iterate over the `count` entries `E` of a table supplied by userspace
+///
+/// Fail if `raw` is too small to contain them
+pub(crate) fn read_entries<E: FromBytes>(
+    raw: &[u8],
+    count: u32,
+) -> Result<impl Iterator<Item = E> + '_> {
+    let entry_size = size_of::<E>();
+    if raw.len() / entry_size < count as usize {
+        return Err(EINVAL);
+    }
+    Ok(raw
+        .chunks_exact(entry_size)
+        .take(count as usize)
+        .map(|raw| {
+            // SAFETY: The chunk has the size of `E` and every bit pattern is valid,
+            // `read_unaligned` doesn't require alignment
+            unsafe { raw.as_ptr().cast::<E>().read_unaligned() }
+        }))
+}
+
+/// Load the database from a buffer
+///
+/// The whole database is validated before being applied, so on error the previous
+/// offsets are kept.
+pub fn load(data: &[u8]) -> Result {
+    let (header, entries) = read_header::<DbHeader>(data)?;
+
+    if header.magic != DB_MAGIC || header.version != DB_VERSION {
+        pr_err!("Invalid offset database header\n");
+        return Err(EINVAL);
+    }
+
+    if !release_matches(&header.release) {
+        pr_err!("The offset database doesn't match the running kernel\n");
+        return Err(EINVAL);
+    }
+
+    let mut offsets = [UNSET; FIELD_COUNT];
+    for entry in read_entries::<DbEntry>(entries, header.count)? {
+        let field = Field::from_id(entry.field).ok_or(EINVAL)?;
+        offsets[field as usize] = field.check_offset(entry.offset as usize)?;
+    }
+
+    for (slot, offset) in OVERRIDES.iter().zip(offsets) {
+        slot.store(offset, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+/// Load the database from a userspace buffer
+pub fn load_from_user(reader: UserSliceReader) -> Result {
+    if reader.len() > DB_MAX_SIZE {
+        return Err(E2BIG);
+    }
+
+    let mut buf = KVec::new();
+    reader.read_all(&mut buf, GFP_KERNEL)?;
+
+    load(&buf)
+}
+
+/// Forget the loaded database and go back to the bindgen offsets
+pub fn reset() {
+    for slot in OVERRIDES.iter() {
+        slot.store(UNSET, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,556 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! ORC audit : the unwind metadata covering the monitored functions
+//!
+//! The stacktraces of the sensors, and so the provenance of their callers, are unwound
+//! with the ORC tables: `.orc_unwind_ip` holds the sorted text addresses (relative to
+//! their slot) and `.orc_unwind` how to find the caller frame from each of them. Corrupting
+//! them blinds every stacktrace based detection: an entry marking a function as the end of
+//! the stack, or giving a wrong stack offset, hides its callers. For each function of
+//! [`SENSORS`] the tables covering it (the ones of the kernel image or of its module) are
+//! checked:
+//! - the tables are in their sections and have as many addresses as entries: the data of
+//!   the kernel image (they sit between `_edata` and `__init_begin`, writable, and
+//!   `orc_lookup` is written by `unwind_init`), the read-only data of a module
+//! - the addresses are ordered, otherwise the binary search of the unwinder misses entries
+//! - the lookup of the function finds an entry describing a call frame, the state of every
+//!   function at its first instruction
+//! - the fast lookup table of the kernel image (`orc_lookup`) leads to the same entry
+//!
+//! The tables are read with the [`nofault`](crate::nofault) reader, a table redirected to
+//! unmapped memory is reported instead of oopsing.
+//!
+//! C header: [`arch/x86/include/asm/orc_types.h`](../../../../arch/x86/include/asm/orc_types.h)
+
+use core::fmt;
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use crate::address::Owner;
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::{symbols_lookup_name, ModMemType, Module, MODULE_NAME_LEN};
+use crate::nofault;
+use crate::sensor_set::SENSORS;
+use crate::str::CStr;
+use crate::sync::rcu;
+use crate::transmute::FromBytes;
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// `ORC_REG_SP` : the frame is found from the stack pointer
+const ORC_REG_SP: u8 = 5;
+
+/// `ORC_TYPE_UNDEFINED` : no unwind information
+const ORC_TYPE_UNDEFINED: u8 = 0;
+
+/// `ORC_TYPE_CALL` : a call frame, the return address is at the top of the frame
+const ORC_TYPE_CALL: u8 = 2;
+
+/// Stack offset of the frame at the first instruction of a function: the return address
+const CALL_SP_OFFSET: i16 = 8;
+
+/// Order of the size of the blocks of text indexed by `orc_lookup` (`LOOKUP_BLOCK_ORDER`)
+const LOOKUP_BLOCK_ORDER: u32 = 8;
+
+/// Number of addresses read at once when checking their order
+const CHUNK: usize = 64;
+
+/// Mirror of `struct orc_entry`
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct OrcEntry {
+    sp_offset: i16,
+    bp_offset: i16,
+    /// `sp_reg:4`, `bp_reg:4`, `type:3`, `signal:1`
+    bits: u16,
+}
+
+// SAFETY: `OrcEntry` only contains integers, every bit pattern is valid
+unsafe impl FromBytes for OrcEntry {}
+
+impl OrcEntry {
+    fn sp_reg(&self) -> u8 {
+        (self.bits & 0xf) as u8
+    }
+
+    fn kind(&self) -> u8 {
+        ((self.bits >> 8) & 0x7) as u8
+    }
+}
+
+/// An ORC table: `count` addresses and their entries
+#[derive(Clone, Copy)]
+struct OrcTable {
+    ip: usize,
+    orc: usize,
+    count: usize,
+}
+
+impl OrcTable {
+    /// Get the address of the index `i`
+    fn ip_at(&self, i: usize) -> Result<u64> {
+        let slot = self.ip + i * size_of::<i32>();
+        let offset: i32 = nofault::read(slot)?;
+        Ok((slot as u64).wrapping_add(offset as i64 as u64))
+    }
+
+    /// Get the entry of the index `i`
+    fn entry_at(&self, i: usize) -> Result<OrcEntry> {
+        nofault::read(self.orc + i * size_of::<OrcEntry>())
+    }
+
+    /// Find the first address lower than the previous one, if any
+    fn first_unordered(&self) -> Result<Option<usize>> {
+        let mut buf = [0u8; CHUNK * size_of::<i32>()];
+        let mut previous = 0;
+        let mut i = 0;
+        while i < self.count {
+            let len = (self.count - i).min(CHUNK);
+            let slot = self.ip + i * size_of::<i32>();
+            nofault::copy(slot, &mut buf[..len * size_of::<i32>()])?;
+
+            for (j, offset) in buf[..len * size_of::<i32>()].chunks_exact(4).enumerate() {
+                let offset = i32::from_ne_bytes([offset[0], offset[1], offset[2], offset[3]]);
+                let ip = ((slot + j * size_of::<i32>()) as u64).wrapping_add(offset as i64 as u64);
+                if i + j != 0 && ip < previous {
+                    return Ok(Some(i + j));
+                }
+                previous = ip;
+            }
+            i += len;
+        }
+        Ok(None)
+    }
+
+    /// Find the index of the last address lower or equal to `addr` among the indexes `start`
+    /// to `end`, like the unwinder (`__orc_find`)
+    fn find(&self, addr: u64, start: usize, end: usize) -> Result<Option<usize>> {
+        let (mut low, mut high) = (start, end.min(self.count));
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.ip_at(mid)? <= addr {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok((low > start).then(|| low - 1))
+    }
+}
+
+/// What is wrong with the unwind metadata
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnwindFinding {
+    /// The tables can't be read
+    Unreadable,
+    /// The tables don't have as many addresses as entries
+    CountMismatch {
+        /// Number of addresses
+        ips: usize,
+        /// Number of entries
+        entries: usize,
+    },
+    /// A table is outside of its section
+    OutsideSection {
+        /// Address of the table
+        table: u64,
+    },
+    /// An address is lower than the previous one
+    Unordered {
+        /// Index of the address
+        index: usize,
+    },
+    /// No entry covers the function
+    LookupFailed,
+    /// The entry covering the start of the function doesn't describe a call frame
+    BadEntry {
+        /// Address of the entry
+        ip: u64,
+        /// Register the frame is found from
+        sp_reg: u8,
+        /// Offset of the frame from the register
+        sp_offset: i16,
+        /// Type of the entry
+        kind: u8,
+    },
+    /// The fast lookup table of the kernel image leads to another entry
+    FastLookupMismatch {
+        /// Index of the entry covering the function
+        index: usize,
+        /// First index of the block given by `orc_lookup`
+        start: usize,
+        /// Last index of the block given by `orc_lookup`
+        stop: usize,
+    },
+}
+
+/// Unwind metadata covering a monitored function in a bad state
+pub struct UnwindAnomaly {
+    /// Owner of the tables
+    pub owner: Owner,
+    /// The monitored function
+    pub function: &'static CStr,
+    /// What is wrong
+    pub finding: UnwindFinding,
+}
+
+/// Result of the audit
+pub struct OrcAudit {
+    /// Number of functions checked
+    pub checked: usize,
+    /// The anomalies, a table is only checked once
+    pub anomalies: KVec<UnwindAnomaly>,
+}
+
+/// The tables of the kernel image
+struct KernelTables {
+    table: OrcTable,
+    /// Count of `.orc_unwind`, checked against the one of `.orc_unwind_ip`
+    entries: usize,
+    /// The data of the image, `_sdata..__init_begin`, holding the tables
+    data: (usize, usize),
+    /// Start of the text indexed by `orc_lookup` and the table itself, if found
+    lookup: Option<(u64, usize, usize)>,
+}
+
+impl KernelTables {
+    fn new() -> Result<Self> {
+        let lookup = |name: &CStr| symbols_lookup_name(name) as usize;
+        let (ip, ip_end) = (
+            lookup(c_str!("__start_orc_unwind_ip")),
+            lookup(c_str!("__stop_orc_unwind_ip")),
+        );
+        let (orc, orc_end) = (
+            lookup(c_str!("__start_orc_unwind")),
+            lookup(c_str!("__stop_orc_unwind")),
+        );
+        let data = (lookup(c_str!("_sdata")), lookup(c_str!("__init_begin")));
+        if ip == 0 || orc == 0 || ip_end < ip || orc_end < orc || data.0 == 0 || data.1 < data.0 {
+            pr_err!("Couldn't find the ORC tables\n");
+            return Err(ENOENT);
+        }
+
+        let (stext, table, table_end) = (
+            symbols_lookup_name(c_str!("_stext")),
+            lookup(c_str!("orc_lookup")),
+            lookup(c_str!("orc_lookup_end")),
+        );
+        Ok(KernelTables {
+            table: OrcTable {
+                ip,
+                orc,
+                count: (ip_end - ip) / size_of::<i32>(),
+            },
+            entries: (orc_end - orc) / size_of::<OrcEntry>(),
+            data,
+            lookup: (stext != 0 && table != 0 && table_end > table).then_some((
+                stext,
+                table,
+                (table_end - table) / size_of::<u32>(),
+            )),
+        })
+    }
+
+    /// Check that the fast lookup of `addr` leads to the entry `index`
+    fn check_lookup(&self, addr: u64, index: usize) -> Result<Option<UnwindFinding>> {
+        let Some((stext, table, len)) = self.lookup else {
+            return Ok(None);
+        };
+        let block = (addr.wrapping_sub(stext) >> LOOKUP_BLOCK_ORDER) as usize;
+        if addr < stext || block + 1 >= len {
+            return Ok(None);
+        }
+
+        let start: u32 = nofault::read(table + block * size_of::<u32>())?;
+        let stop: u32 = nofault::read(table + (block + 1) * size_of::<u32>())?;
+        let (start, stop) = (start as usize, stop as usize + 1);
+        if (start..stop).contains(&index) {
+            return Ok(None);
+        }
+        Ok(Some(UnwindFinding::FastLookupMismatch {
+            index,
+            start,
+            stop,
+        }))
+    }
+}
+
+/// Get the module containing `addr` with a reference on it
+fn module_of(addr: u64) -> Option<ARef<Module>> {
+    let _guard = rcu::read_lock();
+
+    // SAFETY: Just an FFI call, we hold the RCU read lock as required
+    let module = unsafe { bindings::__module_address(addr as _) };
+    // SAFETY: The module can't be freed while we hold the RCU read lock
+    if module.is_null() || !unsafe { bindings::try_module_get(module) } {
+        return None;
+    }
+
+    // SAFETY: We just took a reference on the module, it is owned by the `ARef`
+    Some(unsafe { ARef::from_raw(NonNull::new_unchecked(module.cast::<Module>())) })
+}
+
+/// Get the tables of `module`
+fn module_table(module: &Module) -> OrcTable {
+    // SAFETY: The module is valid by the type invariant, the ORC fields are set at load
+    // time and never modified after
+    let arch = unsafe { &(*module.as_ptr()).arch };
+    OrcTable {
+        ip: arch.orc_unwind_ip as usize,
+        orc: arch.orc_unwind as usize,
+        count: arch.num_orcs as usize,
+    }
+}
+
+/// Get the owner of the tables of `module`
+fn module_owner(module: &Module) -> Owner {
+    let mut name = [0u8; MODULE_NAME_LEN];
+    let src = module.name().as_bytes();
+    name[..src.len()].copy_from_slice(src);
+    Owner::Module(name)
+}
+
+/// Turn the faults reading the tables into a finding
+fn readable(result: Result<Option<UnwindFinding>>) -> Result<Option<UnwindFinding>> {
+    match result {
+        Err(e) if e == EFAULT => Ok(Some(UnwindFinding::Unreadable)),
+        result => result,
+    }
+}
+
+/// Check the entry covering the start of the function at `addr`
+///
+/// # Return
+/// The index of the entry, or what is wrong
+fn check_entry(table: &OrcTable, addr: u64) -> Result<core::result::Result<usize, UnwindFinding>> {
+    let Some(index) = table.find(addr, 0, table.count)? else {
+        return Ok(Err(UnwindFinding::LookupFailed));
+    };
+    let entry = table.entry_at(index)?;
+    if entry.kind() == ORC_TYPE_UNDEFINED {
+        return Ok(Err(UnwindFinding::LookupFailed));
+    }
+    if entry.kind() != ORC_TYPE_CALL || entry.sp_reg() != ORC_REG_SP || { entry.sp_offset }
+        != CALL_SP_OFFSET
+    {
+        return Ok(Err(UnwindFinding::BadEntry {
+            ip: table.ip_at(index)?,
+            sp_reg: entry.sp_reg(),
+            sp_offset: entry.sp_offset,
+            kind: entry.kind(),
+        }));
+    }
+    Ok(Ok(index))
+}
+
+impl OrcAudit {
+    /// Check the unwind metadata covering the functions of the sensors
+    pub fn audit() -> Result<Self> {
+        let kernel = KernelTables::new()?;
+        let mut audit = OrcAudit {
+            checked: 0,
+            anomalies: KVec::new(),
+        };
+        // The `.orc_unwind_ip` of the tables already checked
+        let mut tables: KVec<usize> = KVec::new();
+
+        for sensor in SENSORS.iter() {
+            let addr = symbols_lookup_name(sensor.symbol);
+            if addr == 0 {
+                continue;
+            }
+            audit.checked += 1;
+
+            // SAFETY: Just an FFI call
+            if unsafe { bindings::core_kernel_text(addr as _) } {
+                if !tables.contains(&kernel.table.ip) {
+                    tables.push(kernel.table.ip, GFP_KERNEL)?;
+                    audit.check_kernel_tables(&kernel, sensor.symbol)?;
+                }
+                audit.check_function(
+                    Owner::Kernel,
+                    sensor.symbol,
+                    addr,
+                    &kernel.table,
+                    Some(&kernel),
+                )?;
+                continue;
+            }
+
+            let Some(module) = module_of(addr) else {
+                audit.push(Owner::None, sensor.symbol, UnwindFinding::LookupFailed)?;
+                continue;
+            };
+            let (owner, table) = (module_owner(&module), module_table(&module));
+            if !tables.contains(&table.ip) {
+                tables.push(table.ip, GFP_KERNEL)?;
+                audit.check_module_tables(&module, sensor.symbol)?;
+            }
+            audit.check_function(owner, sensor.symbol, addr, &table, None)?;
+        }
+        Ok(audit)
+    }
+
+    fn push(&mut self, owner: Owner, function: &'static CStr, finding: UnwindFinding) -> Result {
+        self.anomalies.push(
+            UnwindAnomaly {
+                owner,
+                function,
+                finding,
+            },
+            GFP_KERNEL,
+        )?;
+        Ok(())
+    }
+
+    /// Check the sections, the sizes and the order of the tables of the kernel image
+    fn check_kernel_tables(&mut self, kernel: &KernelTables, function: &'static CStr) -> Result {
+        let table = &kernel.table;
+        if table.count != kernel.entries {
+            self.push(
+                Owner::Kernel,
+                function,
+                UnwindFinding::CountMismatch {
+                    ips: table.count,
+                    entries: kernel.entries,
+                },
+            )?;
+        }
+
+        let (start, end) = kernel.data;
+        let tables = [
+            (table.ip, table.count * size_of::<i32>()),
+            (table.orc, kernel.entries * size_of::<OrcEntry>()),
+        ];
+        let lookup = kernel
+            .lookup
+            .map(|(_, lookup, len)| (lookup, len * size_of::<u32>()));
+        for (addr, size) in tables.into_iter().chain(lookup) {
+            if addr < start || addr.saturating_add(size) > end {
+                self.push(
+                    Owner::Kernel,
+                    function,
+                    UnwindFinding::OutsideSection { table: addr as u64 },
+                )?;
+            }
+        }
+
+        let finding = readable(
+            table
+                .first_unordered()
+                .map(|index| index.map(|index| UnwindFinding::Unordered { index })),
+        )?;
+        if let Some(finding) = finding {
+            self.push(Owner::Kernel, function, finding)?;
+        }
+        Ok(())
+    }
+
+    /// Check the sections and the order of the tables of `module`
+    fn check_module_tables(&mut self, module: &Module, function: &'static CStr) -> Result {
+        let table = module_table(module);
+        for addr in [table.ip, table.orc] {
+            let region = module
+                .contains_addr(addr as u64)
+                .map(|region| region.mem_type);
+            if !matches!(region, Some(ModMemType::Rodata | ModMemType::RoAfterInit)) {
+                self.push(
+                    module_owner(module),
+                    function,
+                    UnwindFinding::OutsideSection { table: addr as u64 },
+                )?;
+            }
+        }
+
+        let finding = readable(
+            table
+                .first_unordered()
+                .map(|index| index.map(|index| UnwindFinding::Unordered { index })),
+        )?;
+        if let Some(finding) = finding {
+            self.push(module_owner(module), function, finding)?;
+        }
+        Ok(())
+    }
+
+    /// Check the lookup of the function `function` at `addr` in `table`
+    fn check_function(
+        &mut self,
+        owner: Owner,
+        function: &'static CStr,
+        addr: u64,
+        table: &OrcTable,
+        kernel: Option<&KernelTables>,
+    ) -> Result {
+        let finding = readable(
+            check_entry(table, addr).and_then(|entry| match (entry, kernel) {
+                (Ok(index), Some(kernel)) => kernel.check_lookup(addr, index),
+                (Ok(_), None) => Ok(None),
+                (Err(finding), _) => Ok(Some(finding)),
+            }),
+        )?;
+        if let Some(finding) = finding {
+            self.push(owner, function, finding)?;
+        }
+        Ok(())
+    }
+
+    /// Create the event listing the anomalies of the unwind metadata, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.anomalies.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::UnwindTampering,
+            fmt!("unwind metadata of monitored functions tampered : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for UnwindFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnwindFinding::Unreadable => f.write_str("unreadable tables"),
+            UnwindFinding::CountMismatch { ips, entries } => {
+                write!(f, "{} addresses for {} entries", ips, entries)
+            }
+            UnwindFinding::OutsideSection { table } => {
+                write!(f, "table at {:#x} outside of its section", table)
+            }
+            UnwindFinding::Unordered { index } => write!(f, "address {} out of order", index),
+            UnwindFinding::LookupFailed => f.write_str("no entry"),
+            UnwindFinding::BadEntry {
+                ip,
+                sp_reg,
+                sp_offset,
+                kind,
+            } => write!(
+                f,
+                "entry at {:#x} not a call frame (type {}, sp reg {} offset {})",
+                ip, kind, sp_reg, sp_offset
+            ),
+            UnwindFinding::FastLookupMismatch { index, start, stop } => write!(
+                f,
+                "fast lookup gives entries {}..{} instead of {}",
+                start, stop, index
+            ),
+        }
+    }
+}
+
+impl fmt::Display for OrcAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, anomaly) in self.anomalies.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} ({:?} tables) {}",
+                anomaly.function, anomaly.owner, anomaly.finding
+            )?;
+        }
+        Ok(())
+    }
+}
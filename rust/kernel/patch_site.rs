@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Patch site : the state of the ftrace patch site at the entry of a function
+//!
+//! The comparison of the kernel text with its reference flags every traced function, ftrace
+//! replaces the nops of their patch site with a call. [`classify_patch_site`] decodes the
+//! patch site found by `ftrace_location` and attributes its call:
+//! - the original nops, the function isn't traced
+//! - a call to an ftrace trampoline: `ftrace_caller`, `ftrace_regs_caller` or the trampoline
+//!   allocated for an ops (`is_ftrace_trampoline`)
+//! - a direct call registered for the function (`ftrace_find_rec_direct`) to the text of a
+//!   module, or a call to a BPF trampoline
+//! - anything else is a foreign redirect, a call into a module which ftrace doesn't know
+//!   about is a module patching the site itself
+//!
+//! The patch site is read with the nofault reader without `ftrace_lock`, a site being
+//! patched may briefly hold an `int3` and be reported as overwritten.
+//!
+//! C header: [`include/linux/ftrace.h`](../../../../include/linux/ftrace.h)
+
+use core::fmt;
+
+use crate::address::{resolve_address, AddressInfo, Owner, RegionType};
+use crate::c_str;
+use crate::ftrace_audit::direct_trampoline;
+use crate::insn::{Branch, BranchKind, Insn, InsnClass, Instruction};
+use crate::module::symbols_lookup_name;
+use kernel::prelude::*;
+
+/// State of the ftrace patch site of a function
+pub enum PatchSiteState {
+    /// The function has no patch site, it isn't traceable
+    NotTraceable,
+    /// The patch site holds its original nops
+    Nops,
+    /// A call to an ftrace trampoline
+    Ftrace {
+        /// Address of the trampoline
+        trampoline: u64,
+    },
+    /// A direct call registered for the function, to the text of a module
+    Module {
+        /// The destination of the call
+        target: AddressInfo,
+    },
+    /// A call to a BPF trampoline, a direct call of a BPF tracing program
+    Bpf {
+        /// The destination of the call
+        target: AddressInfo,
+    },
+    /// A branch to anything else
+    Foreign {
+        /// The destination of the branch
+        target: AddressInfo,
+    },
+    /// The patch site holds neither a nop nor a direct call
+    Overwritten,
+}
+
+impl PatchSiteState {
+    /// The state is one ftrace can put the patch site in
+    pub fn is_legitimate(&self) -> bool {
+        !matches!(
+            self,
+            PatchSiteState::Foreign { .. } | PatchSiteState::Overwritten
+        )
+    }
+}
+
+impl fmt::Display for PatchSiteState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchSiteState::NotTraceable => f.write_str("not traceable"),
+            PatchSiteState::Nops => f.write_str("nops"),
+            PatchSiteState::Ftrace { trampoline } => {
+                write!(f, "ftrace trampoline {:#x}", trampoline)
+            }
+            PatchSiteState::Module { target } => write!(f, "module {}", target),
+            PatchSiteState::Bpf { target } => write!(f, "BPF trampoline {}", target),
+            PatchSiteState::Foreign { target } => write!(f, "foreign {}", target),
+            PatchSiteState::Overwritten => f.write_str("overwritten"),
+        }
+    }
+}
+
+/// The patch site of a function
+pub struct PatchSite {
+    /// Address of the function
+    pub function: u64,
+    /// Address of the patch site, 0 if the function isn't traceable
+    pub address: u64,
+    /// State of the patch site
+    pub state: PatchSiteState,
+}
+
+impl fmt::Display for PatchSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "patch site of {:#x} at {:#x} : {}",
+            self.function, self.address, self.state
+        )
+    }
+}
+
+/// The call destination `target` is an ftrace trampoline
+pub(crate) fn is_ftrace_trampoline(target: u64) -> bool {
+    let callers = [c_str!("ftrace_caller"), c_str!("ftrace_regs_caller")];
+    if callers
+        .iter()
+        .any(|name| symbols_lookup_name(name) == target)
+    {
+        return true;
+    }
+    // SAFETY: Just an FFI call, the list of the trampolines is walked under
+    // `preempt_disable`
+    unsafe { bindings::is_ftrace_trampoline(target as _) != 0 }
+}
+
+/// Classify the call of the patch site `site` to `target`
+fn classify_call(site: u64, target: u64) -> Result<PatchSiteState> {
+    if is_ftrace_trampoline(target) {
+        return Ok(PatchSiteState::Ftrace { trampoline: target });
+    }
+    let registered = direct_trampoline(site)? == Some(target);
+    let target = resolve_address(target)?;
+    Ok(match (&target.owner, target.region) {
+        (Owner::Module(_), _) if target.is_text() && registered => {
+            PatchSiteState::Module { target }
+        }
+        (Owner::None, RegionType::BpfJit) => PatchSiteState::Bpf { target },
+        _ => PatchSiteState::Foreign { target },
+    })
+}
+
+/// Classify the ftrace patch site of the function at `function`
+///
+/// The patch site is decoded with [`Insn::new_from_address`], `function` can come from a
+/// suspect pointer.
+pub fn classify_patch_site(function: u64) -> Result<PatchSite> {
+    // SAFETY: Just an FFI call, the records are only read
+    let address = unsafe { bindings::ftrace_location(function as _) } as u64;
+    if address == 0 {
+        return Ok(PatchSite {
+            function,
+            address,
+            state: PatchSiteState::NotTraceable,
+        });
+    }
+
+    let mut insn = Insn::new_from_address(address)?;
+    let state = match (insn.class()?, insn.branch(address)?) {
+        (InsnClass::Nop, _) => PatchSiteState::Nops,
+        (
+            _,
+            Some(Branch {
+                kind: BranchKind::Call,
+                target: Some(target),
+            }),
+        ) => classify_call(address, target)?,
+        (
+            _,
+            Some(Branch {
+                kind: BranchKind::Jump,
+                target: Some(target),
+            }),
+        ) => PatchSiteState::Foreign {
+            target: resolve_address(target)?,
+        },
+        _ => PatchSiteState::Overwritten,
+    };
+    Ok(PatchSite {
+        function,
+        address,
+        state,
+    })
+}
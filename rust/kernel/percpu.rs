@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Per-CPU variables : read per-CPU variables resolved by symbol
+//!
+//! The per-CPU checks (current task pointers, IDT descriptor, nmi counters, ...) need
+//! the value of a non-exported per-CPU variable on each CPU. The variable is resolved
+//! with kallsyms and its per-CPU copies are read with the `per_cpu_ptr` arithmetic, or
+//! on the CPU itself through an IPI when the value must be read locally.
+//!
+//! [`PerCpuCounter`] is a dynamically allocated per-CPU counter, for the statistics
+//! updated on hot paths.
+//!
+//! C header: [`include/linux/percpu-defs.h`](../../../../include/linux/percpu-defs.h)
+
+use core::ffi::c_void;
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::module::symbols_lookup_name;
+use crate::nofault;
+use crate::transmute::FromBytes;
+use kernel::prelude::*;
+
+/// Iterate over the online CPUs
+pub fn online_cpus() -> impl Iterator<Item = u32> {
+    // SAFETY: `nr_cpu_ids` is set at boot and never change after
+    let nr_cpu_ids = unsafe { bindings::nr_cpu_ids };
+    // SAFETY: Just an FFI call, `cpu` is lower than `nr_cpu_ids`
+    (0..nr_cpu_ids).filter(|cpu| unsafe { bindings::cpu_online(*cpu) })
+}
+
+/// Iterate over the possible CPUs
+pub fn possible_cpus() -> impl Iterator<Item = u32> {
+    // SAFETY: `nr_cpu_ids` is set at boot and never change after
+    let nr_cpu_ids = unsafe { bindings::nr_cpu_ids };
+    // SAFETY: Just an FFI call, `cpu` is lower than `nr_cpu_ids`
+    (0..nr_cpu_ids).filter(|cpu| unsafe { bindings::cpu_possible(*cpu) })
+}
+
+/// A per-CPU variable resolved by its symbol
+#[derive(Clone, Copy)]
+pub struct PerCpuSymbol {
+    /// The address of the variable in the per-CPU section
+    addr: usize,
+}
+
+impl PerCpuSymbol {
+    /// Resolve a per-CPU variable with kallsyms
+    pub fn lookup(name: &CStr) -> Result<Self> {
+        let addr = symbols_lookup_name(name);
+        if addr == 0 {
+            pr_err!("Couldn't find per-CPU symbol {:?}\n", name);
+            return Err(ENOENT);
+        }
+        Ok(PerCpuSymbol { addr: addr as _ })
+    }
+
+    /// Get the address of the copy of the variable for `cpu` (`per_cpu_ptr`)
+    pub fn ptr(&self, cpu: u32) -> Option<usize> {
+        // SAFETY: `nr_cpu_ids` is set at boot and never change after
+        if cpu >= unsafe { bindings::nr_cpu_ids } {
+            return None;
+        }
+        // SAFETY: `cpu < nr_cpu_ids <= NR_CPUS` so we are in bounds of the array,
+        // which is set at boot
+        let offset = unsafe { *addr_of!(bindings::__per_cpu_offset[cpu as usize]) };
+        Some(self.addr.wrapping_add(offset as usize))
+    }
+
+    /// Read the copy of the variable for `cpu`
+    ///
+    /// The read is done remotely (without synchronization with the CPU) and is fault-safe.
+    pub fn read<T: FromBytes>(&self, cpu: u32) -> Result<T> {
+        nofault::read(self.ptr(cpu).ok_or(EINVAL)?)
+    }
+
+    /// Read the variable on each online CPU
+    pub fn read_all<T: FromBytes>(&self) -> Result<KVec<(u32, T)>> {
+        let mut values = KVec::new();
+        for cpu in online_cpus() {
+            values.push((cpu, self.read(cpu)?), GFP_KERNEL)?;
+        }
+        Ok(values)
+    }
+
+    /// Read the copy of the variable for `cpu` on the CPU itself (using an IPI)
+    pub fn read_on_cpu<T: FromBytes>(&self, cpu: u32) -> Result<T> {
+        let addr = self.ptr(cpu).ok_or(EINVAL)?;
+        run_on_cpu(cpu, || nofault::read(addr))?
+    }
+}
+
+/// Data passed to the IPI callback
+struct IpiCall<F, R> {
+    f: Option<F>,
+    ret: Option<R>,
+}
+
+/// # Safety
+///     Will be called only from C, `info` must point to an `IpiCall<F, R>`
+unsafe extern "C" fn ipi_callback<F: FnOnce() -> R, R>(info: *mut c_void) {
+    // SAFETY: `info` is the `IpiCall` passed to `smp_call_function_single`, which wait
+    // for the callback completion so it is still alive
+    let call = unsafe { &mut *info.cast::<IpiCall<F, R>>() };
+    if let Some(f) = call.f.take() {
+        call.ret = Some(f());
+    }
+}
+
+/// Run `f` on `cpu` and wait for its result
+///
+/// `f` is run in IPI context (interrupts disabled) so it must not sleep.
+pub fn run_on_cpu<F: FnOnce() -> R + Send, R: Send>(cpu: u32, f: F) -> Result<R> {
+    let mut call = IpiCall {
+        f: Some(f),
+        ret: None,
+    };
+
+    // SAFETY: The callback has the right prototype for `call`, and `wait` is set so
+    // `call` outlive the callback
+    let ret = unsafe {
+        bindings::smp_call_function_single(
+            cpu as _,
+            Some(ipi_callback::<F, R>),
+            &mut call as *mut IpiCall<F, R> as *mut c_void,
+            1,
+        )
+    };
+    crate::error::to_result(ret)?;
+
+    call.ret.ok_or(EINVAL)
+}
+
+/// Get the CPU the current task run on
+///
+/// Only stable while the preemption is disabled, otherwise the task may be migrated
+/// right after the read.
+pub fn current_cpu() -> u32 {
+    // SAFETY: `current` is always valid, `cpu` is only updated when the task is migrated
+    unsafe { core::ptr::read_volatile(&(*bindings::get_current()).thread_info.cpu) }
+}
+
+/// A counter with a copy per CPU (`alloc_percpu(u64)`)
+///
+/// The increments are done on the copy of the current CPU so they don't bounce cache
+/// lines between CPUs, and can be done from any context (probe handlers, NMI, ...).
+///
+/// # Invariants
+///
+///     `ptr` is a per-CPU allocation of an `u64`
+pub struct PerCpuCounter {
+    ptr: *mut c_void,
+}
+
+// SAFETY: The counter is only accessed with atomic operations
+unsafe impl Send for PerCpuCounter {}
+
+// SAFETY: The counter is only accessed with atomic operations
+unsafe impl Sync for PerCpuCounter {}
+
+impl PerCpuCounter {
+    /// Allocate a counter initialized to 0 on every CPU
+    pub fn new() -> Result<Self> {
+        let size = core::mem::size_of::<u64>();
+        // SAFETY: Just an FFI call, the allocation is zeroed
+        let ptr = unsafe { bindings::__alloc_percpu(size, size) };
+        if ptr.is_null() {
+            return Err(ENOMEM);
+        }
+        Ok(PerCpuCounter { ptr })
+    }
+
+    /// Get the copy of the counter of `cpu`
+    fn counter(&self, cpu: u32) -> &AtomicU64 {
+        // SAFETY: `cpu < nr_cpu_ids <= NR_CPUS` by the callers so we are in bounds of the
+        // array, which is set at boot
+        let offset = unsafe { *addr_of!(bindings::__per_cpu_offset[cpu as usize]) };
+        let ptr = (self.ptr as usize).wrapping_add(offset as usize) as *mut u64;
+        // SAFETY: `ptr` is the copy of the per-CPU allocation for `cpu` by the type
+        // invariant, it is aligned and valid as long as `self`
+        unsafe { AtomicU64::from_ptr(ptr) }
+    }
+
+    /// Increment the counter of the current CPU
+    pub fn inc(&self) {
+        // If the task is migrated meanwhile the increment is done on the copy of another
+        // CPU, which is still correct as the operation is atomic
+        self.counter(current_cpu()).fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the value of the counter on each possible CPU
+    pub fn per_cpu(&self) -> Result<KVec<(u32, u64)>> {
+        let mut values = KVec::new();
+        for cpu in possible_cpus() {
+            values.push((cpu, self.counter(cpu).load(Ordering::Relaxed)), GFP_KERNEL)?;
+        }
+        Ok(values)
+    }
+
+    /// Get the sum of the counter on all the CPUs
+    pub fn sum(&self) -> u64 {
+        possible_cpus()
+            .map(|cpu| self.counter(cpu).load(Ordering::Relaxed))
+            .fold(0, u64::wrapping_add)
+    }
+}
+
+impl Drop for PerCpuCounter {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` come from `__alloc_percpu` by the type invariant
+        unsafe { bindings::free_percpu(self.ptr) };
+    }
+}
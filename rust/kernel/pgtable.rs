@@ -2,13 +2,249 @@
 
 //! Kernel page table management.
 
+use core::fmt::{self, Write};
 use core::ptr::NonNull;
+#[cfg(target_arch = "x86_64")]
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use bindings::pgprot_t;
 
+use crate::mm::{Mm, MmapReadGuard, VmArea};
 use crate::prelude::EINVAL;
+use crate::task::cond_resched;
 use kernel::error::Result;
 
+/// The decoded protection flags of a page table entry
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PgProtFlags(u64);
+
+impl PgProtFlags {
+    /// Decode the flags of `pgprot`
+    pub fn new(pgprot: pgprot_t) -> Self {
+        PgProtFlags(pgprot.pgprot as u64)
+    }
+
+    /// Get the raw flags
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    fn has(&self, flag: u64) -> bool {
+        self.0 & flag != 0
+    }
+
+    /// The entry is present, writable and executable
+    pub fn is_wx(&self) -> bool {
+        self.is_present() && self.is_writable() && self.is_executable()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl PgProtFlags {
+    /// The entry is present
+    pub fn is_present(&self) -> bool {
+        self.has(bindings::_PAGE_PRESENT as u64)
+    }
+
+    /// The memory is writable
+    pub fn is_writable(&self) -> bool {
+        self.has(bindings::_PAGE_RW as u64)
+    }
+
+    /// The memory is executable
+    pub fn is_executable(&self) -> bool {
+        !self.has(bindings::_PAGE_NX as u64)
+    }
+
+    /// The memory is executable by userspace, there is no separate bit from the kernel one
+    pub fn is_user_executable(&self) -> bool {
+        self.is_user() && self.is_executable()
+    }
+
+    /// The memory is accessible from userspace
+    pub fn is_user(&self) -> bool {
+        self.has(bindings::_PAGE_USER as u64)
+    }
+
+    /// The entry is global (not flushed on context switch)
+    pub fn is_global(&self) -> bool {
+        self.has(bindings::_PAGE_GLOBAL as u64)
+    }
+
+    /// The memory was written since the flag was cleared
+    pub fn is_dirty(&self) -> bool {
+        self.has(bindings::_PAGE_DIRTY as u64)
+    }
+
+    /// The memory was accessed since the flag was cleared
+    pub fn is_accessed(&self) -> bool {
+        self.has(bindings::_PAGE_ACCESSED as u64)
+    }
+}
+
+/// Bits of the arm64 entries, from `pgtable-hwdef.h` and `pgtable-prot.h`
+#[cfg(target_arch = "aarch64")]
+mod pte_bits {
+    pub(super) const VALID: u64 = 1 << 0;
+    pub(super) const USER: u64 = 1 << 6;
+    pub(super) const RDONLY: u64 = 1 << 7;
+    pub(super) const AF: u64 = 1 << 10;
+    pub(super) const NG: u64 = 1 << 11;
+    /// Dirty Bit Management, the hardware clears `RDONLY` on a write
+    pub(super) const DBM: u64 = 1 << 51;
+    pub(super) const PXN: u64 = 1 << 53;
+    /// Execute never at EL0
+    pub(super) const UXN: u64 = 1 << 54;
+    /// Software dirty bit
+    pub(super) const DIRTY: u64 = 1 << 55;
+    /// Bits of the table entries, restricting the levels below
+    pub(super) const PXN_TABLE: u64 = 1 << 59;
+    pub(super) const UXN_TABLE: u64 = 1 << 60;
+    pub(super) const AP_TABLE_NO_EL0: u64 = 1 << 61;
+    pub(super) const AP_TABLE_RDONLY: u64 = 1 << 62;
+}
+
+/// The arm64 entries have no write bit but a read-only one, and the hardware dirty state
+/// is a writable entry with `DBM` set. The kernel and userspace have their own execute
+/// never bits
+#[cfg(target_arch = "aarch64")]
+impl PgProtFlags {
+    /// The entry is present
+    pub fn is_present(&self) -> bool {
+        self.has(pte_bits::VALID)
+    }
+
+    /// The memory is writable, a clean entry with `DBM` set is made writable by the first
+    /// write
+    pub fn is_writable(&self) -> bool {
+        self.has(pte_bits::DBM) || !self.has(pte_bits::RDONLY)
+    }
+
+    /// The memory is executable by the kernel
+    pub fn is_executable(&self) -> bool {
+        !self.has(pte_bits::PXN)
+    }
+
+    /// The memory is executable by userspace
+    pub fn is_user_executable(&self) -> bool {
+        !self.has(pte_bits::UXN)
+    }
+
+    /// The memory is accessible from userspace
+    pub fn is_user(&self) -> bool {
+        self.has(pte_bits::USER)
+    }
+
+    /// The entry is global (not flushed on context switch)
+    pub fn is_global(&self) -> bool {
+        !self.has(pte_bits::NG)
+    }
+
+    /// The memory was written since the flag was cleared
+    pub fn is_dirty(&self) -> bool {
+        self.has(pte_bits::DIRTY) || (self.has(pte_bits::DBM) && !self.has(pte_bits::RDONLY))
+    }
+
+    /// The memory was accessed since the flag was cleared
+    pub fn is_accessed(&self) -> bool {
+        self.has(pte_bits::AF)
+    }
+}
+
+impl From<pgprot_t> for PgProtFlags {
+    fn from(pgprot: pgprot_t) -> Self {
+        Self::new(pgprot)
+    }
+}
+
+/// Print the flags like `ls` prints the permissions : `pwxugda`, with `-` for the unset
+/// flags
+impl fmt::Display for PgProtFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.is_present(), 'p'),
+            (self.is_writable(), 'w'),
+            (self.is_executable(), 'x'),
+            (self.is_user(), 'u'),
+            (self.is_global(), 'g'),
+            (self.is_dirty(), 'd'),
+            (self.is_accessed(), 'a'),
+        ];
+        for (set, c) in flags {
+            f.write_char(if set { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PgProtFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#x})", self, self.0)
+    }
+}
+
+/// The restrictions the table entries of the upper levels put on the leaf entries below
+///
+/// The CPU applies the most restrictive protections along the walk: a writable and
+/// executable leaf below a read-only or non executable table entry isn't W+X.
+#[derive(Clone, Copy, Default)]
+pub struct TableRestriction {
+    /// Bits cleared from the leaf flags
+    clear: u64,
+    /// Bits set in the leaf flags
+    set: u64,
+}
+
+impl TableRestriction {
+    /// Get the flags of `leaf` once restricted
+    pub fn apply(&self, leaf: PgProtFlags) -> PgProtFlags {
+        PgProtFlags((leaf.0 & !self.clear) | self.set)
+    }
+
+    /// Add the restrictions of the present table entry `table`
+    #[cfg(target_arch = "x86_64")]
+    fn with_table(self, table: u64) -> Self {
+        let (rw, user, nx) = (
+            bindings::_PAGE_RW as u64,
+            bindings::_PAGE_USER as u64,
+            bindings::_PAGE_NX as u64,
+        );
+        let mut restriction = self;
+        restriction.clear |= (rw | user) & !table;
+        restriction.set |= nx & table;
+        restriction
+    }
+
+    /// Add the restrictions of the present table entry `table`
+    #[cfg(target_arch = "aarch64")]
+    fn with_table(self, table: u64) -> Self {
+        let mut restriction = self;
+        if table & pte_bits::PXN_TABLE != 0 {
+            restriction.set |= pte_bits::PXN;
+        }
+        if table & pte_bits::UXN_TABLE != 0 {
+            restriction.set |= pte_bits::UXN;
+        }
+        if table & pte_bits::AP_TABLE_NO_EL0 != 0 {
+            restriction.clear |= pte_bits::USER;
+        }
+        if table & pte_bits::AP_TABLE_RDONLY != 0 {
+            restriction.set |= pte_bits::RDONLY;
+            restriction.clear |= pte_bits::DBM;
+        }
+        restriction
+    }
+}
+
+/// Read the raw value of an entry of any level
+///
+/// # Safety
+///     `entry` must point to a valid entry of a 64 bits page table
+unsafe fn entry_bits<T>(entry: *const T) -> u64 {
+    // SAFETY: By the safety contract of this function
+    unsafe { core::ptr::read_volatile(entry.cast::<u64>()) }
+}
+
 /// Utility function common tp the different page table level
 pub trait Pgtable {
     /// Get the order of the table entry (correspond to the alloc_pages order)
@@ -19,11 +255,224 @@ pub trait Pgtable {
     fn pgprot(&self) -> pgprot_t;
     /// Set the current pgtable to the `new_pgtable`
     /// # Safety
-    ///     This call doesn't update the TLB caches, a call to `flush_tlb` should be made after this function
+    ///     This call doesn't update the TLB caches, a call to [`flush_tlb_kernel_range`] should
+    ///     be made after this function, or use [`set_and_flush`]
     ///     The new_pfn and new_pgprot should have been obtained from `pfn` and `pgprot` call of the same level page
     unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t);
 }
 
+/// Represent a pointer to a page upper directory
+///
+/// # Invariant :
+///     pud point to a valid page upper directory
+pub struct Pud(NonNull<bindings::pud_t>);
+
+impl Pgtable for Pud {
+    fn order(&self) -> u32 {
+        bindings::PUD_ORDER
+    }
+    fn pfn(&self) -> u64 {
+        // SAFETY: According to the type invariant self.0 point to a valid pud
+        let pud = unsafe { *self.0.as_ptr() };
+        // SAFETY: Just an FFI call
+        (unsafe { bindings::pud_pfn(pud) }) as u64
+    }
+
+    fn pgprot(&self) -> pgprot_t {
+        // SAFETY: According to the type invariant self.0 point to a valid pud
+        let pud = unsafe { *self.0.as_ptr() };
+        // SAFETY: Just an FFI call
+        unsafe { bindings::pud_pgprot(pud) }
+    }
+
+    unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
+        // SAFETY: Just an FFI call
+        let pud = unsafe { bindings::pfn_pud(new_pfn, new_pgprot) };
+        // SAFETY: According to the safety ontrat of the trait function
+        // we can change the value of the pud
+        unsafe { bindings::set_pud(self.0.as_ptr(), pud) };
+    }
+}
+
+/// Represent a pointer to a page level 4 directory
+///
+/// With 4-level paging the P4D is folded into the PGD, the pointer is then the PGD entry
+///
+/// # Invariant :
+///     p4d point to a valid page level 4 directory
+pub struct P4d(NonNull<bindings::p4d_t>);
+
+#[cfg(target_arch = "x86_64")]
+impl Pgtable for P4d {
+    fn order(&self) -> u32 {
+        bindings::P4D_SHIFT - bindings::PAGE_SHIFT
+    }
+    fn pfn(&self) -> u64 {
+        // SAFETY: According to the type invariant self.0 point to a valid p4d
+        let p4d = unsafe { *self.0.as_ptr() };
+        // SAFETY: Just an FFI call
+        (unsafe { bindings::p4d_pfn(p4d) }) as u64
+    }
+
+    fn pgprot(&self) -> pgprot_t {
+        // SAFETY: According to the type invariant self.0 point to a valid p4d
+        let p4d = unsafe { *self.0.as_ptr() };
+        pgprot_t {
+            // SAFETY: Just an FFI call
+            pgprot: unsafe { bindings::p4d_flags(p4d) },
+        }
+    }
+
+    unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
+        // There is no `pfn_p4d`, the entry is built like `pfn_pud` does
+        // SAFETY: Just an FFI call
+        let p4d = unsafe {
+            bindings::native_make_p4d((new_pfn << bindings::PAGE_SHIFT) | new_pgprot.pgprot)
+        };
+        // SAFETY: According to the safety ontrat of the trait function
+        // we can change the value of the p4d
+        unsafe { bindings::set_p4d(self.0.as_ptr(), p4d) };
+    }
+}
+
+/// Read the raw value of a P4D or PGD entry
+///
+/// arm64 has no accessor for the PFN and the flags of these levels, they are only leaves
+/// when folded, their layout is then the one of a PTE
+///
+/// # Safety
+///     `entry` must point to a valid entry of a 64 bits page table
+#[cfg(target_arch = "aarch64")]
+unsafe fn read_raw_entry<T>(entry: NonNull<T>) -> bindings::pte_t {
+    bindings::pte_t {
+        // SAFETY: By the safety contract of this function
+        pte: unsafe { entry_bits(entry.as_ptr()) },
+    }
+}
+
+/// Write the raw value of a P4D or PGD entry, like `set_pgd` does
+///
+/// # Safety
+///     `entry` must point to a valid entry of a 64 bits page table, the TLB is not flushed
+#[cfg(target_arch = "aarch64")]
+unsafe fn write_raw_entry<T>(entry: NonNull<T>, new_pfn: u64, new_pgprot: pgprot_t) {
+    // SAFETY: Just an FFI call
+    let pte = unsafe { bindings::pfn_pte(new_pfn, new_pgprot) };
+    // SAFETY: By the safety contract of this function
+    unsafe { core::ptr::write_volatile(entry.as_ptr() as *mut u64, pte.pte) };
+    // SAFETY: The barriers make the write visible to the table walker before the next
+    // access, they have no other effect
+    unsafe { core::arch::asm!("dsb ishst", "isb") };
+}
+
+#[cfg(target_arch = "aarch64")]
+impl Pgtable for P4d {
+    fn order(&self) -> u32 {
+        bindings::P4D_SHIFT - bindings::PAGE_SHIFT
+    }
+    fn pfn(&self) -> u64 {
+        // SAFETY: According to the type invariant self.0 point to a valid p4d
+        let pte = unsafe { read_raw_entry(self.0) };
+        // SAFETY: Just an FFI call
+        (unsafe { bindings::pte_pfn(pte) }) as u64
+    }
+
+    fn pgprot(&self) -> pgprot_t {
+        // SAFETY: According to the type invariant self.0 point to a valid p4d
+        let pte = unsafe { read_raw_entry(self.0) };
+        // SAFETY: Just an FFI call
+        unsafe { bindings::pte_pgprot(pte) }
+    }
+
+    unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
+        // SAFETY: According to the safety ontrat of the trait function
+        // we can change the value of the p4d
+        unsafe { write_raw_entry(self.0, new_pfn, new_pgprot) };
+    }
+}
+
+/// Get `PGDIR_SHIFT`, which is a variable with 5-level paging
+#[cfg(target_arch = "x86_64")]
+fn pgdir_shift() -> u32 {
+    // SAFETY: Just an FFI call
+    if unsafe { bindings::pgtable_l5_enabled() } {
+        // A PGD entry then covers 512 P4D entries
+        bindings::P4D_SHIFT + 9
+    } else {
+        bindings::P4D_SHIFT
+    }
+}
+
+/// Get `PGDIR_SHIFT`
+#[cfg(target_arch = "aarch64")]
+fn pgdir_shift() -> u32 {
+    bindings::PGDIR_SHIFT
+}
+
+/// Represent a pointer to a page global directory entry
+///
+/// # Invariant :
+///     pgd point to a valid page global directory entry
+pub struct Pgd(NonNull<bindings::pgd_t>);
+
+#[cfg(target_arch = "x86_64")]
+impl Pgtable for Pgd {
+    fn order(&self) -> u32 {
+        pgdir_shift() - bindings::PAGE_SHIFT
+    }
+    fn pfn(&self) -> u64 {
+        // SAFETY: According to the type invariant self.0 point to a valid pgd
+        let pgd = unsafe { *self.0.as_ptr() };
+        // SAFETY: Just an FFI call
+        (unsafe { bindings::pgd_pfn(pgd) }) as u64
+    }
+
+    fn pgprot(&self) -> pgprot_t {
+        // SAFETY: According to the type invariant self.0 point to a valid pgd
+        let pgd = unsafe { *self.0.as_ptr() };
+        pgprot_t {
+            // SAFETY: Just an FFI call
+            pgprot: unsafe { bindings::pgd_flags(pgd) },
+        }
+    }
+
+    unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
+        // SAFETY: Just an FFI call
+        let pgd = unsafe {
+            bindings::native_make_pgd((new_pfn << bindings::PAGE_SHIFT) | new_pgprot.pgprot)
+        };
+        // SAFETY: According to the safety ontrat of the trait function
+        // we can change the value of the pgd
+        unsafe { bindings::set_pgd(self.0.as_ptr(), pgd) };
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl Pgtable for Pgd {
+    fn order(&self) -> u32 {
+        pgdir_shift() - bindings::PAGE_SHIFT
+    }
+    fn pfn(&self) -> u64 {
+        // SAFETY: According to the type invariant self.0 point to a valid pgd
+        let pte = unsafe { read_raw_entry(self.0) };
+        // SAFETY: Just an FFI call
+        (unsafe { bindings::pte_pfn(pte) }) as u64
+    }
+
+    fn pgprot(&self) -> pgprot_t {
+        // SAFETY: According to the type invariant self.0 point to a valid pgd
+        let pte = unsafe { read_raw_entry(self.0) };
+        // SAFETY: Just an FFI call
+        unsafe { bindings::pte_pgprot(pte) }
+    }
+
+    unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
+        // SAFETY: According to the safety ontrat of the trait function
+        // we can change the value of the pgd
+        unsafe { write_raw_entry(self.0, new_pfn, new_pgprot) };
+    }
+}
+
 /// Represent a pointer to a page middle directory
 ///
 /// # Invariant :
@@ -89,11 +538,19 @@ unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
 }
 
 /// The different page table level
+///
+/// `lookup_address` never returns a PGD entry, which can't map a page on x86 and arm64
 pub enum PageLevel {
     /// PTE level, 4K page
     Pte(Pte),
     /// PMD level, 2M page
     Pmd(Pmd),
+    /// PUD level, 1G page
+    Pud(Pud),
+    /// P4D level, 512G
+    P4d(P4d),
+    /// PGD level, 512G or 256T with 5-level paging
+    Pgd(Pgd),
 }
 
 impl Pgtable for PageLevel {
@@ -101,18 +558,27 @@ fn order(&self) -> u32 {
         match self {
             PageLevel::Pmd(pmd) => pmd.order(),
             PageLevel::Pte(pte) => pte.order(),
+            PageLevel::Pud(pud) => pud.order(),
+            PageLevel::P4d(p4d) => p4d.order(),
+            PageLevel::Pgd(pgd) => pgd.order(),
         }
     }
     fn pfn(&self) -> u64 {
         match self {
             PageLevel::Pmd(pmd) => pmd.pfn(),
             PageLevel::Pte(pte) => pte.pfn(),
+            PageLevel::Pud(pud) => pud.pfn(),
+            PageLevel::P4d(p4d) => p4d.pfn(),
+            PageLevel::Pgd(pgd) => pgd.pfn(),
         }
     }
     fn pgprot(&self) -> pgprot_t {
         match self {
             PageLevel::Pmd(pmd) => pmd.pgprot(),
             PageLevel::Pte(pte) => pte.pgprot(),
+            PageLevel::Pud(pud) => pud.pgprot(),
+            PageLevel::P4d(p4d) => p4d.pgprot(),
+            PageLevel::Pgd(pgd) => pgd.pgprot(),
         }
     }
     unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
@@ -121,11 +587,18 @@ unsafe fn set_pgtable(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
             PageLevel::Pmd(pmd) => unsafe { pmd.set_pgtable(new_pfn, new_pgprot) },
             // SAFETY: By the safeyt contract of this function
             PageLevel::Pte(pte) => unsafe { pte.set_pgtable(new_pfn, new_pgprot) },
+            // SAFETY: By the safeyt contract of this function
+            PageLevel::Pud(pud) => unsafe { pud.set_pgtable(new_pfn, new_pgprot) },
+            // SAFETY: By the safeyt contract of this function
+            PageLevel::P4d(p4d) => unsafe { p4d.set_pgtable(new_pfn, new_pgprot) },
+            // SAFETY: By the safeyt contract of this function
+            PageLevel::Pgd(pgd) => unsafe { pgd.set_pgtable(new_pfn, new_pgprot) },
         }
     }
 }
 
 /// Lookup for the page at the address `address`
+#[cfg(target_arch = "x86_64")]
 pub fn lookup_address(address: usize) -> Result<PageLevel> {
     let mut level: u32 = 0;
     // SAFETY: Just an FFI call, `&mut level` is not null
@@ -146,6 +619,602 @@ pub fn lookup_address(address: usize) -> Result<PageLevel> {
             // according to the lookup_address contract
             unsafe { NonNull::new_unchecked(ptr as *mut bindings::pmd_t) },
         ))),
+        bindings::pg_level_PG_LEVEL_1G => Ok(PageLevel::Pud(Pud(
+            // SAFETY: `ptr` is not null, checked above
+            // As the level indicate ptr point to a valid pud entry
+            // according to the lookup_address contract
+            unsafe { NonNull::new_unchecked(ptr as *mut bindings::pud_t) },
+        ))),
+        bindings::pg_level_PG_LEVEL_512G => Ok(PageLevel::P4d(P4d(
+            // SAFETY: `ptr` is not null, checked above
+            // As the level indicate ptr point to a valid p4d entry
+            // according to the lookup_address contract
+            unsafe { NonNull::new_unchecked(ptr as *mut bindings::p4d_t) },
+        ))),
         _ => Err(EINVAL),
     }
 }
+
+/// Lookup for the page at the address `address`
+///
+/// arm64 has no `lookup_address`, the kernel page tables are walked like
+/// [`walk_kernel_range`] does
+#[cfg(target_arch = "aarch64")]
+pub fn lookup_address(address: usize) -> Result<PageLevel> {
+    match descend(address)? {
+        (Some(level), _) => Ok(level),
+        (None, _) => Err(EINVAL),
+    }
+}
+
+/// Flush the TLB entries of the kernel range `start..end` on every CPU
+///
+/// Sends IPIs, so it must not be called with the interrupts disabled
+pub fn flush_tlb_kernel_range(start: usize, end: usize) {
+    // SAFETY: Just an FFI call, flushing the TLB never invalidates a mapping
+    unsafe { bindings::flush_tlb_kernel_range(start as _, end as _) };
+}
+
+/// Flush the whole TLB on every CPU
+///
+/// Sends IPIs, so it must not be called with the interrupts disabled
+pub fn flush_tlb_all() {
+    // SAFETY: Just an FFI call, flushing the TLB never invalidates a mapping
+    unsafe { bindings::flush_tlb_all() };
+}
+
+/// Get the size mapped by an entry
+fn mapped_size(level: &PageLevel) -> usize {
+    match level {
+        PageLevel::Pte(_) => 1 << bindings::PAGE_SHIFT,
+        _ => 1 << (level.order() + bindings::PAGE_SHIFT),
+    }
+}
+
+/// Set the entry mapping `address` and flush the TLB of the range it maps
+///
+/// # Safety
+///     The new_pfn and new_pgprot should have been obtained from `pfn` and `pgprot` call of
+///     the same level page, and `level` must be the entry mapping `address`
+pub unsafe fn set_and_flush(
+    level: &mut PageLevel,
+    address: usize,
+    new_pfn: u64,
+    new_pgprot: pgprot_t,
+) {
+    let size = mapped_size(level);
+    let start = address & !(size - 1);
+    // SAFETY: By the safety contract of this function, the TLB is flushed below
+    unsafe { level.set_pgtable(new_pfn, new_pgprot) };
+    // The entry must be written before the flush, otherwise a CPU could cache the old
+    // entry again between the flush and the write
+    flush_tlb_kernel_range(start, start.wrapping_add(size));
+}
+
+/// A mapped leaf entry of the kernel page tables, see [`walk_kernel_range`]
+pub struct LeafEntry {
+    /// Start of the range mapped by the entry, it can start before the walked range
+    pub start: usize,
+    /// End of the range mapped by the entry, it can end after the walked range
+    pub end: usize,
+    /// The entry
+    pub level: PageLevel,
+    /// The flags of the entry restricted by the table entries above it, the protections
+    /// the CPU applies
+    pub flags: PgProtFlags,
+}
+
+impl LeafEntry {
+    /// Set the entry and flush the TLB of the range it maps
+    ///
+    /// # Safety
+    ///     The new_pfn and new_pgprot should have been obtained from `pfn` and `pgprot` call
+    ///     of the same level page
+    pub unsafe fn set_and_flush(&mut self, new_pfn: u64, new_pgprot: pgprot_t) {
+        // SAFETY: By the safety contract of this function, `level` maps `start`
+        unsafe { set_and_flush(&mut self.level, self.start, new_pfn, new_pgprot) };
+    }
+
+    /// Atomically clear the accessed and dirty bits of the entry
+    ///
+    /// The CPU sets them again at the next access through the entry, they are only used by
+    /// the kernel for the user mappings. The TLB is not flushed: a CPU with the entry in
+    /// its TLB doesn't set them again until [`flush_tlb_kernel_range`] is called.
+    ///
+    /// # Return
+    /// The flags of the entry before they were cleared
+    #[cfg(target_arch = "x86_64")]
+    pub fn test_and_clear_accessed_dirty(&mut self) -> PgProtFlags {
+        let ptr = match &self.level {
+            PageLevel::Pte(pte) => pte.0.as_ptr().cast::<u64>(),
+            PageLevel::Pmd(pmd) => pmd.0.as_ptr().cast::<u64>(),
+            PageLevel::Pud(pud) => pud.0.as_ptr().cast::<u64>(),
+            PageLevel::P4d(p4d) => p4d.0.as_ptr().cast::<u64>(),
+            PageLevel::Pgd(pgd) => pgd.0.as_ptr().cast::<u64>(),
+        };
+        let bits = (bindings::_PAGE_ACCESSED | bindings::_PAGE_DIRTY) as u64;
+        // SAFETY: According to the type invariants `ptr` point to a valid entry, which is
+        // aligned and only modified atomically by the CPU
+        let old = unsafe { AtomicU64::from_ptr(ptr) }.fetch_and(!bits, Ordering::SeqCst);
+        // The bits of the physical address, `PTE_PFN_MASK`
+        PgProtFlags(old & !0x000f_ffff_ffff_f000)
+    }
+}
+
+/// Get the start of the next entry of `size` bytes after `addr`, `None` on overflow
+fn next_entry(addr: usize, size: usize) -> Option<usize> {
+    (addr & !(size - 1)).checked_add(size)
+}
+
+/// Descend the page tables of `init_mm` to the entry covering `addr`
+///
+/// # Return
+/// The leaf entry mapping `addr`, or `None` if it is not mapped, and the size covered by
+/// the entry
+fn descend(addr: usize) -> Result<(Option<PageLevel>, usize)> {
+    // SAFETY: `init_mm.pgd` is the kernel PGD, which is never freed
+    unsafe { descend_pgd(bindings::init_mm.pgd, addr) }
+}
+
+/// Descend the page tables of `pgd` to the entry covering `addr`, see [`descend`]
+///
+/// # Safety
+///     `pgd` must point to a valid page global directory, which is not freed (with the
+///     tables it points to) during the descent
+unsafe fn descend_pgd(
+    pgd: *mut bindings::pgd_t,
+    addr: usize,
+) -> Result<(Option<PageLevel>, usize)> {
+    // SAFETY: By the safety contract of this function
+    let pgd = unsafe { bindings::pgd_offset_pgd(pgd, addr as _) };
+    // SAFETY: `pgd` point to a valid pgd entry
+    if unsafe { bindings::pgd_none(*pgd) } {
+        return Ok((None, 1 << pgdir_shift()));
+    }
+
+    // SAFETY: The pgd entry is present so it point to a valid P4D (or is the folded P4D)
+    let p4d = unsafe { bindings::p4d_offset(pgd, addr as _) };
+    // SAFETY: `p4d` point to a valid p4d entry
+    if unsafe { bindings::p4d_none(*p4d) } {
+        return Ok((None, 1 << bindings::P4D_SHIFT));
+    }
+
+    let pud_size = 1usize << bindings::PUD_SHIFT;
+    // SAFETY: The p4d entry is present so it point to a valid PUD
+    let pud = unsafe { bindings::pud_offset(p4d, addr as _) };
+    // SAFETY: `pud` point to a valid pud entry
+    let (none, leaf) = unsafe { (bindings::pud_none(*pud), bindings::pud_leaf(*pud)) };
+    if none {
+        return Ok((None, pud_size));
+    } else if leaf {
+        let pud = NonNull::new(pud).ok_or(EINVAL)?;
+        return Ok((Some(PageLevel::Pud(Pud(pud))), pud_size));
+    }
+
+    let pmd_size = 1usize << bindings::PMD_SHIFT;
+    // SAFETY: The pud entry is present and not a leaf so it point to a valid PMD
+    let pmd = unsafe { bindings::pmd_offset(pud, addr as _) };
+    // SAFETY: `pmd` point to a valid pmd entry
+    let (none, leaf) = unsafe { (bindings::pmd_none(*pmd), bindings::pmd_leaf(*pmd)) };
+    if none {
+        return Ok((None, pmd_size));
+    } else if leaf {
+        let pmd = NonNull::new(pmd).ok_or(EINVAL)?;
+        return Ok((Some(PageLevel::Pmd(Pmd(pmd))), pmd_size));
+    }
+
+    let page_size = 1usize << bindings::PAGE_SHIFT;
+    // SAFETY: The pmd entry is present and not a leaf so it point to a valid PTE table,
+    // which is never freed in the kernel page tables
+    let pte = unsafe { bindings::pte_offset_kernel(pmd, addr as _) };
+    // SAFETY: `pte` point to a valid pte entry
+    if !unsafe { bindings::pte_present(*pte) } {
+        return Ok((None, page_size));
+    }
+    let pte = NonNull::new(pte).ok_or(EINVAL)?;
+    Ok((Some(PageLevel::Pte(Pte(pte))), page_size))
+}
+
+/// The locks keeping the kernel page tables from being freed, see [`walk_kernel_range`]
+///
+/// The tables of the linear map are freed by the memory hot-remove, excluded by the memory
+/// hotplug lock. The mmap lock of `init_mm` is taken too, like `ptdump` does.
+pub struct KernelTablesGuard {
+    _mmap: MmapReadGuard<'static>,
+}
+
+impl KernelTablesGuard {
+    /// Take the locks, sleeping until they are available
+    pub fn lock() -> Self {
+        // SAFETY: Just an FFI call, released in `drop`
+        unsafe { bindings::get_online_mems() };
+        KernelTablesGuard {
+            _mmap: Mm::init_mm().mmap_read_lock(),
+        }
+    }
+}
+
+impl Drop for KernelTablesGuard {
+    fn drop(&mut self) {
+        // SAFETY: Taken in `lock`
+        unsafe { bindings::put_online_mems() };
+    }
+}
+
+/// Walk the page tables of `init_mm` and call `visitor` on each mapped leaf entry covering
+/// `start..end`, in order
+///
+/// The walk holds a [`KernelTablesGuard`] and gives the CPU back between the PMD entries,
+/// `visitor` can sleep. The tables of the vmalloc area aren't protected by any lock: a PTE
+/// or PMD table is freed when a huge mapping replaces it (`pmd_free_pte_page`), so a walk
+/// racing with a huge `vmap` may read a freed table, like `ptdump` may. An entry can also
+/// be modified (split, protected, ...) during the walk. An error of the visitor stops the
+/// walk and is returned.
+pub fn walk_kernel_range(
+    start: usize,
+    end: usize,
+    visitor: impl FnMut(&mut LeafEntry) -> Result,
+) -> Result {
+    let _guard = KernelTablesGuard::lock();
+    // SAFETY: `init_mm.pgd` is the kernel PGD, which is never freed, and the tables it points
+    // to are kept by the guard but for the race documented above
+    unsafe { walk_pgd_range(bindings::init_mm.pgd, start, end, visitor) }
+}
+
+/// The visitor of the leaf entries of a walk
+type Visitor<'a> = dyn FnMut(&mut LeafEntry) -> Result + 'a;
+
+/// Call `f` on each entry of `size` bytes covering `start..end`, with the part of the range
+/// it covers
+fn for_each_entry(
+    start: usize,
+    end: usize,
+    size: usize,
+    mut f: impl FnMut(usize, usize) -> Result,
+) -> Result {
+    let mut addr = start;
+    while addr < end {
+        let next = next_entry(addr, size).map_or(end, |next| next.min(end));
+        f(addr, next)?;
+        addr = next;
+    }
+    Ok(())
+}
+
+/// Call `visitor` on the leaf entry `level` of `size` bytes covering `addr`, below the
+/// table entries of `restriction`
+fn visit(
+    visitor: &mut Visitor<'_>,
+    level: PageLevel,
+    addr: usize,
+    size: usize,
+    restriction: TableRestriction,
+) -> Result {
+    let start = addr & !(size - 1);
+    let flags = restriction.apply(PgProtFlags::new(level.pgprot()));
+    visitor(&mut LeafEntry {
+        start,
+        end: start.wrapping_add(size),
+        level,
+        flags,
+    })
+}
+
+/// Walk the page tables of `pgd` like [`walk_kernel_range`] does, level by level
+///
+/// Used on a copy of the kernel page tables like the user copy of page table isolation,
+/// with a [`KernelTablesGuard`] held.
+///
+/// # Safety
+///     `pgd` must point to a valid page global directory, which is not freed (with the
+///     tables it points to) during the walk
+pub unsafe fn walk_pgd_range(
+    pgd: *mut bindings::pgd_t,
+    start: usize,
+    end: usize,
+    mut visitor: impl FnMut(&mut LeafEntry) -> Result,
+) -> Result {
+    let visitor: &mut Visitor<'_> = &mut visitor;
+    for_each_entry(start, end, 1 << pgdir_shift(), |addr, next| {
+        // SAFETY: By the safety contract of this function
+        let pgd = unsafe { bindings::pgd_offset_pgd(pgd, addr as _) };
+        // SAFETY: `pgd` point to a valid pgd entry
+        if unsafe { bindings::pgd_none(*pgd) } {
+            return Ok(());
+        }
+        // SAFETY: `pgd` point to a valid pgd entry
+        let restriction = TableRestriction::default().with_table(unsafe { entry_bits(pgd) });
+        // SAFETY: The pgd entry is present, the tables it points to are valid
+        unsafe { walk_p4d_range(pgd, addr, next, restriction, visitor) }
+    })
+}
+
+/// Walk the P4D pointed to by the present entry `pgd`
+///
+/// # Safety
+///     `pgd` must point to a present pgd entry, see [`walk_pgd_range`]
+unsafe fn walk_p4d_range(
+    pgd: *mut bindings::pgd_t,
+    start: usize,
+    end: usize,
+    restriction: TableRestriction,
+    visitor: &mut Visitor<'_>,
+) -> Result {
+    for_each_entry(start, end, 1 << bindings::P4D_SHIFT, |addr, next| {
+        // SAFETY: The pgd entry is present so it point to a valid P4D (or is the folded P4D)
+        let p4d = unsafe { bindings::p4d_offset(pgd, addr as _) };
+        // SAFETY: `p4d` point to a valid p4d entry
+        if unsafe { bindings::p4d_none(*p4d) } {
+            return Ok(());
+        }
+        // SAFETY: `p4d` point to a valid p4d entry, the folded P4D is the pgd entry again
+        let restriction = restriction.with_table(unsafe { entry_bits(p4d) });
+        // SAFETY: The p4d entry is present, the tables it points to are valid
+        unsafe { walk_pud_range(p4d, addr, next, restriction, visitor) }
+    })
+}
+
+/// Walk the PUD pointed to by the present entry `p4d`
+///
+/// # Safety
+///     `p4d` must point to a present p4d entry, see [`walk_pgd_range`]
+unsafe fn walk_pud_range(
+    p4d: *mut bindings::p4d_t,
+    start: usize,
+    end: usize,
+    restriction: TableRestriction,
+    visitor: &mut Visitor<'_>,
+) -> Result {
+    let size = 1usize << bindings::PUD_SHIFT;
+    for_each_entry(start, end, size, |addr, next| {
+        // SAFETY: The p4d entry is present so it point to a valid PUD
+        let pud = unsafe { bindings::pud_offset(p4d, addr as _) };
+        // SAFETY: `pud` point to a valid pud entry
+        let (none, leaf) = unsafe { (bindings::pud_none(*pud), bindings::pud_leaf(*pud)) };
+        if none {
+            Ok(())
+        } else if leaf {
+            let pud = NonNull::new(pud).ok_or(EINVAL)?;
+            visit(visitor, PageLevel::Pud(Pud(pud)), addr, size, restriction)
+        } else {
+            // SAFETY: `pud` point to a valid pud entry
+            let restriction = restriction.with_table(unsafe { entry_bits(pud) });
+            // SAFETY: The pud entry is present and not a leaf so it point to a valid PMD
+            unsafe { walk_pmd_range(pud, addr, next, restriction, visitor) }
+        }
+    })
+}
+
+/// Walk the PMD pointed to by the present entry `pud`
+///
+/// # Safety
+///     `pud` must point to a present pud entry which is not a leaf, see [`walk_pgd_range`]
+unsafe fn walk_pmd_range(
+    pud: *mut bindings::pud_t,
+    start: usize,
+    end: usize,
+    restriction: TableRestriction,
+    visitor: &mut Visitor<'_>,
+) -> Result {
+    let size = 1usize << bindings::PMD_SHIFT;
+    for_each_entry(start, end, size, |addr, next| {
+        // Up to a full PTE table is walked between two calls
+        cond_resched();
+        // SAFETY: The pud entry is present and not a leaf so it point to a valid PMD
+        let pmd = unsafe { bindings::pmd_offset(pud, addr as _) };
+        // SAFETY: `pmd` point to a valid pmd entry
+        let (none, leaf) = unsafe { (bindings::pmd_none(*pmd), bindings::pmd_leaf(*pmd)) };
+        if none {
+            Ok(())
+        } else if leaf {
+            let pmd = NonNull::new(pmd).ok_or(EINVAL)?;
+            visit(visitor, PageLevel::Pmd(Pmd(pmd)), addr, size, restriction)
+        } else {
+            // SAFETY: `pmd` point to a valid pmd entry
+            let restriction = restriction.with_table(unsafe { entry_bits(pmd) });
+            // SAFETY: The pmd entry is present and not a leaf so it point to a valid PTE
+            // table
+            unsafe { walk_pte_range(pmd, addr, next, restriction, visitor) }
+        }
+    })
+}
+
+/// Walk the PTE table pointed to by the present entry `pmd`
+///
+/// # Safety
+///     `pmd` must point to a present pmd entry which is not a leaf, see [`walk_pgd_range`]
+unsafe fn walk_pte_range(
+    pmd: *mut bindings::pmd_t,
+    start: usize,
+    end: usize,
+    restriction: TableRestriction,
+    visitor: &mut Visitor<'_>,
+) -> Result {
+    let size = 1usize << bindings::PAGE_SHIFT;
+    for_each_entry(start, end, size, |addr, _| {
+        // SAFETY: The pmd entry is present and not a leaf so it point to a valid PTE table
+        let pte = unsafe { bindings::pte_offset_kernel(pmd, addr as _) };
+        // SAFETY: `pte` point to a valid pte entry
+        if !unsafe { bindings::pte_present(*pte) } {
+            return Ok(());
+        }
+        let pte = NonNull::new(pte).ok_or(EINVAL)?;
+        visit(visitor, PageLevel::Pte(Pte(pte)), addr, size, restriction)
+    })
+}
+
+/// A leaf entry of a user address space, copied during the walk
+///
+/// The user page tables can be freed once the mmap lock is released, so the entry itself
+/// is not kept
+#[derive(Clone, Copy)]
+pub struct UserLeaf {
+    /// Start of the range mapped by the entry
+    pub start: usize,
+    /// End of the range mapped by the entry
+    pub end: usize,
+    /// PFN of the entry
+    pub pfn: u64,
+    /// Protections of the entry
+    pub flags: PgProtFlags,
+}
+
+/// Build the result of [`read_user_leaf`] for an entry of `size` bytes covering `addr`
+fn user_leaf(addr: usize, size: usize, pfn: u64, pgprot: pgprot_t) -> (Option<UserLeaf>, usize) {
+    let start = addr & !(size - 1);
+    let leaf = UserLeaf {
+        start,
+        end: start.wrapping_add(size),
+        pfn,
+        flags: PgProtFlags::new(pgprot),
+    };
+    (Some(leaf), size)
+}
+
+/// Read the leaf entry covering `addr` in the address space locked by `mm`
+///
+/// The entries are copied before being tested, like `follow_pte` does: the PMD can be
+/// changed under us by a THP split or collapse (khugepaged only holds the mmap lock for
+/// reading), it is read with `pmdp_get_lockless` and the PTE table is mapped with
+/// `pte_offset_map`, which fails if it was freed or replaced in between.
+fn read_user_leaf(mm: &MmapReadGuard<'_>, addr: usize) -> Result<(Option<UserLeaf>, usize)> {
+    // SAFETY: The PGD is not freed while the mm has users, the upper tables are only freed
+    // with the mmap lock held for writing
+    let pgd = unsafe { bindings::pgd_offset_pgd((*mm.mm().as_ptr()).pgd, addr as _) };
+    // SAFETY: `pgd` point to a valid pgd entry, see above
+    let pgd_val = unsafe { core::ptr::read_volatile(pgd) };
+    // SAFETY: Just FFI calls on a copy of the entry
+    if unsafe { bindings::pgd_none(pgd_val) || bindings::pgd_bad(pgd_val) } {
+        return Ok((None, 1 << pgdir_shift()));
+    }
+
+    // SAFETY: The pgd entry is present so it point to a valid P4D (or is the folded P4D)
+    let p4d = unsafe { bindings::p4d_offset(pgd, addr as _) };
+    // SAFETY: `p4d` point to a valid p4d entry
+    let p4d_val = unsafe { core::ptr::read_volatile(p4d) };
+    // SAFETY: Just FFI calls on a copy of the entry
+    if unsafe { bindings::p4d_none(p4d_val) || bindings::p4d_bad(p4d_val) } {
+        return Ok((None, 1 << bindings::P4D_SHIFT));
+    }
+
+    let pud_size = 1usize << bindings::PUD_SHIFT;
+    // SAFETY: The p4d entry is present so it point to a valid PUD
+    let pud = unsafe { bindings::pud_offset(p4d, addr as _) };
+    // SAFETY: `pud` point to a valid pud entry
+    let pud_val = unsafe { core::ptr::read_volatile(pud) };
+    // SAFETY: Just FFI calls on a copy of the entry
+    let (none, leaf, bad) = unsafe {
+        (
+            bindings::pud_none(pud_val),
+            bindings::pud_leaf(pud_val),
+            bindings::pud_bad(pud_val),
+        )
+    };
+    if none {
+        return Ok((None, pud_size));
+    } else if leaf {
+        // SAFETY: Just FFI calls on a copy of the entry
+        let (pfn, pgprot) = unsafe { (bindings::pud_pfn(pud_val), bindings::pud_pgprot(pud_val)) };
+        return Ok(user_leaf(addr, pud_size, pfn as u64, pgprot));
+    } else if bad {
+        return Ok((None, pud_size));
+    }
+
+    let pmd_size = 1usize << bindings::PMD_SHIFT;
+    // SAFETY: The pud entry is present and not a leaf so it point to a valid PMD
+    let pmd = unsafe { bindings::pmd_offset(pud, addr as _) };
+    // SAFETY: `pmd` point to a valid pmd entry, read with both halves consistent
+    let pmd_val = unsafe { bindings::pmdp_get_lockless(pmd) };
+    // SAFETY: Just FFI calls on a copy of the entry
+    let (present, leaf, bad) = unsafe {
+        (
+            bindings::pmd_present(pmd_val),
+            bindings::pmd_leaf(pmd_val),
+            bindings::pmd_bad(pmd_val),
+        )
+    };
+    // A non present PMD can be a migration entry of a THP
+    if !present {
+        return Ok((None, pmd_size));
+    } else if leaf {
+        // SAFETY: Just FFI calls on a copy of the entry
+        let (pfn, pgprot) = unsafe { (bindings::pmd_pfn(pmd_val), bindings::pmd_pgprot(pmd_val)) };
+        return Ok(user_leaf(addr, pmd_size, pfn as u64, pgprot));
+    } else if bad {
+        return Ok((None, pmd_size));
+    }
+
+    let page_size = 1usize << bindings::PAGE_SHIFT;
+    // SAFETY: `pmd` point to a valid pmd entry, `pte_offset_map` checks again that it points
+    // to a PTE table and keeps the table from being freed (under RCU) until `pte_unmap`
+    let pte = unsafe { bindings::pte_offset_map(pmd, addr as _) };
+    if pte.is_null() {
+        // The table was freed or replaced by a huge page in between
+        return Ok((None, page_size));
+    }
+    // SAFETY: `pte` is mapped, see above
+    let pte_val = unsafe { core::ptr::read_volatile(pte) };
+    // SAFETY: `pte` was mapped by `pte_offset_map` above
+    unsafe { bindings::pte_unmap(pte) };
+    // SAFETY: Just FFI calls on a copy of the entry
+    if !unsafe { bindings::pte_present(pte_val) } {
+        return Ok((None, page_size));
+    }
+    // SAFETY: Just FFI calls on a copy of the entry
+    let (pfn, pgprot) = unsafe { (bindings::pte_pfn(pte_val), bindings::pte_pgprot(pte_val)) };
+    Ok(user_leaf(addr, page_size, pfn as u64, pgprot))
+}
+
+/// Lookup for the page at the user address `address` in `mm`
+///
+/// The mmap lock is held for reading during the lookup, the entry may be modified right
+/// after it is released
+pub fn lookup_address_in_mm(mm: &Mm, address: usize) -> Result<UserLeaf> {
+    let guard = mm.mmap_read_lock();
+    match read_user_leaf(&guard, address)? {
+        (Some(leaf), _) => Ok(leaf),
+        (None, _) => Err(EINVAL),
+    }
+}
+
+/// Walk the page tables of the VMAs of `mm` covering `start..end` and call `visitor` on
+/// each mapped leaf entry with its VMA, in order
+///
+/// The mmap lock is held for reading during the whole walk, so `visitor` can sleep but must
+/// not lock it again. The CPU is given back between the VMAs and between the PMD entries.
+/// The holes between the VMAs are skipped. An error of the visitor stops the walk and is
+/// returned.
+pub fn walk_vma_range(
+    mm: &Mm,
+    start: usize,
+    end: usize,
+    mut visitor: impl FnMut(&VmArea, &UserLeaf) -> Result,
+) -> Result {
+    let guard = mm.mmap_read_lock();
+    let pmd_size = 1usize << bindings::PMD_SHIFT;
+    let mut addr = start;
+    while addr < end {
+        cond_resched();
+        let Some(vma) = guard.find_vma(addr) else {
+            break;
+        };
+        let vma_end = vma.end().min(end);
+        addr = addr.max(vma.start());
+        while addr < vma_end {
+            // Up to a full PTE table is walked between two calls
+            if addr & (pmd_size - 1) == 0 {
+                cond_resched();
+            }
+            let (leaf, size) = read_user_leaf(&guard, addr)?;
+            if let Some(leaf) = leaf {
+                visitor(vma, &leaf)?;
+            }
+            match next_entry(addr, size) {
+                Some(next) => addr = next,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Probe handles : a common interface over the probe wrappers
+//!
+//! The checks don't register their probes themselves. Each check declares the probes it
+//! needs as a list of [`ProbeRequest`], and the [`ProbeRegistry`] creates them, keeps them
+//! registered and unregisters them when it is dropped. The registered probes are only
+//! seen through the [`ProbeHandle`] trait, whatever their wrapper.
+//!
+//! A probe which is not required (its function may be missing from the running kernel)
+//! is skipped with a warning, like the sensors of
+//! [`SensorSet`](crate::sensor_set::SensorSet).
+
+use crate::fprobe::{Fprobe, FprobeOperations};
+use crate::kprobe::{Kprobe, KprobeOperations, KprobeTarget};
+use crate::sync::Arc;
+#[cfg(CONFIG_TRACEPOINTS)]
+use crate::tracepoint_probe::{Tracepoint, TracepointOperations};
+use kernel::prelude::*;
+
+/// Type of the wrapper behind a [`ProbeHandle`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProbeType {
+    /// A [`Fprobe`]
+    Fprobe,
+    /// A [`Kprobe`]
+    Kprobe,
+    /// A probe on a tracepoint
+    Tracepoint,
+}
+
+/// Statistics of a probe
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProbeStats {
+    /// Number of hits, if the wrapper counts them
+    pub hits: Option<u64>,
+    /// Number of hits missed by the probe
+    pub missed: u64,
+}
+
+/// A registered probe, unregistered when dropped
+pub trait ProbeHandle: Send + Sync {
+    /// Type of the wrapper
+    fn probe_type(&self) -> ProbeType;
+
+    /// Enable the probe, it is enabled at registration
+    fn enable(&self) -> Result;
+
+    /// Disable the probe, its handlers are not called anymore until [`Self::enable`]
+    fn disable(&self) -> Result;
+
+    /// The handlers of the probe are called
+    fn is_enabled(&self) -> bool;
+
+    /// Get the statistics of the probe
+    fn stats(&self) -> ProbeStats;
+}
+
+impl<T: FprobeOperations + 'static> ProbeHandle for Fprobe<T> {
+    fn probe_type(&self) -> ProbeType {
+        ProbeType::Fprobe
+    }
+
+    fn enable(&self) -> Result {
+        Fprobe::enable(self);
+        Ok(())
+    }
+
+    fn disable(&self) -> Result {
+        Fprobe::disable(self);
+        Ok(())
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.is_disabled()
+    }
+
+    fn stats(&self) -> ProbeStats {
+        let stats = Fprobe::stats(self);
+        ProbeStats {
+            hits: Some(stats.entry_hits),
+            missed: stats.missed,
+        }
+    }
+}
+
+impl<T: KprobeOperations + 'static> ProbeHandle for Kprobe<T> {
+    fn probe_type(&self) -> ProbeType {
+        ProbeType::Kprobe
+    }
+
+    fn enable(&self) -> Result {
+        Kprobe::enable(self)
+    }
+
+    fn disable(&self) -> Result {
+        Kprobe::disable(self)
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.is_disabled()
+    }
+
+    fn stats(&self) -> ProbeStats {
+        ProbeStats {
+            hits: None,
+            missed: self.nmissed(),
+        }
+    }
+}
+
+/// A tracepoint probe can't be disabled, only unregistered
+#[cfg(CONFIG_TRACEPOINTS)]
+impl<T: TracepointOperations + 'static> ProbeHandle for Tracepoint<T> {
+    fn probe_type(&self) -> ProbeType {
+        ProbeType::Tracepoint
+    }
+
+    fn enable(&self) -> Result {
+        Ok(())
+    }
+
+    fn disable(&self) -> Result {
+        Err(ENOTSUPP)
+    }
+
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn stats(&self) -> ProbeStats {
+        ProbeStats::default()
+    }
+}
+
+/// Create a [`Fprobe`] on the functions matching `filter`
+pub fn fprobe<T: FprobeOperations + 'static>(
+    filter: &'static CStr,
+    data: T::Data,
+) -> Result<Arc<dyn ProbeHandle>> {
+    let probe: Arc<dyn ProbeHandle> =
+        Arc::pin_init(Fprobe::<T>::new(filter, None, data), GFP_KERNEL)?;
+    Ok(probe)
+}
+
+/// Create a [`Kprobe`] on `target`
+pub fn kprobe<T: KprobeOperations + 'static>(
+    target: KprobeTarget,
+    data: T::Data,
+) -> Result<Arc<dyn ProbeHandle>> {
+    let probe: Arc<dyn ProbeHandle> = Arc::pin_init(Kprobe::<T>::new(target, data), GFP_KERNEL)?;
+    Ok(probe)
+}
+
+/// Create a probe on the tracepoint `name`
+///
+/// # Safety
+///     `T::Args` must match the `TP_PROTO` of the tracepoint `name`, see [`Tracepoint::new`]
+#[cfg(CONFIG_TRACEPOINTS)]
+pub unsafe fn tracepoint<T: TracepointOperations + 'static>(
+    name: &CStr,
+    data: T::Data,
+) -> Result<Arc<dyn ProbeHandle>> {
+    // SAFETY: The caller guarantees that `T::Args` match the prototype of `name`
+    let tracepoint = unsafe { Tracepoint::<T>::new(name, data)? };
+    let probe: Arc<dyn ProbeHandle> = Arc::new(tracepoint, GFP_KERNEL)?;
+    Ok(probe)
+}
+
+/// A probe needed by a check
+pub struct ProbeRequest {
+    /// Name of the probe, unique in a registry
+    pub name: &'static CStr,
+    /// The registration fails if the probe can't be created, otherwise it is skipped
+    pub required: bool,
+    /// Create and register the probe, see [`fprobe`], [`kprobe`] and [`tracepoint`]
+    pub create: fn() -> Result<Arc<dyn ProbeHandle>>,
+}
+
+/// A probe of a registry
+pub struct RegisteredProbe {
+    /// Name of the request
+    pub name: &'static CStr,
+    /// The probe
+    pub handle: Arc<dyn ProbeHandle>,
+}
+
+/// The registered probes
+pub struct ProbeRegistry {
+    probes: KVec<RegisteredProbe>,
+}
+
+impl Default for ProbeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProbeRegistry {
+    /// Create a registry without any probe
+    pub fn new() -> Self {
+        ProbeRegistry {
+            probes: KVec::new(),
+        }
+    }
+
+    /// Create the probes of `requests`
+    ///
+    /// If a required probe can't be created, the probes of `requests` already created are
+    /// unregistered and the error is returned
+    pub fn register(&mut self, requests: &[ProbeRequest]) -> Result {
+        let start = self.probes.len();
+        for request in requests {
+            if let Err(e) = self.register_one(request) {
+                // Only the probes of this call are dropped, without allocating
+                self.probes.truncate(start);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn register_one(&mut self, request: &ProbeRequest) -> Result {
+        if self.get(request.name).is_some() {
+            pr_err!("Probe {} already registered\n", request.name);
+            return Err(EEXIST);
+        }
+        match (request.create)() {
+            Ok(handle) => self.probes.push(
+                RegisteredProbe {
+                    name: request.name,
+                    handle,
+                },
+                GFP_KERNEL,
+            )?,
+            Err(e) if e == ENOMEM || request.required => return Err(e),
+            Err(_) => pr_warn!("Couldn't create probe {}, skipped\n", request.name),
+        }
+        Ok(())
+    }
+
+    /// Keep only the probes for which `f` returns `true`, the others are dropped
+    fn retain(&mut self, mut f: impl FnMut(&RegisteredProbe) -> bool) -> Result {
+        let mut kept = KVec::with_capacity(self.probes.len(), GFP_KERNEL)?;
+        for probe in core::mem::replace(&mut self.probes, KVec::new()) {
+            if f(&probe) {
+                kept.push(probe, GFP_KERNEL)?;
+            }
+        }
+        self.probes = kept;
+        Ok(())
+    }
+
+    /// Unregister the probe `name`
+    ///
+    /// # Return
+    /// `false` if there is no such probe. The probe is only unregistered once the handles
+    /// returned by [`Self::get`] are dropped
+    pub fn unregister(&mut self, name: &CStr) -> Result<bool> {
+        if self.get(name).is_none() {
+            return Ok(false);
+        }
+        self.retain(|probe| probe.name.as_bytes() != name.as_bytes())?;
+        Ok(true)
+    }
+
+    /// Get the probe `name`
+    pub fn get(&self, name: &CStr) -> Option<&Arc<dyn ProbeHandle>> {
+        self.probes
+            .iter()
+            .find(|probe| probe.name.as_bytes() == name.as_bytes())
+            .map(|probe| &probe.handle)
+    }
+
+    /// Get the registered probes
+    pub fn probes(&self) -> &[RegisteredProbe] {
+        &self.probes
+    }
+
+    /// Enable every probe, the probes which can't be enabled are skipped
+    ///
+    /// # Return
+    /// The last error, if any
+    pub fn enable_all(&self) -> Result {
+        self.for_each(|handle| handle.enable())
+    }
+
+    /// Disable every probe which can be disabled
+    ///
+    /// # Return
+    /// The last error, other than the probes which can't be disabled, if any
+    pub fn disable_all(&self) -> Result {
+        self.for_each(|handle| match handle.disable() {
+            Err(e) if e == ENOTSUPP => Ok(()),
+            ret => ret,
+        })
+    }
+
+    fn for_each(&self, f: impl Fn(&dyn ProbeHandle) -> Result) -> Result {
+        let mut ret = Ok(());
+        for probe in self.probes.iter() {
+            if let Err(e) = f(&*probe.handle) {
+                pr_warn!("Probe {} failed with {:?}\n", probe.name, e);
+                ret = Err(e);
+            }
+        }
+        ret
+    }
+
+    /// Get the statistics of every probe
+    pub fn stats(&self) -> Result<KVec<(&'static CStr, ProbeStats)>> {
+        let mut stats = KVec::with_capacity(self.probes.len(), GFP_KERNEL)?;
+        for probe in self.probes.iter() {
+            stats.push((probe.name, probe.handle.stats()), GFP_KERNEL)?;
+        }
+        Ok(stats)
+    }
+}
@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Probe capacity : the misses of the fprobes and the tuning of their rethook
+//!
+//! The exit handler of a fprobe needs a rethook node for each call in flight. When the
+//! nodes are exhausted the call is missed : its exit handler is skipped, which is a
+//! window for a rootkit able to make the hooked function block (or recurse) enough.
+//! A [`CapacityTuner`] follows the `nmissed` counter of a fprobe at each scan, reports the
+//! new misses and grows the rethook once they are sustained.
+//!
+//! `nmissed` also counts the hits skipped because of recursion, which a larger rethook
+//! doesn't fix, so the rethook is never grown beyond [`MAX_NR_MAXACTIVE`].
+
+use core::pin::Pin;
+
+use crate::event::{Event, EventKind};
+use crate::fprobe::{Fprobe, FprobeOperations};
+use kernel::prelude::*;
+
+/// Number of consecutive scans with misses before the rethook is grown
+pub const SUSTAINED_SCANS: u32 = 3;
+
+/// Maximum number of rethook nodes set by the tuner
+pub const MAX_NR_MAXACTIVE: u32 = 4096;
+
+/// The misses observed since the previous scan of a fprobe
+#[derive(Clone, Copy, Debug)]
+pub struct MissReport {
+    /// Address of the `struct fprobe`
+    pub object: u64,
+    /// Number of new misses
+    pub missed: u64,
+    /// Number of rethook nodes during the scan
+    pub nr_maxactive: u32,
+    /// Number of rethook nodes after the tuning, if the rethook was grown
+    pub resized: Option<u32>,
+}
+
+/// Follow the misses of a fprobe across the scans
+pub struct CapacityTuner {
+    last_missed: u64,
+    streak: u32,
+    auto_adjust: bool,
+}
+
+impl CapacityTuner {
+    /// Start following `fp`, the misses before the call are ignored
+    ///
+    /// Without `auto_adjust` the misses are only reported
+    pub fn new<T: FprobeOperations>(fp: &Fprobe<T>, auto_adjust: bool) -> Self {
+        CapacityTuner {
+            last_missed: fp.nmissed(),
+            streak: 0,
+            auto_adjust,
+        }
+    }
+
+    /// Account the misses of `fp` since the previous update and grow its rethook if
+    /// they are sustained
+    pub fn update<T: FprobeOperations>(&mut self, fp: Pin<&mut Fprobe<T>>) -> Result<MissReport> {
+        let missed = fp.nmissed();
+        let nr_maxactive = fp.nr_maxactive();
+        let mut report = MissReport {
+            object: fp.as_ptr() as u64,
+            missed: missed.wrapping_sub(self.last_missed),
+            nr_maxactive,
+            resized: None,
+        };
+        self.last_missed = missed;
+
+        if report.missed == 0 {
+            self.streak = 0;
+            return Ok(report);
+        }
+        self.streak += 1;
+
+        if self.auto_adjust
+            && T::HAS_EXIT_HANDLER
+            && self.streak >= SUSTAINED_SCANS
+            && nr_maxactive < MAX_NR_MAXACTIVE
+        {
+            let size = nr_maxactive.saturating_mul(2).clamp(1, MAX_NR_MAXACTIVE);
+            fp.resize(size)?;
+            report.resized = Some(size);
+            self.streak = 0;
+        }
+
+        Ok(report)
+    }
+}
+
+impl MissReport {
+    /// Create the event reporting the misses, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.missed == 0 {
+            return Ok(None);
+        }
+        let event = match self.resized {
+            Some(size) => Event::new(
+                EventKind::ProbeMissed,
+                fmt!(
+                    "fprobe {:#x} missed {} calls, rethook grown from {} to {} nodes",
+                    self.object,
+                    self.missed,
+                    self.nr_maxactive,
+                    size
+                ),
+            )?,
+            None => Event::new(
+                EventKind::ProbeMissed,
+                fmt!(
+                    "fprobe {:#x} missed {} calls with {} rethook nodes",
+                    self.object,
+                    self.missed,
+                    self.nr_maxactive
+                ),
+            )?,
+        };
+        Ok(Some(event))
+    }
+}
@@ -0,0 +1,446 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Process monitor : the stream of the creations, execs and exits of the processes
+//!
+//! The periodic scans miss the processes living between two of them: a dropper run from a
+//! `memfd`, a short-lived helper of a rootkit hidden from `/proc`. A [`ProcessSensor`]
+//! attaches to the `sched_process_fork`, `sched_process_exec` and `sched_process_exit`
+//! tracepoints and records each step of the life of a process with its parent process,
+//! the executable and the credentials it got at exec.
+//!
+//! Only the processes are recorded, not the threads: a fork creating a thread is ignored
+//! and the exit of a process is the exit of its last thread (`group_dead`), recorded with
+//! the identity of its group leader.
+//!
+//! Like the syscall monitor, the tracepoints run with the preemption disabled: the records
+//! are written in place in a ring without allocation, the paths are truncated to
+//! [`PATH_LEN`].
+//!
+//! The checks take the records with [`ProcessMonitor::take_records`], or the events are
+//! created in process context by [`ProcessMonitor::drain`].
+//!
+//! C header: [`include/trace/events/sched.h`](../../../../include/trace/events/sched.h)
+
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::c_str;
+use crate::error::from_err_ptr;
+use crate::event::{Event, EventKind};
+use crate::str::BStr;
+use crate::sync::{new_spinlock, rcu, Arc, ArcBorrow, SpinLock};
+use crate::task::{Task, TaskCreds, TASK_COMM_LEN};
+use crate::time::Ktime;
+use crate::tracepoint_probe::{args, Tracepoint, TracepointOperations};
+use kernel::prelude::*;
+
+/// Number of records kept by a [`ProcessMonitor`]
+pub const RING_CAPACITY: usize = 64;
+
+/// Length of a recorded path, including its null terminator
+pub const PATH_LEN: usize = 256;
+
+/// A null terminated path, truncated to [`PATH_LEN`]
+#[derive(Clone, Copy)]
+pub struct PathBuf([u8; PATH_LEN]);
+
+impl PathBuf {
+    /// The empty path
+    const EMPTY: Self = PathBuf([0u8; PATH_LEN]);
+
+    /// Copy the kernel string `s` in place, empty if it is null
+    ///
+    /// # Safety
+    ///
+    /// `s` must be null or point to a null terminated string
+    unsafe fn set_from_char_ptr(&mut self, s: *const core::ffi::c_char) {
+        self.0.fill(0);
+        if s.is_null() {
+            return;
+        }
+        for (i, dst) in self.0.iter_mut().take(PATH_LEN - 1).enumerate() {
+            // SAFETY: The string is null terminated and we stop at its terminator
+            let c = unsafe { *s.add(i) } as u8;
+            if c == 0 {
+                break;
+            }
+            *dst = c;
+        }
+    }
+
+    /// Resolve the path of `file` in place with `d_path`, empty if it can't be resolved
+    ///
+    /// # Safety
+    ///
+    /// `file` must be null or point to a referenced file
+    unsafe fn set_from_file(&mut self, file: *mut bindings::file) {
+        if file.is_null() {
+            self.0.fill(0);
+            return;
+        }
+        let buf = &mut self.0;
+        // SAFETY: The file is referenced so is its path, `d_path` writes inside `buf` and
+        // never sleeps
+        let Ok(start) = from_err_ptr(unsafe {
+            bindings::d_path(
+                ptr::addr_of!((*file).f_path),
+                buf.as_mut_ptr().cast(),
+                PATH_LEN as _,
+            )
+        }) else {
+            buf.fill(0);
+            return;
+        };
+        // The path is written at the end of `buf`, null terminated, move it to the start
+        let offset = start as usize - buf.as_ptr() as usize;
+        buf.copy_within(offset.., 0);
+        buf[PATH_LEN - offset..].fill(0);
+    }
+
+    /// Get the bytes of the path (without the null terminator)
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.0.iter().position(|c| *c == 0).unwrap_or(PATH_LEN);
+        &self.0[..len]
+    }
+}
+
+/// A step of the life of a process
+#[derive(Clone, Copy)]
+pub enum ProcessStep {
+    /// The process was created
+    Fork,
+    /// The process executed a new program
+    Exec {
+        /// The process id before the exec, a thread other than the leader may exec
+        old_pid: i32,
+        /// The path given to `execve`
+        filename: PathBuf,
+        /// The path of the executable, `/memfd:<name> (deleted)` for a `memfd`
+        exe: PathBuf,
+        /// The credentials of the process once the program loaded
+        creds: TaskCreds,
+    },
+    /// The process exited
+    Exit {
+        /// The exit code, as given to `wait`
+        code: i32,
+        /// For how long the process lived, in nanoseconds
+        lifetime_ns: u64,
+    },
+}
+
+/// A step of the life of a process and the process
+#[derive(Clone, Copy)]
+pub struct ProcessRecord {
+    /// The step
+    pub step: ProcessStep,
+    /// Time of the step (`ktime_get`) in nanoseconds
+    pub timestamp: i64,
+    /// Process id
+    pub pid: i32,
+    /// Process id of the parent
+    pub ppid: i32,
+    /// Command name of the process, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+}
+
+/// Get the process id of the parent of `task`
+fn parent_tgid(task: &Task) -> i32 {
+    let _guard = rcu::read_lock();
+    // SAFETY: The task is valid and `real_parent` is protected by RCU, the parent stays valid
+    // until the end of the read side critical section. `tgid` never changes
+    unsafe {
+        let parent = ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).real_parent));
+        (*parent).tgid
+    }
+}
+
+impl ProcessRecord {
+    /// An empty record, to initialize the ring
+    const EMPTY: Self = ProcessRecord {
+        step: ProcessStep::Fork,
+        timestamp: 0,
+        pid: 0,
+        ppid: 0,
+        comm: [0u8; TASK_COMM_LEN],
+    };
+
+    /// Set the process of the record to `task`, at the current time
+    fn set_process(&mut self, task: &Task) {
+        self.timestamp = Ktime::ktime_get().to_ns();
+        self.pid = task.pid();
+        self.ppid = parent_tgid(task);
+        self.comm = task.comm();
+    }
+}
+
+impl fmt::Display for ProcessStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessStep::Fork => f.write_str("fork"),
+            ProcessStep::Exec {
+                old_pid,
+                filename,
+                exe,
+                creds,
+            } => write!(
+                f,
+                "exec \"{}\" (exe {}, old pid {}, uid {}, euid {}, gid {}, egid {}, caps {:#x})",
+                BStr::from_bytes(filename.as_bytes()),
+                BStr::from_bytes(exe.as_bytes()),
+                old_pid,
+                creds.uid,
+                creds.euid,
+                creds.gid,
+                creds.egid,
+                creds.cap_effective
+            ),
+            ProcessStep::Exit { code, lifetime_ns } => {
+                write!(f, "exit {:#x} after {}ms", code, lifetime_ns / 1_000_000)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ProcessRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self
+            .comm
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(TASK_COMM_LEN);
+        write!(
+            f,
+            "{} (pid {}, ppid {}) {}",
+            BStr::from_bytes(&self.comm[..len]),
+            self.pid,
+            self.ppid,
+            self.step
+        )
+    }
+}
+
+/// The ring of records
+///
+/// The records are big (two paths), they are allocated once with the monitor and written
+/// in place in their slot instead of being built on the small stack of the tracepoint
+/// probes.
+struct Ring {
+    entries: KVec<ProcessRecord>,
+    /// The slot holds a record not taken yet
+    used: [bool; RING_CAPACITY],
+    /// Next slot to write
+    head: usize,
+}
+
+/// The records of the life of the processes
+///
+/// Filled by the tracepoint probes of a [`ProcessSensor`], and drained in process context.
+#[pin_data]
+pub struct ProcessMonitor {
+    #[pin]
+    ring: SpinLock<Ring>,
+    dropped: AtomicU64,
+}
+
+impl ProcessMonitor {
+    /// Create an empty monitor
+    pub fn new() -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            ring <- new_spinlock!(Ring {
+                entries: KVec::from_elem(ProcessRecord::EMPTY, RING_CAPACITY, GFP_KERNEL)?,
+                used: [false; RING_CAPACITY],
+                head: 0,
+            }),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Number of records overwritten before being drained
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Write a record of the process `task` in the next slot, `set_step` sets its step in
+    /// place
+    fn record(&self, task: &Task, set_step: impl FnOnce(&mut ProcessStep)) {
+        let mut guard = self.ring.lock();
+        let ring = &mut *guard;
+        let head = ring.head;
+        if core::mem::replace(&mut ring.used[head], true) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        let record = &mut ring.entries[head];
+        record.set_process(task);
+        set_step(&mut record.step);
+        ring.head = (head + 1) % RING_CAPACITY;
+    }
+
+    /// Called when `child` is created by the current task
+    fn fork(&self, child: &Task) {
+        // A thread isn't a new process
+        if child.group_leader().as_ptr() != child.as_ptr() {
+            return;
+        }
+        self.record(child, |step| *step = ProcessStep::Fork);
+    }
+
+    /// Called when `task` executed the program loaded in `bprm`
+    ///
+    /// # Safety
+    ///
+    /// `bprm` must be valid, with its file referenced
+    unsafe fn exec(&self, task: &Task, old_pid: i32, bprm: *mut bindings::linux_binprm) {
+        let creds = task.creds();
+        self.record(task, |step| {
+            *step = ProcessStep::Exec {
+                old_pid,
+                filename: PathBuf::EMPTY,
+                exe: PathBuf::EMPTY,
+                creds,
+            };
+            if let ProcessStep::Exec { filename, exe, .. } = step {
+                // SAFETY: `bprm` is valid, its filename is null terminated and its file
+                // referenced
+                unsafe {
+                    filename.set_from_char_ptr((*bprm).filename);
+                    exe.set_from_file((*bprm).file);
+                }
+            }
+        });
+    }
+
+    /// Called when `task` exits, `group_dead` if it is the last thread of its process
+    fn exit(&self, task: &Task, group_dead: bool) {
+        // The process exits with its last thread
+        if !group_dead {
+            return;
+        }
+        // SAFETY: The task is valid, its exit code is set before the tracepoint
+        let code = unsafe { ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).exit_code)) };
+        // The group leader is released after the last thread, it is still valid
+        let leader = task.group_leader();
+        let now = Ktime::ktime_get().to_ns() as u64;
+        let lifetime_ns = now.saturating_sub(leader.start_time());
+        self.record(leader, |step| {
+            *step = ProcessStep::Exit { code, lifetime_ns }
+        });
+    }
+
+    /// Take the records, from the oldest to the newest
+    pub fn take_records(&self) -> Result<KVec<ProcessRecord>> {
+        // Allocate before taking the lock
+        let mut records = KVec::with_capacity(RING_CAPACITY, GFP_KERNEL)?;
+
+        let mut guard = self.ring.lock();
+        let ring = &mut *guard;
+        let head = ring.head;
+        for i in 0..RING_CAPACITY {
+            let slot = (head + i) % RING_CAPACITY;
+            if core::mem::replace(&mut ring.used[slot], false) {
+                // Can't fail, the capacity is reserved
+                records.push(ring.entries[slot], GFP_ATOMIC)?;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Take the records and create their events
+    pub fn drain(&self) -> Result<KVec<Event>> {
+        let records = self.take_records()?;
+        let mut events = KVec::with_capacity(records.len(), GFP_KERNEL)?;
+        for record in records.iter() {
+            events.push(
+                Event::new(EventKind::ProcessLifecycle, fmt!("{}", record))?,
+                GFP_KERNEL,
+            )?;
+        }
+        Ok(events)
+    }
+}
+
+/// The probe of `sched_process_fork`
+pub struct ForkProbe;
+
+impl TracepointOperations for ForkProbe {
+    type Data = Arc<ProcessMonitor>;
+    type Args = args::SchedProcessFork;
+
+    fn probe(monitor: ArcBorrow<'_, ProcessMonitor>, (_parent, child): Self::Args) {
+        if child.is_null() {
+            return;
+        }
+        // SAFETY: The child is valid during the probe, it is referenced by its creator
+        monitor.fork(unsafe { &*child.cast::<Task>() });
+    }
+}
+
+/// The probe of `sched_process_exec`
+pub struct ExecProbe;
+
+impl TracepointOperations for ExecProbe {
+    type Data = Arc<ProcessMonitor>;
+    type Args = args::SchedProcessExec;
+
+    fn probe(monitor: ArcBorrow<'_, ProcessMonitor>, (task, old_pid, bprm): Self::Args) {
+        if task.is_null() || bprm.is_null() {
+            return;
+        }
+        // SAFETY: The tracepoint passes the current task and the program it loaded, both
+        // valid during the probe
+        unsafe { monitor.exec(&*task.cast::<Task>(), old_pid, bprm) };
+    }
+}
+
+/// The probe of `sched_process_exit`
+pub struct ExitProbe;
+
+impl TracepointOperations for ExitProbe {
+    type Data = Arc<ProcessMonitor>;
+    type Args = args::SchedProcessExit;
+
+    fn probe(monitor: ArcBorrow<'_, ProcessMonitor>, (task, group_dead): Self::Args) {
+        if task.is_null() {
+            return;
+        }
+        // SAFETY: The tracepoint passes the current task, valid during the probe
+        monitor.exit(unsafe { &*task.cast::<Task>() }, group_dead);
+    }
+}
+
+/// The tracepoint probes filling a [`ProcessMonitor`]
+pub struct ProcessSensor {
+    monitor: Arc<ProcessMonitor>,
+    _fork: Tracepoint<ForkProbe>,
+    _exec: Tracepoint<ExecProbe>,
+    _exit: Tracepoint<ExitProbe>,
+}
+
+impl ProcessSensor {
+    /// Create a monitor and register the probes filling it
+    pub fn new() -> Result<Self> {
+        let monitor = Arc::pin_init(ProcessMonitor::new(), GFP_KERNEL)?;
+        // SAFETY: The arguments of the probes are the ones of the `TP_PROTO` of the
+        // tracepoints, see `tracepoint_probe::args`
+        let (fork, exec, exit) = unsafe {
+            (
+                Tracepoint::new(c_str!("sched_process_fork"), monitor.clone())?,
+                Tracepoint::new(c_str!("sched_process_exec"), monitor.clone())?,
+                Tracepoint::new(c_str!("sched_process_exit"), monitor.clone())?,
+            )
+        };
+        Ok(ProcessSensor {
+            monitor,
+            _fork: fork,
+            _exec: exec,
+            _exit: exit,
+        })
+    }
+
+    /// Get the monitor filled by the probes
+    pub fn monitor(&self) -> &Arc<ProcessMonitor> {
+        &self.monitor
+    }
+}
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Protection : change the protections of kernel ranges
+//!
+//! Wrappers over the `set_memory_*` family, used by the remediation actions to protect
+//! back the memory a rootkit made writable. The ranges must be page aligned, the
+//! protections are changed in the page tables and the TLB flushed by the kernel.
+//!
+//! Changing the protections of memory in use is dangerous even if it is memory safe from
+//! the point of view of Rust : text made non executable crashes the kernel at its next
+//! execution, data made read-only at its next write.
+//!
+//! C header: [`include/linux/set_memory.h`](../../../../include/linux/set_memory.h)
+
+use crate::page::PAGE_SIZE;
+use kernel::prelude::*;
+
+/// Prototype of the `set_memory_*` functions
+type SetMemory =
+    unsafe extern "C" fn(addr: core::ffi::c_ulong, numpages: core::ffi::c_int) -> core::ffi::c_int;
+
+/// Call `set_memory` on `start..start + len`
+fn set_memory(set_memory: SetMemory, start: usize, len: usize) -> Result {
+    if start % PAGE_SIZE != 0 || len % PAGE_SIZE != 0 || len == 0 {
+        return Err(EINVAL);
+    }
+    start.checked_add(len).ok_or(EINVAL)?;
+    let numpages = core::ffi::c_int::try_from(len / PAGE_SIZE).map_err(|_| EINVAL)?;
+
+    // SAFETY: Just an FFI call, the range is page aligned and doesn't overflow
+    crate::error::to_result(unsafe { set_memory(start as _, numpages) })
+}
+
+/// Make the pages of `start..start + len` read-only
+pub fn set_ro(start: usize, len: usize) -> Result {
+    set_memory(bindings::set_memory_ro, start, len)
+}
+
+/// Make the pages of `start..start + len` writable
+pub fn set_rw(start: usize, len: usize) -> Result {
+    set_memory(bindings::set_memory_rw, start, len)
+}
+
+/// Make the pages of `start..start + len` executable
+pub fn set_x(start: usize, len: usize) -> Result {
+    set_memory(bindings::set_memory_x, start, len)
+}
+
+/// Make the pages of `start..start + len` non executable
+pub fn set_nx(start: usize, len: usize) -> Result {
+    set_memory(bindings::set_memory_nx, start, len)
+}
+
+/// Make the pages of `start..start + len` read-only and executable, the protections of
+/// the text
+pub fn set_rox(start: usize, len: usize) -> Result {
+    set_memory(bindings::set_memory_rox, start, len)
+}
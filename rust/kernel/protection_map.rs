@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Protection map : run-length encoded map of the kernel space protections
+//!
+//! Walk the kernel half of the address space with [`walk_kernel_range`] and merge the
+//! consecutive pages with the same protections in runs, the protections of the table
+//! entries above a leaf applied. The map is compact enough to be
+//! sent to userspace at each scan, where it can be visualized or diffed against the
+//! previous scans, without giving access to the page tables themselves.
+//!
+//! C header: [`arch/x86/include/asm/pgtable_types.h`](../../../../arch/x86/include/asm/pgtable_types.h)
+
+use crate::pgtable::{walk_kernel_range, LeafEntry, PageLevel};
+use crate::transmute::AsBytes;
+use crate::uaccess::UserSliceWriter;
+use crate::wx_audit::kernel_half;
+use kernel::prelude::*;
+
+/// Protection bits of a run
+pub mod prot {
+    /// The pages are mapped
+    pub const PRESENT: u32 = 1 << 0;
+    /// The pages are writable
+    pub const WRITE: u32 = 1 << 1;
+    /// The pages are executable
+    pub const EXEC: u32 = 1 << 2;
+    /// The pages are accessible from userspace
+    pub const USER: u32 = 1 << 3;
+    /// The pages are global (not flushed on context switch)
+    pub const GLOBAL: u32 = 1 << 4;
+    /// The pages are mapped by large pages (2M or 1G)
+    pub const LARGE: u32 = 1 << 5;
+}
+
+/// A run of contiguous pages with the same protections
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ProtectionRun {
+    /// Start address of the run
+    pub start: u64,
+    /// Size of the run in bytes
+    pub size: u64,
+    /// The [`prot`] bits of the run
+    pub prot: u32,
+    _pad: u32,
+}
+
+// SAFETY: `ProtectionRun` is `repr(C)`, only contains integers and has explicit padding
+unsafe impl AsBytes for ProtectionRun {}
+
+/// Header written before the runs
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ProtectionMapHeader {
+    /// Number of runs in the map
+    pub total: u32,
+    /// Number of runs written after this header (bounded by the user buffer)
+    pub count: u32,
+}
+
+// SAFETY: `ProtectionMapHeader` is `repr(C)` and only contains integers
+unsafe impl AsBytes for ProtectionMapHeader {}
+
+/// Convert the flags of the leaf `entry` to [`prot`] bits
+fn entry_prot(entry: &LeafEntry) -> u32 {
+    let flags = entry.flags;
+    if !flags.is_present() {
+        return 0;
+    }
+    let mut prot = prot::PRESENT;
+    for (set, bit) in [
+        (flags.is_writable(), prot::WRITE),
+        (flags.is_executable(), prot::EXEC),
+        (flags.is_user(), prot::USER),
+        (flags.is_global(), prot::GLOBAL),
+        (!matches!(entry.level, PageLevel::Pte(_)), prot::LARGE),
+    ] {
+        if set {
+            prot |= bit;
+        }
+    }
+    prot
+}
+
+/// The protection map of the kernel space
+pub struct ProtectionMap {
+    runs: KVVec<ProtectionRun>,
+}
+
+impl ProtectionMap {
+    /// Walk the kernel space and build the map
+    ///
+    /// The unmapped ranges are not part of the map
+    pub fn snapshot() -> Result<Self> {
+        let mut runs: KVVec<ProtectionRun> = KVVec::new();
+
+        walk_kernel_range(kernel_half(), usize::MAX, |entry| {
+            let prot = entry_prot(entry);
+            if prot == 0 {
+                return Ok(());
+            }
+            let (start, end) = (entry.start as u64, entry.end as u64);
+            let size = end.wrapping_sub(start);
+            if let Some(run) = runs.last_mut() {
+                if run.prot == prot && run.start + run.size == start {
+                    run.size += size;
+                    return Ok(());
+                }
+            }
+            runs.push(
+                ProtectionRun {
+                    start,
+                    size,
+                    prot,
+                    _pad: 0,
+                },
+                GFP_KERNEL,
+            )?;
+            Ok(())
+        })?;
+
+        Ok(ProtectionMap { runs })
+    }
+
+    /// Get the runs, sorted by address
+    pub fn runs(&self) -> &[ProtectionRun] {
+        &self.runs
+    }
+
+    /// Write the map to a user buffer
+    ///
+    /// A [`ProtectionMapHeader`] is written followed by as many runs as fit in the buffer
+    ///
+    /// # Return
+    /// The number of runs written
+    pub fn write_to_user(&self, mut writer: UserSliceWriter) -> Result<usize> {
+        let header_size = core::mem::size_of::<ProtectionMapHeader>();
+        let run_size = core::mem::size_of::<ProtectionRun>();
+        if writer.len() < header_size {
+            return Err(EINVAL);
+        }
+
+        let count = ((writer.len() - header_size) / run_size).min(self.runs.len());
+        writer.write(&ProtectionMapHeader {
+            total: self.runs.len() as u32,
+            count: count as u32,
+        })?;
+
+        for run in &self.runs[..count] {
+            writer.write(run)?;
+        }
+
+        Ok(count)
+    }
+}
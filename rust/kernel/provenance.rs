@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Return address provenance : where the callers of a function live
+//!
+//! The code of a rootkit loaded without `init_module` (a mapped payload, a freed and
+//! unlinked module) runs from executable memory neither the kernel image nor a module
+//! owns. When it calls `commit_creds` or `kallsyms_lookup_name` its return address is left
+//! on the stack: every frame of a stacktrace is classified as core kernel, module text,
+//! trampoline (ftrace, BPF, kprobe slots) or unknown executable memory with
+//! [`classify`], which never sleeps nor allocates so it can be used from the probe
+//! handlers. The sensors on the sensitive functions report the callers found in unknown
+//! memory as an [`UnknownCaller`].
+//!
+//! The trampolines are only recognized while registered, a frame in a trampoline freed
+//! since the capture is unknown.
+//!
+//! C header: [`include/linux/kernel.h`](../../../../include/linux/kernel.h)
+
+use core::fmt;
+
+use crate::address::{resolve_address, AddressInfo};
+use crate::event::{Event, EventKind};
+use crate::str::CStr;
+use kernel::prelude::*;
+
+/// Provenance of a return address
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameClass {
+    /// The text of the kernel image
+    CoreKernel,
+    /// The text of a module
+    Module,
+    /// A trampoline: ftrace, BPF JIT or kprobe instruction slot
+    Trampoline,
+    /// Memory which doesn't hold known kernel code
+    Unknown,
+}
+
+/// Classify the return address `addr`
+pub fn classify(addr: u64) -> FrameClass {
+    let addr = addr as usize;
+    // SAFETY: Just FFI calls, the lookups never sleep and take the locks they need
+    unsafe {
+        if bindings::core_kernel_text(addr as _) {
+            FrameClass::CoreKernel
+        } else if bindings::is_module_text_address(addr as _) {
+            FrameClass::Module
+        } else if bindings::kernel_text_address(addr as _) {
+            // `kernel_text_address` also knows the ftrace trampolines, the BPF images and
+            // the kprobe slots
+            FrameClass::Trampoline
+        } else {
+            FrameClass::Unknown
+        }
+    }
+}
+
+/// Find the innermost frame of `frames` in unknown memory
+///
+/// # Return
+/// The depth of the frame and its return address
+pub fn first_unknown(frames: &[u64]) -> Option<(usize, u64)> {
+    frames
+        .iter()
+        .enumerate()
+        .find(|(_, frame)| classify(**frame) == FrameClass::Unknown)
+        .map(|(depth, frame)| (depth, *frame))
+}
+
+/// A sensitive function called from unknown memory
+pub struct UnknownCaller {
+    /// The called function
+    pub function: &'static CStr,
+    /// Depth of the frame in the stacktrace, 0 for the function itself
+    pub depth: usize,
+    /// The return address in unknown memory
+    pub caller: AddressInfo,
+}
+
+impl UnknownCaller {
+    /// Resolve the return address `caller` found at `depth` in a call to `function`
+    pub fn new(function: &'static CStr, depth: usize, caller: u64) -> Result<Self> {
+        Ok(UnknownCaller {
+            function,
+            depth,
+            caller: resolve_address(caller)?,
+        })
+    }
+
+    /// Create the event reporting the caller
+    pub fn to_event(&self) -> Result<Event> {
+        Event::new(
+            EventKind::UnknownCaller,
+            fmt!("sensitive function called from unknown memory : {}", self),
+        )
+    }
+}
+
+impl fmt::Display for UnknownCaller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} called from {} at depth {}",
+            self.function, self.caller, self.depth
+        )
+    }
+}
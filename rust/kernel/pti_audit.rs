@@ -0,0 +1,388 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! PTI audit : the consistency of the user copy of the page tables
+//!
+//! With page table isolation, each PGD has a user copy (the next page) loaded while
+//! userspace runs. Its kernel half only maps the entry text, the CPU entry areas and,
+//! when its pages are global, the kernel text, cloned from the kernel copy at boot. A
+//! rootkit hooking the entry code through the kernel copy only, or redirecting the user
+//! copy to its own pages, leaves the two copies mapping different pages.
+//!
+//! On each CPU, the PGD entries covering the checked ranges in the PGD loaded by the CPU
+//! are compared to the ones of `init_mm`, which are copied in every PGD. The leaf entries
+//! of the kernel and user copies of `init_mm` are then compared page by page.
+//!
+//! C header: [`arch/x86/include/asm/pgtable.h`](../../../../arch/x86/include/asm/pgtable.h)
+
+use core::fmt;
+use core::ptr::read_volatile;
+
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::symbols_lookup_name;
+use crate::page::{PAGE_SHIFT, PAGE_SIZE};
+use crate::percpu::{online_cpus, run_on_cpu};
+use crate::pgtable::{walk_pgd_range, KernelTablesGuard, PgProtFlags, Pgtable};
+use kernel::prelude::*;
+
+/// Maximum number of pages reported
+const MAX_FINDINGS: usize = 256;
+
+/// Bits of CR3 holding the physical address of the PGD
+const CR3_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Maximum number of PGD entries compared on each CPU
+const MAX_SLOTS: usize = 2;
+
+/// Number of entries of a PGD, the user copy is the next page
+const PTRS_PER_PGD: usize = PAGE_SIZE / core::mem::size_of::<u64>();
+
+/// Prototype of `get_cpu_entry_area`
+type GetCpuEntryArea = unsafe extern "C" fn(cpu: core::ffi::c_int) -> *mut bindings::cpu_entry_area;
+
+/// A range mapped in the user copy
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PtiRegion {
+    /// The entry text, with the syscall and interrupt trampolines
+    EntryText,
+    /// The CPU entry area of a CPU (GDT, TSS, entry stack, exception stacks)
+    CpuEntryArea(u32),
+    /// The kernel text, only mapped in the user copy when its pages are global
+    KernelText,
+}
+
+/// What is inconsistent between the two copies
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PtiFinding {
+    /// The PGD loaded by a CPU doesn't have the PGD entry of `init_mm`
+    PgdMismatch {
+        /// The CPU
+        cpu: u32,
+        /// The entry of the user copy differs, otherwise the one of the kernel copy
+        user: bool,
+    },
+    /// The page is mapped in the kernel copy but not in the user copy
+    MissingInUser,
+    /// The page is mapped in the user copy but not in the kernel copy
+    UserOnly,
+    /// The copies map different pages
+    PfnMismatch {
+        /// PFN in the kernel copy
+        kernel: u64,
+        /// PFN in the user copy
+        user: u64,
+    },
+    /// The copies map the page with different protections
+    ProtMismatch {
+        /// Protections in the kernel copy
+        kernel: PgProtFlags,
+        /// Protections in the user copy
+        user: PgProtFlags,
+    },
+}
+
+/// An inconsistency between the kernel and user copies
+pub struct PtiInconsistency {
+    /// Address of the page, or of the start of the range covered by the PGD entry
+    pub address: u64,
+    /// The checked range
+    pub region: PtiRegion,
+    /// What is inconsistent
+    pub finding: PtiFinding,
+}
+
+/// Result of the audit
+pub struct PtiAudit {
+    /// Number of pages compared
+    pub checked: usize,
+    /// The inconsistencies, the first [`MAX_FINDINGS`]
+    pub findings: KVec<PtiInconsistency>,
+}
+
+/// A leaf entry of one of the copies
+struct Leaf {
+    start: usize,
+    end: usize,
+    pfn: u64,
+    flags: PgProtFlags,
+}
+
+/// PTI is enabled at runtime, otherwise there is no user copy
+fn pti_enabled() -> bool {
+    let bit = bindings::X86_FEATURE_PTI as usize;
+    // SAFETY: `boot_cpu_data` is set at boot and the capabilities are never cleared after
+    let caps = unsafe { bindings::boot_cpu_data.x86_capability };
+    caps[bit / 32] & (1 << (bit % 32)) != 0
+}
+
+/// Get the user copy of `pgd` (`kernel_to_user_pgdp`)
+fn user_pgd(pgd: *mut bindings::pgd_t) -> *mut bindings::pgd_t {
+    (pgd as usize | PAGE_SIZE) as _
+}
+
+/// The copies map a page with the same protections, the accessed and dirty bits are
+/// ignored as well as the global bit, cleared from the kernel copy only when the kernel
+/// image is not global
+fn same_protections(kernel: PgProtFlags, user: PgProtFlags) -> bool {
+    kernel.is_present() == user.is_present()
+        && kernel.is_writable() == user.is_writable()
+        && kernel.is_executable() == user.is_executable()
+        && kernel.is_user() == user.is_user()
+}
+
+/// Get the leaf entries of `pgd` covering `start..end`
+///
+/// # Safety
+///     `pgd` must be the kernel or user copy of the PGD of `init_mm`
+unsafe fn leaves(pgd: *mut bindings::pgd_t, start: usize, end: usize) -> Result<KVec<Leaf>> {
+    let mut leaves = KVec::new();
+    let _guard = KernelTablesGuard::lock();
+    // SAFETY: By the safety contract of this function, the PGD of `init_mm` and its user
+    // copy are never freed, the tables they point to are kept by the guard
+    unsafe {
+        walk_pgd_range(pgd, start, end, |entry| {
+            leaves.push(
+                Leaf {
+                    start: entry.start,
+                    end: entry.end,
+                    pfn: entry.level.pfn(),
+                    flags: PgProtFlags::new(entry.level.pgprot()),
+                },
+                GFP_KERNEL,
+            )?;
+            Ok(())
+        })
+    }?;
+    Ok(leaves)
+}
+
+/// Get the PFN and protections of `page` in `leaves`, advancing `cursor` to its leaf
+///
+/// The pages must be looked up in order
+fn page_of(leaves: &[Leaf], cursor: &mut usize, page: usize) -> Option<(u64, PgProtFlags)> {
+    while *cursor < leaves.len() && leaves[*cursor].end <= page {
+        *cursor += 1;
+    }
+    let leaf = leaves.get(*cursor).filter(|leaf| leaf.start <= page)?;
+    Some((
+        leaf.pfn + ((page - leaf.start) >> PAGE_SHIFT) as u64,
+        leaf.flags,
+    ))
+}
+
+/// Read CR3 on the current CPU
+fn read_cr3() -> u64 {
+    let cr3: u64;
+    // SAFETY: Reading CR3 has no side effect
+    unsafe { crate::asm!("mov %cr3, {}"; out(reg) cr3, options(nomem, nostack, preserves_flags)) };
+    cr3
+}
+
+impl PtiAudit {
+    fn push(&mut self, address: usize, region: PtiRegion, finding: PtiFinding) -> Result {
+        if self.findings.len() < MAX_FINDINGS {
+            self.findings.push(
+                PtiInconsistency {
+                    address: address as u64,
+                    region,
+                    finding,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Compare the copies of `init_mm` over `start..end`
+    ///
+    /// If `required` is not set, the pages missing from the user copy are not reported
+    fn check_range(
+        &mut self,
+        region: PtiRegion,
+        start: usize,
+        end: usize,
+        required: bool,
+    ) -> Result {
+        // SAFETY: `init_mm.pgd` is the kernel PGD
+        let pgd = unsafe { bindings::init_mm.pgd };
+        // SAFETY: `pgd` and its user copy are the copies of the PGD of `init_mm`
+        let (kernel, user) =
+            unsafe { (leaves(pgd, start, end)?, leaves(user_pgd(pgd), start, end)?) };
+
+        let (mut kernel_cursor, mut user_cursor) = (0, 0);
+        let mut page = start & !(PAGE_SIZE - 1);
+        while page < end {
+            let finding = match (
+                page_of(&kernel, &mut kernel_cursor, page),
+                page_of(&user, &mut user_cursor, page),
+            ) {
+                (None, None) => None,
+                (Some(_), None) => required.then_some(PtiFinding::MissingInUser),
+                (None, Some(_)) => Some(PtiFinding::UserOnly),
+                (Some((kernel, _)), Some((user, _))) if kernel != user => {
+                    Some(PtiFinding::PfnMismatch { kernel, user })
+                }
+                (Some((_, kernel)), Some((_, user))) if !same_protections(kernel, user) => {
+                    Some(PtiFinding::ProtMismatch { kernel, user })
+                }
+                _ => None,
+            };
+            self.checked += 1;
+            if let Some(finding) = finding {
+                self.push(page, region, finding)?;
+            }
+            page += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Compare the PGD entries of `slots` in the PGD loaded by each CPU to the ones of
+    /// `init_mm`
+    fn check_cpus(&mut self, slots: &[(usize, PtiRegion)]) -> Result {
+        // SAFETY: `init_mm.pgd` is the kernel PGD
+        let init_pgd = unsafe { bindings::init_mm.pgd };
+        let mut indexes = [0usize; MAX_SLOTS];
+        for (index, (address, _)) in indexes.iter_mut().zip(slots) {
+            // SAFETY: Just an FFI call, the address is only computed
+            let entry = unsafe { bindings::pgd_offset_pgd(init_pgd, *address as _) };
+            *index = (entry as usize - init_pgd as usize) / core::mem::size_of::<u64>();
+        }
+        let init_pgd = init_pgd as *const u64;
+        // The accessed bit is set by the CPU when it walks the entry
+        let mask = !(bindings::_PAGE_ACCESSED as u64);
+
+        for cpu in online_cpus() {
+            let entries = run_on_cpu(cpu, || {
+                // SAFETY: Just an FFI call, the address is only computed
+                let pgd = unsafe { bindings::phys_to_virt((read_cr3() & CR3_ADDR_MASK) as _) }
+                    as *const u64;
+                let mut entries = [(0u64, 0u64); MAX_SLOTS];
+                for (entry, index) in entries.iter_mut().zip(indexes) {
+                    // SAFETY: The PGD loaded by the CPU is not freed while it is loaded, and
+                    // its user copy is the next page
+                    *entry = unsafe {
+                        (
+                            read_volatile(pgd.add(index)),
+                            read_volatile(pgd.add(index + PTRS_PER_PGD)),
+                        )
+                    };
+                }
+                entries
+            })?;
+
+            for (((kernel, user), index), (address, region)) in
+                entries.into_iter().zip(indexes).zip(slots)
+            {
+                // SAFETY: The PGD of `init_mm` and its user copy are never freed
+                let (init_kernel, init_user) = unsafe {
+                    (
+                        read_volatile(init_pgd.add(index)),
+                        read_volatile(init_pgd.add(index + PTRS_PER_PGD)),
+                    )
+                };
+                if kernel & mask != init_kernel & mask {
+                    self.push(
+                        *address,
+                        *region,
+                        PtiFinding::PgdMismatch { cpu, user: false },
+                    )?;
+                }
+                if user & mask != init_user & mask {
+                    self.push(
+                        *address,
+                        *region,
+                        PtiFinding::PgdMismatch { cpu, user: true },
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compare the kernel and user copies of the page tables
+    ///
+    /// Without PTI at runtime (`pti=off`, or a CPU not vulnerable to Meltdown) there is no
+    /// user copy and nothing is checked
+    pub fn audit() -> Result<Self> {
+        let mut audit = PtiAudit {
+            checked: 0,
+            findings: KVec::new(),
+        };
+        if !pti_enabled() {
+            return Ok(audit);
+        }
+
+        let stext = symbols_lookup_name(c_str!("_stext")) as usize;
+        let etext = symbols_lookup_name(c_str!("_etext")) as usize;
+        if stext == 0 || etext == 0 {
+            pr_err!("Couldn't find _stext symbol\n");
+            return Err(ENOENT);
+        }
+        let entry_start = symbols_lookup_name(c_str!("__entry_text_start")) as usize;
+        let entry_end = symbols_lookup_name(c_str!("__entry_text_end")) as usize;
+        if entry_start == 0 || entry_end == 0 {
+            pr_err!("Couldn't find __entry_text_start symbol\n");
+            return Err(ENOENT);
+        }
+        let get_cpu_entry_area = symbols_lookup_name(c_str!("get_cpu_entry_area")) as *const ();
+        if get_cpu_entry_area.is_null() {
+            pr_err!("Couldn't find get_cpu_entry_area symbol\n");
+            return Err(ENOENT);
+        }
+        // SAFETY: The symbol is the function `get_cpu_entry_area` which has this prototype
+        let get_cpu_entry_area =
+            unsafe { core::mem::transmute::<*const (), GetCpuEntryArea>(get_cpu_entry_area) };
+
+        // The entry text is part of the text, it is checked apart as it must be mapped
+        audit.check_range(PtiRegion::EntryText, entry_start, entry_end, true)?;
+        audit.check_range(PtiRegion::KernelText, stext, entry_start, false)?;
+        audit.check_range(PtiRegion::KernelText, entry_end, etext, false)?;
+
+        let mut first_area = None;
+        for cpu in online_cpus() {
+            // SAFETY: Just an FFI call, `cpu` is a valid CPU
+            let area = unsafe { get_cpu_entry_area(cpu as _) } as usize;
+            let size = core::mem::size_of::<bindings::cpu_entry_area>();
+            audit.check_range(PtiRegion::CpuEntryArea(cpu), area, area + size, true)?;
+            first_area.get_or_insert((area, PtiRegion::CpuEntryArea(cpu)));
+        }
+
+        // The kernel image is covered by the PGD entry of the entry text, the CPU entry
+        // areas of all the CPUs by a single PGD entry
+        let mut slots = KVec::with_capacity(MAX_SLOTS, GFP_KERNEL)?;
+        slots.push((entry_start, PtiRegion::EntryText), GFP_KERNEL)?;
+        if let Some(area) = first_area {
+            slots.push(area, GFP_KERNEL)?;
+        }
+        audit.check_cpus(&slots)?;
+
+        Ok(audit)
+    }
+
+    /// Create the event listing the inconsistencies, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.findings.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::PageTableIsolationMismatch,
+            fmt!("inconsistent user copy of the page tables : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for PtiAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, finding) in self.findings.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:#x} [{:?}] {:?}",
+                finding.address, finding.region, finding.finding
+            )?;
+        }
+        Ok(())
+    }
+}
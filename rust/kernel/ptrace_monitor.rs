@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Ptrace monitor : the tracers attached to the critical daemons
+//!
+//! A userland rootkit hijacks the functions of a daemon without kernel code by attaching
+//! to it with ptrace and patching its memory, or by staying attached to intercept its
+//! syscalls (password sniffing in `sshd`). [`ptrace_links`] lists every tracer/tracee pair
+//! of the system by walking every thread with [`AllThreadsIter`]: a traced task has
+//! `PT_PTRACED` set and its tracer as `parent`.
+//!
+//! A debugger attaching briefly is legitimate, the kernel doesn't record when the
+//! attachment started: the [`PtraceMonitor`] keeps the pairs seen between two checks and
+//! reports the ones attached to a critical daemon for longer than its threshold.
+//!
+//! C header: [`include/linux/ptrace.h`](../../../../include/linux/ptrace.h)
+
+use core::fmt;
+use core::ptr;
+
+use crate::event::{Event, EventKind};
+use crate::str::{until_nul, BStr};
+use crate::task::{Task, TASK_COMM_LEN};
+use crate::task_cgroup::Attribution;
+use crate::task_iter::AllThreadsIter;
+use crate::time::Ktime;
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Default time after which an attachment to a critical daemon is reported
+pub const DEFAULT_THRESHOLD_NS: i64 = 60 * 1_000_000_000;
+
+/// Command names of the critical daemons
+pub(crate) const CRITICAL_DAEMONS: [&[u8]; 12] = [
+    b"systemd",
+    b"init",
+    b"sshd",
+    b"login",
+    b"sudo",
+    b"su",
+    b"dbus-daemon",
+    b"polkitd",
+    b"cron",
+    b"containerd",
+    b"dockerd",
+    b"kubelet",
+];
+
+/// A tracer attached to a tracee
+pub struct PtraceLink {
+    /// The tracer
+    pub tracer: ARef<Task>,
+    /// The traced thread
+    pub tracee: ARef<Task>,
+    /// The `PT_*` flags of the tracee
+    pub flags: u32,
+}
+
+impl PtraceLink {
+    /// The tracer attached with `PTRACE_SEIZE`, the tracee wasn't stopped
+    pub fn is_seized(&self) -> bool {
+        self.flags & bindings::PT_SEIZED != 0
+    }
+}
+
+/// List every tracer/tracee pair of the system
+pub fn ptrace_links() -> Result<KVec<PtraceLink>> {
+    let mut links = KVec::new();
+    for task in AllThreadsIter::new(current!().into()) {
+        // SAFETY: The task is referenced, the flags are only read as a snapshot
+        let flags = unsafe { ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).ptrace)) };
+        if flags & bindings::PT_PTRACED == 0 {
+            continue;
+        }
+        // The tracee exited in between, it is detached
+        let Some(tracer) = task.parent() else {
+            continue;
+        };
+        links.push(
+            PtraceLink {
+                tracer,
+                tracee: task,
+                flags,
+            },
+            GFP_KERNEL,
+        )?;
+    }
+    Ok(links)
+}
+
+/// An attachment to a critical daemon seen by a previous check
+struct Attachment {
+    tracer: i32,
+    tracee: i32,
+    since: i64,
+}
+
+/// A long-lived attachment to a critical daemon
+pub struct LongAttachment {
+    /// Thread id of the tracer
+    pub tracer: i32,
+    /// Command name of the tracer, null terminated
+    pub tracer_comm: [u8; TASK_COMM_LEN],
+    /// Path of the cgroup of the tracer
+    pub tracer_cgroup: KVec<u8>,
+    /// Thread id of the tracee
+    pub tracee: i32,
+    /// Command name of the process of the tracee, null terminated
+    pub tracee_comm: [u8; TASK_COMM_LEN],
+    /// The tracer attached with `PTRACE_SEIZE`
+    pub seized: bool,
+    /// For how long the tracer is attached, in nanoseconds
+    pub duration_ns: i64,
+}
+
+/// Monitor of the ptrace attachments
+///
+/// The long-lived attachments can only be detected across several checks, the monitor
+/// keeps the attachments to a critical daemon between two calls to [`PtraceMonitor::check`].
+pub struct PtraceMonitor {
+    threshold_ns: i64,
+    attachments: KVec<Attachment>,
+}
+
+/// Result of a check
+pub struct PtraceReport {
+    /// The long-lived attachments to a critical daemon
+    pub attachments: KVec<LongAttachment>,
+}
+
+impl PtraceMonitor {
+    /// Create a monitor reporting the tracers attached to a critical daemon for more than
+    /// `threshold_ns` nanoseconds
+    pub fn new(threshold_ns: i64) -> Self {
+        PtraceMonitor {
+            threshold_ns,
+            attachments: KVec::new(),
+        }
+    }
+
+    /// Check the ptrace attachments
+    pub fn check(&mut self) -> Result<PtraceReport> {
+        let now = Ktime::ktime_get().to_ns();
+        let mut attachments = KVec::new();
+        let mut report = PtraceReport {
+            attachments: KVec::new(),
+        };
+
+        for link in ptrace_links()? {
+            // The threads of a daemon may be named differently
+            let tracee_comm = link.tracee.group_leader().comm();
+            if !CRITICAL_DAEMONS.contains(&until_nul(&tracee_comm)) {
+                continue;
+            }
+            let (tracer, tracee) = (link.tracer.pid(), link.tracee.pid());
+            let since = self
+                .attachments
+                .iter()
+                .find(|a| a.tracer == tracer && a.tracee == tracee)
+                .map_or(now, |a| a.since);
+            if now - since > self.threshold_ns {
+                report.attachments.push(
+                    LongAttachment {
+                        tracer,
+                        tracer_comm: link.tracer.comm(),
+                        tracer_cgroup: link.tracer.cgroup_path()?,
+                        tracee,
+                        tracee_comm,
+                        seized: link.is_seized(),
+                        duration_ns: now - since,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+            attachments.push(
+                Attachment {
+                    tracer,
+                    tracee,
+                    since,
+                },
+                GFP_KERNEL,
+            )?;
+        }
+        self.attachments = attachments;
+        Ok(report)
+    }
+}
+
+impl PtraceReport {
+    /// Create the event listing the long-lived attachments, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.attachments.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousPtrace,
+            fmt!("tracers attached to critical daemons : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for PtraceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, attachment) in self.attachments.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} ({}, {}) traces {} ({}) for {}s",
+                BStr::from_bytes(until_nul(&attachment.tracer_comm)),
+                attachment.tracer,
+                Attribution(&attachment.tracer_cgroup),
+                BStr::from_bytes(until_nul(&attachment.tracee_comm)),
+                attachment.tracee,
+                attachment.duration_ns / 1_000_000_000
+            )?;
+            if attachment.seized {
+                f.write_str(" (seized)")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Registers : typed access to a `struct pt_regs`
+//!
+//! The layout of `struct pt_regs` and the calling convention are arch-specific, the
+//! probe handlers use [`Registers`] to get the arguments, return value and pointers
+//! without digging in the raw fields. The accessors are only available on x86_64
+//! and arm64.
+//!
+//! C header: [`arch/x86/include/asm/ptrace.h`](../../../../arch/x86/include/asm/ptrace.h)
+
+use bindings::pt_regs;
+
+/// Maximum number of arguments passed in registers
+#[cfg(target_arch = "x86_64")]
+pub const MAX_REG_ARGS: usize = 6;
+
+/// Maximum number of arguments passed in registers
+#[cfg(target_arch = "aarch64")]
+pub const MAX_REG_ARGS: usize = 8;
+
+/// The registers of an interrupted context (`struct pt_regs`)
+#[derive(Clone, Copy)]
+pub struct Registers<'a> {
+    regs: &'a pt_regs,
+}
+
+impl<'a> Registers<'a> {
+    /// Wrap a `struct pt_regs`
+    pub fn new(regs: &'a pt_regs) -> Self {
+        Registers { regs }
+    }
+
+    /// Get the raw `struct pt_regs`
+    pub fn as_raw(&self) -> &'a pt_regs {
+        self.regs
+    }
+
+    /// Get the `n`-th argument (starting at 0) of the function, following the kernel
+    /// calling convention
+    ///
+    /// Only valid at the entry of the function, and only for the arguments passed in
+    /// registers : `None` is returned for `n >= MAX_REG_ARGS`
+    #[cfg(target_arch = "x86_64")]
+    pub fn arg(&self, n: usize) -> Option<u64> {
+        let regs = self.regs;
+        Some(match n {
+            0 => regs.di,
+            1 => regs.si,
+            2 => regs.dx,
+            3 => regs.cx,
+            4 => regs.r8,
+            5 => regs.r9,
+            _ => return None,
+        } as u64)
+    }
+
+    /// Get the `n`-th argument (starting at 0) of a syscall, from the user registers saved
+    /// at the syscall entry
+    ///
+    /// `None` is returned for `n >= 6`
+    #[cfg(target_arch = "x86_64")]
+    pub fn syscall_arg(&self, n: usize) -> Option<u64> {
+        let regs = self.regs;
+        // The fourth argument is in r10 as rcx is clobbered by `syscall`
+        Some(match n {
+            0 => regs.di,
+            1 => regs.si,
+            2 => regs.dx,
+            3 => regs.r10,
+            4 => regs.r8,
+            5 => regs.r9,
+            _ => return None,
+        } as u64)
+    }
+
+    /// Get the `n`-th argument (starting at 0) of a compat (32 bits) syscall, from the user
+    /// registers saved at the syscall entry
+    ///
+    /// `None` is returned for `n >= 6`
+    #[cfg(target_arch = "x86_64")]
+    pub fn compat_syscall_arg(&self, n: usize) -> Option<u64> {
+        let regs = self.regs;
+        // The ia32 syscall convention
+        Some(match n {
+            0 => regs.bx,
+            1 => regs.cx,
+            2 => regs.dx,
+            3 => regs.si,
+            4 => regs.di,
+            5 => regs.bp,
+            _ => return None,
+        } as u32 as u64)
+    }
+
+    /// Get the return value of the function
+    ///
+    /// Only valid at the exit of the function
+    #[cfg(target_arch = "x86_64")]
+    pub fn return_value(&self) -> u64 {
+        self.regs.ax as u64
+    }
+
+    /// Get the instruction pointer
+    #[cfg(target_arch = "x86_64")]
+    pub fn instruction_pointer(&self) -> u64 {
+        self.regs.ip as u64
+    }
+
+    /// Get the stack pointer
+    #[cfg(target_arch = "x86_64")]
+    pub fn stack_pointer(&self) -> u64 {
+        self.regs.sp as u64
+    }
+
+    /// The registers are the ones of a userspace context
+    #[cfg(target_arch = "x86_64")]
+    pub fn user_mode(&self) -> bool {
+        // The privilege level is in the low bits of the code segment selector
+        self.regs.cs & 3 == 3
+    }
+
+    /// Get the general purpose registers of the user view (`struct user_pt_regs`)
+    #[cfg(target_arch = "aarch64")]
+    fn user_regs(&self) -> &'a bindings::user_pt_regs {
+        // SAFETY: Both members of the union have the same layout, `user_regs` is always
+        // a valid view of the registers
+        unsafe { &self.regs.__bindgen_anon_1.user_regs }
+    }
+
+    /// Get the `n`-th argument (starting at 0) of the function, following the kernel
+    /// calling convention
+    ///
+    /// Only valid at the entry of the function, and only for the arguments passed in
+    /// registers : `None` is returned for `n >= MAX_REG_ARGS`
+    #[cfg(target_arch = "aarch64")]
+    pub fn arg(&self, n: usize) -> Option<u64> {
+        if n >= MAX_REG_ARGS {
+            return None;
+        }
+        Some(self.user_regs().regs[n])
+    }
+
+    /// Get the `n`-th argument (starting at 0) of a syscall, from the user registers saved
+    /// at the syscall entry
+    ///
+    /// `None` is returned for `n >= 6`
+    #[cfg(target_arch = "aarch64")]
+    pub fn syscall_arg(&self, n: usize) -> Option<u64> {
+        match n {
+            // x0 is overwritten by the return value, the first argument is kept in orig_x0
+            0 => Some(self.regs.orig_x0),
+            1..6 => Some(self.user_regs().regs[n]),
+            _ => None,
+        }
+    }
+
+    /// Get the `n`-th argument (starting at 0) of a compat (32 bits) syscall, from the user
+    /// registers saved at the syscall entry
+    ///
+    /// `None` is returned for `n >= 6`
+    #[cfg(target_arch = "aarch64")]
+    pub fn compat_syscall_arg(&self, n: usize) -> Option<u64> {
+        // The AArch32 arguments are in the low halves of the same registers
+        self.syscall_arg(n).map(|arg| arg as u32 as u64)
+    }
+
+    /// Get the return value of the function
+    ///
+    /// Only valid at the exit of the function
+    #[cfg(target_arch = "aarch64")]
+    pub fn return_value(&self) -> u64 {
+        self.user_regs().regs[0]
+    }
+
+    /// Get the instruction pointer
+    #[cfg(target_arch = "aarch64")]
+    pub fn instruction_pointer(&self) -> u64 {
+        self.user_regs().pc
+    }
+
+    /// Get the stack pointer
+    #[cfg(target_arch = "aarch64")]
+    pub fn stack_pointer(&self) -> u64 {
+        self.user_regs().sp
+    }
+
+    /// The registers are the ones of a userspace context
+    #[cfg(target_arch = "aarch64")]
+    pub fn user_mode(&self) -> bool {
+        self.user_regs().pstate & bindings::PSR_MODE_MASK as u64 == bindings::PSR_MODE_EL0t as u64
+    }
+
+    /// The registers are the ones of a kernel context
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn kernel_mode(&self) -> bool {
+        !self.user_mode()
+    }
+}
@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Sampling : rate limiting of the checks on hot paths
+//!
+//! Checking every call of an extremely hot syscall (`read`, `ioctl`) is too expensive,
+//! a [`Sampler`] placed at the start of the probe handler decides which calls are checked.
+//! Two modes are available : one call in N, or a per-task token bucket which bound the
+//! number of checks per second of each task. The mode can be changed at runtime.
+//!
+//! The counters of the sampler are attached to the events ([`SamplingInfo`]) so the real
+//! rate of a detection can be reconstructed from the sampled one.
+//!
+//! All the state is kept in atomics, the sampler never allocate or lock so it can be
+//! used from any probe handler. The updates are not synchronized between them, a few
+//! extra or missing samples are possible when the mode is changed or when two tasks
+//! share a bucket.
+
+use core::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, AtomicU64, Ordering};
+
+use crate::time::Ktime;
+use kernel::prelude::*;
+
+/// Number of per-task buckets of a [`Sampler`], the tasks are hashed by pid
+pub const TASK_BUCKETS: usize = 64;
+
+const NSEC_PER_SEC: i64 = 1_000_000_000;
+
+/// Maximum burst of [`SamplingMode::TokenBucket`], it is packed with the mode and the rate
+pub const MAX_BURST: u32 = (1 << 30) - 1;
+
+/// Sampling policy of a [`Sampler`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SamplingMode {
+    /// Every call is checked
+    All,
+    /// One call in N is checked
+    OneIn(u32),
+    /// Each task can be checked `rate` times per second, with bursts up to `burst` checks
+    TokenBucket {
+        /// Number of tokens refilled per second
+        rate: u32,
+        /// Maximum number of tokens of a task
+        burst: u32,
+    },
+}
+
+impl SamplingMode {
+    const ALL: u32 = 0;
+    const ONE_IN: u32 = 1;
+    const TOKEN_BUCKET: u32 = 2;
+
+    /// Pack the mode, its parameter and its burst in a single word: the mode on the 2 top
+    /// bits, the burst on the next 30 and the parameter on the 32 low bits
+    fn pack(mode: u32, param: u32, burst: u32) -> u64 {
+        ((mode as u64) << 62) | (((burst & MAX_BURST) as u64) << 32) | param as u64
+    }
+
+    fn unpack(config: u64) -> Self {
+        let (mode, burst, param) = (
+            (config >> 62) as u32,
+            (config >> 32) as u32 & MAX_BURST,
+            config as u32,
+        );
+        match mode {
+            // A null divisor can't be set, but sample everything rather than divide by it
+            Self::ONE_IN if param != 0 => SamplingMode::OneIn(param),
+            Self::TOKEN_BUCKET if param != 0 => SamplingMode::TokenBucket { rate: param, burst },
+            _ => SamplingMode::All,
+        }
+    }
+}
+
+/// The sampling metadata attached to an event
+#[derive(Clone, Copy, Debug)]
+pub struct SamplingInfo {
+    /// The mode of the sampler when the event was raised
+    pub mode: SamplingMode,
+    /// Number of calls seen by the sampler since its creation or the last mode change
+    pub seen: u64,
+    /// Number of calls checked among them
+    pub sampled: u64,
+}
+
+/// Token bucket of the tasks with a given pid hash
+struct TaskBucket {
+    pid: AtomicI32,
+    tokens: AtomicU32,
+    last_refill: AtomicI64,
+}
+
+impl TaskBucket {
+    const fn new() -> Self {
+        TaskBucket {
+            pid: AtomicI32::new(0),
+            tokens: AtomicU32::new(0),
+            last_refill: AtomicI64::new(0),
+        }
+    }
+}
+
+/// Decide which calls of a probe are checked
+pub struct Sampler {
+    /// The mode, N for `OneIn` or the rate for `TokenBucket`, and the burst, packed to be
+    /// read at once by [`SamplingMode::unpack`]
+    config: AtomicU64,
+    seen: AtomicU64,
+    sampled: AtomicU64,
+    buckets: [TaskBucket; TASK_BUCKETS],
+}
+
+impl Sampler {
+    /// Create a sampler with the given mode
+    pub fn new(mode: SamplingMode) -> Self {
+        let sampler = Sampler {
+            config: AtomicU64::new(SamplingMode::pack(SamplingMode::ALL, 0, 0)),
+            seen: AtomicU64::new(0),
+            sampled: AtomicU64::new(0),
+            buckets: [const { TaskBucket::new() }; TASK_BUCKETS],
+        };
+        if sampler.set_mode(mode).is_err() {
+            pr_warn!("Invalid sampling mode {:?}, sampling everything\n", mode);
+        }
+        sampler
+    }
+
+    /// Change the mode of the sampler, the counters are reset
+    ///
+    /// Fail with `EINVAL` for a null N, rate or burst, or a burst above [`MAX_BURST`]
+    pub fn set_mode(&self, mode: SamplingMode) -> Result {
+        let (raw, param, burst) = match mode {
+            SamplingMode::All => (SamplingMode::ALL, 0, 0),
+            SamplingMode::OneIn(0) => return Err(EINVAL),
+            SamplingMode::OneIn(n) => (SamplingMode::ONE_IN, n, 0),
+            SamplingMode::TokenBucket { rate, burst }
+                if rate == 0 || burst == 0 || burst > MAX_BURST =>
+            {
+                return Err(EINVAL)
+            }
+            SamplingMode::TokenBucket { rate, burst } => (SamplingMode::TOKEN_BUCKET, rate, burst),
+        };
+
+        for bucket in self.buckets.iter() {
+            bucket.pid.store(0, Ordering::Relaxed);
+        }
+        self.seen.store(0, Ordering::Relaxed);
+        self.sampled.store(0, Ordering::Relaxed);
+        self.config
+            .store(SamplingMode::pack(raw, param, burst), Ordering::Release);
+        Ok(())
+    }
+
+    /// Get the current mode of the sampler
+    pub fn mode(&self) -> SamplingMode {
+        SamplingMode::unpack(self.config.load(Ordering::Acquire))
+    }
+
+    /// Decide if the current call must be checked
+    pub fn sample(&self) -> bool {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed);
+
+        let take = match self.mode() {
+            SamplingMode::All => true,
+            SamplingMode::OneIn(n) => seen % n as u64 == 0,
+            SamplingMode::TokenBucket { rate, burst } => self.take_token(rate, burst),
+        };
+
+        if take {
+            self.sampled.fetch_add(1, Ordering::Relaxed);
+        }
+        take
+    }
+
+    /// Take a token in the bucket of the current task
+    fn take_token(&self, rate: u32, burst: u32) -> bool {
+        if rate == 0 {
+            return true;
+        }
+        let pid = crate::current!().pid();
+        let bucket = &self.buckets[pid as usize % TASK_BUCKETS];
+        let now = Ktime::ktime_get().to_ns();
+
+        if bucket.pid.swap(pid, Ordering::Relaxed) != pid {
+            // New task in this bucket, start full
+            bucket.tokens.store(burst, Ordering::Relaxed);
+            bucket.last_refill.store(now, Ordering::Relaxed);
+        } else {
+            let elapsed = now - bucket.last_refill.load(Ordering::Relaxed);
+            let refill = elapsed.saturating_mul(rate as i64) / NSEC_PER_SEC;
+            if refill > 0 {
+                let tokens = bucket.tokens.load(Ordering::Relaxed) as i64 + refill;
+                if tokens >= burst as i64 {
+                    bucket.tokens.store(burst, Ordering::Relaxed);
+                    bucket.last_refill.store(now, Ordering::Relaxed);
+                } else {
+                    // Only advance by the time corresponding to the refilled tokens so the
+                    // fractions of token are not lost
+                    bucket.tokens.store(tokens as u32, Ordering::Relaxed);
+                    bucket
+                        .last_refill
+                        .fetch_add(refill * NSEC_PER_SEC / rate as i64, Ordering::Relaxed);
+                }
+            }
+        }
+
+        bucket
+            .tokens
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |tokens| {
+                tokens.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Get the sampling metadata to attach to an event
+    pub fn info(&self) -> SamplingInfo {
+        SamplingInfo {
+            mode: self.mode(),
+            seen: self.seen.load(Ordering::Relaxed),
+            sampled: self.sampled.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,366 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Scoring : final severity of the events
+//!
+//! The checks only report raw findings, the severity of the resulting [`Event`] is
+//! computed here from :
+//! - the base severity of the kind of event
+//! - the security posture of the kernel : a finding is adjusted differently if the
+//!   kernel is hardened (lockdown active, module signature enforced) or not. The posture
+//!   is read when a policy is loaded (or at the first score), not for each event
+//! - the proximity of the finding with an allowlisted object
+//! - the number of other findings correlated with this one
+//!
+//! The rules are data : the default ones can be replaced at runtime by a policy
+//! supplied by userspace.
+//!
+//! # Policy format
+//!
+//! All the integers are native endian :
+//! - [`PolicyHeader`] : magic, version, the global adjustments, rule count
+//! - `count` times [`PolicyRule`] : event kind, its base severity and posture adjustments
+//!
+//! [`Event`]: crate::event::Event
+
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+
+use crate::event::{EventKind, Severity, EVENT_KIND_COUNT};
+use crate::transmute::FromBytes;
+use crate::uaccess::UserSliceReader;
+use kernel::prelude::*;
+
+/// Magic number at the start of the policy (`"RKSP"`)
+pub const POLICY_MAGIC: u32 = 0x5053_4b52;
+
+/// Current version of the policy format
+pub const POLICY_VERSION: u32 = 1;
+
+/// Maximum size of a policy, to bound the allocation when reading it from userspace
+pub const POLICY_MAX_SIZE: usize = 4096;
+
+/// Default adjustment when the finding is near an allowlisted object
+const DEFAULT_ALLOWLIST_ADJUSTMENT: i32 = -1;
+
+/// Default adjustment per correlated finding
+const DEFAULT_CORRELATION_ADJUSTMENT: i32 = 1;
+
+/// Maximum adjustment due to the correlated findings
+const MAX_CORRELATION_ADJUSTMENT: i32 = 2;
+
+/// Maximum adjustment of the policy, enough to go from any severity to any other
+const MAX_ADJUSTMENT: i32 = Severity::Critical as i32 - Severity::Info as i32;
+
+/// [`HARDENED`] : the posture wasn't read yet
+const POSTURE_UNKNOWN: u32 = 0;
+
+/// [`HARDENED`] : the kernel is not hardened
+const POSTURE_WEAK: u32 = 1;
+
+/// [`HARDENED`] : the kernel is hardened
+const POSTURE_HARDENED: u32 = 2;
+
+/// Header of the policy
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PolicyHeader {
+    /// Must be [`POLICY_MAGIC`]
+    pub magic: u32,
+    /// Must be [`POLICY_VERSION`]
+    pub version: u32,
+    /// Adjustment when the finding is near an allowlisted object
+    pub allowlist_adjustment: i32,
+    /// Adjustment per correlated finding (bounded)
+    pub correlation_adjustment: i32,
+    /// The number of [`PolicyRule`] following the header
+    pub count: u32,
+}
+
+// SAFETY: `PolicyHeader` only contains integers, every bit pattern is valid
+unsafe impl FromBytes for PolicyHeader {}
+
+/// The scoring rule of a kind of event
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PolicyRule {
+    /// The [`EventKind`] identifier
+    pub kind: u32,
+    /// The base [`Severity`]
+    pub base: u32,
+    /// Adjustment when the kernel is not hardened
+    pub weak_posture_adjustment: i32,
+    /// Adjustment when the kernel is hardened
+    pub hardened_posture_adjustment: i32,
+}
+
+// SAFETY: `PolicyRule` only contains integers, every bit pattern is valid
+unsafe impl FromBytes for PolicyRule {}
+
+/// The security posture of the running kernel
+#[derive(Clone, Copy, Debug)]
+pub struct Posture {
+    /// The kernel is locked down (integrity or confidentiality)
+    pub lockdown: bool,
+    /// Only modules with a valid signature can be loaded
+    pub sig_enforced: bool,
+}
+
+impl Posture {
+    /// Get the posture of the running kernel
+    pub fn current() -> Self {
+        #[cfg(CONFIG_SECURITY_LOCKDOWN_LSM)]
+        // SAFETY: Just an FFI call
+        let lockdown = unsafe {
+            bindings::security_locked_down(bindings::lockdown_reason_LOCKDOWN_MODULE_SIGNATURE)
+        } != 0;
+        #[cfg(not(CONFIG_SECURITY_LOCKDOWN_LSM))]
+        let lockdown = false;
+
+        #[cfg(CONFIG_MODULE_SIG)]
+        // SAFETY: Just an FFI call
+        let sig_enforced = unsafe { bindings::is_module_sig_enforced() };
+        #[cfg(not(CONFIG_MODULE_SIG))]
+        let sig_enforced = false;
+
+        Posture {
+            lockdown,
+            sig_enforced,
+        }
+    }
+
+    /// Lockdown is active or unsigned modules are refused
+    pub fn is_hardened(&self) -> bool {
+        self.lockdown || self.sig_enforced
+    }
+}
+
+/// The context of a finding, given by the check
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Signals {
+    /// The finding is close to an allowlisted object (same owner, same symbol, ...)
+    pub near_allowlist: bool,
+    /// Number of other findings correlated with this one
+    pub correlated: u32,
+}
+
+/// Pack a rule in an atomic slot : base, weak and hardened adjustments on a byte each
+const fn pack(base: Severity, weak: i8, hardened: i8) -> u32 {
+    base as u32 | ((weak as u8 as u32) << 8) | ((hardened as u8 as u32) << 16)
+}
+
+fn unpack(rule: u32) -> (u32, i32, i32) {
+    (
+        rule & 0xff,
+        (rule >> 8) as u8 as i8 as i32,
+        (rule >> 16) as u8 as i8 as i32,
+    )
+}
+
+/// The default rules, indexed by [`EventKind`]
+const DEFAULT_RULES: [u32; EVENT_KIND_COUNT] = [
+    // ModuleViewMismatch
+    pack(Severity::High, 0, 0),
+    // ModuleTextReuse
+    pack(Severity::Medium, 0, 0),
+    // UnsignedModule : should be impossible with the signature enforced
+    pack(Severity::Medium, 0, 1),
+    // HiddenModuleParam
+    pack(Severity::High, 0, 0),
+    // AnomalousModuleState
+    pack(Severity::High, 0, 0),
+    // SuspiciousImport
+    pack(Severity::Medium, 0, 0),
+    // ModuleTampering
+    pack(Severity::High, 0, 0),
+    // SuspiciousFtraceOps
+    pack(Severity::High, 0, 0),
+    // SuspiciousKprobe
+    pack(Severity::High, 0, 0),
+    // SuspiciousLivepatch
+    pack(Severity::High, 0, 0),
+    // SuspiciousBpfProgram
+    pack(Severity::High, 0, 0),
+    // MonitoredSyscall : not a detection by itself, correlated with the other findings
+    pack(Severity::Low, 0, 0),
+    // ProbeTampering
+    pack(Severity::High, 0, 0),
+    // SuspiciousDirectCall
+    pack(Severity::High, 0, 0),
+    // ProbeMissed : a blind spot of the probes, not a detection by itself
+    pack(Severity::Medium, 0, 0),
+    // WritableExecutableMapping
+    pack(Severity::High, 0, 0),
+    // TextAliasTampering
+    pack(Severity::High, 0, 0),
+    // TextMappingSplit : the protections may have been restored since
+    pack(Severity::Medium, 0, 0),
+    // PageTableIsolationMismatch
+    pack(Severity::High, 0, 0),
+    // InjectedTrap
+    pack(Severity::High, 0, 0),
+    // InlineHook
+    pack(Severity::High, 0, 0),
+    // CredentialAnomaly
+    pack(Severity::High, 0, 0),
+    // SuspiciousUserMapping : the JIT compilers map anonymous memory writable and executable
+    pack(Severity::Medium, 0, 0),
+    // FakeKernelThread
+    pack(Severity::High, 0, 0),
+    // NamespaceAnomaly : a user can isolate a program with `unshare`
+    pack(Severity::Medium, 0, 0),
+    // SuspiciousPtrace : an administrator may be debugging the daemon
+    pack(Severity::Medium, 0, 0),
+    // ConfinementLoss
+    pack(Severity::High, 0, 0),
+    // ProcessLifecycle : not a detection by itself, correlated with the other findings
+    pack(Severity::Info, 0, 0),
+    // CommMismatch : a program may be run from a temporary directory under another name
+    pack(Severity::Medium, 0, 0),
+    // UnknownCaller
+    pack(Severity::High, 0, 0),
+    // UnwindTampering
+    pack(Severity::High, 0, 0),
+    // SuspiciousStackReference : the orphan memory may be a BPF image or a trampoline
+    pack(Severity::Medium, 0, 0),
+];
+
+/// The current rules, indexed by [`EventKind`]
+static RULES: [AtomicU32; EVENT_KIND_COUNT] = {
+    let mut rules = [const { AtomicU32::new(0) }; EVENT_KIND_COUNT];
+    let mut i = 0;
+    while i < EVENT_KIND_COUNT {
+        rules[i] = AtomicU32::new(DEFAULT_RULES[i]);
+        i += 1;
+    }
+    rules
+};
+
+static ALLOWLIST_ADJUSTMENT: AtomicI32 = AtomicI32::new(DEFAULT_ALLOWLIST_ADJUSTMENT);
+static CORRELATION_ADJUSTMENT: AtomicI32 = AtomicI32::new(DEFAULT_CORRELATION_ADJUSTMENT);
+
+/// The posture of the kernel, read when a policy is loaded or at the first score
+static HARDENED: AtomicU32 = AtomicU32::new(POSTURE_UNKNOWN);
+
+/// Read the posture of the kernel again
+fn refresh_posture() -> bool {
+    let hardened = Posture::current().is_hardened();
+    let posture = if hardened {
+        POSTURE_HARDENED
+    } else {
+        POSTURE_WEAK
+    };
+    HARDENED.store(posture, Ordering::Relaxed);
+    hardened
+}
+
+/// The kernel is hardened, as read when the policy was loaded
+fn is_hardened() -> bool {
+    match HARDENED.load(Ordering::Relaxed) {
+        POSTURE_UNKNOWN => refresh_posture(),
+        posture => posture == POSTURE_HARDENED,
+    }
+}
+
+/// Check that a global adjustment of the policy is in range
+fn check_adjustment(adjustment: i32) -> Result<i32> {
+    if (-MAX_ADJUSTMENT..=MAX_ADJUSTMENT).contains(&adjustment) {
+        Ok(adjustment)
+    } else {
+        Err(EINVAL)
+    }
+}
+
+/// Compute the severity of a finding
+pub fn score(kind: EventKind, signals: &Signals) -> Severity {
+    let (base, weak, hardened) = unpack(RULES[kind as usize].load(Ordering::Relaxed));
+
+    let mut severity = base as i32;
+    severity = severity.saturating_add(if is_hardened() { hardened } else { weak });
+    if signals.near_allowlist {
+        severity = severity.saturating_add(ALLOWLIST_ADJUSTMENT.load(Ordering::Relaxed));
+    }
+    severity = severity.saturating_add(
+        (signals.correlated as i32)
+            .saturating_mul(CORRELATION_ADJUSTMENT.load(Ordering::Relaxed))
+            .clamp(-MAX_CORRELATION_ADJUSTMENT, MAX_CORRELATION_ADJUSTMENT),
+    );
+
+    Severity::from_raw(severity.clamp(Severity::Info as i32, Severity::Critical as i32) as u32)
+        .unwrap_or(Severity::Critical)
+}
+
+/// Load a policy from a buffer
+///
+/// The kinds not in the policy keep their current rule. The whole policy is validated
+/// before being applied, so on error the previous rules are kept.
+pub fn load(data: &[u8]) -> Result {
+    let header_size = core::mem::size_of::<PolicyHeader>();
+    let header_bytes = data.get(..header_size).ok_or(EINVAL)?;
+    // SAFETY: The slice has the size of `PolicyHeader` and every bit pattern is valid,
+    // `read_unaligned` doesn't require alignment
+    let header = unsafe {
+        header_bytes
+            .as_ptr()
+            .cast::<PolicyHeader>()
+            .read_unaligned()
+    };
+
+    if header.magic != POLICY_MAGIC || header.version != POLICY_VERSION {
+        pr_err!("Invalid scoring policy header\n");
+        return Err(EINVAL);
+    }
+    let allowlist_adjustment = check_adjustment(header.allowlist_adjustment)?;
+    let correlation_adjustment = check_adjustment(header.correlation_adjustment)?;
+
+    let rule_size = core::mem::size_of::<PolicyRule>();
+    let raw_rules = data.get(header_size..).ok_or(EINVAL)?;
+    if raw_rules.len() < header.count as usize * rule_size {
+        return Err(EINVAL);
+    }
+
+    let mut rules = [None; EVENT_KIND_COUNT];
+    for raw in raw_rules
+        .chunks_exact(rule_size)
+        .take(header.count as usize)
+    {
+        // SAFETY: The chunk has the size of `PolicyRule` and every bit pattern is valid
+        let rule = unsafe { raw.as_ptr().cast::<PolicyRule>().read_unaligned() };
+        let kind = EventKind::from_id(rule.kind).ok_or(EINVAL)?;
+        let base = Severity::from_raw(rule.base).ok_or(EINVAL)?;
+        let weak = check_adjustment(rule.weak_posture_adjustment)? as i8;
+        let hardened = check_adjustment(rule.hardened_posture_adjustment)? as i8;
+        rules[kind as usize] = Some(pack(base, weak, hardened));
+    }
+
+    for (slot, rule) in RULES.iter().zip(rules) {
+        if let Some(rule) = rule {
+            slot.store(rule, Ordering::Relaxed);
+        }
+    }
+    ALLOWLIST_ADJUSTMENT.store(allowlist_adjustment, Ordering::Relaxed);
+    CORRELATION_ADJUSTMENT.store(correlation_adjustment, Ordering::Relaxed);
+    refresh_posture();
+
+    Ok(())
+}
+
+/// Load a policy from a userspace buffer
+pub fn load_from_user(reader: UserSliceReader) -> Result {
+    if reader.len() > POLICY_MAX_SIZE {
+        return Err(E2BIG);
+    }
+
+    let mut buf = KVec::new();
+    reader.read_all(&mut buf, GFP_KERNEL)?;
+
+    load(&buf)
+}
+
+/// Go back to the default policy
+pub fn reset() {
+    for (slot, rule) in RULES.iter().zip(DEFAULT_RULES) {
+        slot.store(rule, Ordering::Relaxed);
+    }
+    ALLOWLIST_ADJUSTMENT.store(DEFAULT_ALLOWLIST_ADJUSTMENT, Ordering::Relaxed);
+    CORRELATION_ADJUSTMENT.store(DEFAULT_CORRELATION_ADJUSTMENT, Ordering::Relaxed);
+    refresh_posture();
+}
@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Sensor set : probes on the security-critical functions, managed as a unit
+//!
+//! The functions a rootkit almost always calls (to get root credentials, resolve
+//! unexported symbols, make the kernel text writable, ...) are declared in [`SENSORS`].
+//! A [`SensorSet`] registers an entry-only [`Fprobe`] on each of them, and unregisters
+//! them all when dropped. A function missing from the running kernel (or not traceable)
+//! is skipped with a warning, the other sensors are still registered.
+//!
+//! Each hit records the stacktrace of the call and the innermost return address in unknown
+//! executable memory (see [`provenance`](crate::provenance)), the callers found this way are
+//! taken as events by [`SensorSet::unknown_callers`].
+//!
+//! Each sensor can be toggled at runtime, from the userspace control interface
+//! ([`IOCTL_SENSOR_TOGGLE`](crate::control::IOCTL_SENSOR_TOGGLE)). The toggles and the
+//! counters are global, like the scoring rules, so they are kept across the
+//! registrations of the set.
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::c_str;
+use crate::event::Event;
+use crate::fprobe::{Fprobe, FprobeOperations};
+use crate::provenance::{first_unknown, UnknownCaller};
+use crate::registers::Registers;
+use crate::stacktrace::ProbeStacktrace;
+use crate::transmute::FromBytes;
+use kernel::prelude::*;
+
+/// Identifier of a sensor, the discriminant is the one used by the control interface
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum SensorId {
+    /// `commit_creds`
+    CommitCreds = 0,
+    /// `prepare_kernel_cred`
+    PrepareKernelCred = 1,
+    /// `kallsyms_lookup_name`
+    KallsymsLookupName = 2,
+    /// `set_memory_rw`
+    SetMemoryRw = 3,
+    /// `register_kprobe`
+    RegisterKprobe = 4,
+    /// `do_init_module`
+    DoInitModule = 5,
+}
+
+/// Number of [`SensorId`]
+pub const SENSOR_COUNT: usize = 6;
+
+impl SensorId {
+    /// Get the sensor corresponding to an identifier
+    pub fn from_id(id: u32) -> Option<Self> {
+        Some(match id {
+            0 => SensorId::CommitCreds,
+            1 => SensorId::PrepareKernelCred,
+            2 => SensorId::KallsymsLookupName,
+            3 => SensorId::SetMemoryRw,
+            4 => SensorId::RegisterKprobe,
+            5 => SensorId::DoInitModule,
+            _ => return None,
+        })
+    }
+}
+
+/// The configuration of a sensor
+pub struct SensorConfig {
+    /// The sensor
+    pub id: SensorId,
+    /// The probed function
+    pub symbol: &'static CStr,
+    /// The sensor is enabled when the module is loaded
+    pub enabled: bool,
+}
+
+/// The sensors, indexed by [`SensorId`]
+pub const SENSORS: [SensorConfig; SENSOR_COUNT] = [
+    SensorConfig {
+        id: SensorId::CommitCreds,
+        symbol: c_str!("commit_creds"),
+        enabled: true,
+    },
+    SensorConfig {
+        id: SensorId::PrepareKernelCred,
+        symbol: c_str!("prepare_kernel_cred"),
+        enabled: true,
+    },
+    SensorConfig {
+        id: SensorId::KallsymsLookupName,
+        symbol: c_str!("kallsyms_lookup_name"),
+        enabled: true,
+    },
+    SensorConfig {
+        id: SensorId::SetMemoryRw,
+        symbol: c_str!("set_memory_rw"),
+        enabled: true,
+    },
+    SensorConfig {
+        id: SensorId::RegisterKprobe,
+        symbol: c_str!("register_kprobe"),
+        enabled: true,
+    },
+    // Hit at each module load, only useful to correlate with the other sensors
+    SensorConfig {
+        id: SensorId::DoInitModule,
+        symbol: c_str!("do_init_module"),
+        enabled: false,
+    },
+];
+
+/// The enabled sensors, a bit per [`SensorId`]
+static ENABLED: AtomicU32 = {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < SENSOR_COUNT {
+        if SENSORS[i].enabled {
+            mask |= 1 << i;
+        }
+        i += 1;
+    }
+    AtomicU32::new(mask)
+};
+
+/// Number of hits of each sensor while enabled
+static HITS: [AtomicU64; SENSOR_COUNT] = [const { AtomicU64::new(0) }; SENSOR_COUNT];
+
+/// Return address of the last hit of each sensor
+static LAST_CALLER: [AtomicU64; SENSOR_COUNT] = [const { AtomicU64::new(0) }; SENSOR_COUNT];
+
+/// Number of frames of the stacktraces checked at each hit
+const PROVENANCE_DEPTH: usize = 16;
+
+/// Last return address in unknown memory seen by each sensor and not taken yet, 0 if none
+static UNKNOWN_CALLER: [AtomicU64; SENSOR_COUNT] = [const { AtomicU64::new(0) }; SENSOR_COUNT];
+
+/// Depth of the frame of [`UNKNOWN_CALLER`], 0 for the probed function itself
+static UNKNOWN_DEPTH: [AtomicU64; SENSOR_COUNT] = [const { AtomicU64::new(0) }; SENSOR_COUNT];
+
+/// Argument of [`IOCTL_SENSOR_TOGGLE`](crate::control::IOCTL_SENSOR_TOGGLE)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SensorToggle {
+    /// The [`SensorId`]
+    pub id: u32,
+    /// Non-zero to enable the sensor, zero to disable it
+    pub enabled: u32,
+}
+
+// SAFETY: `SensorToggle` only contains integers, every bit pattern is valid
+unsafe impl FromBytes for SensorToggle {}
+
+/// Enable or disable a sensor
+pub fn set_enabled(id: SensorId, enabled: bool) {
+    let bit = 1 << id as u32;
+    if enabled {
+        ENABLED.fetch_or(bit, Ordering::Relaxed);
+    } else {
+        ENABLED.fetch_and(!bit, Ordering::Relaxed);
+    }
+}
+
+/// The sensor is enabled
+pub fn is_enabled(id: SensorId) -> bool {
+    ENABLED.load(Ordering::Relaxed) & (1 << id as u32) != 0
+}
+
+/// Apply a toggle received from userspace
+pub fn toggle(toggle: &SensorToggle) -> Result {
+    let id = SensorId::from_id(toggle.id).ok_or(EINVAL)?;
+    set_enabled(id, toggle.enabled != 0);
+    Ok(())
+}
+
+/// The state of a sensor
+#[derive(Clone, Copy, Debug)]
+pub struct SensorStats {
+    /// The sensor
+    pub id: SensorId,
+    /// The probe of the sensor is registered
+    pub registered: bool,
+    /// The sensor is enabled
+    pub enabled: bool,
+    /// Number of hits while enabled
+    pub hits: u64,
+    /// Return address of the last hit, 0 if never hit
+    pub last_caller: u64,
+}
+
+/// The entry handler of the sensors
+pub struct SensorProbe;
+
+impl FprobeOperations for SensorProbe {
+    type Data = KBox<SensorId>;
+    type EntryData = ();
+
+    const HAS_EXIT_HANDLER: bool = false;
+
+    const STACKTRACE_DEPTH: usize = PROVENANCE_DEPTH;
+
+    fn entry_handler(
+        id: &SensorId,
+        _entry_ip: usize,
+        ret_ip: usize,
+        _regs: Registers<'_>,
+        stack: Option<ProbeStacktrace<'_>>,
+        _entry_data: Option<&mut ()>,
+    ) -> Option<()> {
+        if is_enabled(*id) {
+            HITS[*id as usize].fetch_add(1, Ordering::Relaxed);
+            LAST_CALLER[*id as usize].store(ret_ip as u64, Ordering::Relaxed);
+
+            let frames = stack.map_or(&[][..], |stack| stack.frames());
+            if let Some((depth, caller)) = first_unknown(frames) {
+                UNKNOWN_DEPTH[*id as usize].store(depth as u64, Ordering::Relaxed);
+                UNKNOWN_CALLER[*id as usize].store(caller, Ordering::Relaxed);
+            }
+        }
+        Some(())
+    }
+}
+
+/// The registered sensors
+pub struct SensorSet {
+    probes: KVec<(SensorId, Pin<KBox<Fprobe<SensorProbe>>>)>,
+}
+
+impl SensorSet {
+    /// Register a probe on each function of [`SENSORS`]
+    pub fn new() -> Result<Self> {
+        let mut probes = KVec::with_capacity(SENSOR_COUNT, GFP_KERNEL)?;
+        for sensor in SENSORS.iter() {
+            let data = KBox::new(sensor.id, GFP_KERNEL)?;
+            match KBox::pin_init(Fprobe::new(sensor.symbol, None, data), GFP_KERNEL) {
+                Ok(probe) => probes.push((sensor.id, probe), GFP_KERNEL)?,
+                Err(e) if e == ENOMEM => return Err(e),
+                Err(_) => pr_warn!("Couldn't probe {}, sensor skipped\n", sensor.symbol),
+            }
+        }
+        Ok(SensorSet { probes })
+    }
+
+    /// The probe of the sensor is registered
+    pub fn is_registered(&self, id: SensorId) -> bool {
+        self.probes.iter().any(|(probe_id, _)| *probe_id == id)
+    }
+
+    /// Get the state of each sensor
+    pub fn stats(&self) -> [SensorStats; SENSOR_COUNT] {
+        SENSORS.each_ref().map(|sensor| SensorStats {
+            id: sensor.id,
+            registered: self.is_registered(sensor.id),
+            enabled: is_enabled(sensor.id),
+            hits: HITS[sensor.id as usize].load(Ordering::Relaxed),
+            last_caller: LAST_CALLER[sensor.id as usize].load(Ordering::Relaxed),
+        })
+    }
+
+    /// Take the callers in unknown memory seen by the sensors since the last call and
+    /// create their events
+    pub fn unknown_callers(&self) -> Result<KVec<Event>> {
+        let mut events = KVec::new();
+        for sensor in SENSORS.iter() {
+            let caller = UNKNOWN_CALLER[sensor.id as usize].swap(0, Ordering::Relaxed);
+            if caller == 0 {
+                continue;
+            }
+            let depth = UNKNOWN_DEPTH[sensor.id as usize].load(Ordering::Relaxed) as usize;
+            let caller = UnknownCaller::new(sensor.symbol, depth, caller)?;
+            events.push(caller.to_event()?, GFP_KERNEL)?;
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,287 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Stack scan : the sleeping tasks referencing suspicious memory from their kernel stack
+//!
+//! A rootkit thread (a kernel thread started by a hidden module, a hijacked worker) sleeps
+//! most of the time, with the return addresses into its code and the pointers to its data
+//! left on its kernel stack. The kernel stack of every sleeping task is read with the
+//! [`nofault`](crate::nofault) reader, from its saved stack pointer to its top, and every
+//! word falling in one of the configured [`SuspiciousRange`] is reported with the task and
+//! its offset in the stack. The ranges usually come from the
+//! [`hidden_module`](crate::hidden_module) scanner: the `struct module` candidates and the
+//! orphan executable memory.
+//!
+//! The stack is pinned while read. A task woken up during the scan overwrites its stack, a
+//! reference may be missed or stale, the read never faults.
+//!
+//! C header: [`include/linux/sched/task_stack.h`](../../../../include/linux/sched/task_stack.h)
+
+use core::fmt;
+use core::mem::size_of;
+use core::ptr;
+
+use crate::event::{Event, EventKind};
+use crate::hidden_module::HiddenModuleFinding;
+use crate::nofault;
+use crate::page::PAGE_SIZE;
+use crate::str::{until_nul, BStr};
+use crate::task::{Task, TASK_COMM_LEN, TASK_NORMAL};
+use crate::task_iter::AllThreadsIter;
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Size of a kernel stack
+const THREAD_SIZE: usize = bindings::THREAD_SIZE as usize;
+
+/// Maximum number of references reported
+const MAX_REFERENCES: usize = 256;
+
+/// What a suspicious range holds
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RangeKind {
+    /// Something looking like the `struct module` of a hidden module
+    HiddenModule,
+    /// Executable memory not owned by any listed module
+    OrphanExec,
+}
+
+/// A range of kernel memory no task should reference
+#[derive(Clone, Copy, Debug)]
+pub struct SuspiciousRange {
+    /// Start of the range
+    pub start: u64,
+    /// End of the range (excluded)
+    pub end: u64,
+    /// What the range holds
+    pub kind: RangeKind,
+}
+
+impl SuspiciousRange {
+    /// Get the ranges of the findings of the [`hidden_module`](crate::hidden_module)
+    /// scanner: the `struct module` candidates and the executable orphan ranges
+    pub fn from_hidden_modules(findings: &[HiddenModuleFinding]) -> Result<KVec<Self>> {
+        let mut ranges = KVec::new();
+        for finding in findings {
+            let range = match *finding {
+                HiddenModuleFinding::Module { address, .. } => SuspiciousRange {
+                    start: address,
+                    end: address + size_of::<bindings::module>() as u64,
+                    kind: RangeKind::HiddenModule,
+                },
+                HiddenModuleFinding::OrphanRange {
+                    start,
+                    end,
+                    executable: true,
+                } => SuspiciousRange {
+                    start,
+                    end,
+                    kind: RangeKind::OrphanExec,
+                },
+                HiddenModuleFinding::OrphanRange { .. } => continue,
+            };
+            ranges.push(range, GFP_KERNEL)?;
+        }
+        Ok(ranges)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        (self.start..self.end).contains(&value)
+    }
+}
+
+/// A word of a kernel stack in a suspicious range
+pub struct StackReference {
+    /// Thread id of the task
+    pub pid: i32,
+    /// Command name of the task, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Offset of the word from the base of the stack
+    pub offset: usize,
+    /// The word
+    pub value: u64,
+    /// The range containing the word
+    pub range: SuspiciousRange,
+}
+
+/// Result of the scan
+pub struct StackScan {
+    /// Number of sleeping tasks whose stack was read
+    pub scanned: usize,
+    /// The references, the first [`MAX_REFERENCES`]
+    pub references: KVec<StackReference>,
+}
+
+/// The task `task` is sleeping and off CPU, its stack only changes once woken up
+fn is_sleeping(task: &Task) -> bool {
+    // SAFETY: The task is valid by the type invariant, we only take a snapshot of its state
+    let state = unsafe { ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).__state)) };
+    if state & TASK_NORMAL == 0 {
+        return false;
+    }
+
+    #[cfg(CONFIG_SMP)]
+    {
+        // SAFETY: The task is valid by the type invariant, `on_cpu` is cleared once the task
+        // is switched out
+        let on_cpu = unsafe { ptr::read_volatile(ptr::addr_of!((*task.as_ptr()).on_cpu)) };
+        if on_cpu != 0 {
+            return false;
+        }
+    }
+    true
+}
+
+/// A kernel stack pinned with `try_get_task_stack`
+struct PinnedStack<'a> {
+    task: &'a Task,
+    base: usize,
+}
+
+impl<'a> PinnedStack<'a> {
+    /// Pin the stack of `task`, `None` if the task already released it
+    fn new(task: &'a Task) -> Option<Self> {
+        // SAFETY: Just an FFI call, the task is valid by the type invariant
+        let base = unsafe { bindings::try_get_task_stack(task.as_ptr()) };
+        (!base.is_null()).then_some(PinnedStack {
+            task,
+            base: base as usize,
+        })
+    }
+
+    /// Get the offset of the saved stack pointer from the base, 0 if it isn't in the stack
+    fn sp_offset(&self) -> usize {
+        // SAFETY: The task is valid by the type invariant, `thread.sp` is saved at each
+        // context switch
+        let sp = unsafe { ptr::read_volatile(ptr::addr_of!((*self.task.as_ptr()).thread.sp)) };
+        let offset = (sp as usize).wrapping_sub(self.base);
+        if offset < THREAD_SIZE {
+            offset & !(size_of::<u64>() - 1)
+        } else {
+            0
+        }
+    }
+}
+
+impl Drop for PinnedStack<'_> {
+    fn drop(&mut self) {
+        // SAFETY: We pinned the stack with `try_get_task_stack` in `new`
+        unsafe { bindings::put_task_stack(self.task.as_ptr()) };
+    }
+}
+
+impl StackScan {
+    /// Scan the kernel stacks of the sleeping tasks for words in `ranges`
+    pub fn scan(ranges: &[SuspiciousRange]) -> Result<Self> {
+        let mut scan = StackScan {
+            scanned: 0,
+            references: KVec::new(),
+        };
+        if ranges.is_empty() {
+            return Ok(scan);
+        }
+
+        let mut page = KBox::new([0u8; PAGE_SIZE], GFP_KERNEL)?;
+        let origin: ARef<Task> = current!().into();
+        for task in AllThreadsIter::new(origin) {
+            if !is_sleeping(&task) {
+                continue;
+            }
+            let Some(stack) = PinnedStack::new(&task) else {
+                continue;
+            };
+            scan.scanned += 1;
+            if scan.scan_stack(&task, &stack, ranges, &mut page)? {
+                break;
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Scan the stack of `task` from its saved stack pointer
+    ///
+    /// # Return
+    /// `true` once [`MAX_REFERENCES`] references are found
+    fn scan_stack(
+        &mut self,
+        task: &Task,
+        stack: &PinnedStack<'_>,
+        ranges: &[SuspiciousRange],
+        page: &mut [u8; PAGE_SIZE],
+    ) -> Result<bool> {
+        let mut offset = stack.sp_offset();
+        while offset < THREAD_SIZE {
+            let len = (PAGE_SIZE - offset % PAGE_SIZE).min(THREAD_SIZE - offset);
+            if nofault::copy(stack.base + offset, &mut page[..len]).is_err() {
+                offset += len;
+                continue;
+            }
+
+            for (i, word) in page[..len].chunks_exact(size_of::<u64>()).enumerate() {
+                let Ok(word) = <[u8; 8]>::try_from(word) else {
+                    continue;
+                };
+                let value = u64::from_ne_bytes(word);
+                let Some(range) = ranges.iter().find(|range| range.contains(value)) else {
+                    continue;
+                };
+                if self.references.len() >= MAX_REFERENCES {
+                    return Ok(true);
+                }
+                self.references.push(
+                    StackReference {
+                        pid: task.pid(),
+                        comm: task.comm(),
+                        offset: offset + i * size_of::<u64>(),
+                        value,
+                        range: *range,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+            offset += len;
+        }
+        Ok(false)
+    }
+
+    /// Create the event listing the references, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.references.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::SuspiciousStackReference,
+            fmt!("sleeping tasks referencing suspicious memory : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for RangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeKind::HiddenModule => f.write_str("hidden module"),
+            RangeKind::OrphanExec => f.write_str("orphan executable memory"),
+        }
+    }
+}
+
+impl fmt::Display for StackScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, reference) in self.references.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} (pid {}) stack+{:#x} = {:#x} in {} {:#x}-{:#x}",
+                BStr::from_bytes(until_nul(&reference.comm)),
+                reference.pid,
+                reference.offset,
+                reference.value,
+                reference.range.kind,
+                reference.range.start,
+                reference.range.end
+            )?;
+        }
+        Ok(())
+    }
+}
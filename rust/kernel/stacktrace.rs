@@ -1,14 +1,26 @@
 //! Stacktrace : stack trace getter
 //!
+//! The raw return addresses are meaningless once KASLR shuffled the kernel and the modules,
+//! a [`SymbolizedStacktrace`] resolves each of them with [`resolve_address`] for the reports.
+//! The resolution allocates, in the probe handlers a [`ProbeStacktrace`] resolves the symbol
+//! of the frames without allocating instead.
+//!
 //! C header : [`arch/x86/include/stacktrace.h`](../../../../include/linux/stacktrace.h)
 
+use core::fmt;
 use core::ops::Deref;
 
 use crate::{
-    alloc::{Flags, KVec},
+    address::{resolve_address, AddressInfo, Owner},
+    alloc::{flags::GFP_KERNEL, Flags, KVec},
     kernel::error::Result,
+    module::{symbols_lookup_address_buf, ResolvedSymbol, SymbolBuffer},
+    str::BStr,
 };
 
+/// Maximum depth of a [`ProbeStacktrace`], the frames are kept on the stack of the handler
+pub const MAX_PROBE_DEPTH: usize = 32;
+
 /// Represent a captured stacktrace of the current process
 pub struct Stacktrace(KVec<u64>);
 
@@ -24,6 +36,87 @@ pub fn new(size: usize, flag: Flags) -> Result<Self> {
         unsafe { buf.set_len(len as _) };
         Ok(Stacktrace(buf))
     }
+
+    /// Save the stacktrace of the context interrupted with the registers `regs`
+    ///
+    /// In a probe handler the unwinding starts at the probed function instead of the
+    /// frames of the probe: the first entry is the instruction pointer of `regs`, followed
+    /// by the callers. The instruction, stack and frame pointers of `regs` must be set.
+    pub fn new_from_regs(regs: &bindings::pt_regs, size: usize, flag: Flags) -> Result<Self> {
+        let mut buf = KVec::from_elem(0u64, size, flag)?;
+        let len = Self::save_regs_into(regs, &mut buf);
+
+        // SAFETY: We have by the `stack_trace_save_regs` contract that `len<size` so
+        // `new_len<old_len`.
+        unsafe { buf.set_len(len) };
+        Ok(Stacktrace(buf))
+    }
+
+    /// Save the stacktrace of the context interrupted with the registers `regs` in `buf`
+    /// without allocating, so it can be used from the probe handlers
+    ///
+    /// # Return
+    /// The number of entries written
+    pub fn save_regs_into(regs: &bindings::pt_regs, buf: &mut [u64]) -> usize {
+        // SAFETY: The unwinder only reads `regs`, which is valid, and the stack of the current
+        // process. `buf` is valid for `buf.len()` entries
+        let len = unsafe {
+            bindings::stack_trace_save_regs(
+                regs as *const _ as *mut _,
+                buf.as_mut_ptr(),
+                buf.len() as _,
+                0,
+            )
+        };
+        len as usize
+    }
+
+    /// Save the userspace stacktrace of the current process, the return addresses of its
+    /// userspace frames when it entered the kernel
+    ///
+    /// The frames are found by following the frame pointers, the trace is empty for a
+    /// kernel thread and stops at the first frame built without them.
+    pub fn new_user(size: usize, flag: Flags) -> Result<Self> {
+        let mut buf = KVec::from_elem(0u64, size, flag)?;
+        let len = Self::save_user_into(&mut buf);
+
+        // SAFETY: We have by the `stack_trace_save_user` contract that `len<size` so
+        // `new_len<old_len`.
+        unsafe { buf.set_len(len) };
+        Ok(Stacktrace(buf))
+    }
+
+    /// Save the userspace stacktrace of the current process in `buf` without allocating, so
+    /// it can be used from the probe handlers
+    ///
+    /// # Return
+    /// The number of entries written
+    pub fn save_user_into(buf: &mut [u64]) -> usize {
+        // SAFETY: This function save the userspace stacktrace of the current process so it is
+        // always safe to call, the userspace memory is read with the page faults disabled.
+        // `buf` is valid for `buf.len()` entries
+        let len = unsafe { bindings::stack_trace_save_user(buf.as_mut_ptr(), buf.len() as _) };
+        len as usize
+    }
+
+    /// Save the stacktrace of the current process in `buf` without allocating, so it can
+    /// be used from the probe handlers
+    ///
+    /// # Return
+    /// The number of entries written
+    pub fn save_into(buf: &mut [u64]) -> usize {
+        // SAFETY: This function save the stacktrace of the current process so it is always safe to call,
+        // `buf` is valid for `buf.len()` entries
+        let len = unsafe { bindings::stack_trace_save(buf.as_mut_ptr(), buf.len() as _, 0) };
+        len as usize
+    }
+
+    /// Resolve every frame of the stacktrace
+    ///
+    /// The resolution allocates, it can't be done from the probe handlers.
+    pub fn symbolize(&self) -> Result<SymbolizedStacktrace> {
+        SymbolizedStacktrace::resolve(&self.0)
+    }
 }
 
 impl Deref for Stacktrace {
@@ -33,3 +126,114 @@ fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
+
+/// A stacktrace whose frames are resolved to their owner, region and symbol
+pub struct SymbolizedStacktrace(KVec<AddressInfo>);
+
+impl SymbolizedStacktrace {
+    /// Resolve every frame of `frames`, the return addresses of a stacktrace
+    pub fn resolve(frames: &[u64]) -> Result<Self> {
+        let mut resolved = KVec::with_capacity(frames.len(), GFP_KERNEL)?;
+        for frame in frames {
+            resolved.push(resolve_address(*frame)?, GFP_KERNEL)?;
+        }
+        Ok(SymbolizedStacktrace(resolved))
+    }
+
+    /// Save and resolve a new stacktrace of the current process
+    pub fn new(size: usize) -> Result<Self> {
+        Stacktrace::new(size, GFP_KERNEL)?.symbolize()
+    }
+}
+
+impl Deref for SymbolizedStacktrace {
+    type Target = [AddressInfo];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Compact form for the events, from the innermost frame: `symbol+offset` for the kernel,
+/// `symbol+offset [module]` for a module and `address (region)` for an unknown symbol
+impl fmt::Display for SymbolizedStacktrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, frame) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" < ")?;
+            }
+            if frame.symbol.is_some() {
+                write!(
+                    f,
+                    "{}+{:#x}",
+                    BStr::from_bytes(frame.symbol_name()),
+                    frame.offset
+                )?;
+            } else {
+                write!(f, "{:#x} ({:?})", frame.address, frame.region)?;
+            }
+            if let Owner::Module(_) = frame.owner {
+                write!(f, " [{}]", BStr::from_bytes(frame.owner_name()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A stacktrace captured in a probe handler, borrowing the frames saved on its stack
+///
+/// The symbols are resolved on demand without allocating, with a [`SymbolBuffer`], so the
+/// stacktrace can be inspected and formatted from the handler itself.
+#[derive(Clone, Copy)]
+pub struct ProbeStacktrace<'a>(&'a [u64]);
+
+impl<'a> ProbeStacktrace<'a> {
+    /// Wrap the return addresses `frames`
+    pub fn new(frames: &'a [u64]) -> Self {
+        ProbeStacktrace(&frames[..frames.len().min(MAX_PROBE_DEPTH)])
+    }
+
+    /// Get the return addresses, from the innermost frame
+    pub fn frames(&self) -> &'a [u64] {
+        self.0
+    }
+
+    /// Resolve the symbol of the frame `index` in `buf`, if any
+    pub fn symbol<'b>(
+        &self,
+        index: usize,
+        buf: &'b mut SymbolBuffer,
+    ) -> Option<ResolvedSymbol<'b>> {
+        symbols_lookup_address_buf(*self.0.get(index)?, buf)
+    }
+
+    /// Resolve every frame to its owner, region and symbol
+    ///
+    /// The resolution allocates, it can't be done from the probe handlers.
+    pub fn symbolize(&self) -> Result<SymbolizedStacktrace> {
+        SymbolizedStacktrace::resolve(self.0)
+    }
+}
+
+/// Same compact form as [`SymbolizedStacktrace`], but a frame without symbol is only shown
+/// by its address
+impl fmt::Display for ProbeStacktrace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = SymbolBuffer::new();
+        for (i, frame) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" < ")?;
+            }
+            match symbols_lookup_address_buf(*frame, &mut buf) {
+                Some(symbol) => {
+                    write!(f, "{}+{:#x}", BStr::from_bytes(symbol.name), symbol.offset)?;
+                    if let Some(module) = symbol.module {
+                        write!(f, " [{}]", BStr::from_bytes(module))?;
+                    }
+                }
+                None => write!(f, "{:#x}", frame)?,
+            }
+        }
+        Ok(())
+    }
+}
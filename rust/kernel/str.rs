@@ -33,6 +33,35 @@ pub const fn from_bytes(bytes: &[u8]) -> &Self {
     }
 }
 
+/// Returns `bytes` up to its first null byte, all of it if there is none.
+///
+/// For the null padded buffers of the kernel: the `comm` of a task, the name of a module.
+#[inline]
+pub fn until_nul(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// Copies the C string `ptr` in a null padded buffer, truncated to keep a null terminator.
+///
+/// The buffer is all zeroes if `ptr` is null.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer to a `NUL`-terminated C string, not mutated for the
+/// duration of the call.
+pub unsafe fn copy_from_char_ptr<const N: usize>(ptr: *const crate::ffi::c_char) -> [u8; N] {
+    let mut dst = [0u8; N];
+    if ptr.is_null() {
+        return dst;
+    }
+    // SAFETY: `ptr` is a valid C string by the safety requirements of this function.
+    let src = unsafe { CStr::from_char_ptr(ptr) }.as_bytes();
+    let len = src.len().min(N.saturating_sub(1));
+    dst[..len].copy_from_slice(&src[..len]);
+    dst
+}
+
 impl fmt::Display for BStr {
     /// Formats printable ASCII characters, escaping the rest.
     ///
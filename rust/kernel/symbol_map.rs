@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Symbol map : resolution of the unexported symbols without kallsyms
+//!
+//! Some hardened kernels restrict `kallsyms_lookup_name`, in which case the checks
+//! can't resolve the unexported symbols they need. In the kallsyms-free mode, selected
+//! at load time with [`set_kallsyms_free`], [`symbols_lookup_name`] only uses a map of
+//! addresses supplied by userspace (extracted from the `System.map` of the running kernel).
+//!
+//! The `System.map` addresses are the link-time ones, the KASLR slide is computed
+//! from the exported `_printk` which must be part of the map.
+//!
+//! # Map format
+//!
+//! All the integers are native endian :
+//! - [`MapHeader`] : magic, version, kernel release the map was generated for, entry count
+//! - `count` times [`MapEntry`] : link-time address and null terminated name of the symbol
+//!
+//! [`symbols_lookup_name`]: crate::module::symbols_lookup_name
+
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::offsets::{read_entries, read_header, release_matches, RELEASE_LEN};
+use crate::str::CStr;
+use crate::transmute::FromBytes;
+use crate::uaccess::UserSliceReader;
+use kernel::prelude::*;
+
+/// Magic number at the start of the map (`"RKSM"`)
+pub const MAP_MAGIC: u32 = 0x4d53_4b52;
+
+/// Current version of the map format
+pub const MAP_VERSION: u32 = 1;
+
+/// Maximum size of a map, to bound the allocation when reading it from userspace
+pub const MAP_MAX_SIZE: usize = 256 << 10;
+
+/// Length of the symbol names in the map, longer symbols can't be supplied
+pub const NAME_LEN: usize = 64;
+
+/// Header of the map
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MapHeader {
+    /// Must be [`MAP_MAGIC`]
+    pub magic: u32,
+    /// Must be [`MAP_VERSION`]
+    pub version: u32,
+    /// The `uname -r` of the kernel the map was extracted from, null terminated
+    pub release: [u8; RELEASE_LEN],
+    _pad: [u8; 3],
+    /// The number of [`MapEntry`] following the header
+    pub count: u32,
+}
+
+// SAFETY: `MapHeader` only contains integers and arrays of integers, every bit pattern is valid
+unsafe impl FromBytes for MapHeader {}
+
+/// An entry of the map
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MapEntry {
+    /// Address of the symbol in the `System.map`
+    pub address: u64,
+    /// Name of the symbol, null terminated
+    pub name: [u8; NAME_LEN],
+}
+
+// SAFETY: `MapEntry` only contains integers and arrays of integers, every bit pattern is valid
+unsafe impl FromBytes for MapEntry {}
+
+/// The loaded map, sorted by name, with the addresses relocated
+struct SymbolMap {
+    entries: KVVec<MapEntry>,
+}
+
+impl SymbolMap {
+    fn lookup(&self, name: &[u8]) -> Option<u64> {
+        let i = self
+            .entries
+            .binary_search_by(|entry| entry_name(entry).cmp(name))
+            .ok()?;
+        Some(self.entries[i].address)
+    }
+}
+
+/// The kallsyms-free mode is enabled
+static KALLSYMS_FREE: AtomicBool = AtomicBool::new(false);
+
+/// The map loaded by [`load`], set once
+static MAP: AtomicPtr<SymbolMap> = AtomicPtr::new(core::ptr::null_mut());
+
+fn entry_name(entry: &MapEntry) -> &[u8] {
+    let len = entry.name.iter().position(|c| *c == 0).unwrap_or(NAME_LEN);
+    &entry.name[..len]
+}
+
+/// Select the kallsyms-free mode, must be done at load time before any check is created
+pub fn set_kallsyms_free(enable: bool) {
+    KALLSYMS_FREE.store(enable, Ordering::Relaxed);
+}
+
+/// The kallsyms-free mode is enabled
+pub fn is_kallsyms_free() -> bool {
+    KALLSYMS_FREE.load(Ordering::Relaxed)
+}
+
+/// Get the address of a symbol from the loaded map
+///
+/// # Return
+/// The relocated address, or `None` if there is no map or the symbol is not in it
+pub fn lookup(name: &CStr) -> Option<u64> {
+    let map = MAP.load(Ordering::Acquire);
+    if map.is_null() {
+        return None;
+    }
+    // SAFETY: The map is set once and only freed by `clear` when no lookup can happen
+    unsafe { &*map }.lookup(name.as_bytes())
+}
+
+/// Load the map from a buffer
+///
+/// The map can only be loaded once, fail with `EBUSY` if one is already loaded
+pub fn load(data: &[u8]) -> Result {
+    let (header, raw_entries) = read_header::<MapHeader>(data)?;
+
+    if header.magic != MAP_MAGIC || header.version != MAP_VERSION {
+        pr_err!("Invalid symbol map header\n");
+        return Err(EINVAL);
+    }
+
+    if !release_matches(&header.release) {
+        pr_err!("The symbol map doesn't match the running kernel\n");
+        return Err(EINVAL);
+    }
+
+    let mut entries = KVVec::with_capacity(header.count as usize, GFP_KERNEL)?;
+    for entry in read_entries::<MapEntry>(raw_entries, header.count)? {
+        entries.push(entry, GFP_KERNEL)?;
+    }
+    entries.sort_unstable_by(|a, b| entry_name(a).cmp(entry_name(b)));
+
+    let mut map = SymbolMap { entries };
+
+    // `_printk` is exported so we know its real address
+    let printk = map.lookup(b"_printk").ok_or_else(|| {
+        pr_err!("The symbol map doesn't contain _printk\n");
+        EINVAL
+    })?;
+    let slide = (bindings::_printk as usize as u64).wrapping_sub(printk);
+    for entry in map.entries.iter_mut() {
+        entry.address = entry.address.wrapping_add(slide);
+    }
+
+    let map = KBox::into_raw(KBox::new(map, GFP_KERNEL)?);
+    if MAP
+        .compare_exchange(
+            core::ptr::null_mut(),
+            map,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_err()
+    {
+        // SAFETY: `map` come from `KBox::into_raw` just above and was not published
+        drop(unsafe { KBox::from_raw(map) });
+        return Err(EBUSY);
+    }
+
+    Ok(())
+}
+
+/// Load the map from a userspace buffer
+pub fn load_from_user(reader: UserSliceReader) -> Result {
+    if reader.len() > MAP_MAX_SIZE {
+        return Err(E2BIG);
+    }
+
+    let mut buf = KVec::new();
+    reader.read_all(&mut buf, GFP_KERNEL)?;
+
+    load(&buf)
+}
+
+/// Free the loaded map
+///
+/// # Safety
+///
+/// No lookup must happen concurrently or after this call (called at module exit).
+pub unsafe fn clear() {
+    let map = MAP.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    if !map.is_null() {
+        // SAFETY: `map` come from `KBox::into_raw` in `load`, and by the safety contract
+        // no one borrow it anymore
+        drop(unsafe { KBox::from_raw(map) });
+    }
+}
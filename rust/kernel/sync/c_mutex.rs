@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Locking of the static C mutexes of the kernel.
+//!
+//! Many kernel lists are protected by a `static DEFINE_MUTEX()` private to their C file
+//! (`module_mutex`, `ddebug_lock`, `tracepoint_module_list_mutex`, ...). The mutex is
+//! resolved by its symbol and held by a [`StaticCMutexGuard`] for the traversal.
+//!
+//! C header: [`include/linux/mutex.h`](srctree/include/linux/mutex.h)
+
+use crate::module::symbols_lookup_name;
+use crate::prelude::*;
+use crate::str::CStr;
+use crate::types::NotThreadSafe;
+
+/// Evidence that a static C mutex is held by the current thread.
+///
+/// The type is explicitly not `Send` because a mutex must be unlocked by its owner.
+///
+/// # Invariants
+///
+/// `lock` points to a static `struct mutex` of the kernel, locked while the guard exists.
+pub struct StaticCMutexGuard {
+    lock: *mut bindings::mutex,
+    _not_send: NotThreadSafe,
+}
+
+impl StaticCMutexGuard {
+    /// Resolve the mutex `name` and lock it, sleeping until it is available.
+    pub fn lock(name: &CStr) -> Result<Self> {
+        let lock = symbols_lookup_name(name) as *mut bindings::mutex;
+        if lock.is_null() {
+            pr_err!("Couldn't find {:?} symbol\n", name);
+            return Err(ENOENT);
+        }
+        // SAFETY: `lock` is the address of a static mutex of the kernel.
+        unsafe { bindings::mutex_lock(lock) };
+        // INVARIANT: The mutex was just locked above.
+        Ok(Self {
+            lock,
+            _not_send: NotThreadSafe,
+        })
+    }
+
+    /// Explicitly unlocks the mutex.
+    pub fn unlock(self) {}
+}
+
+impl Drop for StaticCMutexGuard {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the mutex is locked by us.
+        unsafe { bindings::mutex_unlock(self.lock) };
+    }
+}
@@ -0,0 +1,727 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Syscall monitor : decoding of the syscalls used by the rootkits and their loaders
+//!
+//! A [`SyscallSensor`] attaches to the `sys_enter` and `sys_exit` tracepoints and
+//! decodes the arguments of a configurable set of syscalls : `kill`, `ptrace`,
+//! `init_module`, `finit_module`, `setuid`, and the opens of sensitive paths. Each call
+//! is recorded with the credentials and the kernel stacktrace of the caller, which
+//! shows the frames of a hook placed on the syscall path. The loading of a module and
+//! `ptrace` also record the userspace stacktrace of the caller, the code of the loader
+//! behind the call.
+//!
+//! The compat (32 bits) syscalls have their own numbering and calling convention, they are
+//! decoded with the compat table and flagged as such in the records.
+//!
+//! The tracepoints run with the preemption disabled, so the records are kept in a ring
+//! without allocation and the userspace strings are read with the nofault accessors.
+//! The events are created in process context by [`SyscallMonitor::drain`]. The return
+//! value is only filled if the call returns before its record is overwritten or drained.
+//!
+//! C header: [`include/trace/events/syscalls.h`](../../../../include/trace/events/syscalls.h)
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::registers::Registers;
+use crate::stacktrace::Stacktrace;
+use crate::str::BStr;
+use crate::sync::{new_spinlock, Arc, ArcBorrow, SpinLock};
+use crate::task::Task;
+use crate::time::Ktime;
+use crate::tracepoint_probe::{args, Tracepoint, TracepointOperations};
+use kernel::prelude::*;
+
+/// Number of records kept by a [`SyscallMonitor`]
+pub const RING_CAPACITY: usize = 32;
+
+/// Number of entries of the recorded stacktraces
+pub const STACK_DEPTH: usize = 16;
+
+/// Number of entries of the recorded userspace stacktraces
+pub const USER_STACK_DEPTH: usize = 16;
+
+/// Length of a recorded userspace string (path, module parameters)
+pub const STRING_LEN: usize = 128;
+
+/// Length of the command name of a task (`TASK_COMM_LEN`)
+pub const TASK_COMM_LEN: usize = bindings::TASK_COMM_LEN as usize;
+
+/// The monitored syscalls, to combine in the filter of a [`SyscallMonitor`]
+pub mod syscalls {
+    /// `kill`
+    pub const KILL: u32 = 1 << 0;
+    /// `ptrace`
+    pub const PTRACE: u32 = 1 << 1;
+    /// `init_module`
+    pub const INIT_MODULE: u32 = 1 << 2;
+    /// `finit_module`
+    pub const FINIT_MODULE: u32 = 1 << 3;
+    /// `setuid`
+    pub const SETUID: u32 = 1 << 4;
+    /// `open`, `openat` and `openat2` on a sensitive path
+    pub const OPEN: u32 = 1 << 5;
+    /// All of them
+    pub const ALL: u32 = KILL | PTRACE | INIT_MODULE | FINIT_MODULE | SETUID | OPEN;
+}
+
+/// The numbers of the monitored syscalls in a syscall table
+struct SyscallTable {
+    kill: i64,
+    ptrace: i64,
+    init_module: i64,
+    finit_module: i64,
+    setuid: i64,
+    /// `setuid` with a 16 bits uid, only in the 32 bits tables
+    setuid16: Option<i64>,
+    open: Option<i64>,
+    openat: i64,
+    openat2: i64,
+}
+
+/// The native syscall table
+#[cfg(target_arch = "x86_64")]
+const NATIVE: SyscallTable = SyscallTable {
+    kill: bindings::__NR_kill as i64,
+    ptrace: bindings::__NR_ptrace as i64,
+    init_module: bindings::__NR_init_module as i64,
+    finit_module: bindings::__NR_finit_module as i64,
+    setuid: bindings::__NR_setuid as i64,
+    setuid16: None,
+    open: Some(bindings::__NR_open as i64),
+    openat: bindings::__NR_openat as i64,
+    openat2: bindings::__NR_openat2 as i64,
+};
+
+/// The ia32 syscall table, `setuid` is `setuid32`
+#[cfg(all(target_arch = "x86_64", CONFIG_COMPAT))]
+const COMPAT: SyscallTable = SyscallTable {
+    kill: bindings::__NR_ia32_kill as i64,
+    ptrace: bindings::__NR_ia32_ptrace as i64,
+    init_module: bindings::__NR_ia32_init_module as i64,
+    finit_module: bindings::__NR_ia32_finit_module as i64,
+    setuid: bindings::__NR_ia32_setuid32 as i64,
+    setuid16: Some(bindings::__NR_ia32_setuid as i64),
+    open: Some(bindings::__NR_ia32_open as i64),
+    openat: bindings::__NR_ia32_openat as i64,
+    openat2: bindings::__NR_ia32_openat2 as i64,
+};
+
+/// The native syscall table (the generic table, without `open`)
+#[cfg(target_arch = "aarch64")]
+const NATIVE: SyscallTable = SyscallTable {
+    kill: bindings::__NR_kill as i64,
+    ptrace: bindings::__NR_ptrace as i64,
+    init_module: bindings::__NR_init_module as i64,
+    finit_module: bindings::__NR_finit_module as i64,
+    setuid: bindings::__NR_setuid as i64,
+    setuid16: None,
+    open: None,
+    openat: bindings::__NR_openat as i64,
+    openat2: bindings::__NR_openat2 as i64,
+};
+
+/// The AArch32 syscall table, `setuid` is `setuid32`
+///
+/// `asm/unistd32.h` defines the AArch32 numbers with the `__NR_` names of the native
+/// table and is only included to build `compat_sys_call_table`, so there are no bindings
+/// for them. They are the numbers of `arch/arm/tools/syscall.tbl`, which never change.
+#[cfg(all(target_arch = "aarch64", CONFIG_COMPAT))]
+const COMPAT: SyscallTable = SyscallTable {
+    kill: 37,
+    ptrace: 26,
+    init_module: 128,
+    finit_module: 379,
+    setuid: 213,
+    setuid16: Some(23),
+    open: Some(5),
+    openat: 322,
+    openat2: 437,
+};
+
+/// Get the syscall table of the current syscall, and whether it is the compat one
+fn current_table() -> (&'static SyscallTable, bool) {
+    #[cfg(CONFIG_COMPAT)]
+    {
+        // SAFETY: Just an FFI call, we are in the context of a syscall
+        if unsafe { bindings::in_compat_syscall() } {
+            return (&COMPAT, true);
+        }
+    }
+    (&NATIVE, false)
+}
+
+/// Paths whose opening is reported, a path is sensitive if it starts with one of them
+const SENSITIVE_PATHS: &[&[u8]] = &[
+    b"/etc/shadow",
+    b"/etc/gshadow",
+    b"/etc/sudoers",
+    b"/etc/ld.so.preload",
+    b"/proc/kcore",
+    b"/proc/kallsyms",
+    b"/dev/mem",
+    b"/dev/kmem",
+    b"/dev/port",
+    b"/boot/",
+    b"/lib/modules/",
+    b"/sys/kernel/debug/",
+];
+
+impl SyscallTable {
+    /// Get the filter flag of a syscall number
+    fn flag(&self, id: i64) -> Option<u32> {
+        Some(if id == self.kill {
+            syscalls::KILL
+        } else if id == self.ptrace {
+            syscalls::PTRACE
+        } else if id == self.init_module {
+            syscalls::INIT_MODULE
+        } else if id == self.finit_module {
+            syscalls::FINIT_MODULE
+        } else if id == self.setuid || Some(id) == self.setuid16 {
+            syscalls::SETUID
+        } else if id == self.openat || id == self.openat2 || Some(id) == self.open {
+            syscalls::OPEN
+        } else {
+            return None;
+        })
+    }
+}
+
+/// A null terminated string copied from userspace, truncated to [`STRING_LEN`]
+#[derive(Clone, Copy)]
+pub struct UserString([u8; STRING_LEN]);
+
+impl UserString {
+    /// Copy the string at the userspace address `addr`, empty if it can't be read
+    fn read(addr: u64) -> Self {
+        let mut buf = [0u8; STRING_LEN];
+        // SAFETY: `buf` is valid for `STRING_LEN` bytes, the source is checked by
+        // `strncpy_from_user_nofault` itself which never sleeps
+        let ret = unsafe {
+            bindings::strncpy_from_user_nofault(
+                buf.as_mut_ptr().cast(),
+                addr as *const core::ffi::c_void as _,
+                (STRING_LEN - 1) as _,
+            )
+        };
+        if ret < 0 {
+            buf[0] = 0;
+        }
+        buf[STRING_LEN - 1] = 0;
+        UserString(buf)
+    }
+
+    /// Get the bytes of the string (without the null terminator)
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.0.iter().position(|c| *c == 0).unwrap_or(STRING_LEN);
+        &self.0[..len]
+    }
+}
+
+/// A decoded syscall
+#[derive(Clone, Copy)]
+pub enum SyscallCall {
+    /// `kill(pid, sig)`
+    Kill {
+        /// Target process
+        pid: i32,
+        /// Signal sent
+        sig: i32,
+    },
+    /// `ptrace(request, pid, addr, data)`
+    Ptrace {
+        /// The `PTRACE_*` request
+        request: i64,
+        /// Target process
+        pid: i32,
+        /// Address argument
+        addr: u64,
+    },
+    /// `init_module(umod, len, uargs)`
+    InitModule {
+        /// Size of the module image
+        len: u64,
+        /// Module parameters
+        uargs: UserString,
+    },
+    /// `finit_module(fd, uargs, flags)`
+    FinitModule {
+        /// File descriptor of the module image
+        fd: i32,
+        /// Module parameters
+        uargs: UserString,
+        /// The `MODULE_INIT_*` flags
+        flags: u32,
+    },
+    /// `setuid(uid)`
+    Setuid {
+        /// The requested uid
+        uid: u32,
+    },
+    /// `open`, `openat` or `openat2` of a sensitive path
+    Open {
+        /// The opened path
+        path: UserString,
+        /// The `O_*` flags (0 for `openat2`, they are in `struct open_how`)
+        flags: i32,
+    },
+}
+
+impl SyscallCall {
+    /// Decode the arguments of the syscall `id` of `table`, the compat one if `compat`
+    ///
+    /// `None` is returned if the syscall is not monitored, or is an open of a path which
+    /// is not sensitive
+    fn decode(table: &SyscallTable, compat: bool, id: i64, regs: &Registers<'_>) -> Option<Self> {
+        let arg = |n| {
+            if compat {
+                regs.compat_syscall_arg(n).unwrap_or(0)
+            } else {
+                regs.syscall_arg(n).unwrap_or(0)
+            }
+        };
+
+        let (path, flags) = if id == table.kill {
+            return Some(SyscallCall::Kill {
+                pid: arg(0) as i32,
+                sig: arg(1) as i32,
+            });
+        } else if id == table.ptrace {
+            return Some(SyscallCall::Ptrace {
+                request: arg(0) as i64,
+                pid: arg(1) as i32,
+                addr: arg(2),
+            });
+        } else if id == table.init_module {
+            return Some(SyscallCall::InitModule {
+                len: arg(1),
+                uargs: UserString::read(arg(2)),
+            });
+        } else if id == table.finit_module {
+            return Some(SyscallCall::FinitModule {
+                fd: arg(0) as i32,
+                uargs: UserString::read(arg(1)),
+                flags: arg(2) as u32,
+            });
+        } else if id == table.setuid {
+            return Some(SyscallCall::Setuid { uid: arg(0) as u32 });
+        } else if Some(id) == table.setuid16 {
+            // The 16 bits -1 is extended to the 32 bits one
+            let uid = match arg(0) as u16 {
+                u16::MAX => u32::MAX,
+                uid => uid as u32,
+            };
+            return Some(SyscallCall::Setuid { uid });
+        } else if id == table.openat {
+            (arg(1), arg(2) as i32)
+        } else if id == table.openat2 {
+            (arg(1), 0)
+        } else if Some(id) == table.open {
+            (arg(0), arg(1) as i32)
+        } else {
+            return None;
+        };
+
+        let path = UserString::read(path);
+        SENSITIVE_PATHS
+            .iter()
+            .any(|prefix| path.as_bytes().starts_with(prefix))
+            .then_some(SyscallCall::Open { path, flags })
+    }
+
+    /// The userspace stacktrace of the caller is recorded: the call loads code in the
+    /// kernel or in another process
+    fn wants_user_stack(&self) -> bool {
+        matches!(
+            self,
+            SyscallCall::InitModule { .. }
+                | SyscallCall::FinitModule { .. }
+                | SyscallCall::Ptrace { .. }
+        )
+    }
+}
+
+impl fmt::Display for SyscallCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyscallCall::Kill { pid, sig } => write!(f, "kill({}, {})", pid, sig),
+            SyscallCall::Ptrace { request, pid, addr } => {
+                write!(f, "ptrace({}, {}, {:#x})", request, pid, addr)
+            }
+            SyscallCall::InitModule { len, uargs } => write!(
+                f,
+                "init_module({}, \"{}\")",
+                len,
+                BStr::from_bytes(uargs.as_bytes())
+            ),
+            SyscallCall::FinitModule { fd, uargs, flags } => write!(
+                f,
+                "finit_module({}, \"{}\", {:#x})",
+                fd,
+                BStr::from_bytes(uargs.as_bytes()),
+                flags
+            ),
+            SyscallCall::Setuid { uid } => write!(f, "setuid({})", uid),
+            SyscallCall::Open { path, flags } => write!(
+                f,
+                "open(\"{}\", {:#x})",
+                BStr::from_bytes(path.as_bytes()),
+                flags
+            ),
+        }
+    }
+}
+
+/// The credentials of the caller
+#[derive(Clone, Copy, Debug)]
+pub struct Credentials {
+    /// Real uid
+    pub uid: u32,
+    /// Effective uid
+    pub euid: u32,
+    /// Real gid
+    pub gid: u32,
+    /// Effective gid
+    pub egid: u32,
+    /// Effective capabilities
+    pub cap_effective: u64,
+}
+
+/// A monitored syscall and its caller
+#[derive(Clone, Copy)]
+pub struct SyscallRecord {
+    /// The syscall number
+    pub nr: i64,
+    /// The syscall is a compat (32 bits) one, `nr` is in the compat table
+    pub compat: bool,
+    /// The decoded syscall
+    pub call: SyscallCall,
+    /// Time of the call (`ktime_get`) in nanoseconds
+    pub timestamp: i64,
+    /// Thread id of the caller
+    pub pid: i32,
+    /// Process id of the caller
+    pub tgid: i32,
+    /// Command name of the caller, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Credentials of the caller
+    pub creds: Credentials,
+    /// Kernel stacktrace of the call, the first `stack_len` entries are valid
+    pub stack: [u64; STACK_DEPTH],
+    /// Number of valid entries of `stack`
+    pub stack_len: usize,
+    /// Userspace stacktrace of the call, the first `user_stack_len` entries are valid
+    pub user_stack: [u64; USER_STACK_DEPTH],
+    /// Number of valid entries of `user_stack`, 0 if it isn't recorded for the syscall
+    pub user_stack_len: usize,
+    /// Return value, `None` if the call didn't return yet
+    pub ret: Option<i64>,
+}
+
+impl SyscallRecord {
+    /// Record a syscall of the current task
+    fn current(nr: i64, compat: bool, call: SyscallCall) -> Self {
+        let task = Task::current_raw();
+        let mut comm = [0u8; TASK_COMM_LEN];
+
+        // SAFETY: `current` is always valid, `pid` and `tgid` never change and `comm` is
+        // always null terminated (it may be modified concurrently, we take a snapshot).
+        // Only the task itself can change its `cred`, so it is stable while we read it
+        let (pid, tgid, creds) = unsafe {
+            for (dst, src) in comm.iter_mut().zip((*task).comm.iter()) {
+                *dst = *src as u8;
+            }
+            let cred = (*task).cred;
+            (
+                (*task).pid,
+                (*task).tgid,
+                Credentials {
+                    uid: (*cred).uid.val,
+                    euid: (*cred).euid.val,
+                    gid: (*cred).gid.val,
+                    egid: (*cred).egid.val,
+                    cap_effective: (*cred).cap_effective.val,
+                },
+            )
+        };
+        comm[TASK_COMM_LEN - 1] = 0;
+
+        let mut stack = [0u64; STACK_DEPTH];
+        let stack_len = Stacktrace::save_into(&mut stack);
+
+        let mut user_stack = [0u64; USER_STACK_DEPTH];
+        let user_stack_len = if call.wants_user_stack() {
+            Stacktrace::save_user_into(&mut user_stack)
+        } else {
+            0
+        };
+
+        SyscallRecord {
+            nr,
+            compat,
+            call,
+            timestamp: Ktime::ktime_get().to_ns(),
+            pid,
+            tgid,
+            comm,
+            creds,
+            stack,
+            stack_len,
+            user_stack,
+            user_stack_len,
+            ret: None,
+        }
+    }
+}
+
+impl fmt::Display for SyscallRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self
+            .comm
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(TASK_COMM_LEN);
+        if self.compat {
+            f.write_str("compat ")?;
+        }
+        write!(
+            f,
+            "{} by {} (pid {}, tgid {}, uid {}, euid {}, gid {}, egid {}, caps {:#x})",
+            self.call,
+            BStr::from_bytes(&self.comm[..len]),
+            self.pid,
+            self.tgid,
+            self.creds.uid,
+            self.creds.euid,
+            self.creds.gid,
+            self.creds.egid,
+            self.creds.cap_effective
+        )?;
+        if let Some(ret) = self.ret {
+            write!(f, " = {}", ret)?;
+        }
+        f.write_str(", stack [")?;
+        for (i, addr) in self.stack[..self.stack_len].iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:#x}", addr)?;
+        }
+        f.write_str("]")?;
+        if self.user_stack_len != 0 {
+            f.write_str(", user stack [")?;
+            for (i, addr) in self.user_stack[..self.user_stack_len].iter().enumerate() {
+                if i != 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{:#x}", addr)?;
+            }
+            f.write_str("]")?;
+        }
+        Ok(())
+    }
+}
+
+/// The ring of records
+///
+/// The records are big (two stacktraces), the ring is allocated once with the monitor
+/// instead of being built on the stack.
+struct Ring {
+    entries: KVec<Option<SyscallRecord>>,
+    /// Next slot to write
+    head: usize,
+}
+
+/// The records of the monitored syscalls
+///
+/// Filled by the tracepoint probes of a [`SyscallSensor`], and drained in process context.
+#[pin_data]
+pub struct SyscallMonitor {
+    #[pin]
+    ring: SpinLock<Ring>,
+    filter: AtomicU32,
+    dropped: AtomicU64,
+}
+
+impl SyscallMonitor {
+    /// Create an empty monitor recording the syscalls of `filter` (see [`syscalls`])
+    pub fn new(filter: u32) -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            ring <- new_spinlock!(Ring {
+                entries: KVec::from_elem(None, RING_CAPACITY, GFP_KERNEL)?,
+                head: 0,
+            }),
+            filter: AtomicU32::new(filter),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Change the monitored syscalls (see [`syscalls`])
+    pub fn set_filter(&self, filter: u32) {
+        self.filter.store(filter, Ordering::Relaxed);
+    }
+
+    /// Get the monitored syscalls
+    pub fn filter(&self) -> u32 {
+        self.filter.load(Ordering::Relaxed)
+    }
+
+    /// Number of records overwritten before being drained
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn is_monitored(&self, table: &SyscallTable, id: i64) -> bool {
+        table.flag(id).is_some_and(|flag| self.filter() & flag != 0)
+    }
+
+    /// Called at the entry of every syscall
+    fn enter(&self, regs: Registers<'_>, id: i64) {
+        let (table, compat) = current_table();
+        if !self.is_monitored(table, id) {
+            return;
+        }
+        let Some(call) = SyscallCall::decode(table, compat, id, &regs) else {
+            return;
+        };
+        let record = SyscallRecord::current(id, compat, call);
+
+        let mut ring = self.ring.lock();
+        let head = ring.head;
+        if ring.entries[head].replace(record).is_some() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        ring.head = (head + 1) % RING_CAPACITY;
+    }
+
+    /// Called at the exit of every syscall
+    fn exit(&self, id: i64, ret: i64) {
+        let (table, compat) = current_table();
+        if !self.is_monitored(table, id) {
+            return;
+        }
+        // SAFETY: `current` is always valid and its pid never change
+        let pid = unsafe { (*Task::current_raw()).pid };
+
+        let mut ring = self.ring.lock();
+        let head = ring.head;
+        // The most recent record of the call is the one of this exit
+        for i in 1..=RING_CAPACITY {
+            let slot = &mut ring.entries[(head + RING_CAPACITY - i) % RING_CAPACITY];
+            if let Some(record) = slot {
+                if record.pid == pid
+                    && record.nr == id
+                    && record.compat == compat
+                    && record.ret.is_none()
+                {
+                    record.ret = Some(ret);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Take the records, from the oldest to the newest
+    pub fn take_records(&self) -> Result<KVec<SyscallRecord>> {
+        // Allocate before taking the lock
+        let mut records = KVec::with_capacity(RING_CAPACITY, GFP_KERNEL)?;
+
+        let mut ring = self.ring.lock();
+        let head = ring.head;
+        for i in 0..RING_CAPACITY {
+            if let Some(record) = ring.entries[(head + i) % RING_CAPACITY].take() {
+                // Can't fail, the capacity is reserved
+                records.push(record, GFP_ATOMIC)?;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Take the records and create their events
+    pub fn drain(&self) -> Result<KVec<Event>> {
+        let records = self.take_records()?;
+        let mut events = KVec::with_capacity(records.len(), GFP_KERNEL)?;
+        for record in records.iter() {
+            events.push(
+                Event::new(EventKind::MonitoredSyscall, fmt!("{}", record))?,
+                GFP_KERNEL,
+            )?;
+        }
+        Ok(events)
+    }
+}
+
+/// The probe of `sys_enter`
+pub struct SysEnterProbe;
+
+impl TracepointOperations for SysEnterProbe {
+    type Data = Arc<SyscallMonitor>;
+    type Args = args::SysEnter;
+
+    fn probe(monitor: ArcBorrow<'_, SyscallMonitor>, (regs, id): Self::Args) {
+        if regs.is_null() {
+            return;
+        }
+        // SAFETY: The tracepoint passes the user registers saved at the syscall entry,
+        // which are valid during the probe
+        monitor.enter(Registers::new(unsafe { &*regs }), id as i64);
+    }
+}
+
+/// The probe of `sys_exit`
+pub struct SysExitProbe;
+
+impl TracepointOperations for SysExitProbe {
+    type Data = Arc<SyscallMonitor>;
+    type Args = args::SysExit;
+
+    fn probe(monitor: ArcBorrow<'_, SyscallMonitor>, (regs, ret): Self::Args) {
+        if regs.is_null() {
+            return;
+        }
+        // SAFETY: See `SysEnterProbe::probe`
+        let regs = unsafe { &*regs };
+        #[cfg(target_arch = "x86_64")]
+        let id = regs.orig_ax as i64;
+        #[cfg(target_arch = "aarch64")]
+        let id = regs.syscallno as i64;
+        monitor.exit(id, ret as i64);
+    }
+}
+
+/// The tracepoint probes filling a [`SyscallMonitor`]
+pub struct SyscallSensor {
+    monitor: Arc<SyscallMonitor>,
+    _enter: Tracepoint<SysEnterProbe>,
+    _exit: Tracepoint<SysExitProbe>,
+}
+
+impl SyscallSensor {
+    /// Create a monitor recording the syscalls of `filter` (see [`syscalls`]) and
+    /// register the probes filling it
+    pub fn new(filter: u32) -> Result<Self> {
+        let monitor = Arc::pin_init(SyscallMonitor::new(filter), GFP_KERNEL)?;
+        // SAFETY: The arguments of the probes are the ones of the `TP_PROTO` of the
+        // tracepoints, see `tracepoint_probe::args`
+        let (enter, exit) = unsafe {
+            (
+                Tracepoint::new(c_str!("sys_enter"), monitor.clone())?,
+                Tracepoint::new(c_str!("sys_exit"), monitor.clone())?,
+            )
+        };
+        Ok(SyscallSensor {
+            monitor,
+            _enter: enter,
+            _exit: exit,
+        })
+    }
+
+    /// Get the monitor filled by the probes
+    pub fn monitor(&self) -> &Arc<SyscallMonitor> {
+        &self.monitor
+    }
+}
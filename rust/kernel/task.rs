@@ -7,7 +7,9 @@
 use crate::ffi::{c_int, c_long, c_uint};
 use crate::{
     bindings,
+    cred::Credential,
     pid_namespace::PidNamespace,
+    sync::rcu,
     types::{ARef, NotThreadSafe, Opaque},
 };
 use core::{
@@ -16,6 +18,9 @@
     ptr,
 };
 
+/// Length of the command name of a task.
+pub const TASK_COMM_LEN: usize = bindings::TASK_COMM_LEN as usize;
+
 /// A sentinel value used for infinite timeouts.
 pub const MAX_SCHEDULE_TIMEOUT: c_long = c_long::MAX;
 
@@ -108,6 +113,71 @@ unsafe impl Sync for Task {}
 /// The type of process identifiers (PIDs).
 type Pid = bindings::pid_t;
 
+/// A snapshot of the credentials of a task.
+///
+/// The ids are the kernel ids, as seen from the initial user namespace.
+#[derive(Clone, Copy, Debug)]
+pub struct TaskCreds {
+    /// Real UID.
+    pub uid: u32,
+    /// Effective UID.
+    pub euid: u32,
+    /// Real GID.
+    pub gid: u32,
+    /// Effective GID.
+    pub egid: u32,
+    /// Capabilities the task can pass across an exec.
+    pub cap_inheritable: u64,
+    /// Capabilities the task may assume.
+    pub cap_permitted: u64,
+    /// Capabilities the task can actually use.
+    pub cap_effective: u64,
+    /// Capability bounding set.
+    pub cap_bset: u64,
+    /// Ambient capability set.
+    pub cap_ambient: u64,
+    /// The `SECURE_*` bits of the task.
+    pub securebits: c_uint,
+    /// Id of the LSM security context, 0 if no LSM provides one.
+    pub secid: u32,
+}
+
+impl TaskCreds {
+    /// Takes a snapshot of the credentials `cred`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `cred` is valid for the duration of the call.
+    pub unsafe fn from_raw(cred: *const bindings::cred) -> Self {
+        // SAFETY: `cred` is valid by the safety requirements of this function. The fields of a
+        // credential are never changed after it was committed, so there is no data race.
+        unsafe {
+            TaskCreds {
+                uid: (*cred).uid.val,
+                euid: (*cred).euid.val,
+                gid: (*cred).gid.val,
+                egid: (*cred).egid.val,
+                cap_inheritable: (*cred).cap_inheritable.val,
+                cap_permitted: (*cred).cap_permitted.val,
+                cap_effective: (*cred).cap_effective.val,
+                cap_bset: (*cred).cap_bset.val,
+                cap_ambient: (*cred).cap_ambient.val,
+                securebits: (*cred).securebits,
+                secid: Credential::from_ptr(cred).get_secid(),
+            }
+        }
+    }
+}
+
+/// Gives the CPU to another task if a reschedule is pending.
+///
+/// Called in the long loops of the scanners, which run in process context and must not hog
+/// the CPU on a kernel without preemption. It may sleep.
+pub fn cond_resched() {
+    // SAFETY: Just an FFI call, the caller is in a context which can sleep.
+    unsafe { bindings::cond_resched() };
+}
+
 /// The type of user identifiers (UIDs).
 #[derive(Copy, Clone)]
 pub struct Kuid {
@@ -265,6 +335,51 @@ pub fn group_leader(&self) -> &Task {
         unsafe { &*ptr.cast() }
     }
 
+    /// Returns whether the given task is still hashed, in the lists of the tasks.
+    ///
+    /// Its parent links are only valid while it is, see `pid_alive`.
+    pub fn pid_alive(&self) -> bool {
+        // SAFETY: By the type invariant, we know that `self.0` is valid. `thread_pid` is only
+        // cleared by `__unhash_process` under the `tasklist_lock`, we only take a snapshot.
+        !unsafe { ptr::read_volatile(ptr::addr_of!((*self.as_ptr()).thread_pid)) }.is_null()
+    }
+
+    /// Returns the real parent of the given task, the one which created it.
+    ///
+    /// The idle task is its own parent. `None` once the task was unhashed: its parent may
+    /// already be released.
+    pub fn real_parent(&self) -> Option<ARef<Task>> {
+        let _guard = rcu::read_lock();
+        if !self.pid_alive() {
+            return None;
+        }
+        // SAFETY: By the type invariant, we know that `self.0` is valid. `real_parent` is
+        // protected by RCU and the task is still hashed: the parent is valid until the end of
+        // the read side critical section, so we can take a reference.
+        unsafe {
+            let parent = ptr::read_volatile(ptr::addr_of!((*self.as_ptr()).real_parent));
+            Some(ARef::from(&*parent.cast::<Task>()))
+        }
+    }
+
+    /// Returns the parent of the given task, the one notified of its exit.
+    ///
+    /// It differs from the real parent while the task is traced. `None` once the task was
+    /// unhashed: its parent may already be released.
+    pub fn parent(&self) -> Option<ARef<Task>> {
+        let _guard = rcu::read_lock();
+        if !self.pid_alive() {
+            return None;
+        }
+        // SAFETY: By the type invariant, we know that `self.0` is valid. `parent` is protected
+        // by RCU and the task is still hashed: the parent is valid until the end of the read
+        // side critical section, so we can take a reference.
+        unsafe {
+            let parent = ptr::read_volatile(ptr::addr_of!((*self.as_ptr()).parent));
+            Some(ARef::from(&*parent.cast::<Task>()))
+        }
+    }
+
     /// Returns the PID of the given task.
     pub fn pid(&self) -> Pid {
         // SAFETY: The pid of a task never changes after initialization, so reading this field is
@@ -272,6 +387,15 @@ pub fn pid(&self) -> Pid {
         unsafe { *ptr::addr_of!((*self.as_ptr()).pid) }
     }
 
+    /// Returns the time the given task was started at, in nanoseconds of monotonic time.
+    ///
+    /// Together with the PID it identifies a task across PID reuse.
+    pub fn start_time(&self) -> u64 {
+        // SAFETY: The start time of a task never changes after initialization, so reading this
+        // field is not a data race.
+        unsafe { *ptr::addr_of!((*self.as_ptr()).start_time) }
+    }
+
     /// Returns the UID of the given task.
     pub fn uid(&self) -> Kuid {
         // SAFETY: It's always safe to call `task_uid` on a valid task.
@@ -284,6 +408,48 @@ pub fn euid(&self) -> Kuid {
         Kuid::from_raw(unsafe { bindings::task_euid(self.as_ptr()) })
     }
 
+    /// Returns a snapshot of the command name of the given task, null terminated.
+    pub fn comm(&self) -> [u8; TASK_COMM_LEN] {
+        let mut comm = [0u8; TASK_COMM_LEN];
+        // SAFETY: By the type invariant, we know that `self.0` is valid. `comm` is always null
+        // terminated, it may be modified concurrently so we only take a snapshot.
+        let src = unsafe { &*ptr::addr_of!((*self.as_ptr()).comm) };
+        for (dst, src) in comm.iter_mut().zip(src.iter()) {
+            *dst = *src as u8;
+        }
+        comm[TASK_COMM_LEN - 1] = 0;
+        comm
+    }
+
+    /// Determines whether the given task is a kernel thread.
+    pub fn is_kthread(&self) -> bool {
+        // SAFETY: By the type invariant, we know that `self.0` is valid. `PF_KTHREAD` is set when
+        // the task is created and only cleared by the exec of the init process.
+        let flags = unsafe { *ptr::addr_of!((*self.as_ptr()).flags) };
+        flags & bindings::PF_KTHREAD != 0
+    }
+
+    /// Determines whether the given task exited, it is a zombie or being reaped.
+    pub fn has_exited(&self) -> bool {
+        // SAFETY: By the type invariant, we know that `self.0` is valid. `exit_state` is only set
+        // once the task exits, we only take a snapshot.
+        unsafe { ptr::read_volatile(ptr::addr_of!((*self.as_ptr()).exit_state)) != 0 }
+    }
+
+    /// Returns a snapshot of the credentials of the given task.
+    ///
+    /// These are the objective credentials (`__task_cred`), the ones used when the task is acted
+    /// upon.
+    pub fn creds(&self) -> TaskCreds {
+        let _guard = rcu::read_lock();
+        // SAFETY: By the type invariant, we know that `self.0` is valid. `real_cred` is protected by
+        // RCU: the credentials it points to stay valid until the end of the read side critical
+        // section.
+        let cred = unsafe { ptr::read_volatile(ptr::addr_of!((*self.as_ptr()).real_cred)) };
+        // SAFETY: `cred` is valid while we hold the RCU read lock, see above.
+        unsafe { TaskCreds::from_raw(cred) }
+    }
+
     /// Determines whether the given task has pending signals.
     pub fn signal_pending(&self) -> bool {
         // SAFETY: It's always safe to call `signal_pending` on a valid task.
@@ -318,6 +484,22 @@ pub fn tgid_nr_ns(&self, pidns: Option<&PidNamespace>) -> Pid {
         unsafe { bindings::task_tgid_nr_ns(self.0.get(), pidns) }
     }
 
+    /// Returns the given task's thread id in the provided pid namespace.
+    ///
+    /// Returns 0 if the task isn't visible in the pid namespace.
+    #[doc(alias = "task_pid_nr_ns")]
+    pub fn pid_nr_ns(&self, pidns: Option<&PidNamespace>) -> Pid {
+        let pidns = match pidns {
+            Some(pidns) => pidns.as_ptr(),
+            None => core::ptr::null_mut(),
+        };
+        // SAFETY: By the type invariant, we know that `self.0` is valid. We received a valid
+        // PidNamespace that we can use as a pointer or we received an empty PidNamespace and
+        // thus pass a null pointer. The underlying C function is safe to be used with NULL
+        // pointers.
+        unsafe { bindings::task_pid_nr_ns(self.0.get(), pidns) }
+    }
+
     /// Wakes up the task.
     pub fn wake_up(&self) {
         // SAFETY: It's always safe to call `signal_pending` on a valid task, even if the task
@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Task cgroup : the cgroup of a task and the container it belongs to
+//!
+//! On a Kubernetes node a host pid is useless to the responder, a finding must be
+//! attributed to a container and to its pod. [`Task::cgroup_path`] gets the path of the
+//! cgroup of a task in the default hierarchy, the way `/proc/<pid>/cgroup` shows it, and
+//! [`container`] extracts the container id and the pod uid the runtimes write in it:
+//! - `/system.slice/docker-<id>.scope` (docker with the systemd driver)
+//! - `/docker/<id>` (docker with the cgroupfs driver)
+//! - `/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod<uid>.slice/cri-containerd-<id>.scope`
+//! - `/kubepods/burstable/pod<uid>/<id>`
+//!
+//! [`Attribution`] formats a cgroup path for the reports.
+//!
+//! C header: [`include/linux/cgroup.h`](../../../../include/linux/cgroup.h)
+
+use core::fmt;
+#[cfg(CONFIG_CGROUPS)]
+use core::ptr;
+
+use crate::str::BStr;
+use crate::task::Task;
+use kernel::prelude::*;
+
+/// Length of a container id
+const CONTAINER_ID_LEN: usize = 64;
+
+/// Length of a container id in the reports, as shown by `docker ps`
+const SHORT_ID_LEN: usize = 12;
+
+/// The container a cgroup belongs to
+pub struct Container<'a> {
+    /// Id of the container, 64 hexadecimal digits
+    pub id: &'a [u8],
+    /// Uid of the pod of the container, with `_` instead of `-` for the systemd driver
+    pub pod: Option<&'a [u8]>,
+}
+
+/// Get the id of the container from the cgroup path component `component`, if any
+fn container_id(component: &[u8]) -> Option<&[u8]> {
+    let name = component.strip_suffix(b".scope").unwrap_or(component);
+    // `docker-<id>`, `cri-containerd-<id>`, `crio-<id>`, `libpod-<id>`
+    let start = name.iter().rposition(|c| *c == b'-').map_or(0, |i| i + 1);
+    let id = &name[start..];
+    (id.len() == CONTAINER_ID_LEN && id.iter().all(u8::is_ascii_hexdigit)).then_some(id)
+}
+
+/// Get the uid of the pod from the cgroup path component `component`, if any
+fn pod_uid(component: &[u8]) -> Option<&[u8]> {
+    let name = component.strip_suffix(b".slice").unwrap_or(component);
+    // `kubepods-<qos>-pod<uid>` or `pod<uid>`
+    let start = name.windows(3).rposition(|w| w == b"pod")? + 3;
+    let uid = &name[start..];
+    (!uid.is_empty() && (start == 3 || name[start - 4] == b'-')).then_some(uid)
+}
+
+/// Get the container the cgroup `path` belongs to, if any
+pub fn container(path: &[u8]) -> Option<Container<'_>> {
+    let mut pod = None;
+    let mut id = None;
+    for component in path.split(|c| *c == b'/') {
+        if let Some(uid) = pod_uid(component) {
+            pod = Some(uid);
+        } else if let Some(container) = container_id(component) {
+            id = Some(container);
+        }
+    }
+    Some(Container { id: id?, pod })
+}
+
+/// A cgroup path formatted for a report: the container and the pod it belongs to, or the
+/// path itself
+pub struct Attribution<'a>(pub &'a [u8]);
+
+impl fmt::Display for Attribution<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(container) = container(self.0) else {
+            return write!(f, "cgroup {}", BStr::from_bytes(self.0));
+        };
+        write!(
+            f,
+            "container {}",
+            BStr::from_bytes(&container.id[..SHORT_ID_LEN])
+        )?;
+        if let Some(pod) = container.pod {
+            write!(f, " pod {}", BStr::from_bytes(pod))?;
+        }
+        Ok(())
+    }
+}
+
+impl Task {
+    /// Get the path of the cgroup of the task in the default hierarchy, from the root of the
+    /// initial cgroup namespace
+    ///
+    /// `task_cgroup_path` would give the path in the first mounted hierarchy, a v1 one on a
+    /// hybrid setup. The cgroup of the task is looked up under RCU, its css_set may be switched
+    /// by a migration but the cgroup is only released after a grace period, and
+    /// `cgroup_path_ns` walks it under the `css_set_lock`. The path is empty without cgroup
+    /// support.
+    pub fn cgroup_path(&self) -> Result<KVec<u8>> {
+        #[cfg(not(CONFIG_CGROUPS))]
+        {
+            Ok(KVec::new())
+        }
+
+        #[cfg(CONFIG_CGROUPS)]
+        {
+            use crate::{error::to_result, sync::rcu, task_files::PATH_MAX};
+
+            let mut buf = KVec::from_elem(0u8, PATH_MAX, GFP_KERNEL)?;
+            {
+                let _guard = rcu::read_lock();
+                // SAFETY: The task is valid by the type invariant, its css_set is read under the
+                // RCU read lock
+                let cgroup = unsafe { bindings::task_dfl_cgroup(self.as_ptr()) };
+                // SAFETY: Just an FFI call, the cgroup is valid until the end of the read side
+                // critical section, `cgroup_path_ns` doesn't sleep and `buf` is valid for
+                // `PATH_MAX` bytes. The path written is null terminated
+                let len = unsafe {
+                    bindings::cgroup_path_ns(
+                        cgroup,
+                        buf.as_mut_ptr().cast(),
+                        PATH_MAX,
+                        ptr::addr_of_mut!(bindings::init_cgroup_ns),
+                    )
+                };
+                to_result(len)?;
+            }
+            let len = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+            let mut path = KVec::with_capacity(len, GFP_KERNEL)?;
+            path.extend_from_slice(&buf[..len], GFP_KERNEL)?;
+            Ok(path)
+        }
+    }
+}
@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Task files : the open file descriptors of a task
+//!
+//! [`Task::files`] walks the file descriptor table of a task with `fget_task_next`, each
+//! open file is referenced, its path is resolved with `d_path` and its type is deduced
+//! from its inode: a shell whose standard streams are sockets or a task holding
+//! `/dev/mem` or a raw socket is found this way.
+//!
+//! The table isn't locked between two descriptors, a descriptor opened or closed during
+//! the walk may be missed.
+//!
+//! [`Task::exe_path`] and [`Task::cmdline`] identify the binary run by a task in the
+//! reports, a process id alone is useless once the process exited.
+//!
+//! C header: [`include/linux/fdtable.h`](../../../../include/linux/fdtable.h)
+
+use core::fmt;
+use core::ptr;
+
+use crate::error::from_err_ptr;
+use crate::fs::File;
+use crate::page::PAGE_SIZE;
+use crate::str::BStr;
+use crate::task::Task;
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Maximum length of a path, including its null terminator (`PATH_MAX`)
+pub const PATH_MAX: usize = bindings::PATH_MAX as usize;
+
+/// Number of bits of the minor in a device number (`MINORBITS`)
+const MINOR_BITS: u32 = 20;
+
+/// Type of an open file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileKind {
+    /// A regular file
+    Regular,
+    /// A directory
+    Directory,
+    /// A pipe or a FIFO
+    Pipe,
+    /// A socket
+    Socket {
+        /// The address family, `AF_*`
+        family: u16,
+        /// The socket type, `SOCK_*`
+        kind: i16,
+    },
+    /// A character device
+    CharDevice {
+        /// Major number of the device
+        major: u32,
+        /// Minor number of the device
+        minor: u32,
+    },
+    /// A block device
+    BlockDevice {
+        /// Major number of the device
+        major: u32,
+        /// Minor number of the device
+        minor: u32,
+    },
+    /// A file of the anonymous inode file system (eventfd, epoll, BPF objects, ...)
+    AnonInode,
+    /// Any other file (symbolic link opened with `O_PATH`)
+    Other,
+}
+
+impl FileKind {
+    /// Get the type of `file`
+    pub fn of(file: &File) -> Self {
+        // SAFETY: The file is referenced, its inode and the super block of the inode are valid
+        // as long as the file is. The mode and the device number never change
+        let (mode, rdev, magic) = unsafe {
+            let inode = (*file.as_ptr()).f_inode;
+            (
+                (*inode).i_mode as u32,
+                (*inode).i_rdev,
+                (*(*inode).i_sb).s_magic,
+            )
+        };
+        let (major, minor) = (rdev >> MINOR_BITS, rdev & ((1 << MINOR_BITS) - 1));
+
+        match mode & bindings::S_IFMT {
+            _ if magic == bindings::ANON_INODE_FS_MAGIC as _ => FileKind::AnonInode,
+            bindings::S_IFREG => FileKind::Regular,
+            bindings::S_IFDIR => FileKind::Directory,
+            bindings::S_IFIFO => FileKind::Pipe,
+            bindings::S_IFSOCK => socket_kind(file),
+            bindings::S_IFCHR => FileKind::CharDevice { major, minor },
+            bindings::S_IFBLK => FileKind::BlockDevice { major, minor },
+            _ => FileKind::Other,
+        }
+    }
+}
+
+/// Get the family and the type of the socket `file`
+fn socket_kind(file: &File) -> FileKind {
+    // SAFETY: Just an FFI call, the file is referenced
+    let sock = unsafe { bindings::sock_from_file(file.as_ptr()) };
+    if sock.is_null() {
+        return FileKind::Other;
+    }
+    // SAFETY: The socket lives as long as its file, the type and the family of a socket never
+    // change once created
+    unsafe {
+        let sk = (*sock).sk;
+        FileKind::Socket {
+            family: if sk.is_null() {
+                0
+            } else {
+                (*sk).__sk_common.skc_family
+            },
+            kind: (*sock).type_,
+        }
+    }
+}
+
+/// Resolve the path of `file` with `d_path`, `buf` is the storage of the resolution
+///
+/// The path of an unlinked file ends with ` (deleted)`.
+pub fn file_path(file: &File, buf: &mut [u8]) -> Result<KVec<u8>> {
+    // SAFETY: The file is referenced so is its path, `d_path` writes inside `buf`
+    let start = from_err_ptr(unsafe {
+        bindings::d_path(
+            ptr::addr_of!((*file.as_ptr()).f_path),
+            buf.as_mut_ptr().cast(),
+            buf.len() as _,
+        )
+    })?;
+    // The path is written at the end of `buf`, null terminated
+    let path = &buf[start as usize - buf.as_ptr() as usize..];
+    let len = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+
+    let mut resolved = KVec::with_capacity(len, GFP_KERNEL)?;
+    resolved.extend_from_slice(&path[..len], GFP_KERNEL)?;
+    Ok(resolved)
+}
+
+/// An open file descriptor of a task
+pub struct OpenFile {
+    /// The file descriptor
+    pub fd: u32,
+    /// The open file
+    pub file: ARef<File>,
+    /// The path of the file
+    pub path: KVec<u8>,
+    /// The type of the file
+    pub kind: FileKind,
+}
+
+impl fmt::Display for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} ({:?})",
+            self.fd,
+            BStr::from_bytes(&self.path),
+            self.kind
+        )
+    }
+}
+
+/// Iterator over the open file descriptors of a task, in increasing order
+pub struct FdIter {
+    task: ARef<Task>,
+    /// The next file descriptor to look up
+    fd: u32,
+    /// Storage of the resolution of the paths
+    buf: KVec<u8>,
+}
+
+impl Task {
+    /// Iterate over the open file descriptors of the task
+    pub fn files(&self) -> Result<FdIter> {
+        Ok(FdIter {
+            task: self.into(),
+            fd: 0,
+            buf: KVec::from_elem(0u8, PATH_MAX, GFP_KERNEL)?,
+        })
+    }
+
+    /// Get the executable run by the task (`mm->exe_file`), `None` for a kernel thread or an
+    /// exiting task
+    pub fn exe_file(&self) -> Option<ARef<File>> {
+        // SAFETY: Just an FFI call, the task is valid. The returned file is referenced
+        let file = unsafe { bindings::get_task_exe_file(self.as_ptr()) };
+        // SAFETY: We own the reference taken by `get_task_exe_file`. `File` is a transparent
+        // wrapper of `struct file`
+        ptr::NonNull::new(file).map(|file| unsafe { ARef::from_raw(file.cast()) })
+    }
+
+    /// Get the path of the executable run by the task, `None` for a kernel thread or an
+    /// exiting task
+    pub fn exe_path(&self) -> Result<Option<KVec<u8>>> {
+        let Some(file) = self.exe_file() else {
+            return Ok(None);
+        };
+        let mut buf = KVec::from_elem(0u8, PATH_MAX, GFP_KERNEL)?;
+        Ok(Some(file_path(&file, &mut buf)?))
+    }
+
+    /// Get the command line of the task, its arguments separated by spaces
+    ///
+    /// The command line is read from the memory of the process and is truncated to a page,
+    /// it is empty for a kernel thread. The process can rewrite it, it is only a hint.
+    pub fn cmdline(&self) -> Result<KVec<u8>> {
+        let mut cmdline = KVec::from_elem(0u8, PAGE_SIZE, GFP_KERNEL)?;
+        // SAFETY: Just an FFI call, the task is valid and `get_cmdline` writes at most
+        // `PAGE_SIZE` bytes inside `cmdline`
+        let len = unsafe {
+            bindings::get_cmdline(self.as_ptr(), cmdline.as_mut_ptr().cast(), PAGE_SIZE as _)
+        };
+        let mut len = (len.max(0) as usize).min(PAGE_SIZE);
+
+        // The arguments are null terminated
+        while len > 0 && cmdline[len - 1] == 0 {
+            len -= 1;
+        }
+        for c in cmdline[..len].iter_mut() {
+            if *c == 0 {
+                *c = b' ';
+            }
+        }
+        let mut args = KVec::with_capacity(len, GFP_KERNEL)?;
+        args.extend_from_slice(&cmdline[..len], GFP_KERNEL)?;
+        Ok(args)
+    }
+}
+
+impl Iterator for FdIter {
+    type Item = Result<OpenFile>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut fd = self.fd;
+        // SAFETY: Just an FFI call, the task is referenced. The returned file is referenced
+        let file = unsafe { bindings::fget_task_next(self.task.as_ptr(), &mut fd) };
+        let file = ptr::NonNull::new(file)?;
+        self.fd = fd + 1;
+
+        // SAFETY: We own the reference taken by `fget_task_next`. `File` is a transparent
+        // wrapper of `struct file`
+        let file: ARef<File> = unsafe { ARef::from_raw(file.cast()) };
+        let path = match file_path(&file, &mut self.buf) {
+            Ok(path) => path,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(Ok(OpenFile {
+            fd,
+            kind: FileKind::of(&file),
+            file,
+            path,
+        }))
+    }
+}
@@ -1,9 +1,22 @@
 //! Implement iterator for the tasks's linked list
+//!
+//! [`TaskIter`] walks the list of the processes, [`ThreadIter`] the threads of a process
+//! (`for_each_thread`) and [`AllThreadsIter`] every thread of the system
+//! (`for_each_process_thread`). [`Task::children`] and [`AncestorIter`] walk the lineage of
+//! a task.
 use core::iter::Iterator;
+use core::ptr;
 
-use crate::{task::Task, types::ARef};
+use crate::{container_of, prelude::*, sync::rcu, task::Task, types::ARef};
 
 /// Implement the Iterator trait for `ARef<Task>`
+///
+/// Each step is done under the RCU read lock, the yielded processes are referenced and the
+/// ones being reaped (`EXIT_DEAD`) are skipped. The link of a process unhashed since it was
+/// yielded isn't guaranteed to be valid anymore: the walk resumes after the last process
+/// still in the list which was forked before it, the list being in fork order. Two
+/// concurrent forks may be inverted, a process may then be missed or yielded twice. The
+/// iteration ends early if the origin exits.
 pub struct TaskIter {
     task: Option<ARef<Task>>,
     task_origin: ARef<Task>,
@@ -21,49 +34,239 @@ fn into_iter(self) -> Self::IntoIter {
     }
 }
 
+/// Get the process following `task` in the list of the processes
+///
+/// # Safety
+///
+/// `task` must be in the list, or be `init_task`, and the RCU read lock must be held
+unsafe fn next_process(task: *mut bindings::task_struct) -> *mut bindings::task_struct {
+    // SAFETY: By the safety requirements, the successor of `task` is valid until the end of
+    // the RCU read side critical section
+    unsafe { bindings::next_task(task as *const _) }
+}
+
+/// The task is being reaped, its resources are already released
+///
+/// # Safety
+///
+/// `task` must be valid
+unsafe fn is_dead(task: *mut bindings::task_struct) -> bool {
+    // SAFETY: `task` is valid by the safety requirements, we only take a snapshot
+    let exit_state = unsafe { ptr::read_volatile(ptr::addr_of!((*task).exit_state)) };
+    exit_state as u32 & bindings::EXIT_DEAD != 0
+}
+
+impl TaskIter {
+    /// Get the last process still in the list which was forked before `task`
+    ///
+    /// The RCU read lock must be held.
+    fn resume_point(task: &Task) -> *mut bindings::task_struct {
+        let start_time = task.start_time();
+        let init = ptr::addr_of_mut!(bindings::init_task);
+        let mut cursor = init;
+        loop {
+            // SAFETY: `cursor` is `init_task` or a process found in the list during this RCU
+            // read side critical section
+            let next = unsafe { next_process(cursor) };
+            if next.is_null() || next == init {
+                return cursor;
+            }
+            // SAFETY: `next` is in the list, valid until the end of the critical section
+            if unsafe { (*next).start_time } > start_time {
+                return cursor;
+            }
+            cursor = next;
+        }
+    }
+}
+
 impl Iterator for TaskIter {
     type Item = ARef<Task>;
     fn next(&mut self) -> Option<Self::Item> {
-        let next_task: *mut Task = if let Some(task) = &self.task {
+        let _guard = rcu::read_lock();
+        let origin = self.task_origin.as_ptr();
+        if !self.task_origin.pid_alive() {
+            return None;
+        }
+
+        let mut cursor = match &self.task {
             // We made it around the linked list
-            if task.as_ptr() == self.task_origin.as_ptr() {
+            Some(task) if task.as_ptr() == origin => return None,
+            Some(task) if task.pid_alive() => task.as_ptr(),
+            Some(task) => Self::resume_point(task),
+            None => origin,
+        };
+        loop {
+            // SAFETY: `cursor` is the origin or a process which is still in the list, or was
+            // found in it during this RCU read side critical section
+            let next = unsafe { next_process(cursor) };
+            if next.is_null() {
                 return None;
             }
-            // SAFETY: For the FFI call: the underlying macro
-            // is safe to use and use the good synchronization macro
-            // For the casting
-            unsafe { bindings::next_task(task.as_ptr() as *const _) }.cast()
-        } else {
-            // SAFETY: For the FFI call: the underlying macro
-            // is safe to use and use the good synchronization macro
-            // For the casting
-
-            unsafe { bindings::next_task(self.task_origin.as_ptr() as *const _) }.cast()
+            // SAFETY: `next` was found in the list, valid until the end of the critical section
+            if next != origin && unsafe { is_dead(next) } {
+                cursor = next;
+                continue;
+            }
+            // SAFETY: The process is freed after a RCU grace period once removed from the list,
+            // it is valid for the duration of the critical section so we can take a reference
+            let next = ARef::from(unsafe { &*next.cast::<Task>() });
+            self.task = Some(next.clone());
+            return Some(next);
+        }
+    }
+}
+
+/// Iterator over the threads of a process, the leader included
+///
+/// Each step is done under the RCU read lock and the yielded threads are referenced. The
+/// iteration stops early if the last yielded thread exited in between: its link to the
+/// next thread isn't guaranteed to be valid anymore.
+pub struct ThreadIter {
+    thread: Option<ARef<Task>>,
+    /// A thread of the process, its reference keeps the `signal_struct` alive
+    process: ARef<Task>,
+    done: bool,
+}
+
+impl ThreadIter {
+    /// Iterate over the threads of the process of `task`
+    pub fn new(task: ARef<Task>) -> Self {
+        ThreadIter {
+            thread: None,
+            process: task,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for ThreadIter {
+    type Item = ARef<Task>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let _guard = rcu::read_lock();
+
+        // SAFETY: The `signal_struct` is only freed with the last task referencing it, we
+        // hold a reference on `process`
+        let head = unsafe { ptr::addr_of_mut!((*(*self.process.as_ptr()).signal).thread_head) };
+        let node = match &self.thread {
+            Some(thread) if !thread.pid_alive() => ptr::null_mut(),
+            // SAFETY: The thread is still in the list, its successor is valid until the end
+            // of the RCU read side critical section
+            Some(thread) => unsafe {
+                ptr::read_volatile(ptr::addr_of!((*thread.as_ptr()).thread_node.next))
+            },
+            // SAFETY: The head lives in the `signal_struct`, see above
+            None => unsafe { ptr::read_volatile(ptr::addr_of!((*head).next)) },
         };
+        // We made it around the linked list
+        if node.is_null() || node == head {
+            self.done = true;
+            self.thread = None;
+            return None;
+        }
 
-        if !next_task.is_null() {
-            // SAFETY: The reference is not null and aligned according to C guaranty
-            // However it may be aliased...
-            let next_task = ARef::from(unsafe { &*next_task });
+        // SAFETY: Every node of the list except the head is the `thread_node` of a task
+        let next_thread = unsafe { container_of!(node, bindings::task_struct, thread_node) };
+        // SAFETY: The task is freed after a RCU grace period once removed from the list, it
+        // is valid for the duration of the critical section so we can take a reference
+        let next_thread = ARef::from(unsafe { &*next_thread.cast::<Task>() });
+        self.thread = Some(next_thread.clone());
+        Some(next_thread)
+    }
+}
 
-            self.task = Some(next_task.clone());
+/// Iterator over every thread of the system, process by process
+pub struct AllThreadsIter {
+    processes: TaskIter,
+    threads: Option<ThreadIter>,
+}
+
+impl AllThreadsIter {
+    /// Iterate over the threads of every process, starting after the process of `task`
+    /// and ending with it
+    pub fn new(task: ARef<Task>) -> Self {
+        AllThreadsIter {
+            // Only the group leaders are linked in the list of the processes
+            processes: ARef::from(task.group_leader()).into_iter(),
+            threads: None,
+        }
+    }
+}
 
-            Some(next_task)
-        } else {
-            None
+impl Iterator for AllThreadsIter {
+    type Item = ARef<Task>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(thread) = self.threads.as_mut().and_then(Iterator::next) {
+                return Some(thread);
+            }
+            let process = self.processes.next()?;
+            self.threads = Some(ThreadIter::new(process));
         }
+    }
+}
 
-        /*
-        // SAFETY: The pointer is valid we just increment it's refcount
-        unsafe { (*next_task).inc_ref() };
+impl Task {
+    /// Returns the children of the task, the tasks it created and the ones reparented to it
+    ///
+    /// Only the leaders of the children processes are listed. The list is copied under
+    /// `tasklist_lock`, the children may exit afterwards.
+    pub fn children(&self) -> Result<KVec<ARef<Task>>> {
+        let mut children = KVec::new();
+        let lock = ptr::addr_of_mut!(bindings::tasklist_lock);
+        // SAFETY: Just an FFI call, `tasklist_lock` is a static lock
+        unsafe { bindings::_raw_read_lock(lock) };
 
-        let next_task = match NonNull::<Task>::new(next_task) {
-            // SAFETY: We incrmeented the recount above
-            Some(non_null) => unsafe { ARef::from_raw(non_null) },
-            None => return None,
-        };
-        self.task = Some(next_task.clone());
-        Some(next_task)
-        */
+        // SAFETY: The task is valid. The `children` list is protected by `tasklist_lock`, we
+        // hold it for reading until the end of the walk
+        let head = unsafe { ptr::addr_of_mut!((*self.as_ptr()).children) };
+        // SAFETY: See above
+        let mut node = unsafe { (*head).next };
+        let mut ret = Ok(());
+        while node != head {
+            // SAFETY: Every node of the list except the head is the `sibling` of a task, the
+            // task is valid while we hold `tasklist_lock` so we can take a reference
+            let child =
+                unsafe { &*container_of!(node, bindings::task_struct, sibling).cast::<Task>() };
+            // We can't sleep while holding the lock
+            if let Err(e) = children.push(ARef::from(child), GFP_ATOMIC) {
+                ret = Err(e.into());
+                break;
+            }
+            // SAFETY: See above
+            node = unsafe { (*node).next };
+        }
+
+        // SAFETY: We took the lock above
+        unsafe { bindings::_raw_read_unlock(lock) };
+        ret.map(|_| children)
+    }
+
+    /// Iterate over the ancestors of the task, from its real parent to the idle task
+    pub fn ancestors(&self) -> AncestorIter {
+        AncestorIter { task: self.into() }
+    }
+}
+
+/// Iterator over the ancestors of a task, following the real parents
+///
+/// A task whose parent exited is reparented to a subreaper or to init, the walk ends at the
+/// idle task, its own parent, or early at an ancestor which exited in between.
+pub struct AncestorIter {
+    task: ARef<Task>,
+}
+
+impl Iterator for AncestorIter {
+    type Item = ARef<Task>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let parent = self.task.real_parent()?;
+        if parent.as_ptr() == self.task.as_ptr() {
+            return None;
+        }
+        self.task = parent.clone();
+        Some(parent)
     }
 }
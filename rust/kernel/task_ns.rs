@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Task namespaces : the namespaces a task belongs to
+//!
+//! [`Task::namespaces`] gets the namespaces the way `/proc/<pid>/ns` does, through the
+//! `get` operation of each `proc_ns_operations`, and identifies each one by the inode
+//! number shown by `/proc`. A namespace type disabled in the configuration is reported with
+//! the inode number of its initial namespace (0 for the network one), a task can't leave
+//! it.
+//!
+//! A task has a pid in its pid namespace and in each of its ancestors. [`Task::ns_pids`]
+//! lists them (the `NSpid` line of `/proc/<pid>/status`), [`find_task_in`] translates a pid
+//! of a namespace back to its task: a check compares both to diff the kernel view with the
+//! view of `/proc` mounted inside each namespace.
+//!
+//! C header: [`include/linux/proc_ns.h`](../../../../include/linux/proc_ns.h)
+
+use core::fmt;
+use core::ptr;
+
+use crate::pid_namespace::PidNamespace;
+use crate::sync::rcu;
+use crate::task::Task;
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// The namespaces of a task, identified by their inode number
+///
+/// An inode number is 0 when the namespace couldn't be taken, the task is exiting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Namespaces {
+    /// The pid namespace of the task, not the one of its future children
+    pub pid: u32,
+    /// The mount namespace
+    pub mnt: u32,
+    /// The network namespace
+    pub net: u32,
+    /// The user namespace
+    pub user: u32,
+    /// The UTS namespace
+    pub uts: u32,
+    /// The IPC namespace
+    pub ipc: u32,
+    /// The cgroup namespace
+    pub cgroup: u32,
+}
+
+impl Namespaces {
+    /// The pid, mount, network or user namespace differ from the ones of `other`
+    pub fn isolated_from(&self, other: &Namespaces) -> bool {
+        self.pid != other.pid
+            || self.mnt != other.mnt
+            || self.net != other.net
+            || self.user != other.user
+    }
+}
+
+impl fmt::Display for Namespaces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pid:[{}] mnt:[{}] net:[{}] user:[{}] uts:[{}] ipc:[{}] cgroup:[{}]",
+            self.pid, self.mnt, self.net, self.user, self.uts, self.ipc, self.cgroup
+        )
+    }
+}
+
+/// The pid of a task in one of the pid namespaces it is visible in
+pub struct NsPid {
+    /// The pid namespace
+    pub ns: ARef<PidNamespace>,
+    /// Depth of the namespace, 0 for the initial one
+    pub level: u32,
+    /// The pid of the task in the namespace
+    pub nr: i32,
+}
+
+/// Find the task whose pid in the pid namespace `ns` is `nr`, the way `/proc` mounted for
+/// `ns` does
+pub fn find_task_in(ns: &PidNamespace, nr: i32) -> Option<ARef<Task>> {
+    let _guard = rcu::read_lock();
+    // SAFETY: The namespace is valid, the pids are looked up under RCU and the task found is
+    // valid until the end of the read side critical section, so we can take a reference
+    unsafe {
+        let pid = bindings::find_pid_ns(nr, ns.as_ptr());
+        if pid.is_null() {
+            return None;
+        }
+        let task = bindings::pid_task(pid, bindings::pid_type_PIDTYPE_PID);
+        (!task.is_null()).then(|| ARef::from(&*task.cast::<Task>()))
+    }
+}
+
+/// Get the inode number of the namespace of `task` given by `ops`
+fn ns_inum(task: &Task, ops: &bindings::proc_ns_operations) -> u32 {
+    let (Some(get), Some(put)) = (ops.get, ops.put) else {
+        return 0;
+    };
+    // SAFETY: The task is valid, `get` takes a reference on its namespace
+    let ns = unsafe { get(task.as_ptr()) };
+    if ns.is_null() {
+        return 0;
+    }
+    // SAFETY: We hold the reference taken by `get`, the inode number never changes
+    let inum = unsafe { (*ns).inum };
+    // SAFETY: We drop the reference taken by `get`
+    unsafe { put(ns) };
+    inum
+}
+
+impl Task {
+    /// Get the namespaces of the task
+    pub fn namespaces(&self) -> Namespaces {
+        // SAFETY: The operations are constant, defined for each namespace type enabled
+        unsafe {
+            Namespaces {
+                #[cfg(CONFIG_PID_NS)]
+                pid: ns_inum(self, &*ptr::addr_of!(bindings::pidns_operations)),
+                #[cfg(not(CONFIG_PID_NS))]
+                pid: bindings::PROC_PID_INIT_INO,
+                mnt: ns_inum(self, &*ptr::addr_of!(bindings::mntns_operations)),
+                #[cfg(CONFIG_NET_NS)]
+                net: ns_inum(self, &*ptr::addr_of!(bindings::netns_operations)),
+                #[cfg(not(CONFIG_NET_NS))]
+                net: 0,
+                #[cfg(CONFIG_USER_NS)]
+                user: ns_inum(self, &*ptr::addr_of!(bindings::userns_operations)),
+                #[cfg(not(CONFIG_USER_NS))]
+                user: bindings::PROC_USER_INIT_INO,
+                #[cfg(CONFIG_UTS_NS)]
+                uts: ns_inum(self, &*ptr::addr_of!(bindings::utsns_operations)),
+                #[cfg(not(CONFIG_UTS_NS))]
+                uts: bindings::PROC_UTS_INIT_INO,
+                #[cfg(CONFIG_IPC_NS)]
+                ipc: ns_inum(self, &*ptr::addr_of!(bindings::ipcns_operations)),
+                #[cfg(not(CONFIG_IPC_NS))]
+                ipc: bindings::PROC_IPC_INIT_INO,
+                #[cfg(CONFIG_CGROUPS)]
+                cgroup: ns_inum(self, &*ptr::addr_of!(bindings::cgroupns_operations)),
+                #[cfg(not(CONFIG_CGROUPS))]
+                cgroup: bindings::PROC_CGROUP_INIT_INO,
+            }
+        }
+    }
+
+    /// Get the pid of the task in every pid namespace it is visible in, from the initial one
+    /// to its own
+    ///
+    /// The list is empty once the task is unhashed from the pids.
+    pub fn ns_pids(&self) -> Result<KVec<NsPid>> {
+        let mut pids = KVec::new();
+        let _guard = rcu::read_lock();
+        // SAFETY: The task is valid, its `struct pid` is freed after a grace period once it
+        // is unhashed. The pid holds its namespaces, valid while we take a reference on them
+        unsafe {
+            let pid = ptr::read_volatile(ptr::addr_of!((*self.as_ptr()).thread_pid));
+            if pid.is_null() {
+                return Ok(pids);
+            }
+            for level in 0..=(*pid).level {
+                let upid = (*pid).numbers.as_ptr().add(level as usize);
+                pids.push(
+                    NsPid {
+                        ns: ARef::from(PidNamespace::from_ptr((*upid).ns)),
+                        level,
+                        nr: (*upid).nr,
+                    },
+                    GFP_ATOMIC,
+                )?;
+            }
+        }
+        Ok(pids)
+    }
+
+    /// The task is visible in the pid namespace `ns`: its pid in `ns` leads back to it, so
+    /// `/proc` mounted for `ns` lists it
+    pub fn is_visible_in(&self, ns: &PidNamespace) -> bool {
+        let nr = self.pid_nr_ns(Some(ns));
+        nr != 0 && find_task_in(ns, nr).is_some_and(|task| task.as_ptr() == self.as_ptr())
+    }
+}
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Task security : the seccomp state and the LSM label of a task
+//!
+//! [`Task::seccomp`] reads the seccomp mode of a task and its number of filters,
+//! [`Task::process_seccomp`] the weakest of the ones of the threads of its process,
+//! [`Task::security_label`] gets the security context of its objective credentials from
+//! the active LSM (`unconfined_u:unconfined_r:unconfined_t:s0` with SELinux, the profile
+//! name with AppArmor).
+//!
+//! C header: [`include/linux/seccomp.h`](../../../../include/linux/seccomp.h)
+
+use core::fmt;
+
+use crate::security::SecurityCtx;
+use crate::task::Task;
+use crate::task_iter::ThreadIter;
+use kernel::prelude::*;
+
+/// Seccomp mode of a task
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SeccompMode {
+    /// The task isn't filtered
+    Disabled,
+    /// Only `read`, `write`, `exit` and `sigreturn` are allowed
+    Strict,
+    /// The syscalls are filtered by BPF programs
+    Filter,
+    /// A mode outside of the known ones
+    Unknown(i32),
+}
+
+impl SeccompMode {
+    fn from_raw(mode: i32) -> Self {
+        match mode as u32 {
+            bindings::SECCOMP_MODE_DISABLED => SeccompMode::Disabled,
+            bindings::SECCOMP_MODE_STRICT => SeccompMode::Strict,
+            bindings::SECCOMP_MODE_FILTER => SeccompMode::Filter,
+            _ => SeccompMode::Unknown(mode),
+        }
+    }
+}
+
+/// The seccomp state of a task
+///
+/// The state only grows: a mode can't be left and a filter can't be removed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Seccomp {
+    /// The mode
+    pub mode: SeccompMode,
+    /// Number of filters attached to the task
+    pub filters: i32,
+}
+
+impl fmt::Display for Seccomp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({} filters)", self.mode, self.filters)
+    }
+}
+
+impl Task {
+    /// Get the seccomp state of the task
+    pub fn seccomp(&self) -> Seccomp {
+        #[cfg(CONFIG_SECCOMP)]
+        // SAFETY: The task is valid, the state is only read as a snapshot
+        let (mode, filters) = unsafe {
+            use core::ptr::{addr_of, read_volatile};
+            let seccomp = addr_of!((*self.as_ptr()).seccomp);
+            (
+                read_volatile(addr_of!((*seccomp).mode)),
+                read_volatile(addr_of!((*seccomp).filter_count.counter)),
+            )
+        };
+        #[cfg(not(CONFIG_SECCOMP))]
+        let (mode, filters) = (0, 0);
+
+        Seccomp {
+            mode: SeccompMode::from_raw(mode),
+            filters,
+        }
+    }
+
+    /// Get the weakest seccomp state among the threads of the process of the task
+    ///
+    /// The state is per thread, a thread left without its filters is enough to escape them.
+    pub fn process_seccomp(&self) -> Seccomp {
+        ThreadIter::new(self.into())
+            .map(|thread| thread.seccomp())
+            .min_by_key(|seccomp| (seccomp.mode != SeccompMode::Disabled, seccomp.filters))
+            .unwrap_or_else(|| self.seccomp())
+    }
+
+    /// Get the LSM label of the task, the security context of its objective credentials
+    ///
+    /// The label is empty if no LSM provides one.
+    pub fn security_label(&self) -> Result<KVec<u8>> {
+        let secid = self.creds().secid;
+        let mut label = KVec::new();
+        if secid == 0 {
+            return Ok(label);
+        }
+        let ctx = SecurityCtx::from_secid(secid)?;
+        // The context may be null terminated
+        let bytes = ctx.as_bytes();
+        let len = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+        label.extend_from_slice(&bytes[..len], GFP_KERNEL)?;
+        Ok(label)
+    }
+}
@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Text alias : the direct map aliases of the kernel and module text
+//!
+//! Each physical page of the text is also mapped in the direct map, where the kernel
+//! keeps it non executable (and read-only for the kernel image and the protected module
+//! text). A rootkit unable to make the text writable can write through this alias
+//! instead, after making it writable with a `set_memory_rw` on the alias address.
+//!
+//! The pages of the text are found by walking the page tables of the kernel image text
+//! and of the text of the listed modules, their alias with `phys_to_virt`. An
+//! [`AliasBaseline`] records the protections of the aliases, [`AliasBaseline::check`]
+//! reports the executable aliases and the ones made writable or executable since.
+//!
+//! C header: [`arch/x86/include/asm/page.h`](../../../../arch/x86/include/asm/page.h)
+
+use core::fmt;
+
+use crate::address::Owner;
+use crate::c_str;
+use crate::event::{Event, EventKind};
+use crate::module::{symbols_lookup_name, ModuleIter};
+use crate::page::PAGE_SIZE;
+use crate::pgtable::{lookup_address, walk_kernel_range, PgProtFlags, Pgtable};
+use kernel::prelude::*;
+
+/// Maximum number of pages reported
+const MAX_FINDINGS: usize = 1024;
+
+/// What is wrong with an alias
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AliasFinding {
+    /// The alias is executable
+    Executable,
+    /// The alias is writable and executable
+    WritableExecutable,
+    /// The alias was made writable since the baseline
+    Downgraded {
+        /// The protections of the alias in the baseline
+        before: PgProtFlags,
+    },
+}
+
+/// A text page with a suspicious alias
+pub struct TextAlias {
+    /// Address of the page in the text mapping
+    pub text: u64,
+    /// PFN of the page
+    pub pfn: u64,
+    /// Address of the page in the direct map
+    pub alias: u64,
+    /// Owner of the text page
+    pub owner: Owner,
+    /// The current protections of the alias
+    pub flags: PgProtFlags,
+    /// What is wrong with the alias
+    pub finding: AliasFinding,
+}
+
+/// Call `f` with the start, end and owner of the text of the kernel image and of each
+/// listed module
+pub(crate) fn for_each_text_range(mut f: impl FnMut(u64, u64, Owner) -> Result) -> Result {
+    let stext = symbols_lookup_name(c_str!("_stext"));
+    let etext = symbols_lookup_name(c_str!("_etext"));
+    if stext == 0 || etext == 0 {
+        pr_err!("Couldn't find _stext symbol\n");
+        return Err(ENOENT);
+    }
+    f(stext, etext, Owner::Kernel)?;
+
+    for module in ModuleIter::try_new()? {
+        let owner = Owner::Module(*module.raw_name());
+        for region in module.regions().filter(|region| region.mem_type.is_text()) {
+            f(region.base, region.base + region.size as u64, owner)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Call `f` with the address, PFN and owner of each mapped page of the text of the kernel
+/// image and of the listed modules
+fn for_each_text_page(mut f: impl FnMut(u64, u64, Owner) -> Result) -> Result {
+    for_each_text_range(|start, end, owner| {
+        walk_kernel_range(start as usize, end as usize, |entry| {
+            let pfn = entry.level.pfn();
+            let from = (entry.start as u64).max(start);
+            let to = (entry.end as u64).min(end);
+            let mut page = from & !(PAGE_SIZE as u64 - 1);
+            while page < to {
+                f(
+                    page,
+                    pfn + ((page - entry.start as u64) >> bindings::PAGE_SHIFT),
+                    owner,
+                )?;
+                page += PAGE_SIZE as u64;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Get the direct map alias of `pfn` and its protections, `None` if it is not mapped
+fn alias(pfn: u64) -> Option<(u64, PgProtFlags)> {
+    // SAFETY: Just an FFI call, the address is only computed
+    let alias = unsafe { bindings::phys_to_virt((pfn << bindings::PAGE_SHIFT) as _) } as u64;
+    let level = lookup_address(alias as usize).ok()?;
+    Some((alias, PgProtFlags::new(level.pgprot())))
+}
+
+/// The protections of the aliases of the text pages, sorted by PFN
+pub struct AliasBaseline {
+    pages: KVVec<(u64, PgProtFlags)>,
+}
+
+/// Result of a check
+pub struct AliasReport {
+    /// Number of text pages checked
+    pub checked: usize,
+    /// The suspicious aliases, the first [`MAX_FINDINGS`]
+    pub findings: KVec<TextAlias>,
+}
+
+impl AliasBaseline {
+    /// Record the protections of the aliases of the current text pages
+    pub fn snapshot() -> Result<Self> {
+        let mut pages = KVVec::new();
+        for_each_text_page(|_, pfn, _| {
+            if let Some((_, flags)) = alias(pfn) {
+                pages.push((pfn, flags), GFP_KERNEL)?;
+            }
+            Ok(())
+        })?;
+        pages.sort_unstable_by_key(|(pfn, _)| *pfn);
+        Ok(AliasBaseline { pages })
+    }
+
+    /// Check the aliases of the current text pages, the pages missing from the baseline
+    /// (modules loaded since) are only checked for executable aliases
+    pub fn check(&self) -> Result<AliasReport> {
+        let mut report = AliasReport {
+            checked: 0,
+            findings: KVec::new(),
+        };
+
+        for_each_text_page(|text, pfn, owner| {
+            let Some((alias, flags)) = alias(pfn) else {
+                return Ok(());
+            };
+            report.checked += 1;
+
+            let before = self
+                .pages
+                .binary_search_by_key(&pfn, |(pfn, _)| *pfn)
+                .ok()
+                .map(|i| self.pages[i].1);
+            let finding = if flags.is_wx() {
+                AliasFinding::WritableExecutable
+            } else if flags.is_executable() {
+                AliasFinding::Executable
+            } else {
+                match before {
+                    Some(before) if flags.is_writable() && !before.is_writable() => {
+                        AliasFinding::Downgraded { before }
+                    }
+                    _ => return Ok(()),
+                }
+            };
+
+            if report.findings.len() < MAX_FINDINGS {
+                report.findings.push(
+                    TextAlias {
+                        text,
+                        pfn,
+                        alias,
+                        owner,
+                        flags,
+                        finding,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+            Ok(())
+        })?;
+
+        Ok(report)
+    }
+}
+
+impl AliasReport {
+    /// Create the event listing the suspicious aliases, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.findings.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::TextAliasTampering,
+            fmt!("suspicious direct map aliases of the text : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for AliasReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, page) in self.findings.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:#x} [{:?}] alias {:#x} {} ({:?})",
+                page.text, page.owner, page.alias, page.flags, page.finding
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Text split : the large pages of the text split into smaller ones
+//!
+//! The kernel text is mapped with large pages. Changing the protections of a single
+//! page of it (a `set_memory_rw` to patch a function) forces the kernel to split the
+//! large page in 4K pages, and the split stays once the protections are restored. A
+//! [`SplitBaseline`] records the large pages mapping the text of the kernel image and of
+//! the modules, [`SplitBaseline::check`] reports the ones now mapped by smaller pages.
+//!
+//! C header: [`arch/x86/include/asm/pgtable_types.h`](../../../../arch/x86/include/asm/pgtable_types.h)
+
+use core::fmt;
+
+use crate::address::Owner;
+use crate::event::{Event, EventKind};
+use crate::page::PAGE_SIZE;
+use crate::pgtable::walk_kernel_range;
+use crate::text_alias::for_each_text_range;
+use kernel::prelude::*;
+
+/// A large page mapping text
+pub struct LargeTextMapping {
+    /// Start of the page
+    pub start: u64,
+    /// End of the page
+    pub end: u64,
+    /// Owner of the text
+    pub owner: Owner,
+}
+
+/// A large page of the baseline now mapped by smaller pages
+pub struct SplitMapping {
+    /// Start of the large page
+    pub start: u64,
+    /// End of the large page
+    pub end: u64,
+    /// Owner of the text
+    pub owner: Owner,
+    /// Size of the smallest page now mapping the range
+    pub smallest: u64,
+    /// Number of entries now mapping the range
+    pub entries: usize,
+}
+
+/// The large pages mapping the text
+pub struct SplitBaseline {
+    mappings: KVec<LargeTextMapping>,
+}
+
+/// Result of a check
+pub struct SplitReport {
+    /// The split large pages
+    pub splits: KVec<SplitMapping>,
+}
+
+impl SplitBaseline {
+    /// Record the large pages mapping the text of the kernel image and of the modules
+    pub fn snapshot() -> Result<Self> {
+        let mut mappings: KVec<LargeTextMapping> = KVec::new();
+        for_each_text_range(|start, end, owner| {
+            walk_kernel_range(start as usize, end as usize, |entry| {
+                let (start, end) = (entry.start as u64, entry.end as u64);
+                // A large page can hold the text of several modules
+                if end - start <= PAGE_SIZE as u64
+                    || mappings.iter().any(|mapping| mapping.start == start)
+                {
+                    return Ok(());
+                }
+                mappings.push(LargeTextMapping { start, end, owner }, GFP_KERNEL)?;
+                Ok(())
+            })
+        })?;
+        Ok(SplitBaseline { mappings })
+    }
+
+    /// Get the large pages of the baseline
+    pub fn mappings(&self) -> &[LargeTextMapping] {
+        &self.mappings
+    }
+
+    /// Check the large pages of the baseline
+    ///
+    /// A range not mapped anymore (the module was unloaded) is not a split
+    pub fn check(&self) -> Result<SplitReport> {
+        let mut splits = KVec::new();
+        for mapping in self.mappings.iter() {
+            let size = mapping.end - mapping.start;
+            let mut smallest = size;
+            let mut entries = 0;
+            walk_kernel_range(mapping.start as usize, mapping.end as usize, |entry| {
+                smallest = smallest.min((entry.end - entry.start) as u64);
+                entries += 1;
+                Ok(())
+            })?;
+
+            if entries != 0 && smallest < size {
+                splits.push(
+                    SplitMapping {
+                        start: mapping.start,
+                        end: mapping.end,
+                        owner: mapping.owner,
+                        smallest,
+                        entries,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+        Ok(SplitReport { splits })
+    }
+}
+
+impl SplitReport {
+    /// Create the event listing the split large pages, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.splits.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::TextMappingSplit,
+            fmt!("large pages of the text split : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for SplitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, split) in self.splits.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:#x}-{:#x} [{:?}] {} entries down to {:#x} bytes",
+                split.start, split.end, split.owner, split.entries, split.smallest
+            )?;
+        }
+        Ok(())
+    }
+}
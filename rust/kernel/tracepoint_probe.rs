@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Tracepoint probes : attach a callback to an existing kernel tracepoint
+//!
+//! A tracepoint is much cheaper than a fprobe on the same event, as it is a static
+//! call site with a static key. The tracepoint is looked up by name among the
+//! tracepoints of the kernel image (`for_each_kernel_tracepoint`), the arguments it
+//! passes to its probes are given by the `TP_PROTO` of its declaration and are
+//! described here by a tuple of [`TracepointArg`].
+//!
+//! C header: [`include/linux/tracepoint.h`](../../../../include/linux/tracepoint.h)
+
+use crate::error::Result;
+use crate::str::CStr;
+use crate::types::ForeignOwnable;
+use core::ffi::{c_long, c_void};
+use core::marker::PhantomData;
+use kernel::prelude::*;
+
+/// Argument types of some common tracepoints
+pub mod args {
+    use super::*;
+
+    /// `module_load(struct module *mod)`
+    pub type ModuleLoad = (*mut bindings::module,);
+
+    /// `sched_process_fork(struct task_struct *parent, struct task_struct *child)`
+    pub type SchedProcessFork = (*mut bindings::task_struct, *mut bindings::task_struct);
+
+    /// `sched_process_exec(struct task_struct *p, pid_t old_pid, struct linux_binprm *bprm)`
+    pub type SchedProcessExec = (
+        *mut bindings::task_struct,
+        bindings::pid_t,
+        *mut bindings::linux_binprm,
+    );
+
+    /// `sched_process_exit(struct task_struct *p, bool group_dead)`
+    pub type SchedProcessExit = (*mut bindings::task_struct, bool);
+
+    /// `sys_enter(struct pt_regs *regs, long id)`
+    pub type SysEnter = (*mut bindings::pt_regs, c_long);
+
+    /// `sys_exit(struct pt_regs *regs, long ret)`
+    pub type SysExit = (*mut bindings::pt_regs, c_long);
+}
+
+/// An argument of a tracepoint, passed in a register
+pub trait TracepointArg: Copy {
+    /// Convert from the raw register value
+    fn from_raw(raw: usize) -> Self;
+}
+
+macro_rules! impl_tracepoint_arg {
+    ($($t:ty),*) => {$(
+        impl TracepointArg for $t {
+            fn from_raw(raw: usize) -> Self {
+                raw as $t
+            }
+        }
+    )*}
+}
+
+impl_tracepoint_arg!(usize, isize, u64, i64, u32, i32, u16, i16, u8, i8);
+
+impl TracepointArg for bool {
+    fn from_raw(raw: usize) -> Self {
+        raw as u8 != 0
+    }
+}
+
+impl<T> TracepointArg for *mut T {
+    fn from_raw(raw: usize) -> Self {
+        raw as *mut T
+    }
+}
+
+impl<T> TracepointArg for *const T {
+    fn from_raw(raw: usize) -> Self {
+        raw as *const T
+    }
+}
+
+/// The arguments of a tracepoint, as a tuple of [`TracepointArg`]
+pub trait TracepointArgs: Sized {
+    /// Get the probe function dispatching these arguments to `T::probe`
+    fn probe<T: TracepointOperations<Args = Self>>() -> *mut c_void;
+}
+
+macro_rules! impl_tracepoint_args {
+    ($($a:ident),*) => {
+        impl<$($a: TracepointArg),*> TracepointArgs for ($($a,)*) {
+            fn probe<T: TracepointOperations<Args = Self>>() -> *mut c_void {
+                /// # Safety
+                ///     Will be called only from C, prototype correspond to the probe
+                ///     prototype of a tracepoint with these arguments
+                #[allow(non_snake_case)]
+                unsafe extern "C" fn probe<T, $($a),*>(data: *mut c_void, $($a: usize),*)
+                where
+                    T: TracepointOperations<Args = ($($a,)*)>,
+                    $($a: TracepointArg),*
+                {
+                    // SAFETY: The probe is called only while registered, so the data
+                    // is still valid
+                    let data = unsafe { T::Data::borrow(data) };
+                    T::probe(data, ($(<$a as TracepointArg>::from_raw($a),)*));
+                }
+
+                probe::<T, $($a),*> as *mut c_void
+            }
+        }
+    };
+}
+
+impl_tracepoint_args!();
+impl_tracepoint_args!(A0);
+impl_tracepoint_args!(A0, A1);
+impl_tracepoint_args!(A0, A1, A2);
+impl_tracepoint_args!(A0, A1, A2, A3);
+impl_tracepoint_args!(A0, A1, A2, A3, A4);
+impl_tracepoint_args!(A0, A1, A2, A3, A4, A5);
+
+/// The callback of a tracepoint probe
+///
+/// You need to implement this trait each time you need a new callbacks
+pub trait TracepointOperations
+where
+    Self: Sized,
+{
+    /// The global type that will be transmited to all the callbacks
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// The arguments of the tracepoint, see [`args`]
+    type Args: TracepointArgs;
+
+    /// Callback called each time the tracepoint is hit
+    fn probe(data: <Self::Data as ForeignOwnable>::Borrowed<'_>, args: Self::Args);
+}
+
+/// State of a lookup by `for_each_kernel_tracepoint`
+struct Lookup<'a> {
+    name: &'a CStr,
+    found: *mut bindings::tracepoint,
+}
+
+/// # Safety
+///     Will be called only from C, prototype correspond to the `for_each_kernel_tracepoint`
+///     callback prototype, `priv_` point to a `Lookup`
+unsafe extern "C" fn lookup_callback(tp: *mut bindings::tracepoint, priv_: *mut c_void) {
+    // SAFETY: `priv_` is the `Lookup` given to `for_each_kernel_tracepoint` by `find`
+    let lookup = unsafe { &mut *(priv_ as *mut Lookup<'_>) };
+    // SAFETY: The tracepoints of the kernel image are valid and have a static name
+    let name = unsafe { CStr::from_char_ptr((*tp).name) };
+    if lookup.found.is_null() && name.as_bytes() == lookup.name.as_bytes() {
+        lookup.found = tp;
+    }
+}
+
+/// Find a tracepoint of the kernel image by name
+pub fn find(name: &CStr) -> Option<*mut bindings::tracepoint> {
+    let mut lookup = Lookup {
+        name,
+        found: core::ptr::null_mut(),
+    };
+    // SAFETY: Just an FFI call, `lookup` outlive the call and has the type expected by
+    // the callback
+    unsafe {
+        bindings::for_each_kernel_tracepoint(
+            Some(lookup_callback),
+            &mut lookup as *mut Lookup<'_> as *mut c_void,
+        )
+    };
+    (!lookup.found.is_null()).then_some(lookup.found)
+}
+
+/// A probe registered on a tracepoint
+///
+/// # Invariants
+///
+///     The probe of `T` is registered on `tp` with `data`
+pub struct Tracepoint<T: TracepointOperations> {
+    tp: *mut bindings::tracepoint,
+    data: *mut c_void,
+    _t: PhantomData<T>,
+}
+
+// SAFETY: There is no `&self` methods
+unsafe impl<T: TracepointOperations> Sync for Tracepoint<T> where T::Data: Sync {}
+
+// SAFETY: It is safe to unregister the probe on a different thread than
+// the one used to register
+unsafe impl<T: TracepointOperations> Send for Tracepoint<T> where T::Data: Send {}
+
+impl<T: TracepointOperations> Tracepoint<T> {
+    /// Find the tracepoint `name` and register the probe on it
+    ///
+    /// # Safety
+    ///     `T::Args` must match the `TP_PROTO` of the tracepoint `name`, there is no way to
+    ///     check it at runtime and the probe would read its arguments from the wrong
+    ///     registers
+    pub unsafe fn new(name: &CStr, private_data: T::Data) -> Result<Self> {
+        let tp = find(name).ok_or_else(|| {
+            pr_err!("Couldn't find {} tracepoint\n", name);
+            ENOENT
+        })?;
+
+        let data = private_data.into_foreign() as *mut c_void;
+        // SAFETY: Just an FFI call, `tp` is a tracepoint of the kernel image, the probe
+        // has its prototype by the safety contract of this function
+        // INVARIANT: if this return `Ok(())` the probe is registered
+        let ret = crate::error::to_result(unsafe {
+            bindings::tracepoint_probe_register(tp, T::Args::probe::<T>(), data)
+        });
+        if let Err(e) = ret {
+            // SAFETY: The probe is not registered so no one borrowed the data,
+            // this is the only call to `from_foreign` for this `into_foreign`
+            unsafe { T::Data::from_foreign(data) };
+            return Err(e);
+        }
+
+        Ok(Tracepoint {
+            tp,
+            data,
+            _t: PhantomData,
+        })
+    }
+}
+
+impl<T: TracepointOperations> Drop for Tracepoint<T> {
+    fn drop(&mut self) {
+        // SAFETY: The probe is registered by the type invariant
+        unsafe { bindings::tracepoint_probe_unregister(self.tp, T::Args::probe::<T>(), self.data) };
+
+        // Same as `tracepoint_synchronize_unregister`, wait for the running probes, the
+        // faultable tracepoints run under RCU tasks trace only when it is built
+        #[cfg(CONFIG_TASKS_TRACE_RCU)]
+        // SAFETY: Just an FFI call
+        unsafe {
+            bindings::synchronize_rcu_tasks_trace()
+        };
+        // SAFETY: Just an FFI call
+        unsafe { bindings::synchronize_rcu() };
+
+        // SAFETY: We unregistered the probe and waited for the running ones, so no one
+        // hold a Borrowed reference to the pointer, and this is the only call to
+        // `from_foreign` corresponding to the `into_foreign` of the creation
+        unsafe { T::Data::from_foreign(self.data) };
+    }
+}
@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Trap scan : the breakpoint and trap instructions injected in kernel functions
+//!
+//! The hooking frameworks which don't patch a jump in the prologue place an `int3` (or a
+//! `ud2`, `hlt`) in the body of the function and redirect it from the trap handler. Each
+//! monitored function is decoded with [`InsnIter`](crate::insn::InsnIter) and its trap instructions are flagged,
+//! except the legitimate ones:
+//! - the `int3` padding after a `ret` or a `jmp` (straight-line speculation, alignment)
+//! - the `int3` of an armed kprobe of the `kprobe_table`
+//! - the `ud2` of a `BUG()` or a `WARN()`, listed in the bug table (`__bug_table`), and the
+//!   `ud2` of a failed kCFI type check, listed in the `.kcfi_traps` section
+//! - the `ud2` emitted by the compiler for a `__builtin_trap()` (the UBSAN checks with
+//!   `CONFIG_UBSAN_TRAP`, the unreachable code after a noreturn call): it is out of line,
+//!   after a `ret`, a `jmp` or a `call`, and only reached by a branch
+//! - the `hlt` of the idle and halt paths (`default_idle`, `native_halt`, ...)
+//!
+//! A text patching in progress (`text_poke_bp`) also places a temporary `int3`.
+//!
+//! C header: [`arch/x86/include/asm/insn.h`](../../../../arch/x86/include/asm/insn.h)
+
+use core::fmt;
+
+use crate::event::{Event, EventKind};
+use crate::hook_table::for_each_kprobe;
+use crate::insn::FunctionCode;
+use kernel::prelude::*;
+
+/// A trap instruction
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrapKind {
+    /// `int3`
+    Int3,
+    /// `ud2`
+    Ud2,
+    /// `hlt`
+    Hlt,
+}
+
+/// A trap instruction injected in a function
+pub struct Trap {
+    /// The function
+    pub function: &'static CStr,
+    /// Address of the instruction
+    pub address: u64,
+    /// Offset of the instruction in the function
+    pub offset: usize,
+    /// The instruction
+    pub kind: TrapKind,
+}
+
+/// Result of a scan
+pub struct TrapScan {
+    /// Number of functions scanned
+    pub scanned: usize,
+    /// The injected trap instructions
+    pub traps: KVec<Trap>,
+}
+
+/// Get the trap encoded by `insn`, the bytes of a decoded instruction
+fn trap_kind(insn: &[u8]) -> Option<TrapKind> {
+    match insn {
+        [0xcc] => Some(TrapKind::Int3),
+        [0x0f, 0x0b] => Some(TrapKind::Ud2),
+        [0xf4] => Some(TrapKind::Hlt),
+        _ => None,
+    }
+}
+
+/// The functions of the idle and halt paths, whose `hlt` is legitimate
+const HALT_FUNCTIONS: [&str; 9] = [
+    "default_idle",
+    "native_halt",
+    "native_safe_halt",
+    "arch_safe_halt",
+    "acpi_safe_halt",
+    "amd_e400_idle",
+    "halt",
+    "hlt_play_dead",
+    "stop_this_cpu",
+];
+
+/// The execution never continues after `insn`, the bytes of a decoded instruction
+fn is_terminator(insn: &[u8]) -> bool {
+    match insn {
+        // ret, ret imm16, jmp rel32, jmp rel8
+        [0xc3] | [0xc2, ..] | [0xe9, ..] | [0xeb, ..] => true,
+        // jmp through a register or a pointer, `FF /4` and `FF /5`
+        [0xff, modrm, ..] | [0x41, 0xff, modrm, ..] => matches!((modrm >> 3) & 7, 4 | 5),
+        _ => false,
+    }
+}
+
+/// `insn`, the bytes of a decoded instruction, is a call: `call rel32`, or a call through a
+/// register or a pointer (`FF /2`)
+fn is_call(insn: &[u8]) -> bool {
+    match insn {
+        [0xe8, ..] => true,
+        [0xff, modrm, ..] | [0x41, 0xff, modrm, ..] => (modrm >> 3) & 7 == 2,
+        _ => false,
+    }
+}
+
+/// The `ud2` at `address` is the trap of a failed kCFI type check
+fn is_cfi_trap(address: u64) -> bool {
+    #[cfg(CONFIG_ARCH_USES_CFI_TRAPS)]
+    {
+        // SAFETY: Just an FFI call, the traps sections are only read
+        unsafe { bindings::is_cfi_trap(address as _) }
+    }
+    #[cfg(not(CONFIG_ARCH_USES_CFI_TRAPS))]
+    {
+        let _ = address;
+        false
+    }
+}
+
+/// The `ud2` at `address` is a `BUG()` or a `WARN()`
+fn is_bug(address: u64) -> bool {
+    #[cfg(CONFIG_GENERIC_BUG)]
+    {
+        // SAFETY: Just an FFI call, the bug table is only read
+        !unsafe { bindings::find_bug(address as _) }.is_null()
+    }
+    #[cfg(not(CONFIG_GENERIC_BUG))]
+    {
+        let _ = address;
+        false
+    }
+}
+
+impl TrapScan {
+    /// Scan the functions `names`, the functions which can't be read are skipped with a
+    /// warning
+    pub fn scan(names: &[&'static CStr]) -> Result<Self> {
+        // The walker runs under RCU so we can't allocate with GFP_KERNEL inside
+        let mut probes = KVec::new();
+        for_each_kprobe(|hook| {
+            let inactive = bindings::KPROBE_FLAG_GONE | bindings::KPROBE_FLAG_DISABLED;
+            if hook.flags & inactive as u64 == 0 {
+                probes.push(hook.target, GFP_ATOMIC)?;
+            }
+            Ok(())
+        })?;
+
+        let mut scan = TrapScan {
+            scanned: 0,
+            traps: KVec::new(),
+        };
+        for name in names {
+            match FunctionCode::lookup(name) {
+                Ok(function) => scan.scan_function(name, &function, &probes)?,
+                Err(e) if e == ENOMEM => return Err(e),
+                Err(e) => pr_warn!("Couldn't read function {}, skipped ({:?})\n", name, e),
+            }
+        }
+        Ok(scan)
+    }
+
+    fn scan_function(
+        &mut self,
+        name: &'static CStr,
+        function: &FunctionCode,
+        probes: &[u64],
+    ) -> Result {
+        self.scanned += 1;
+        let code = function.code();
+        let halt = HALT_FUNCTIONS
+            .iter()
+            .any(|halt| halt.as_bytes() == name.as_bytes());
+        let mut after_terminator = false;
+        let mut after_call = false;
+
+        for insn in function.iter() {
+            // The end of the function can be data or padding the decoder doesn't understand
+            let Ok((offset, mut insn)) = insn else {
+                break;
+            };
+            let bytes = &code[offset..offset + insn.get_length()? as usize];
+            let address = function.address() + offset as u64;
+
+            let Some(kind) = trap_kind(bytes) else {
+                after_terminator = is_terminator(bytes);
+                after_call = is_call(bytes);
+                continue;
+            };
+            let legitimate = match kind {
+                TrapKind::Int3 => after_terminator || probes.contains(&address),
+                TrapKind::Ud2 => {
+                    is_bug(address) || is_cfi_trap(address) || after_terminator || after_call
+                }
+                TrapKind::Hlt => halt,
+            };
+            if !legitimate {
+                self.traps.push(
+                    Trap {
+                        function: name,
+                        address,
+                        offset,
+                        kind,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the event listing the injected trap instructions, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.traps.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::InjectedTrap,
+            fmt!("trap instructions injected in kernel functions : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for TrapScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, trap) in self.traps.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:?} at {}+{:#x} ({:#x})",
+                trap.kind, trap.function, trap.offset, trap.address
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Uprobes (probing of userspace instructions)
+//!
+//! A uprobe is placed at an offset of a file (an ELF binary or library) and is hit by
+//! every process executing the instruction at this offset of its mapping of the file.
+//! This is how the userland components of a rootkit are caught, for example by probing
+//! the `dlopen` path of the dynamic loader to see the `LD_PRELOAD` libraries.
+//!
+//! C header: [`include/linux/uprobes.h`](../../../../include/linux/uprobes.h)
+
+use crate::error::{from_err_ptr, to_result, Result};
+use crate::init::PinInit;
+use crate::registers::Registers;
+use crate::str::CStr;
+use crate::task::Task;
+use crate::try_pin_init;
+use crate::types::{ForeignOwnable, Opaque};
+use bindings::{pt_regs, uprobe_consumer};
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use kernel::container_of;
+use kernel::error::Error;
+use kernel::prelude::*;
+use macros::{pin_data, pinned_drop};
+
+/// Length of the command name of a task (`TASK_COMM_LEN`)
+pub const TASK_COMM_LEN: usize = bindings::TASK_COMM_LEN as usize;
+
+/// The task context of a uprobe hit
+pub struct UprobeHit<'a> {
+    /// Thread id of the task which hit the probe
+    pub pid: i32,
+    /// Process id of the task which hit the probe
+    pub tgid: i32,
+    /// Command name of the task, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// The userspace registers at the hit
+    pub regs: Registers<'a>,
+}
+
+impl<'a> UprobeHit<'a> {
+    /// Get the context of the current task
+    fn current(regs: Registers<'a>) -> Self {
+        let task = Task::current_raw();
+        let mut comm = [0u8; TASK_COMM_LEN];
+        // SAFETY: `current` is always valid, `pid` and `tgid` never change and `comm` is
+        // always null terminated (it may be modified concurrently, we take a snapshot)
+        let (pid, tgid) = unsafe {
+            for (dst, src) in comm.iter_mut().zip((*task).comm.iter()) {
+                *dst = *src as u8;
+            }
+            ((*task).pid, (*task).tgid)
+        };
+        comm[TASK_COMM_LEN - 1] = 0;
+
+        UprobeHit {
+            pid,
+            tgid,
+            comm,
+            regs,
+        }
+    }
+}
+
+/// Correspond to the kernel `handler` and `ret_handler` function of a uprobe consumer
+///
+/// You need to implement this trait each time you need a new callbacks
+pub trait UprobeOperations
+where
+    Self: Sized,
+{
+    /// The global type that will be transmited to all the callbacks
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// Register the return handler, which need to hijack the return address of the
+    /// probed function in userspace
+    const HAS_RET_HANDLER: bool = false;
+
+    /// Callback called when a task execute the probed instruction
+    fn handler(data: <Self::Data as ForeignOwnable>::Borrowed<'_>, hit: &UprobeHit<'_>);
+
+    /// Callback called when the probed function return, `func` is its address,
+    /// only if [`Self::HAS_RET_HANDLER`] is set
+    fn ret_handler(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _func: usize,
+        _hit: &UprobeHit<'_>,
+    ) {
+    }
+}
+
+/// The consumer and the private data of an [`Uprobe`]
+#[repr(C)]
+struct UprobeInner {
+    uc: uprobe_consumer,
+    data: *const c_void,
+    inode: *mut bindings::inode,
+    uprobe: *mut bindings::uprobe,
+}
+
+/// A uprobe registered on a file
+///
+/// # Invariants
+///
+///     `inner.uc` is registered on `inner.uprobe`, and a reference on `inner.inode` is held
+#[pin_data(PinnedDrop)]
+pub struct Uprobe<T: UprobeOperations> {
+    #[pin]
+    inner: Opaque<UprobeInner>,
+    _t: PhantomData<T>,
+}
+
+// SAFETY: There is no `&self` methods
+unsafe impl<T: UprobeOperations> Sync for Uprobe<T> where T::Data: Sync {}
+
+// SAFETY: It is safe to unregister the probe on a different thread than
+// the one used to register
+unsafe impl<T: UprobeOperations> Send for Uprobe<T> where T::Data: Send {}
+
+/// Get a reference on the inode of a file
+fn lookup_inode(path: &CStr) -> Result<*mut bindings::inode> {
+    let mut kpath = bindings::path::default();
+    // SAFETY: Just an FFI call, `kpath` is filled on success
+    to_result(unsafe {
+        bindings::kern_path(path.as_char_ptr(), bindings::LOOKUP_FOLLOW as _, &mut kpath)
+    })?;
+
+    // SAFETY: `kern_path` returned a valid path with a positive dentry, we take a
+    // reference on its inode before releasing the path
+    let inode = unsafe { bindings::igrab((*kpath.dentry).d_inode) };
+    // SAFETY: The path was got by `kern_path`
+    unsafe { bindings::path_put(&kpath) };
+
+    if inode.is_null() {
+        return Err(ENOENT);
+    }
+    Ok(inode)
+}
+
+impl<T: UprobeOperations> Uprobe<T> {
+    /// Create a new uprobe consumer
+    ///
+    /// But it dont register it
+    fn new_inner(inode: *mut bindings::inode, data: T::Data) -> UprobeInner {
+        // SAFETY: All zeroes is a valid `struct uprobe_consumer`
+        let mut uc: uprobe_consumer = unsafe { core::mem::zeroed() };
+        uc.handler = Some(Uprobe::<T>::handler_callback);
+        if T::HAS_RET_HANDLER {
+            uc.ret_handler = Some(Uprobe::<T>::ret_handler_callback);
+        }
+
+        UprobeInner {
+            uc,
+            data: data.into_foreign(),
+            inode,
+            uprobe: core::ptr::null_mut(),
+        }
+    }
+
+    /// Create a new uprobe at `offset` in the file `path` and register it
+    pub fn new(path: &CStr, offset: u64, private_data: T::Data) -> impl PinInit<Self, Error> + '_ {
+        try_pin_init!(Self {
+            inner <- Opaque::try_ffi_init(move |slot: *mut UprobeInner| {
+                let inode = lookup_inode(path)?;
+
+                // SAFETY: The initializer can write to the provided `slot`.
+                unsafe { slot.write(Self::new_inner(inode, private_data)) };
+
+                // SAFETY: `inode` is referenced and the consumer is pinned in our structure,
+                // it will be unregistered in the drop
+                // INVARIANT: if this succeed the consumer is registered
+                let uprobe = from_err_ptr(unsafe {
+                    bindings::uprobe_register(
+                        inode,
+                        offset as _,
+                        0,
+                        core::ptr::addr_of_mut!((*slot).uc),
+                    )
+                });
+
+                match uprobe {
+                    Ok(uprobe) => {
+                        // SAFETY: `slot` was written above
+                        unsafe { (*slot).uprobe = uprobe };
+                        Ok(())
+                    }
+                    Err(e) => {
+                        // SAFETY: The consumer is not registered so no one borrowed the data,
+                        // this is the only call to `from_foreign` for this `into_foreign`, and
+                        // the reference on the inode is ours
+                        unsafe {
+                            T::Data::from_foreign((*slot).data);
+                            bindings::iput(inode);
+                        }
+                        Err(e)
+                    }
+                }
+            }),
+            _t: PhantomData,
+        })
+    }
+
+    /// Get the private data of a registered consumer
+    ///
+    /// # Safety
+    ///
+    /// `uc` must be the `uc` field of a registered [`UprobeInner`]
+    unsafe fn data<'a>(uc: *mut uprobe_consumer) -> <T::Data as ForeignOwnable>::Borrowed<'a> {
+        // SAFETY: By the safety contract `uc` is embedded in a `UprobeInner`
+        let inner = unsafe { &*container_of!(uc, UprobeInner, uc) };
+
+        // SAFETY: The consumer is still registered so the data is still valid
+        unsafe { T::Data::borrow(inner.data) }
+    }
+
+    /// # Safety
+    ///     Will be called only from C, prototype correspond to the uprobe consumer's
+    ///     callback prototype
+    unsafe extern "C" fn handler_callback(
+        uc: *mut uprobe_consumer,
+        regs: *mut pt_regs,
+    ) -> core::ffi::c_int {
+        // SAFETY: This callback is called only when the consumer is still registered
+        let data = unsafe { Self::data(uc) };
+
+        // SAFETY: The pointer is created at the call of our callback so no need to check for race
+        // However writting to it has side effect so we set it to non mutable
+        let regs = Registers::new(unsafe { &*regs });
+
+        T::handler(data, &UprobeHit::current(regs));
+
+        // Keep the probe installed in this mm
+        0
+    }
+
+    /// # Safety
+    ///     Will be called only from C, prototype correspond to the uprobe consumer's
+    ///     callback prototype
+    unsafe extern "C" fn ret_handler_callback(
+        uc: *mut uprobe_consumer,
+        func: core::ffi::c_ulong,
+        regs: *mut pt_regs,
+    ) -> core::ffi::c_int {
+        // SAFETY: This callback is called only when the consumer is still registered
+        let data = unsafe { Self::data(uc) };
+
+        // SAFETY: See `handler_callback`
+        let regs = Registers::new(unsafe { &*regs });
+
+        T::ret_handler(data, func as usize, &UprobeHit::current(regs));
+        0
+    }
+}
+
+#[pinned_drop]
+impl<T: UprobeOperations> PinnedDrop for Uprobe<T> {
+    fn drop(self: Pin<&mut Self>) {
+        let inner = self.inner.get();
+
+        // SAFETY: The consumer is registered by the type invariant, `uprobe_unregister_sync`
+        // wait for the running handlers
+        unsafe {
+            bindings::uprobe_unregister_nosync(
+                (*inner).uprobe,
+                core::ptr::addr_of_mut!((*inner).uc),
+            );
+            bindings::uprobe_unregister_sync();
+        }
+
+        // SAFETY: We unregistered the consumer so no one hold a Borrowed reference to the
+        // pointer, this is the first and last call to `from_foreign` corresponding exactly
+        // with the call to `into_foreign`. The reference on the inode is ours.
+        unsafe {
+            T::Data::from_foreign((*inner).data);
+            bindings::iput((*inner).inode);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! User W^X audit : the executable mappings of the processes hinting at an injection
+//!
+//! A payload injected in a process (shellcode, reflectively loaded library) or run
+//! without touching the disk lives in memory the loader never maps executable. The VMAs of
+//! every process are walked with [`Task::vmas`] and flagged when:
+//! - an anonymous mapping is both writable and executable
+//! - an executable mapping is backed by a file unlinked since (` (deleted)`)
+//! - an executable mapping is backed by a memfd (`/memfd:`)
+//!
+//! The JIT compilers (Java, browsers, BPF loaders in userspace) legitimately map
+//! anonymous memory writable and executable, the severity of the events is left to the
+//! scoring. The threads share the address space of their process, only the processes are
+//! walked.
+//!
+//! C header: [`include/linux/mm.h`](../../../../include/linux/mm.h)
+
+use core::fmt;
+
+use crate::event::{Event, EventKind};
+use crate::mm::VmaInfo;
+use crate::str::BStr;
+use crate::task::{Task, TASK_COMM_LEN};
+use crate::task_cgroup::Attribution;
+use crate::task_files::{file_path, PATH_MAX};
+use crate::types::ARef;
+use kernel::prelude::*;
+
+/// Prefix of the path of a memfd
+const MEMFD_PREFIX: &[u8] = b"/memfd:";
+
+/// Suffix appended by `d_path` to the path of an unlinked file
+const DELETED_SUFFIX: &[u8] = b" (deleted)";
+
+/// Why a mapping is suspicious
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MappingKind {
+    /// An anonymous mapping both writable and executable
+    AnonymousWx,
+    /// An executable mapping of an unlinked file
+    DeletedFile,
+    /// An executable mapping of a memfd
+    Memfd,
+}
+
+/// A suspicious mapping of a process
+pub struct SuspiciousMapping {
+    /// Start of the mapping
+    pub start: usize,
+    /// End of the mapping, excluded
+    pub end: usize,
+    /// The `VM_*` flags of the mapping
+    pub flags: u64,
+    /// Why the mapping is suspicious
+    pub kind: MappingKind,
+    /// Path of the backing file, empty for an anonymous mapping
+    pub path: KVec<u8>,
+}
+
+/// The suspicious mappings of a process
+pub struct TaskMappings {
+    /// Process id
+    pub pid: i32,
+    /// Command name of the process, null terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// Path of the executable of the process
+    pub exe: KVec<u8>,
+    /// Path of the cgroup of the process
+    pub cgroup: KVec<u8>,
+    /// The suspicious mappings
+    pub mappings: KVec<SuspiciousMapping>,
+}
+
+/// Result of the audit
+pub struct UserWxAudit {
+    /// Number of processes whose address space was walked
+    pub scanned: usize,
+    /// The processes with suspicious mappings
+    pub tasks: KVec<TaskMappings>,
+}
+
+/// Classify `vma`, `buf` is the storage of the resolution of the path of its file
+fn classify(vma: &VmaInfo, buf: &mut [u8]) -> Result<Option<(MappingKind, KVec<u8>)>> {
+    if !vma.is_executable() {
+        return Ok(None);
+    }
+    let Some(file) = &vma.file else {
+        return Ok(vma
+            .is_writable()
+            .then_some((MappingKind::AnonymousWx, KVec::new())));
+    };
+    let path = file_path(file, buf)?;
+    let kind = if path.starts_with(MEMFD_PREFIX) {
+        MappingKind::Memfd
+    } else if path.ends_with(DELETED_SUFFIX) {
+        MappingKind::DeletedFile
+    } else {
+        return Ok(None);
+    };
+    Ok(Some((kind, path)))
+}
+
+impl TaskMappings {
+    /// Walk the address space of `task`, `None` if it has no suspicious mapping or no
+    /// address space
+    fn audit(task: &Task, buf: &mut [u8]) -> Result<Option<Self>> {
+        let Some(vmas) = task.vmas() else {
+            return Ok(None);
+        };
+        let mut mappings = KVec::new();
+        for vma in vmas {
+            if let Some((kind, path)) = classify(&vma, buf)? {
+                mappings.push(
+                    SuspiciousMapping {
+                        start: vma.start,
+                        end: vma.end,
+                        flags: vma.flags,
+                        kind,
+                        path,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+        if mappings.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(TaskMappings {
+            pid: task.pid(),
+            comm: task.comm(),
+            exe: task.exe_path()?.unwrap_or_else(KVec::new),
+            cgroup: task.cgroup_path()?,
+            mappings,
+        }))
+    }
+
+    /// Create the event listing the suspicious mappings of the process
+    pub fn to_event(&self) -> Result<Event> {
+        Event::new(
+            EventKind::SuspiciousUserMapping,
+            fmt!("suspicious executable mappings in a process : {}", self),
+        )
+    }
+}
+
+impl fmt::Display for TaskMappings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self
+            .comm
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(TASK_COMM_LEN);
+        write!(
+            f,
+            "{} (pid {}, exe {}, {})",
+            BStr::from_bytes(&self.comm[..len]),
+            self.pid,
+            BStr::from_bytes(&self.exe),
+            Attribution(&self.cgroup)
+        )?;
+        for (i, mapping) in self.mappings.iter().enumerate() {
+            f.write_str(if i == 0 { " " } else { ", " })?;
+            write!(
+                f,
+                "{:#x}-{:#x} {:?} (flags {:#x})",
+                mapping.start, mapping.end, mapping.kind, mapping.flags
+            )?;
+            if !mapping.path.is_empty() {
+                write!(f, " {}", BStr::from_bytes(&mapping.path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UserWxAudit {
+    /// Walk the address space of every process
+    pub fn audit() -> Result<Self> {
+        let mut buf = KVec::from_elem(0u8, PATH_MAX, GFP_KERNEL)?;
+        let mut audit = UserWxAudit {
+            scanned: 0,
+            tasks: KVec::new(),
+        };
+
+        let origin: ARef<Task> = current!().group_leader().into();
+        for task in origin {
+            audit.scanned += 1;
+            if let Some(mappings) = TaskMappings::audit(&task, &mut buf)? {
+                audit.tasks.push(mappings, GFP_KERNEL)?;
+            }
+        }
+        Ok(audit)
+    }
+
+    /// Create an event per process with suspicious mappings
+    pub fn to_events(&self) -> Result<KVec<Event>> {
+        let mut events = KVec::with_capacity(self.tasks.len(), GFP_KERNEL)?;
+        for task in self.tasks.iter() {
+            events.push(task.to_event()?, GFP_KERNEL)?;
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Watchdog : self-check of the probes of rkchk
+//!
+//! A rootkit aware of rkchk doesn't need to evade its checks, it can simply unregister
+//! its probes or redirect their callbacks. The [`Watchdog`] records the state of our
+//! probes when they are watched, and each [`Watchdog::check`] verifies that :
+//! - each probe is still on `ftrace_ops_list` or `kprobe_table`
+//! - the callbacks called by the kernel are still the recorded ones
+//! - the probes were not disabled behind our back
+//!
+//! The check is cheap, it is meant to be run at each periodic scan. A probe disabled on
+//! purpose must be watched again with its new state.
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::event::{Event, EventKind};
+use crate::fprobe::{flags, Fprobe, FprobeOperations};
+use crate::hook_table::{for_each_ftrace_ops, for_each_kprobe, HookKind, RawHook};
+use crate::kprobe::{Kprobe, KprobeOperations};
+use kernel::prelude::*;
+
+/// Kind of watched probe
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProbeKind {
+    /// A [`Fprobe`], found through its ftrace_ops
+    Fprobe,
+    /// A [`Kprobe`]
+    Kprobe,
+}
+
+/// The recorded state of a probe
+struct Watched {
+    kind: ProbeKind,
+    /// The `struct fprobe` or `struct kprobe`
+    object: u64,
+    /// The object on the kernel list : the ftrace_ops of a fprobe, the kprobe itself
+    listed: u64,
+    /// The callback called by the kernel : `ftrace_ops::func` or `kprobe::pre_handler`
+    callback: u64,
+    /// Our handlers : the entry and exit handlers of a fprobe, the pre and post handlers
+    /// of a kprobe
+    handlers: (u64, u64),
+    disabled: bool,
+}
+
+/// How a probe was tampered with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tampering {
+    /// The probe is not on the kernel list anymore
+    Unregistered,
+    /// The callback called by the kernel changed
+    CallbackRedirected {
+        /// The recorded callback
+        expected: u64,
+        /// The current callback
+        found: u64,
+    },
+    /// Our handlers in the probe structure changed
+    HandlerRedirected,
+    /// The probe was disabled
+    Disabled,
+}
+
+/// A tampered probe
+pub struct TamperedProbe {
+    /// Kind of probe
+    pub kind: ProbeKind,
+    /// Address of the `struct fprobe` or `struct kprobe`
+    pub object: u64,
+    /// How it was tampered with
+    pub tampering: Tampering,
+}
+
+/// Result of a check
+pub struct WatchdogReport {
+    /// The tampered probes
+    pub tampered: KVec<TamperedProbe>,
+}
+
+/// The watched probes, which must outlive the watchdog
+pub struct Watchdog<'a> {
+    watched: KVec<Watched>,
+    _probes: PhantomData<&'a ()>,
+}
+
+impl Default for Watchdog<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Watchdog<'a> {
+    /// Create a watchdog without any probe
+    pub fn new() -> Self {
+        Watchdog {
+            watched: KVec::new(),
+            _probes: PhantomData,
+        }
+    }
+
+    /// Read the current state of a watched probe
+    fn read(kind: ProbeKind, object: u64) -> Watched {
+        match kind {
+            ProbeKind::Fprobe => {
+                let fp = object as *const bindings::fprobe;
+                // SAFETY: The fprobe outlive the watchdog, its fields are only modified at
+                // registration (or by a rootkit)
+                unsafe {
+                    Watched {
+                        kind,
+                        object,
+                        listed: core::ptr::addr_of!((*fp).ops) as u64,
+                        callback: (*fp).ops.func.map_or(0, |f| f as u64),
+                        handlers: (
+                            (*fp).entry_handler.map_or(0, |f| f as u64),
+                            (*fp).exit_handler.map_or(0, |f| f as u64),
+                        ),
+                        disabled: core::ptr::read_volatile(core::ptr::addr_of!((*fp).flags))
+                            & flags::FTRACE_FL_DISABLED
+                            != 0,
+                    }
+                }
+            }
+            ProbeKind::Kprobe => {
+                let kp = object as *const bindings::kprobe;
+                // SAFETY: The kprobe outlive the watchdog, its fields are only modified at
+                // registration (or by a rootkit), and the flags under `kprobe_mutex`
+                unsafe {
+                    let handlers = (
+                        (*kp).pre_handler.map_or(0, |f| f as u64),
+                        (*kp).post_handler.map_or(0, |f| f as u64),
+                    );
+                    Watched {
+                        kind,
+                        object,
+                        listed: object,
+                        callback: handlers.0,
+                        handlers,
+                        disabled: core::ptr::read_volatile(core::ptr::addr_of!((*kp).flags))
+                            & bindings::KPROBE_FLAG_DISABLED
+                            != 0,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record the current state of a registered fprobe
+    pub fn watch_fprobe<T: FprobeOperations>(&mut self, fp: &'a Fprobe<T>) -> Result {
+        let watched = Self::read(ProbeKind::Fprobe, fp.as_ptr() as u64);
+        self.watch(watched)
+    }
+
+    /// Record the current state of a registered kprobe
+    pub fn watch_kprobe<T: KprobeOperations>(&mut self, kp: &'a Kprobe<T>) -> Result {
+        let watched = Self::read(ProbeKind::Kprobe, kp.as_ptr() as u64);
+        self.watch(watched)
+    }
+
+    fn watch(&mut self, watched: Watched) -> Result {
+        match self.watched.iter_mut().find(|w| w.object == watched.object) {
+            Some(w) => *w = watched,
+            None => self.watched.push(watched, GFP_KERNEL)?,
+        }
+        Ok(())
+    }
+
+    /// Verify the watched probes
+    pub fn check(&self) -> Result<WatchdogReport> {
+        // The walkers run under RCU so we can't allocate with GFP_KERNEL inside
+        let mut raw: KVec<RawHook> = KVec::new();
+        for_each_ftrace_ops(|hook| Ok(raw.push(*hook, GFP_ATOMIC)?))?;
+        for_each_kprobe(|hook| Ok(raw.push(*hook, GFP_ATOMIC)?))?;
+
+        let mut tampered = KVec::new();
+        for watched in self.watched.iter() {
+            let hook_kind = match watched.kind {
+                ProbeKind::Fprobe => HookKind::Ftrace,
+                ProbeKind::Kprobe => HookKind::Kprobe,
+            };
+            let current = Self::read(watched.kind, watched.object);
+
+            let tampering = match raw
+                .iter()
+                .find(|hook| hook.kind == hook_kind && hook.object == watched.listed)
+            {
+                None => Some(Tampering::Unregistered),
+                Some(hook) if hook.handler != watched.callback => {
+                    Some(Tampering::CallbackRedirected {
+                        expected: watched.callback,
+                        found: hook.handler,
+                    })
+                }
+                Some(_) if current.handlers != watched.handlers => {
+                    Some(Tampering::HandlerRedirected)
+                }
+                Some(_) if current.disabled && !watched.disabled => Some(Tampering::Disabled),
+                Some(_) => None,
+            };
+
+            if let Some(tampering) = tampering {
+                tampered.push(
+                    TamperedProbe {
+                        kind: watched.kind,
+                        object: watched.object,
+                        tampering,
+                    },
+                    GFP_KERNEL,
+                )?;
+            }
+        }
+
+        Ok(WatchdogReport { tampered })
+    }
+}
+
+impl WatchdogReport {
+    /// Create the event listing the tampered probes, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.tampered.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::ProbeTampering,
+            fmt!("rkchk probes tampered with : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for WatchdogReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, t) in self.tampered.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{:?} {:#x} ({:?})", t.kind, t.object, t.tampering)?;
+        }
+        Ok(())
+    }
+}
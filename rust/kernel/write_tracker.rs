@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Write tracker : the pages of a monitored region written since the last scan
+//!
+//! Comparing the whole monitored memory at each scan is expensive. The CPU sets the dirty
+//! bit of an entry at the first write through it: a [`WriteTracker`] clears the accessed
+//! and dirty bits of the leaf entries covering its region, [`WriteTracker::written`]
+//! reports the ranges of the entries dirtied since and clears them again, so only these
+//! ranges are compared.
+//!
+//! Only the writes through the monitored mapping are seen, not the ones through another
+//! alias of the same pages (the direct map, the temporary mapping of `text_poke`), and a
+//! write done through a stale TLB entry between the clearing and the flush is missed: a
+//! full comparison is still needed from time to time. A large page is reported as a
+//! whole, and an entry replaced since the last scan is reported as the kernel creates its
+//! entries dirty.
+//!
+//! C header: [`arch/x86/include/asm/pgtable_types.h`](../../../../arch/x86/include/asm/pgtable_types.h)
+
+use crate::page::PAGE_SIZE;
+use crate::pgtable::{flush_tlb_kernel_range, walk_kernel_range};
+use kernel::prelude::*;
+
+/// A range written since the last scan
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WrittenRange {
+    /// Start of the range
+    pub start: usize,
+    /// End of the range
+    pub end: usize,
+}
+
+/// Track the writes to a kernel region
+pub struct WriteTracker {
+    start: usize,
+    end: usize,
+}
+
+impl WriteTracker {
+    /// Start tracking the writes to `start..end`, the range must be page aligned
+    ///
+    /// Sends IPIs to flush the TLB, so it must not be called with the interrupts disabled
+    pub fn new(start: usize, end: usize) -> Result<Self> {
+        if start % PAGE_SIZE != 0 || end % PAGE_SIZE != 0 || start >= end {
+            return Err(EINVAL);
+        }
+        let tracker = WriteTracker { start, end };
+        tracker.clear()?;
+        Ok(tracker)
+    }
+
+    /// Get the tracked region
+    pub fn range(&self) -> (usize, usize) {
+        (self.start, self.end)
+    }
+
+    /// Clear the accessed and dirty bits of the region and flush its TLB entries
+    ///
+    /// # Return
+    /// The ranges of the entries which were dirty, merged and clipped to the region
+    fn clear(&self) -> Result<KVec<WrittenRange>> {
+        let mut written: KVec<WrittenRange> = KVec::new();
+        let ret = walk_kernel_range(self.start, self.end, |entry| {
+            if !entry.test_and_clear_accessed_dirty().is_dirty() {
+                return Ok(());
+            }
+            let start = entry.start.max(self.start);
+            let end = entry.end.min(self.end);
+            if let Some(last) = written.last_mut() {
+                if last.end == start {
+                    last.end = end;
+                    return Ok(());
+                }
+            }
+            written.push(WrittenRange { start, end }, GFP_KERNEL)?;
+            Ok(())
+        });
+        // The bits already cleared must be flushed even if the walk failed
+        flush_tlb_kernel_range(self.start, self.end);
+        ret?;
+        Ok(written)
+    }
+
+    /// Get the ranges written since the creation of the tracker or the last call, and
+    /// start tracking again
+    ///
+    /// Sends IPIs to flush the TLB, so it must not be called with the interrupts disabled
+    pub fn written(&mut self) -> Result<KVec<WrittenRange>> {
+        self.clear()
+    }
+}
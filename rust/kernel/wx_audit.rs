@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! W^X audit : the kernel mappings both writable and executable
+//!
+//! The kernel never maps memory writable and executable at the same time once booted,
+//! the text is written through `text_poke` which uses a temporary mapping. A rootkit
+//! calling `set_memory_rw` on the text to install its hooks (or `set_memory_x` on its
+//! payload without `set_memory_ro`) leaves such a mapping. The kernel half of the
+//! address space is walked with [`walk_kernel_range`], a leaf is W+X with the protections
+//! of the table entries above it applied. The consecutive W+X entries with the same
+//! protections are merged and each range is attributed with [`resolve_address`].
+//!
+//! C header: [`arch/x86/include/asm/pgtable_types.h`](../../../../arch/x86/include/asm/pgtable_types.h)
+
+use core::fmt;
+
+use crate::address::{resolve_address, Owner, RegionType};
+use crate::event::{Event, EventKind};
+use crate::pgtable::{walk_kernel_range, PgProtFlags};
+use crate::task::cond_resched;
+use kernel::prelude::*;
+
+/// Start of the kernel half of the address space, `-(1 << __VIRTUAL_MASK_SHIFT)`
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn kernel_half() -> usize {
+    // SAFETY: Just an FFI call, the paging mode is set in the early boot
+    let va_bits = if unsafe { bindings::pgtable_l5_enabled() } {
+        56
+    } else {
+        47
+    };
+    usize::MAX << va_bits
+}
+
+/// Start of the kernel half of the address space, `-(1 << vabits_actual)`
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn kernel_half() -> usize {
+    // With 52 bits addresses the number of bits used is only known at boot
+    #[cfg(CONFIG_ARM64_VA_BITS_52)]
+    // SAFETY: `vabits_actual` is set in the early boot and never modified
+    let va_bits = unsafe { bindings::vabits_actual } as u32;
+    #[cfg(not(CONFIG_ARM64_VA_BITS_52))]
+    let va_bits = bindings::VA_BITS;
+    usize::MAX << va_bits
+}
+
+/// Maximum number of ranges reported, the walk is stopped beyond
+const MAX_MAPPINGS: usize = 1024;
+
+/// A range of the kernel space mapped writable and executable
+pub struct WxMapping {
+    /// Start of the range
+    pub start: u64,
+    /// End of the range
+    pub end: u64,
+    /// The page protection flags of the entries mapping the range, restricted by the upper
+    /// levels
+    pub pgprot: PgProtFlags,
+    /// Owner of the start of the range
+    pub owner: Owner,
+    /// Region of the start of the range
+    pub region: RegionType,
+}
+
+/// Result of the audit
+pub struct WxAudit {
+    /// The W+X ranges
+    pub mappings: KVec<WxMapping>,
+}
+
+impl WxAudit {
+    /// Walk the kernel space and list the W+X ranges
+    pub fn audit() -> Result<Self> {
+        let mut mappings: KVec<WxMapping> = KVec::new();
+
+        let ret = walk_kernel_range(kernel_half(), usize::MAX, |entry| {
+            // A W+X leaf below a read-only or non executable table entry isn't W+X
+            let pgprot = entry.flags;
+            if !pgprot.is_wx() {
+                return Ok(());
+            }
+            let (start, end) = (entry.start as u64, entry.end as u64);
+            if let Some(last) = mappings.last_mut() {
+                if last.end == start && last.pgprot == pgprot {
+                    last.end = end;
+                    return Ok(());
+                }
+            }
+            if mappings.len() >= MAX_MAPPINGS {
+                return Err(E2BIG);
+            }
+            mappings.push(
+                WxMapping {
+                    start,
+                    end,
+                    pgprot,
+                    owner: Owner::None,
+                    region: RegionType::Unknown,
+                },
+                GFP_KERNEL,
+            )?;
+            Ok(())
+        });
+        match ret {
+            Err(e) if e == E2BIG => pr_warn!("Too many W+X mappings, the audit is truncated\n"),
+            ret => ret?,
+        }
+
+        // The addresses are resolved once the walk is done
+        for mapping in mappings.iter_mut() {
+            cond_resched();
+            if let Ok(info) = resolve_address(mapping.start) {
+                mapping.owner = info.owner;
+                mapping.region = info.region;
+            }
+        }
+
+        Ok(WxAudit { mappings })
+    }
+
+    /// Create the event listing the W+X ranges, if any
+    pub fn to_event(&self) -> Result<Option<Event>> {
+        if self.mappings.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Event::new(
+            EventKind::WritableExecutableMapping,
+            fmt!("writable and executable kernel mappings : {}", self),
+        )?))
+    }
+}
+
+impl fmt::Display for WxAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, mapping) in self.mappings.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:#x}-{:#x} [{:?} {:?}] {:?}",
+                mapping.start, mapping.end, mapping.owner, mapping.region, mapping.pgprot
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Simulated rootkit for the rkchk detection suite
+//!
+//! Perform benign versions of the rootkit techniques so the checks can be validated end
+//! to end in a lab environment. Nothing here alters the behaviour of the kernel :
+//! - a private copy of the start of `sys_call_table` has one of its entries redirected
+//!   to a function of this module
+//! - a fake `struct module` is written in an orphan allocation of the module space,
+//!   unlinked as if removed from the module list
+//! - a netfilter hook accepting every packet is registered
+//!
+//! The addresses of the fixtures are logged so the test harness can point the checks
+//! at them. Only built with `CONFIG_SAMPLE_RUST_RKCHK_SIMULATOR`, never load it on a
+//! production system.
+
+use core::ffi::c_void;
+use core::ptr::addr_of_mut;
+
+use kernel::c_str;
+use kernel::module::{symbols_lookup_name, ModMemType, MODULE_NAME_LEN};
+use kernel::nofault;
+use kernel::page::PAGE_SIZE;
+use kernel::prelude::*;
+
+module! {
+    type: RkchkSimulator,
+    name: "rkchk_simulator",
+    author: "rkchk",
+    description: "Benign rootkit techniques to validate rkchk",
+    license: "GPL",
+}
+
+/// Number of entries of the syscall table copy
+const TABLE_COPY_LEN: usize = 16;
+
+/// Entry of the copy which is redirected
+const HOOKED_NR: usize = 0;
+
+/// Name of the fake hidden module
+const HIDDEN_NAME: &[u8] = b"rkchk_sim_hidden";
+const _: () = assert!(HIDDEN_NAME.len() < MODULE_NAME_LEN);
+
+/// `LIST_POISON1` and `LIST_POISON2` with the x86_64 `POISON_POINTER_DELTA`
+const LIST_POISON1: u64 = 0xdead_0000_0000_0100;
+const LIST_POISON2: u64 = 0xdead_0000_0000_0122;
+
+/// The function the copy of the syscall table is redirected to
+extern "C" fn hooked_syscall() -> i64 {
+    -(bindings::ENOSYS as i64)
+}
+
+/// # Safety
+///     Will be called only from C, prototype correspond to the netfilter hook prototype
+unsafe extern "C" fn nf_hook(
+    _priv: *mut c_void,
+    _skb: *mut bindings::sk_buff,
+    _state: *const bindings::nf_hook_state,
+) -> core::ffi::c_uint {
+    bindings::NF_ACCEPT
+}
+
+/// A private copy of the start of the syscall table with one entry hooked
+fn hook_table_copy() -> Result<KBox<[u64; TABLE_COPY_LEN]>> {
+    let mut copy = KBox::new([0u64; TABLE_COPY_LEN], GFP_KERNEL)?;
+
+    let table = symbols_lookup_name(c_str!("sys_call_table"));
+    if table == 0 {
+        pr_err!("Couldn't find sys_call_table symbol\n");
+        return Err(ENOENT);
+    }
+    for (i, entry) in copy.iter_mut().enumerate() {
+        *entry = nofault::read(table as usize + i * core::mem::size_of::<u64>())?;
+    }
+
+    copy[HOOKED_NR] = hooked_syscall as usize as u64;
+    pr_info!("Syscall table copy at {:p}\n", copy.as_ptr());
+    Ok(copy)
+}
+
+/// Size of the fake hidden module allocation
+fn hidden_size() -> usize {
+    core::mem::size_of::<bindings::module>().next_multiple_of(PAGE_SIZE)
+}
+
+/// Write a fake unlinked `struct module` in an orphan allocation of the module space
+fn hide_fake_module() -> Result<*mut c_void> {
+    let size = hidden_size();
+    // SAFETY: Just an FFI call
+    let mem = unsafe { bindings::execmem_alloc(bindings::execmem_type_EXECMEM_MODULE_DATA, size) };
+    if mem.is_null() {
+        return Err(ENOMEM);
+    }
+
+    let module = mem.cast::<bindings::module>();
+    // SAFETY: `mem` is a writable allocation of at least the size of `struct module`,
+    // all zeroes is a valid `struct module`
+    unsafe {
+        core::ptr::write_bytes(mem.cast::<u8>(), 0, size);
+
+        let name = &mut (*module).name;
+        for (dst, src) in name.iter_mut().zip(HIDDEN_NAME.iter()) {
+            *dst = *src as _;
+        }
+
+        (*module).state = bindings::module_state_MODULE_STATE_LIVE;
+        // As left by `list_del` when unlinked from the module list
+        (*module).list.next = LIST_POISON1 as _;
+        (*module).list.prev = LIST_POISON2 as _;
+
+        let text = &mut (*module).mem[ModMemType::Text as usize];
+        text.base = mem;
+        text.size = size as _;
+    }
+
+    pr_info!("Fake hidden module at {:p}\n", mem);
+    Ok(mem)
+}
+
+/// Register a netfilter hook accepting every IPv4 packet
+fn register_nf_hook() -> Result<KBox<bindings::nf_hook_ops>> {
+    // SAFETY: All zeroes is a valid `struct nf_hook_ops`
+    let mut ops: KBox<bindings::nf_hook_ops> =
+        KBox::new(unsafe { core::mem::zeroed() }, GFP_KERNEL)?;
+    ops.hook = Some(nf_hook);
+    ops.pf = bindings::NFPROTO_IPV4 as _;
+    ops.hooknum = bindings::nf_inet_hooks_NF_INET_PRE_ROUTING;
+    ops.priority = bindings::nf_ip_hook_priorities_NF_IP_PRI_FIRST;
+
+    // SAFETY: `ops` is filled and boxed so it won't move, it is unregistered in the drop
+    kernel::error::to_result(unsafe {
+        bindings::nf_register_net_hook(addr_of_mut!(bindings::init_net), &*ops)
+    })?;
+
+    pr_info!("Netfilter hook at {:p}\n", nf_hook as *const c_void);
+    Ok(ops)
+}
+
+struct RkchkSimulator {
+    table_copy: KBox<[u64; TABLE_COPY_LEN]>,
+    hidden: *mut c_void,
+    nf_ops: KBox<bindings::nf_hook_ops>,
+}
+
+// SAFETY: `hidden` is only freed in the drop, the other fields are owned
+unsafe impl Send for RkchkSimulator {}
+
+// SAFETY: There is no `&self` methods
+unsafe impl Sync for RkchkSimulator {}
+
+impl kernel::Module for RkchkSimulator {
+    fn init(_module: &'static ThisModule) -> Result<Self> {
+        pr_info!("rkchk simulator loaded, this is a test fixture\n");
+
+        let table_copy = hook_table_copy()?;
+        let hidden = hide_fake_module()?;
+        let nf_ops = match register_nf_hook() {
+            Ok(ops) => ops,
+            Err(e) => {
+                // SAFETY: `hidden` come from `execmem_alloc` and is not used anymore
+                unsafe { bindings::execmem_free(hidden) };
+                return Err(e);
+            }
+        };
+
+        Ok(RkchkSimulator {
+            table_copy,
+            hidden,
+            nf_ops,
+        })
+    }
+}
+
+impl Drop for RkchkSimulator {
+    fn drop(&mut self) {
+        // SAFETY: The hook was registered in `init`
+        unsafe {
+            bindings::nf_unregister_net_hook(addr_of_mut!(bindings::init_net), &*self.nf_ops)
+        };
+        // SAFETY: `hidden` come from `execmem_alloc` and is not used anymore
+        unsafe { bindings::execmem_free(self.hidden) };
+        pr_info!(
+            "rkchk simulator unloaded (syscall table copy at {:p})\n",
+            self.table_copy.as_ptr()
+        );
+    }
+}